@@ -0,0 +1,124 @@
+//! Pinning concurrent test processes to distinct CPUs, for `--pin-cpus`.
+
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    process::Command,
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+
+/// The CPUs that test processes may be pinned to.
+#[derive(Debug)]
+pub(crate) struct CpuPool {
+    free: Mutex<Vec<usize>>,
+    available: Arc<Semaphore>,
+}
+
+/// A CPU reserved for a single test process, which is returned to its pool
+/// when this is dropped.
+#[derive(Debug)]
+pub(crate) struct PinnedCpu {
+    cpu: usize,
+    pool: Arc<CpuPool>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl CpuPool {
+    /// Returns a pool of the CPUs this process is allowed to run on.
+    pub(crate) fn new() -> io::Result<Self> {
+        let cpus = allowed_cpus()?;
+        Ok(Self {
+            available: Arc::new(Semaphore::new(cpus.len())),
+            free: Mutex::new(cpus),
+        })
+    }
+
+    /// Waits until a CPU is free, and reserves it.
+    ///
+    /// Since each process gets a CPU to itself, no more processes can run
+    /// at once than there are CPUs.
+    pub(crate) async fn acquire(self: &Arc<Self>) -> PinnedCpu {
+        let permit = self
+            .available
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        let cpu = self
+            .free()
+            .pop()
+            .expect("a permit was acquired, so a CPU is free");
+        PinnedCpu {
+            cpu,
+            pool: self.clone(),
+            _permit: permit,
+        }
+    }
+
+    fn free(&self) -> std::sync::MutexGuard<'_, Vec<usize>> {
+        self.free
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+}
+
+impl PinnedCpu {
+    /// Pins the process spawned by `cmd` to this CPU.
+    pub(crate) fn pin(&self, cmd: &mut Command) {
+        pin(cmd, self.cpu);
+    }
+}
+
+impl Drop for PinnedCpu {
+    fn drop(&mut self) {
+        self.pool.free().push(self.cpu);
+    }
+}
+
+/// Returns whether processes can be pinned to CPUs on this platform.
+pub(crate) fn is_supported() -> bool {
+    cfg!(target_os = "linux")
+}
+
+#[cfg(target_os = "linux")]
+fn allowed_cpus() -> io::Result<Vec<usize>> {
+    // Safety: `cpu_set_t` is a plain bitmask, which may be zeroed.
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    // Safety: `set` is a valid `cpu_set_t`, of the size we pass.
+    let result =
+        unsafe { libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((0..libc::CPU_SETSIZE as usize)
+        // Safety: `cpu` is less than `CPU_SETSIZE`.
+        .filter(|&cpu| unsafe { libc::CPU_ISSET(cpu, &set) })
+        .collect())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allowed_cpus() -> io::Result<Vec<usize>> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(target_os = "linux")]
+fn pin(cmd: &mut Command, cpu: usize) {
+    // Safety: `sched_setaffinity` is async-signal-safe, so it may be called
+    // between `fork` and `exec`.
+    unsafe {
+        cmd.pre_exec(move || {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            libc::CPU_SET(cpu, &mut set);
+            if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        });
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin(_: &mut Command, _: usize) {}
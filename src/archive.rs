@@ -0,0 +1,142 @@
+//! Bundling the files describing a run's failing tests into one archive.
+//!
+//! The archive written by `--archive-failures` contains:
+//!
+//! - `run.json`: the version of `cargo loom`, its arguments, and the loom
+//!   environment variables tests were run with
+//! - `summary.json`: the run's [`Summary`]
+//! - for each failing test, under `<package>/<suite>/`:
+//!   - `<test>.failure.json`: the test's [`FailureSummary`]
+//!   - `<test>.log`: the output of its logged rerun
+//!   - `<test>.checkpoint.json`: the checkpoint it was rerun from
+//!   - `<test>.sanitizer.log`: the reports of `--sanitizer`, if it reported
+//!     anything
+//!
+//! When running a feature matrix, each feature set's failures are under
+//! `<package>[<features>]/` instead.
+//!
+//! [`FailureSummary`]: crate::summary::FailureSummary
+
+use crate::{config::Config, file_stem, summary::Summary, App};
+use camino::Utf8Path;
+use color_eyre::{eyre::WrapErr, Result};
+use flate2::{write::GzEncoder, Compression};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::Write,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The metadata describing a run, written to `run.json`.
+#[derive(Debug, Serialize)]
+struct RunMetadata {
+    cargo_loom_version: &'static str,
+    args: Vec<String>,
+    workspace_root: String,
+    /// The loom environment variables set for the checkpointing run of each
+    /// failing test. These may be changed for individual packages by
+    /// `[package.metadata.loom]`, and for individual tests by `--override`.
+    env: BTreeMap<String, String>,
+}
+
+impl App {
+    /// Writes an archive of the logs, checkpoints, and classifications of
+    /// every failing test in `summary` to `path`, as a `.tar.gz`.
+    pub(crate) fn archive_failures(&self, summary: &Summary, path: &Utf8Path) -> Result<()> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("creating archive directory `{}`", dir))?;
+        }
+        let file =
+            fs::File::create(path).with_context(|| format!("creating archive `{}`", path))?;
+        let mut archive = Archive {
+            tar: tar::Builder::new(GzEncoder::new(file, Compression::default())),
+            mtime: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+        };
+
+        let settings = self.settings(&Config::load_root(&self.metadata)?);
+        let mut cmd = Command::new("test");
+        self.configure_loom_command(&mut cmd, &settings);
+        let env = cmd
+            .get_envs()
+            .filter_map(|(name, value)| {
+                let value = value?.to_string_lossy().into_owned();
+                Some((name.to_string_lossy().into_owned(), value))
+            })
+            .collect();
+        let run = RunMetadata {
+            cargo_loom_version: env!("CARGO_PKG_VERSION"),
+            args: std::env::args().collect(),
+            workspace_root: self.metadata.workspace_root.to_string(),
+            env,
+        };
+        archive.append_json("run.json", &run)?;
+        archive.append_json("summary.json", summary)?;
+
+        let mut archived = 0;
+        for pkg in &summary.packages {
+            let package = match pkg.features {
+                Some(ref features) => format!("{}[{}]", pkg.name, features),
+                None => pkg.name.clone(),
+            };
+            for failure in &pkg.failures {
+                let stem = format!("{}/{}/{}", package, failure.suite, file_stem(&failure.name));
+                archive.append_json(&format!("{}.failure.json", stem), failure)?;
+                archive.append_file(&format!("{}.log", stem), &failure.log)?;
+                archive.append_file(&format!("{}.checkpoint.json", stem), &failure.checkpoint)?;
+                if let Some(log) = failure.sanitizer.as_ref().and_then(|run| run.log.as_ref()) {
+                    archive.append_file(&format!("{}.sanitizer.log", stem), log)?;
+                }
+                archived += 1;
+            }
+        }
+
+        archive
+            .tar
+            .into_inner()
+            .and_then(|gz| gz.finish())
+            .and_then(|mut file| file.flush())
+            .with_context(|| format!("writing archive `{}`", path))?;
+        tracing::info!("Archived {} failing tests to {}", archived, path);
+        Ok(())
+    }
+}
+
+struct Archive {
+    tar: tar::Builder<GzEncoder<fs::File>>,
+    /// The modification time of files which are written from memory.
+    mtime: u64,
+}
+
+impl Archive {
+    fn append_json(&mut self, name: &str, value: &impl Serialize) -> Result<()> {
+        let json = serde_json::to_vec_pretty(value).context("serializing archive file")?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(json.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(self.mtime);
+        self.tar
+            .append_data(&mut header, name, json.as_slice())
+            .with_context(|| format!("adding `{}` to archive", name))
+    }
+
+    /// Adds the file at `path` to the archive as `name`, if it exists.
+    ///
+    /// A test's checkpoint may be missing (e.g. if the test was killed
+    /// before writing it), which shouldn't stop the rest of its files from
+    /// being archived.
+    fn append_file(&mut self, name: &str, path: &Utf8Path) -> Result<()> {
+        if !path.exists() {
+            tracing::debug!(file = %path, "Not archiving missing file");
+            return Ok(());
+        }
+        self.tar
+            .append_path_with_name(path, name)
+            .with_context(|| format!("adding `{}` to archive", path))
+    }
+}
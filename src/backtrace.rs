@@ -0,0 +1,134 @@
+//! Filtering the backtraces in a failing test's output, so that the frames
+//! from the test itself aren't buried in frames from loom and the standard
+//! library.
+
+use crate::trace::BacktraceStyle;
+use std::io;
+
+/// Frames whose names start with these prefixes are hidden.
+const HIDDEN: &[&str] = &[
+    "std::",
+    "core::",
+    "alloc::",
+    "test::",
+    "loom::",
+    "generator::",
+    "scoped_tls::",
+    "rust_begin_unwind",
+    "__rust",
+];
+
+/// Filters the backtraces out of a test's output, one line at a time.
+#[derive(Clone, Debug)]
+pub(crate) struct Filter {
+    enabled: bool,
+    in_backtrace: bool,
+    hiding: bool,
+    hidden: usize,
+}
+
+impl Filter {
+    /// Returns a filter for output from a test run with `style` backtraces.
+    ///
+    /// Only `short` backtraces are filtered; `full` backtraces are left as
+    /// they are.
+    pub(crate) fn new(style: BacktraceStyle) -> Self {
+        Self {
+            enabled: style == BacktraceStyle::Short,
+            in_backtrace: false,
+            hiding: false,
+            hidden: 0,
+        }
+    }
+
+    /// Filters `line`, passing it (and a note about any frames hidden before
+    /// it) to `emit` unless it's part of a hidden frame.
+    pub(crate) fn line(
+        &mut self,
+        line: &str,
+        mut emit: impl FnMut(&str) -> io::Result<()>,
+    ) -> io::Result<()> {
+        if !self.enabled {
+            return emit(line);
+        }
+
+        if !self.in_backtrace {
+            self.in_backtrace = line.trim_end() == "stack backtrace:";
+            return emit(line);
+        }
+
+        if let Some(name) = frame_name(line) {
+            self.hiding = is_hidden(name);
+            if self.hiding {
+                self.hidden += 1;
+                return Ok(());
+            }
+            self.finish(&mut emit)?;
+            return emit(line);
+        }
+
+        // A frame's source location is printed on the line after its name.
+        if line.trim_start().starts_with("at ") {
+            if self.hiding {
+                return Ok(());
+            }
+            return emit(line);
+        }
+
+        // Anything else ends the backtrace.
+        self.in_backtrace = false;
+        self.hiding = false;
+        self.finish(&mut emit)?;
+        emit(line)
+    }
+
+    /// Passes a note about any frames hidden since the last line was emitted
+    /// to `emit`.
+    pub(crate) fn finish(
+        &mut self,
+        mut emit: impl FnMut(&str) -> io::Result<()>,
+    ) -> io::Result<()> {
+        match std::mem::replace(&mut self.hidden, 0) {
+            0 => Ok(()),
+            1 => emit("      ⋮ 1 frame hidden ⋮"),
+            hidden => emit(&format!("      ⋮ {} frames hidden ⋮", hidden)),
+        }
+    }
+}
+
+/// Filters the backtraces in a test's captured `output`.
+pub(crate) fn filter(style: BacktraceStyle, output: &str) -> String {
+    let mut filtered = String::with_capacity(output.len());
+    let mut push = |line: &str| {
+        filtered.push_str(line);
+        filtered.push('\n');
+        Ok(())
+    };
+    let mut filter = Filter::new(style);
+    for line in output.lines() {
+        filter
+            .line(line, &mut push)
+            .expect("writing to a string can't fail");
+    }
+    filter
+        .finish(&mut push)
+        .expect("writing to a string can't fail");
+    filtered
+}
+
+/// Returns the name of the frame on `line` (e.g. `   4: loom::model`), if it
+/// starts a frame.
+fn frame_name(line: &str) -> Option<&str> {
+    let (idx, name) = line.trim_start().split_once(": ")?;
+    if idx.is_empty() || !idx.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(name)
+}
+
+fn is_hidden(name: &str) -> bool {
+    // Trait methods are named like `<loom::thing as core::ops::FnOnce>::call`,
+    // so the type is what identifies where a frame came from.
+    let name = name.trim_start_matches('<');
+    HIDDEN.iter().any(|prefix| name.starts_with(prefix))
+}
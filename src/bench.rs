@@ -0,0 +1,151 @@
+use crate::{
+    config::Config, libtest, model_log, trace::ColorMode, App, ENV_LOOM_LOG, ENV_MAX_DURATION,
+};
+use color_eyre::{eyre::WrapErr, Help, Result};
+use escargot::CommandMessages;
+use owo_colors::{OwoColorize, Style};
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, clap::Args)]
+pub(crate) struct BenchArgs {
+    /// If specified, only benchmark tests containing this string in their names
+    filter: Option<String>,
+}
+
+/// How quickly loom explored the models in a single test.
+#[derive(Debug, Serialize)]
+struct ModelBench {
+    name: String,
+    suite: String,
+    /// The total number of iterations of every model in the test, or `None`
+    /// if no model ran to completion.
+    iterations: Option<u64>,
+    /// The test's run time, in seconds.
+    exec_time: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    iterations_per_sec: Option<f64>,
+}
+
+impl App {
+    /// Runs the tests matching `args.filter` once each, and reports how many
+    /// iterations loom explored per second.
+    pub(crate) fn bench_models(&self, args: &BenchArgs) -> Result<()> {
+        let json = self.args.trace_settings.message_format().is_json();
+        let mut total_iterations = 0;
+        let mut total_time = Duration::default();
+
+        for features in &self.feature_sets {
+            for pkg in self.wanted_packages() {
+                let settings = self.settings(&Config::load(&self.metadata, pkg)?);
+                let suites = self
+                    .build_tests(pkg, &settings, features)
+                    .with_context(|| format!("Error building tests for package `{}`", pkg.name))?;
+                for suite in suites {
+                    let suite = suite.context("Getting next test failed")?;
+                    tracing::info!(path = %suite.path().display(), "Benchmarking {}", suite.name());
+
+                    let mut cmd = suite.command();
+                    // Run one test at a time, so that tests don't compete for
+                    // CPU time and skew each other's results.
+                    cmd.args(["--test-threads=1", "--show-output", "--report-time"]);
+                    self.configure_loom_command(&mut cmd, &settings)
+                        .env(ENV_LOOM_LOG, model_log::LOOM_LOG_MODEL);
+                    if let Some(max_duration) = settings.max_duration_secs {
+                        cmd.env(ENV_MAX_DURATION, max_duration.to_string());
+                    }
+                    if let Some(filter) = args.filter.as_deref() {
+                        cmd.arg(filter);
+                    }
+
+                    let msgs = CommandMessages::with_command(cmd)
+                        .with_note(|| format!("running test suite `{}`", suite.name()))?;
+                    let mut started = Instant::now();
+                    for msg in msgs {
+                        use libtest::*;
+                        let (test, passed) = match msg.and_then(|msg| msg.decode_custom::<Event>())
+                        {
+                            Ok(Event::Test(Test::Started(_))) => {
+                                started = Instant::now();
+                                continue;
+                            }
+                            Ok(Event::Test(Test::Ok(test))) => (test, true),
+                            Ok(Event::Test(Test::Failed(test))) => (test, false),
+                            Ok(_) => continue,
+                            Err(error) => {
+                                tracing::debug!(suite = %suite.name(), %error, "error from test");
+                                continue;
+                            }
+                        };
+
+                        let exec_time = test
+                            .exec_time
+                            .map(Duration::from_secs_f64)
+                            .unwrap_or_else(|| started.elapsed());
+                        if !passed {
+                            tracing::warn!(test = %test.name, "test failed; run `cargo loom` to debug it");
+                            continue;
+                        }
+
+                        let iterations = test
+                            .stdout
+                            .as_deref()
+                            .and_then(model_log::completed_iterations);
+                        if let Some(iterations) = iterations {
+                            total_iterations += iterations;
+                            total_time += exec_time;
+                        }
+                        let bench = ModelBench {
+                            iterations_per_sec: iterations
+                                .map(|iterations| iterations as f64 / exec_time.as_secs_f64()),
+                            name: test.name,
+                            suite: suite.name().to_owned(),
+                            iterations,
+                            exec_time: exec_time.as_secs_f64(),
+                        };
+                        if json {
+                            serde_json::to_writer(std::io::stderr(), &bench)
+                                .context("write json message")?;
+                        } else {
+                            bench.print(self.args.trace_settings.color());
+                        }
+                    }
+                }
+            }
+        }
+
+        if total_time > Duration::ZERO {
+            tracing::info!(
+                "Explored {} iterations in {:.2?} ({:.0} iterations/s)",
+                total_iterations,
+                total_time,
+                total_iterations as f64 / total_time.as_secs_f64(),
+            );
+        } else {
+            tracing::warn!("no loom models ran to completion");
+        }
+
+        Ok(())
+    }
+}
+
+impl ModelBench {
+    fn print(&self, color: ColorMode) {
+        let name = format!("{}::{}", self.suite, self.name);
+        match (self.iterations, self.iterations_per_sec) {
+            (Some(iterations), Some(per_sec)) => eprintln!(
+                "test {} ... {} iterations in {:.2}s ({} iterations/s)",
+                name,
+                iterations,
+                self.exec_time,
+                format_args!("{:.0}", per_sec).style(color.if_color(Style::new().bold())),
+            ),
+            _ => eprintln!(
+                "test {} ... {}",
+                name,
+                "no model completed (it may have hit an exploration bound)"
+                    .style(color.if_color(Style::new().dimmed())),
+            ),
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! Annotating failing tests with the commits that last changed the lines
+//! involved in them, for `--blame`.
+//!
+//! The lines are taken from where the test panicked, and from the
+//! `location` fields of loom's trace (which are only logged when location
+//! tracking is enabled, as it is when rerunning failing tests).
+
+use crate::{
+    summary::{Blame, Location},
+    trace::ColorMode,
+    App,
+};
+use camino::Utf8Path;
+use owo_colors::{OwoColorize, Style};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    process::Command,
+};
+
+/// The most lines blamed for a single failing test.
+///
+/// A long trace may touch many lines; the first ones (where the test
+/// panicked, then the earliest operations in the trace) are the most
+/// relevant.
+const MAX_LINES: usize = 20;
+
+impl App {
+    /// Returns the commit that last changed each line in the workspace that
+    /// a failing test points to: the `panic` location, then the locations in
+    /// the captured `stdout` of its rerun.
+    ///
+    /// Lines outside of the workspace (such as in the standard library), and
+    /// lines that `git blame` can't be run on (such as in untracked files),
+    /// are skipped.
+    pub(crate) fn blame(&self, panic: Option<&Location>, stdout: &str) -> Vec<Blame> {
+        let root = &self.metadata.workspace_root;
+        // Group the lines by file, so that `git blame` is run once per file.
+        let mut files = BTreeMap::<String, Vec<usize>>::new();
+        let mut order = Vec::new();
+        for location in panic.cloned().into_iter().chain(locations(stdout)) {
+            if location.file.starts_with('/') || !root.join(&location.file).is_file() {
+                continue;
+            }
+            let lines = files.entry(location.file.clone()).or_default();
+            if lines.contains(&location.line) {
+                continue;
+            }
+            lines.push(location.line);
+            order.push((location.file, location.line));
+            if order.len() == MAX_LINES {
+                break;
+            }
+        }
+
+        let mut blamed = HashMap::new();
+        for (file, mut lines) in files {
+            // git rejects every range if any is past the end of the file,
+            // which it may be if the file changed since the test was built.
+            let len =
+                fs::read_to_string(root.join(&file)).map_or(0, |contents| contents.lines().count());
+            lines.retain(|&line| line >= 1 && line <= len);
+            if lines.is_empty() {
+                continue;
+            }
+            // If blaming the lines together fails, blame them one at a time,
+            // so that one line git can't blame doesn't lose the others.
+            let porcelain = git_blame(root, &file, &lines).unwrap_or_else(|| {
+                lines
+                    .iter()
+                    .filter_map(|&line| git_blame(root, &file, &[line]))
+                    .collect()
+            });
+            for (line, blame) in parse_porcelain(&file, &porcelain) {
+                blamed.insert((file.clone(), line), blame);
+            }
+        }
+
+        order
+            .into_iter()
+            .filter_map(|location| blamed.remove(&location))
+            .collect()
+    }
+}
+
+/// Runs `git blame --porcelain` on `lines` of `file`, returning its output, or
+/// `None` if it failed.
+fn git_blame(root: &Utf8Path, file: &str, lines: &[usize]) -> Option<String> {
+    let mut cmd = Command::new("git");
+    cmd.arg("blame").arg("--porcelain");
+    for line in lines {
+        cmd.arg("-L").arg(format!("{},{}", line, line));
+    }
+    let output = cmd.arg("--").arg(file).current_dir(root).output();
+    match output {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+        // This is expected for files git doesn't track.
+        Ok(output) => {
+            tracing::debug!(
+                %file,
+                ?lines,
+                status = %output.status,
+                stderr = %String::from_utf8_lossy(&output.stderr).trim(),
+                "git blame failed"
+            );
+            None
+        }
+        Err(error) => {
+            tracing::debug!(%file, %error, "failed to run git blame");
+            None
+        }
+    }
+}
+
+/// Prints the blamed lines for a failing test to stderr, below its output.
+pub(crate) fn print(test: &str, blame: &[Blame], color: ColorMode) {
+    if blame.is_empty() {
+        return;
+    }
+    eprintln!(
+        "{}",
+        format_args!("blame for {}:", test).style(color.if_color(Style::new().bold()))
+    );
+    let width = blame
+        .iter()
+        .map(|blame| blame.location.len())
+        .max()
+        .unwrap_or(0);
+    for blame in blame {
+        eprintln!("    {:width$}  {}", blame.location, blame, width = width);
+    }
+}
+
+/// Returns the source locations in each `location` field of loom's trace in
+/// a test's output, in the order they were logged.
+fn locations(stdout: &str) -> Vec<Location> {
+    let mut locations = Vec::new();
+    let stdout = strip_ansi(stdout);
+    const FIELD: &str = "location=";
+    for (idx, _) in stdout.match_indices(FIELD) {
+        let rest = &stdout[idx + FIELD.len()..];
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        if let Some(location) = Location::parse(&rest[..end]) {
+            locations.push(location);
+        }
+    }
+    locations
+}
+
+/// Removes the escape sequences that color loom's trace.
+pub(crate) fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            // Skip a CSI sequence, which ends with a letter.
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+/// Parses the output of `git blame --porcelain` for `file` into the commit
+/// that last changed each line.
+///
+/// The author and summary of a commit are only included the first time the
+/// commit appears in the output.
+fn parse_porcelain(file: &str, porcelain: &str) -> Vec<(usize, Blame)> {
+    #[derive(Default)]
+    struct Commit {
+        author: String,
+        author_time: u64,
+        summary: String,
+    }
+
+    let mut commits = HashMap::<&str, Commit>::new();
+    let mut blamed = Vec::new();
+    let mut current: Option<(&str, usize)> = None;
+    for line in porcelain.lines() {
+        if line.starts_with('\t') {
+            // The contents of the line end its entry.
+            if let Some((commit, line)) = current.take() {
+                let info = commits.get(commit);
+                blamed.push((
+                    line,
+                    Blame {
+                        location: format!("{}:{}", file, line),
+                        commit: commit.to_owned(),
+                        author: info.map(|info| info.author.clone()).unwrap_or_default(),
+                        author_time: info.map_or(0, |info| info.author_time),
+                        summary: info.map(|info| info.summary.clone()).unwrap_or_default(),
+                    },
+                ));
+            }
+            continue;
+        }
+
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        match current {
+            None => {
+                // `<commit> <original line> <final line> [<lines in group>]`
+                let line = value.split(' ').nth(1).and_then(|line| line.parse().ok());
+                if let Some(line) = line {
+                    commits.entry(key).or_default();
+                    current = Some((key, line));
+                }
+            }
+            Some((commit, _)) => {
+                let info = commits.entry(commit).or_default();
+                match key {
+                    "author" => info.author = value.to_owned(),
+                    "author-time" => info.author_time = value.parse().unwrap_or(0),
+                    "summary" => info.summary = value.to_owned(),
+                    _ => {}
+                }
+            }
+        }
+    }
+    blamed
+}
@@ -0,0 +1,201 @@
+//! Reading loom's checkpoint files.
+//!
+//! A checkpoint is loom's serialized execution path: the branch points of
+//! the execution it resumes from, each with the choices that have already
+//! been explored. loom's own types for these aren't public, so the parts of
+//! them that `cargo loom` needs are mirrored here.
+
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::Deserialize;
+use std::{error::Error, fmt, fs, io};
+
+/// A loom checkpoint file.
+#[derive(Debug, Deserialize)]
+pub(crate) struct Checkpoint {
+    pub(crate) preemption_bound: Option<u8>,
+    branches: Store,
+}
+
+/// Returned when a checkpoint file can't be read or parsed.
+#[derive(Debug)]
+pub(crate) struct InvalidCheckpoint {
+    path: Utf8PathBuf,
+    kind: Invalid,
+}
+
+#[derive(Debug)]
+enum Invalid {
+    Io(io::Error),
+    Empty,
+    Json(serde_json::Error),
+}
+
+#[derive(Debug, Deserialize)]
+struct Store {
+    entries: Vec<Branch>,
+}
+
+/// A single branch point in an execution.
+#[derive(Debug, Deserialize)]
+pub(crate) enum Branch {
+    /// A choice of which thread to run next.
+    Schedule(Schedule),
+    /// A choice of which value an atomic load returns.
+    Load(Load),
+    /// A choice of whether a compare-and-swap fails spuriously.
+    Spurious(Spurious),
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Schedule {
+    /// How many times the running thread has been preempted.
+    pub(crate) preemptions: u8,
+    threads: Vec<Thread>,
+    exploring: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Load {
+    pos: u8,
+    len: u8,
+    exploring: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct Spurious {
+    spur: bool,
+    exploring: bool,
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, Eq, PartialEq)]
+enum Thread {
+    Disabled,
+    Skip,
+    Yield,
+    Pending,
+    Active,
+    Visited,
+}
+
+impl Checkpoint {
+    /// Reads the checkpoint at `path`.
+    pub(crate) fn load(path: &Utf8Path) -> Result<Self, InvalidCheckpoint> {
+        let invalid = |kind| InvalidCheckpoint {
+            path: path.to_owned(),
+            kind,
+        };
+        let json = fs::read_to_string(path).map_err(|error| invalid(Invalid::Io(error)))?;
+        if json.trim().is_empty() {
+            return Err(invalid(Invalid::Empty));
+        }
+        serde_json::from_str(&json).map_err(|error| invalid(Invalid::Json(error)))
+    }
+
+    /// Returns the branch points of the execution this checkpoint resumes
+    /// from, in order.
+    pub(crate) fn branches(&self) -> &[Branch] {
+        &self.branches.entries
+    }
+
+    /// Returns the number of threads which could run at any of the
+    /// checkpoint's branch points.
+    pub(crate) fn threads(&self) -> usize {
+        let mut seen = Vec::new();
+        for branch in self.branches() {
+            if let Branch::Schedule(schedule) = branch {
+                seen.resize(seen.len().max(schedule.threads.len()), false);
+                for (seen, &thread) in seen.iter_mut().zip(&schedule.threads) {
+                    *seen |= thread != Thread::Disabled;
+                }
+            }
+        }
+        seen.into_iter().filter(|&seen| seen).count()
+    }
+
+    /// Estimates how much of the model had been explored when this
+    /// checkpoint was written, from 0 to 1.
+    ///
+    /// loom explores a model depth-first, so the choices made at each branch
+    /// point count like the digits of a number. This assumes every choice
+    /// at a branch leads to an equally large part of the model, which is
+    /// rarely true, but it's enough to tell a checkpoint that barely started
+    /// from one that's nearly done.
+    pub(crate) fn progress(&self) -> f64 {
+        let mut progress = 0.0;
+        let mut weight = 1.0;
+        for branch in self.branches() {
+            let (done, choices) = branch.position();
+            progress += weight * done as f64 / choices as f64;
+            weight /= choices as f64;
+            // Later branches can't make a visible difference.
+            if weight < 1e-9 {
+                break;
+            }
+        }
+        progress
+    }
+}
+
+/// Returns whether there's a usable checkpoint at `path`.
+///
+/// A checkpoint that can't be loaded (e.g. because loom was killed while
+/// writing it) would make loom panic when resuming from it, so it's deleted,
+/// to be regenerated.
+pub(crate) fn exists(path: &Utf8Path) -> bool {
+    if !path.exists() {
+        return false;
+    }
+    match Checkpoint::load(path) {
+        Ok(_) => true,
+        Err(error) => {
+            tracing::warn!("{}; deleting it so that it's regenerated", error);
+            if let Err(error) = fs::remove_file(path) {
+                tracing::warn!(%error, "failed to delete invalid checkpoint `{}`", path);
+            }
+            false
+        }
+    }
+}
+
+impl Branch {
+    /// Returns how many of this branch's choices have been fully explored,
+    /// and how many choices it has.
+    fn position(&self) -> (usize, usize) {
+        let (done, choices) = match self {
+            Branch::Schedule(schedule) if schedule.exploring => {
+                let done = schedule
+                    .threads
+                    .iter()
+                    .filter(|&&thread| thread == Thread::Visited)
+                    .count();
+                let choices = schedule
+                    .threads
+                    .iter()
+                    .filter(|&&thread| !matches!(thread, Thread::Disabled | Thread::Skip))
+                    .count();
+                (done, choices)
+            }
+            Branch::Load(load) if load.exploring => (load.pos.into(), load.len.into()),
+            Branch::Spurious(spurious) if spurious.exploring => (spurious.spur.into(), 2),
+            // Branches that aren't explored only have the one choice.
+            _ => (0, 1),
+        };
+        (done.min(choices), choices.max(1))
+    }
+}
+
+impl fmt::Display for InvalidCheckpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            Invalid::Io(ref error) => {
+                write!(f, "could not read checkpoint `{}`: {}", self.path, error)
+            }
+            Invalid::Empty => write!(f, "checkpoint `{}` is empty", self.path),
+            Invalid::Json(ref error) => {
+                write!(f, "checkpoint `{}` is not valid: {}", self.path, error)
+            }
+        }
+    }
+}
+
+impl Error for InvalidCheckpoint {}
@@ -0,0 +1,209 @@
+use crate::{summary::Classification, trace::Styles};
+use camino::Utf8PathBuf;
+use owo_colors::OwoColorize;
+use std::{
+    fmt,
+    io::{self, Write},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Options for running `cargo loom` in continuous integration.
+#[derive(Debug, clap::Args)]
+#[clap(
+    next_help_heading = "CI OPTIONS",
+    group = clap::ArgGroup::new("ci-opts")
+)]
+pub(crate) struct CiOptions {
+    /// Use settings suitable for continuous integration
+    ///
+    /// This disables colors, writes a JSON summary and the checkpoint and
+    /// output of each failing test to `target/loom/artifacts`, groups the
+    /// output of each failing test into a collapsible section (on GitHub
+    /// Actions and GitLab CI), and limits each test run to 30 minutes. Each
+    /// of these can be overridden by its own flag.
+    #[clap(long)]
+    pub(crate) ci: bool,
+
+    /// Exit successfully even if tests fail
+    ///
+    /// By default, `cargo loom` exits with 1 if any test fails, 2 if the
+    /// tests can't be built, and 3 if the run fails for any other reason.
+    /// With this flag, failing tests (and, with `--require-complete`,
+    /// partially explored ones) are only reported, for workflows that
+    /// collect the results rather than gating on them.
+    #[clap(long)]
+    pub(crate) no_fail_exit_code: bool,
+
+    /// Write a JSON summary of the run to this file
+    #[clap(long, value_name = "PATH")]
+    pub(crate) summary_file: Option<Utf8PathBuf>,
+
+    /// Copy the checkpoint and output of each failing test into this directory
+    #[clap(long, value_name = "DIR")]
+    pub(crate) artifacts_dir: Option<Utf8PathBuf>,
+
+    /// Bundle the output, checkpoint, and classification of each failing
+    /// test into this `.tar.gz` archive, along with the run's summary
+    ///
+    /// This is written at the end of the run, even if no tests failed, so
+    /// that it can always be uploaded as a CI artifact.
+    #[clap(long, value_name = "PATH")]
+    pub(crate) archive_failures: Option<Utf8PathBuf>,
+
+    /// Write a report about the run's failures, as `KIND=PATH`
+    ///
+    /// This may be passed more than once. Valid kinds:
+    ///
+    /// •  issue-md: a ready-to-paste GitHub issue body for each distinct
+    /// failure, in the directory `PATH`, with how it was classified, its
+    /// panic message, a `cargo test` command that reproduces it, the bounds
+    /// it was found with, an excerpt of loom's trace, and the environment it
+    /// was run in.
+    #[clap(long, value_name = "KIND=PATH", multiple_occurrences = true)]
+    pub(crate) emit: Vec<crate::issue::Emit>,
+
+    /// Run this shell command for each failing test, once it has been rerun
+    ///
+    /// The command is run with `CARGO_LOOM_PACKAGE`, `CARGO_LOOM_SUITE`,
+    /// `CARGO_LOOM_TEST`, `CARGO_LOOM_CHECKPOINT`, `CARGO_LOOM_LOG` (the path
+    /// to the test's output), and `CARGO_LOOM_CLASSIFICATION` (how the test
+    /// failed) set. This overrides the `on-failure` setting in
+    /// `[package.metadata.loom]` or `[workspace.metadata.loom]`.
+    #[clap(long, value_name = "COMMAND")]
+    pub(crate) on_failure: Option<String>,
+
+    /// Only run the `K`th of `N` disjoint subsets of the tests
+    ///
+    /// Tests are assigned to shards by a hash of their names, so that a
+    /// suite can be split across `N` CI jobs, each running `--shard K/N`
+    /// with a different `K` (from 1 to `N`). Every job still builds all of
+    /// the tests.
+    #[clap(long, value_name = "K/N")]
+    pub(crate) shard: Option<crate::shard::Shard>,
+
+    /// Only run a pseudo-random sample of the tests: `N%` of them, or `N`
+    /// tests from each test suite
+    ///
+    /// The same seed always picks the same tests. By default, the seed is
+    /// the current date, so that the runs on each day sample the same tests,
+    /// and different days sample different ones.
+    #[clap(long, value_name = "N%|N")]
+    pub(crate) sample: Option<crate::sample::Sample>,
+
+    /// The seed of the `--sample`, instead of the current date
+    #[clap(long, value_name = "SEED", requires = "sample")]
+    pub(crate) sample_seed: Option<u64>,
+}
+
+/// The default `--test-timeout` in CI mode, in seconds.
+pub(crate) const DEFAULT_TEST_TIMEOUT_SECS: u64 = 30 * 60;
+
+/// The heading of a rerun test's output.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Heading<'a> {
+    pub(crate) test: &'a str,
+    pub(crate) classification: Classification,
+    pub(crate) duration: Duration,
+}
+
+/// How wide the separators around a test's output are.
+const SEPARATOR_WIDTH: usize = 80;
+
+/// Collapsible log sections, for CI providers that support them.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum LogGroups {
+    None,
+    GitHub,
+    GitLab,
+}
+
+impl LogGroups {
+    pub(crate) fn detect() -> Self {
+        if std::env::var("GITHUB_ACTIONS").as_deref() == Ok("true") {
+            Self::GitHub
+        } else if std::env::var_os("GITLAB_CI").is_some() {
+            Self::GitLab
+        } else {
+            Self::None
+        }
+    }
+
+    /// Writes `body` to `out` under `heading`, in a collapsed section if
+    /// supported, or between separators styled with `styles` otherwise.
+    pub(crate) fn print(
+        self,
+        mut out: impl Write,
+        heading: Heading<'_>,
+        styles: &Styles,
+        body: &str,
+    ) -> io::Result<()> {
+        let title = heading.to_string();
+        match self {
+            Self::None => {
+                let status = heading.status();
+                let status = if heading.classification == Classification::Passed {
+                    status.style(styles.info)
+                } else {
+                    status.style(styles.error)
+                };
+                // The title is measured without its styles.
+                let rest = SEPARATOR_WIDTH.saturating_sub(title.chars().count() + 4);
+                writeln!(
+                    out,
+                    "\n{} {} {} ({}, {:.1?}) {}\n\n{}\n{}",
+                    "──".style(styles.pipes),
+                    "test".style(styles.bold),
+                    heading.test.style(styles.bold),
+                    status,
+                    heading.duration,
+                    "─".repeat(rest).style(styles.pipes),
+                    body.trim_end(),
+                    "─".repeat(SEPARATOR_WIDTH).style(styles.pipes),
+                )
+            }
+            Self::GitHub => writeln!(out, "::group::{title}\n{body}\n::endgroup::"),
+            Self::GitLab => {
+                let id = title
+                    .chars()
+                    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                    .collect::<String>();
+                writeln!(
+                    out,
+                    "section_start:{}:{id}[collapsed=true]\r\x1b[0K{title}\n{body}",
+                    timestamp()
+                )?;
+                writeln!(out, "section_end:{}:{id}\r\x1b[0K", timestamp())
+            }
+        }
+    }
+}
+
+impl Heading<'_> {
+    fn status(&self) -> &'static str {
+        match self.classification {
+            Classification::Panicked => "panicked",
+            Classification::TimedOut => "timed out",
+            Classification::Crashed => "crashed",
+            Classification::Passed => "passed",
+        }
+    }
+}
+
+impl fmt::Display for Heading<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "test {} ({}, {:.1?})",
+            self.test,
+            self.status(),
+            self.duration
+        )
+    }
+}
+
+fn timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
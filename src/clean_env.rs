@@ -0,0 +1,46 @@
+//! Running test processes with only the environment they need, for
+//! `--clean-env`.
+//!
+//! By default, test processes inherit `cargo loom`'s whole environment, so
+//! code under test that reads a stray variable (a `RUST_LOG` in someone's
+//! shell profile, or a variable a CI runner sets) may behave differently on
+//! different machines. With `--clean-env`, test processes are started with an
+//! empty environment, and only get the loom variables `cargo loom` sets for
+//! them, the variables in [`ALLOWED`], and those passed with `--keep-env`.
+
+use std::{env, process::Command};
+
+/// The variables that are passed to test processes with `--clean-env`, since
+/// a process can't reliably start, find its dynamic libraries, or create
+/// temporary files without them.
+const ALLOWED: &[&str] = &[
+    // libtest only accepts `-Z unstable-options` (which `cargo loom` passes
+    // to get its JSON output) on a stable toolchain with this set.
+    "RUSTC_BOOTSTRAP",
+    "PATH",
+    "HOME",
+    "TMPDIR",
+    "LD_LIBRARY_PATH",
+    "DYLD_FALLBACK_LIBRARY_PATH",
+    // Windows
+    "SYSTEMROOT",
+    "TEMP",
+    "TMP",
+    "USERPROFILE",
+];
+
+/// Clears `cmd`'s environment, and passes it the variables in [`ALLOWED`]
+/// and `keep` that are set for `cargo loom`.
+pub(crate) fn configure<'cmd>(keep: &[String], cmd: &'cmd mut Command) -> &'cmd mut Command {
+    cmd.env_clear();
+    let names = ALLOWED
+        .iter()
+        .copied()
+        .chain(keep.iter().map(String::as_str));
+    for name in names {
+        if let Some(value) = env::var_os(name) {
+            cmd.env(name, value);
+        }
+    }
+    cmd
+}
@@ -0,0 +1,151 @@
+//! Configuration from the `[workspace.metadata.loom]` and
+//! `[package.metadata.loom]` tables in `Cargo.toml`.
+
+use crate::hooks::Hook;
+use cargo_metadata::{Metadata, Package};
+use color_eyre::{eyre::WrapErr, Help, Result};
+use serde::Deserialize;
+use std::{collections::HashMap, error::Error, fmt, sync::Arc};
+
+/// The configuration for a single package, combining the workspace's
+/// configuration with the package's own.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct Config {
+    /// `LOOM_LOG` filters to use instead of `--loom-log` when rerunning
+    /// specific failing tests, keyed by test name.
+    ///
+    /// A test's name may be given either with or without the name of its
+    /// suite (e.g. `integration::tests::my_model` or `tests::my_model`).
+    #[serde(default)]
+    loom_log: HashMap<String, Arc<str>>,
+
+    /// The `LOOM_LOG` filter to use when rerunning failing tests that don't
+    /// have one in `loom_log`, if `--loom-log` isn't passed.
+    pub(crate) default_loom_log: Option<Arc<str>>,
+
+    /// Loom's bounds, if the flags that set them aren't passed.
+    pub(crate) max_branches: Option<usize>,
+    pub(crate) max_permutations: Option<usize>,
+    pub(crate) max_preemptions: Option<usize>,
+    pub(crate) max_threads: Option<usize>,
+    pub(crate) max_duration_secs: Option<usize>,
+    pub(crate) checkpoint_interval: Option<usize>,
+
+    /// Whether to test only the package's library unit tests, or all of its
+    /// tests, if neither `--lib` nor `--tests` is passed.
+    pub(crate) lib: Option<bool>,
+    pub(crate) tests: Option<bool>,
+
+    /// Patterns matching the names of tests whose models are expensive, so
+    /// that `--lint-models` reports them if they aren't marked `#[ignore]`.
+    #[serde(default)]
+    pub(crate) expensive_models: Vec<String>,
+
+    /// A shell command to run for each failing test, if `--on-failure` isn't
+    /// passed.
+    pub(crate) on_failure: Option<String>,
+
+    /// A shell command to run before any tests are built.
+    ///
+    /// This is only read from the workspace's configuration (or its root
+    /// package's).
+    pub(crate) pre_run: Option<Hook>,
+
+    /// A shell command to run once all tests have run, even if the run
+    /// failed.
+    ///
+    /// This is only read from the workspace's configuration (or its root
+    /// package's).
+    pub(crate) post_run: Option<Hook>,
+}
+
+impl Config {
+    /// Loads the configuration for `pkg`.
+    ///
+    /// Settings in `[package.metadata.loom]` take precedence over those in
+    /// `[workspace.metadata.loom]`.
+    pub(crate) fn load(metadata: &Metadata, pkg: &Package) -> Result<Self> {
+        let mut config = Self::workspace(metadata)?;
+        config.merge(Self::package(pkg)?);
+        Ok(config)
+    }
+
+    /// Loads the configuration for the run as a whole, from the workspace and
+    /// its root package (if it has one).
+    pub(crate) fn load_root(metadata: &Metadata) -> Result<Self> {
+        let mut config = Self::workspace(metadata)?;
+        if let Some(pkg) = metadata.root_package() {
+            config.merge(Self::package(pkg)?);
+        }
+        Ok(config)
+    }
+
+    fn workspace(metadata: &Metadata) -> Result<Self> {
+        Self::from_table(&metadata.workspace_metadata)
+            .context("invalid `[workspace.metadata.loom]` configuration")
+            .with_note(|| format!("in `{}`", metadata.workspace_root.join("Cargo.toml")))
+    }
+
+    fn package(pkg: &Package) -> Result<Self> {
+        Self::from_table(&pkg.metadata)
+            .with_context(|| {
+                format!(
+                    "invalid `[package.metadata.loom]` configuration for `{}`",
+                    pkg.name
+                )
+            })
+            .with_note(|| format!("in `{}`", pkg.manifest_path))
+    }
+
+    /// Overrides this configuration's settings with those set in `other`.
+    fn merge(&mut self, other: Self) {
+        fn merge<T>(this: &mut Option<T>, other: Option<T>) {
+            if other.is_some() {
+                *this = other;
+            }
+        }
+
+        self.loom_log.extend(other.loom_log);
+        self.expensive_models.extend(other.expensive_models);
+        merge(&mut self.default_loom_log, other.default_loom_log);
+        merge(&mut self.max_branches, other.max_branches);
+        merge(&mut self.max_permutations, other.max_permutations);
+        merge(&mut self.max_preemptions, other.max_preemptions);
+        merge(&mut self.max_threads, other.max_threads);
+        merge(&mut self.max_duration_secs, other.max_duration_secs);
+        merge(&mut self.checkpoint_interval, other.checkpoint_interval);
+        merge(&mut self.lib, other.lib);
+        merge(&mut self.tests, other.tests);
+        merge(&mut self.on_failure, other.on_failure);
+        merge(&mut self.pre_run, other.pre_run);
+        merge(&mut self.post_run, other.post_run);
+    }
+
+    /// Returns the `LOOM_LOG` filter configured for the test `name` in
+    /// `suite`, if there is one.
+    pub(crate) fn loom_log(&self, suite: &str, name: &str) -> Option<&Arc<str>> {
+        self.loom_log
+            .get(&format!("{}::{}", suite, name))
+            .or_else(|| self.loom_log.get(name))
+    }
+
+    fn from_table(metadata: &serde_json::Value) -> Result<Self, InvalidConfig> {
+        match metadata.get("loom") {
+            Some(table) => Self::deserialize(table).map_err(InvalidConfig),
+            None => Ok(Self::default()),
+        }
+    }
+}
+
+/// Returned when the configuration in `Cargo.toml` is invalid.
+#[derive(Debug)]
+pub(crate) struct InvalidConfig(serde_json::Error);
+
+impl fmt::Display for InvalidConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for InvalidConfig {}
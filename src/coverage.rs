@@ -0,0 +1,187 @@
+//! Measuring which code loom models exercise, for `--coverage`.
+//!
+//! Tests are built with `-C instrument-coverage`, and every test process
+//! writes a profile to `target/loom/coverage/profraw`. Once all the tests have
+//! run, the profiles are merged with the `llvm-tools-preview` rustup
+//! component's `llvm-profdata`, and exported as an lcov report to
+//! `target/loom/coverage/lcov.info` with its `llvm-cov`. Other builds of the
+//! tools (which must match the LLVM version of the toolchain) can be used by
+//! setting `LLVM_PROFDATA` and `LLVM_COV`.
+
+use crate::toolchain::Toolchain;
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Result,
+};
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+};
+
+/// The flags tests are built with to instrument them.
+pub(crate) const RUSTFLAGS: &str = "-C instrument-coverage";
+
+const ENV_LLVM_PROFILE_FILE: &str = "LLVM_PROFILE_FILE";
+
+/// Source files that aren't part of the workspace, which are left out of the
+/// report.
+const IGNORE_FILENAME_REGEX: &str = r"[/\\]\.cargo[/\\](registry|git)[/\\]|^[/\\]rustc[/\\]";
+
+#[derive(Debug)]
+pub(crate) struct Coverage {
+    dir: Utf8PathBuf,
+    /// The instrumented test binaries that have been run, whose coverage
+    /// mappings are needed to export the report.
+    binaries: Mutex<BTreeSet<PathBuf>>,
+}
+
+impl Coverage {
+    pub(crate) fn new(target_dir: &Utf8Path) -> Self {
+        Self {
+            dir: target_dir.join("coverage"),
+            binaries: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    pub(crate) fn profraw_dir(&self) -> Utf8PathBuf {
+        self.dir.join("profraw")
+    }
+
+    /// Removes the profiles written by a previous run, so that the report
+    /// only covers the current one.
+    pub(crate) fn reset(&self) -> Result<()> {
+        let dir = self.profraw_dir();
+        match fs::remove_dir_all(&dir) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                return Err(error).with_context(|| format!("removing `{}`", dir))
+            }
+            _ => {}
+        }
+        fs::create_dir_all(&dir).with_context(|| format!("creating `{}`", dir))?;
+        self.binaries
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+            .clear();
+        Ok(())
+    }
+
+    /// Configures an instrumented test binary to write its profile to the
+    /// coverage directory.
+    pub(crate) fn configure<'cmd>(&self, cmd: &'cmd mut Command) -> &'cmd mut Command {
+        // Each process writes its own profile, so concurrent tests don't
+        // clobber each other's.
+        cmd.env(
+            ENV_LLVM_PROFILE_FILE,
+            self.profraw_dir().join("%p-%m.profraw"),
+        )
+    }
+
+    /// Records that the instrumented test binary at `path` was run.
+    pub(crate) fn add_binary(&self, path: &Path) {
+        self.binaries
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+            .insert(path.to_owned());
+    }
+
+    /// Merges the profiles written by the run's tests, and exports them as an
+    /// lcov report, returning the report's path.
+    pub(crate) fn report(&self, toolchain: &Toolchain) -> Result<Option<Utf8PathBuf>> {
+        let binaries = std::mem::take(
+            &mut *self
+                .binaries
+                .lock()
+                .unwrap_or_else(|poison| poison.into_inner()),
+        );
+        let dir = self.profraw_dir();
+        let profiles = fs::read_dir(&dir)
+            .with_context(|| format!("reading `{}`", dir))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()
+            .with_context(|| format!("reading `{}`", dir))?;
+        let profiles = profiles
+            .into_iter()
+            .filter(|path| path.extension().is_some_and(|ext| ext == "profraw"))
+            .collect::<Vec<_>>();
+        if profiles.is_empty() || binaries.is_empty() {
+            tracing::warn!("no coverage profiles were written");
+            return Ok(None);
+        }
+
+        let llvm_profdata = llvm_tool(toolchain, "llvm-profdata", "LLVM_PROFDATA")?;
+        let llvm_cov = llvm_tool(toolchain, "llvm-cov", "LLVM_COV")?;
+
+        let profdata = self.dir.join("loom.profdata");
+        run(Command::new(llvm_profdata)
+            .arg("merge")
+            .arg("-sparse")
+            .args(&profiles)
+            .arg("-o")
+            .arg(&profdata))?;
+
+        let mut cmd = Command::new(llvm_cov);
+        cmd.arg("export")
+            .arg("-format=lcov")
+            .arg(format!("-instr-profile={}", profdata))
+            .arg(format!("-ignore-filename-regex={}", IGNORE_FILENAME_REGEX));
+        let mut binaries = binaries.iter();
+        if let Some(first) = binaries.next() {
+            cmd.arg(first);
+        }
+        for binary in binaries {
+            cmd.arg("-object").arg(binary);
+        }
+        let lcov = run(&mut cmd)?;
+        let path = self.dir.join("lcov.info");
+        fs::write(&path, lcov).with_context(|| format!("writing coverage report `{}`", path))?;
+        Ok(Some(path))
+    }
+}
+
+/// Returns the path to the LLVM tool `name`, from the environment variable
+/// `env` if it's set, or from the toolchain's `llvm-tools-preview` component.
+fn llvm_tool(toolchain: &Toolchain, name: &str, env: &str) -> Result<PathBuf> {
+    if let Some(path) = std::env::var_os(env) {
+        return Ok(PathBuf::from(path));
+    }
+    let bin_dir = toolchain
+        .sysroot()?
+        .join("lib")
+        .join("rustlib")
+        .join(toolchain.host()?)
+        .join("bin");
+    let path = bin_dir.join(format!("{}{}", name, std::env::consts::EXE_SUFFIX));
+    if path.exists() {
+        return Ok(path);
+    }
+    Err(eyre!(
+        "`{}` was not found in `{}`; install it with `rustup component add \
+        llvm-tools-preview`, or set `{}` to its path",
+        name,
+        bin_dir.display(),
+        env
+    ))
+}
+
+/// Runs an LLVM tool, returning its stdout.
+fn run(cmd: &mut Command) -> Result<Vec<u8>> {
+    let name = cmd.get_program().to_string_lossy().into_owned();
+    tracing::debug!(?cmd, "Running {}", name);
+    let output = cmd
+        .output()
+        .with_context(|| format!("running `{}`", name))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(eyre!(
+            "`{}` failed ({}): {}",
+            name,
+            output.status,
+            stderr.trim()
+        ));
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,405 @@
+//! Widening the bounds of tests that were only partially explored, with
+//! `--deepen`.
+//!
+//! A test that reached `--max-permutations` passed without exploring its
+//! whole model, so its result is inconclusive. `--deepen` reruns only the
+//! tests whose most recent result in the history was inconclusive, each with
+//! wider bounds than the last (one more thread, and ten times as many
+//! branches and permutations), at a low priority so that it can run in the
+//! background. Each result is recorded in the history, so the next `--deepen`
+//! continues from the widest bounds this one reached, and the strongest
+//! bounds each test has passed with are recorded in
+//! `target/loom/deepened.json`.
+
+use crate::{
+    config::Config, escalate::LOOM_MAX_THREADS, history, libtest, model_log, process,
+    settings::Settings, test_args, App, TestFilter,
+};
+use camino::Utf8PathBuf;
+use color_eyre::{eyre::WrapErr, Result};
+use owo_colors::{OwoColorize, Style};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs, io,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::task::JoinSet;
+
+/// How many times each test's bounds are widened in a single `--deepen`.
+const STEPS: usize = 3;
+
+/// How much the branch and permutation bounds are multiplied by each step.
+const FACTOR: usize = 10;
+
+/// The niceness tests are run with, unless `--nice` is passed.
+const NICE: i32 = 19;
+
+/// The bounds a test is run with.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct Bounds {
+    pub(crate) max_threads: usize,
+    pub(crate) max_branches: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_preemptions: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_permutations: Option<usize>,
+}
+
+/// The strongest bounds a test has passed with, in `deepened.json`.
+#[derive(Debug, Deserialize, Serialize)]
+struct Strongest {
+    package: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    features: Option<String>,
+    suite: String,
+    name: String,
+    bounds: Bounds,
+    /// Whether the test explored its whole model with these bounds.
+    complete: bool,
+    /// When the test passed with these bounds, in seconds since the Unix
+    /// epoch.
+    time: u64,
+}
+
+/// The result of running a test with wider bounds.
+#[derive(Debug)]
+struct Step {
+    bounds: Bounds,
+    outcome: Option<history::Outcome>,
+    /// How long the test took, in seconds.
+    duration: Option<f64>,
+    iterations: Option<u64>,
+    killed_by: Option<process::Signal>,
+}
+
+impl Bounds {
+    /// Returns whether these bounds are at least as strong as `other`.
+    fn covers(self, other: Self) -> bool {
+        // An unset bound is unbounded.
+        let at_least = |bound: Option<usize>, other: Option<usize>| match (bound, other) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(bound), Some(other)) => bound >= other,
+        };
+        self.max_threads >= other.max_threads
+            && self.max_branches >= other.max_branches
+            && at_least(self.max_preemptions, other.max_preemptions)
+            && at_least(self.max_permutations, other.max_permutations)
+    }
+
+    /// Returns the next, wider bounds after these.
+    fn widen(self) -> Self {
+        Self {
+            max_threads: (self.max_threads + 1).min(LOOM_MAX_THREADS),
+            max_branches: self.max_branches.saturating_mul(FACTOR),
+            max_preemptions: self.max_preemptions.map(|preemptions| preemptions + 1),
+            max_permutations: self
+                .max_permutations
+                .map(|permutations| permutations.saturating_mul(FACTOR)),
+        }
+    }
+
+    /// Returns `settings` with these bounds.
+    fn apply(self, settings: &Settings) -> Settings {
+        Settings {
+            max_threads: self.max_threads,
+            max_branches: self.max_branches,
+            max_preemptions: self.max_preemptions,
+            max_permutations: self.max_permutations,
+            ..settings.clone()
+        }
+    }
+
+    /// Returns the flags that run a test with these bounds.
+    fn flags(self) -> String {
+        let mut flags = format!(
+            "--max-threads {} --max-branches {}",
+            self.max_threads, self.max_branches
+        );
+        if let Some(preemptions) = self.max_preemptions {
+            flags.push_str(&format!(" --max-preemptions {}", preemptions));
+        }
+        if let Some(permutations) = self.max_permutations {
+            flags.push_str(&format!(" --max-permutations {}", permutations));
+        }
+        flags
+    }
+}
+
+impl fmt::Display for Bounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "threads={} branches={}",
+            self.max_threads, self.max_branches
+        )?;
+        if let Some(preemptions) = self.max_preemptions {
+            write!(f, " preemptions={}", preemptions)?;
+        }
+        if let Some(permutations) = self.max_permutations {
+            write!(f, " permutations={}", permutations)?;
+        }
+        Ok(())
+    }
+}
+
+impl App {
+    fn deepened_file(&self) -> Utf8PathBuf {
+        self.target_dir.join("deepened.json")
+    }
+
+    /// Reruns the tests whose most recent result was inconclusive with
+    /// progressively wider bounds, recording the strongest bounds each one
+    /// passes with.
+    pub(crate) async fn deepen(&self) -> Result<()> {
+        let inconclusive = self.inconclusive_tests()?;
+        if inconclusive.is_empty() {
+            tracing::info!("Found no tests which were only partially explored in their last run");
+            return Ok(());
+        }
+
+        let path = self.deepened_file();
+        let mut deepened = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str::<Vec<Strongest>>(&json)
+                .with_context(|| format!("parsing `{}`", path))?,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(error) => return Err(error).with_context(|| format!("reading `{}`", path)),
+        };
+        let filter = TestFilter::Contains(self.args.testname.as_deref());
+
+        for features in &self.feature_sets {
+            let label = self
+                .args
+                .cargo
+                .feature_matrix
+                .as_ref()
+                .map(|_| features.to_string());
+            for pkg in self.wanted_packages() {
+                let tests = inconclusive
+                    .iter()
+                    .filter(|test| test.package == pkg.name && test.features == label)
+                    .filter(|test| self.excludes(filter, &test.suite, &test.name).is_none())
+                    .collect::<Vec<_>>();
+                if tests.is_empty() {
+                    continue;
+                }
+                tracing::info!(
+                    "Deepening {} partially explored tests in `{}`",
+                    tests.len(),
+                    pkg.name
+                );
+
+                let settings = self.settings(&Config::load(&self.metadata, pkg)?);
+                let suites = self
+                    .build_tests(pkg, &settings, features)
+                    .with_context(|| format!("Error building tests for package `{}`", pkg.name))?;
+                let mut tasks = JoinSet::new();
+                for suite in suites {
+                    let suite = suite.context("Getting next test failed")?;
+                    for &test in tests.iter().filter(|test| test.suite == suite.name()) {
+                        let mut cmds = Vec::new();
+                        let mut step_bounds = test.bounds;
+                        for _ in 0..STEPS {
+                            step_bounds = step_bounds.widen();
+                            let mut cmd = suite.command();
+                            self.configure_discovery_command(
+                                &mut cmd,
+                                &step_bounds.apply(&settings),
+                            );
+                            test_args::exact(&mut cmd).arg(&test.name);
+                            if self.args.loom.nice.is_none() {
+                                process::set_nice(&mut cmd, NICE);
+                            }
+                            cmds.push((step_bounds, cmd));
+                        }
+                        let name = test.name.clone();
+                        let suite = test.suite.clone();
+                        let processes = self.processes.clone();
+                        let test_timeout = self.test_timeout;
+                        tasks.spawn(async move {
+                            let mut steps = Vec::new();
+                            for (bounds, cmd) in cmds {
+                                let slot = processes.acquire().await;
+                                let mut cmd = tokio::process::Command::from(cmd);
+                                tracing::debug!(test = %name, %bounds, "Deepening");
+                                let finished = process::output(
+                                    &mut cmd,
+                                    &slot,
+                                    test_timeout,
+                                    None,
+                                    None,
+                                    None,
+                                )
+                                .await
+                                .with_context(|| {
+                                    format!("spawn process to deepen {suite}::{name}")
+                                })?;
+                                let step = Step::new(&name, bounds, &finished);
+                                // Stop widening once the test fails, or has
+                                // been fully explored.
+                                let done =
+                                    step.outcome != Some(history::Outcome::PartiallyExplored);
+                                steps.push(step);
+                                if done {
+                                    break;
+                                }
+                            }
+                            Ok::<_, color_eyre::Report>((suite, name, steps))
+                        });
+                    }
+                }
+
+                while let Some(result) = tasks.join_one().await? {
+                    let (suite, name, steps) = result?;
+                    for step in steps {
+                        self.deepened(pkg, label.as_deref(), &settings, &suite, &name, &step)?;
+                        let passed = matches!(
+                            step.outcome,
+                            Some(history::Outcome::Passed)
+                                | Some(history::Outcome::PartiallyExplored)
+                        );
+                        if !passed {
+                            continue;
+                        }
+                        let strongest = Strongest {
+                            package: pkg.name.clone(),
+                            features: label.clone(),
+                            suite: suite.clone(),
+                            name: name.clone(),
+                            bounds: step.bounds,
+                            complete: step.outcome == Some(history::Outcome::Passed),
+                            time: SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .map_or(0, |time| time.as_secs()),
+                        };
+                        // A test which was run with narrower bounds since
+                        // (which made it inconclusive again) keeps the
+                        // stronger ones it passed with before.
+                        match deepened
+                            .iter_mut()
+                            .find(|other| other.same_test(&strongest))
+                        {
+                            Some(other) if strongest.bounds.covers(other.bounds) => {
+                                *other = strongest
+                            }
+                            Some(_) => {}
+                            None => deepened.push(strongest),
+                        }
+                    }
+                }
+            }
+        }
+
+        let json = serde_json::to_vec_pretty(&deepened).context("serializing deepened tests")?;
+        fs::write(&path, json).with_context(|| format!("writing `{}`", path))?;
+        tracing::info!(
+            "Recorded the strongest bounds each test passed with in {}",
+            path
+        );
+        Ok(())
+    }
+
+    /// Reports and records the result of running `suite::name` with wider
+    /// bounds.
+    fn deepened(
+        &self,
+        pkg: &cargo_metadata::Package,
+        features: Option<&str>,
+        settings: &Settings,
+        suite: &str,
+        name: &str,
+        step: &Step,
+    ) -> Result<()> {
+        let test = format!("{}::{}", suite, name);
+        let color = self.args.trace_settings.color();
+        let result = match (step.outcome, step.killed_by) {
+            (Some(history::Outcome::Passed), _) => format!("fully explored with {}", step.bounds)
+                .style(color.if_color(Style::new().green()))
+                .to_string(),
+            (Some(history::Outcome::PartiallyExplored), _) => {
+                format!("partially explored with {}", step.bounds)
+                    .style(color.if_color(Style::new().cyan()))
+                    .to_string()
+            }
+            (_, Some(_)) => format!("timed out with {}", step.bounds)
+                .style(color.if_color(Style::new().yellow()))
+                .to_string(),
+            _ => format!("failed with {}", step.bounds)
+                .style(color.if_color(Style::new().red()))
+                .to_string(),
+        };
+        eprintln!("test {} ... {}", test, result);
+        if step.outcome == Some(history::Outcome::Failed) {
+            tracing::warn!(
+                "`{}` fails with wider bounds; run `cargo loom {} --exact {}` to debug it",
+                test,
+                step.bounds.flags(),
+                name,
+            );
+        }
+
+        if let Some(outcome) = step.outcome {
+            let entry = history::Entry {
+                suite: suite.to_owned(),
+                name: name.to_owned(),
+                outcome,
+                duration: step.duration,
+                iterations: step.iterations,
+            };
+            self.record_history(
+                &pkg.name,
+                features,
+                &step.bounds.apply(settings),
+                vec![entry],
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Step {
+    /// Returns the result of running the test `name` with `bounds`, from
+    /// the test binary's JSON output.
+    fn new(name: &str, bounds: Bounds, finished: &process::Finished) -> Self {
+        let mut step = Self {
+            bounds,
+            outcome: None,
+            duration: None,
+            iterations: None,
+            killed_by: finished.killed_by,
+        };
+        let stdout = String::from_utf8_lossy(finished.stdout.bytes());
+        for line in stdout.lines() {
+            use libtest::{Event, Test};
+            let (test, passed) = match serde_json::from_str::<Event>(line) {
+                Ok(Event::Test(Test::Ok(test))) => (test, true),
+                Ok(Event::Test(Test::Failed(test))) => (test, false),
+                _ => continue,
+            };
+            if test.name != name {
+                continue;
+            }
+            let output = test.stdout.as_deref().unwrap_or_default();
+            let partial = bounds
+                .max_permutations
+                .is_some_and(|max| model_log::hit_max_permutations(output, max as u64));
+            step.outcome = Some(match (passed, partial) {
+                (false, _) => history::Outcome::Failed,
+                (true, true) => history::Outcome::PartiallyExplored,
+                (true, false) => history::Outcome::Passed,
+            });
+            step.duration = test.exec_time;
+            step.iterations = model_log::completed_iterations(output);
+        }
+        step
+    }
+}
+
+impl Strongest {
+    fn same_test(&self, other: &Self) -> bool {
+        self.package == other.package
+            && self.features == other.features
+            && self.suite == other.suite
+            && self.name == other.name
+    }
+}
@@ -0,0 +1,184 @@
+//! Rendering failing tests like compiler errors, for `--error-format
+//! diagnostic`.
+
+use crate::{
+    summary::{FailureSummary, Location},
+    trace::ColorMode,
+};
+use camino::Utf8Path;
+use owo_colors::{OwoColorize, Style};
+use std::{
+    fmt, fs,
+    io::{self, Write},
+};
+
+/// A failing test, rendered like a compiler error.
+#[derive(Debug)]
+pub(crate) struct Diagnostic {
+    message: String,
+    /// Any lines of the panic message after the first.
+    detail: Vec<String>,
+    location: Option<Location>,
+    notes: Vec<String>,
+}
+
+impl Diagnostic {
+    /// Returns a diagnostic for a failing test, pointing at where it
+    /// panicked.
+    pub(crate) fn for_failure(failure: &FailureSummary) -> Self {
+        let mut lines = failure.message.as_deref().unwrap_or("").lines();
+        let message = match lines.next() {
+            _ if failure.timed_out => "loom model timed out".to_owned(),
+            Some(message) => format!("loom model failed: {}", message),
+            None => "loom model failed".to_owned(),
+        };
+        Self {
+            message,
+            detail: lines.map(str::to_owned).collect(),
+            location: failure.location.clone(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Adds a `= note: ` line to the end of the diagnostic.
+    pub(crate) fn note(&mut self, note: impl fmt::Display) -> &mut Self {
+        self.notes.push(note.to_string());
+        self
+    }
+
+    /// Writes the diagnostic to `out`, including a snippet of the source
+    /// file it points to, which is resolved relative to `root`, with colors
+    /// if `color` allows them on stderr.
+    pub(crate) fn render(
+        &self,
+        root: &Utf8Path,
+        color: ColorMode,
+        mut out: impl Write,
+    ) -> io::Result<()> {
+        let error = Style::new().red().bold();
+        let gutter = Style::new().blue().bold();
+        let bold = Style::new().bold();
+        let paint = |text: &str, style: Style| text.style(color.if_color(style)).to_string();
+
+        writeln!(
+            out,
+            "{}{}",
+            paint("error", error),
+            paint(&format!(": {}", self.message), bold)
+        )?;
+
+        let location = match self.location {
+            Some(ref location) => location,
+            None => {
+                for line in &self.detail {
+                    writeln!(out, "{}", line)?;
+                }
+                for note in &self.notes {
+                    writeln!(out, "{} {}", paint("= note:", bold), note)?;
+                }
+                return writeln!(out);
+            }
+        };
+
+        let line_number = location.line.to_string();
+        let width = line_number.len();
+        let pipe = paint(&format!("{:width$} |", "", width = width), gutter);
+        writeln!(
+            out,
+            "{:width$}{} {}:{}:{}",
+            "",
+            paint("-->", gutter),
+            location.file,
+            location.line,
+            location.column,
+            width = width,
+        )?;
+        writeln!(out, "{}", pipe)?;
+
+        let source = fs::read_to_string(root.join(&location.file)).ok();
+        let source_line = source
+            .as_deref()
+            .and_then(|source| source.lines().nth(location.line.saturating_sub(1)));
+        if let Some(source_line) = source_line {
+            writeln!(
+                out,
+                "{} {}",
+                paint(&format!("{} |", line_number), gutter),
+                source_line
+            )?;
+            let indent = location.column.saturating_sub(1);
+            let mut detail = self.detail.iter();
+            match detail.next() {
+                Some(first) => writeln!(
+                    out,
+                    "{} {:indent$}{} {}",
+                    pipe,
+                    "",
+                    paint("^", error),
+                    paint(first, error),
+                    indent = indent,
+                )?,
+                None => writeln!(
+                    out,
+                    "{} {:indent$}{}",
+                    pipe,
+                    "",
+                    paint("^", error),
+                    indent = indent
+                )?,
+            }
+            for line in detail {
+                writeln!(
+                    out,
+                    "{} {:indent$}{}",
+                    pipe,
+                    "",
+                    paint(line, error),
+                    indent = indent + 2
+                )?;
+            }
+        } else {
+            for line in &self.detail {
+                writeln!(out, "{} {}", pipe, line)?;
+            }
+        }
+
+        writeln!(out, "{}", pipe)?;
+        for note in &self.notes {
+            writeln!(
+                out,
+                "{:width$} {} {}",
+                "",
+                paint("= note:", bold),
+                note,
+                width = width
+            )?;
+        }
+        writeln!(out)
+    }
+}
+
+/// Splits a panic message (as returned by [`panic_message`]) into the
+/// location of the panic and the message it was raised with.
+///
+/// [`panic_message`]: crate::discovery::panic_message
+pub(crate) fn split_panic(panic: &str) -> (Option<Location>, &str) {
+    let rest = match panic.split_once(" panicked at ") {
+        Some((_, rest)) => rest,
+        None => return (None, panic),
+    };
+
+    // Before Rust 1.73, panics were reported as
+    // `panicked at 'message', src/lib.rs:1:1`.
+    if let Some(quoted) = rest.strip_prefix('\'') {
+        return match quoted.rsplit_once("', ") {
+            Some((message, location)) => (Location::parse(location.trim_end()), message),
+            None => (None, quoted),
+        };
+    }
+
+    // Since then, they're reported as `panicked at src/lib.rs:1:1:\nmessage`.
+    let (location, message) = rest.split_once('\n').unwrap_or((rest, ""));
+    let location = Location::parse(location.trim_end().trim_end_matches(':'));
+    (location, message)
+}
@@ -0,0 +1,1035 @@
+//! The initial run of each test suite, which finds the tests that fail.
+
+use crate::{
+    history,
+    libtest::{self, Event, SuiteFinished},
+    model_log::{self, Exploration},
+    process::{self, Pool, Timeout},
+    status_line::StatusLine,
+    suite::TestSuite,
+    summary::{Counts, FailureKind},
+    trace::{ColorMode, ProgressWhen, TestFormat, TraceSettings},
+};
+use camino::Utf8PathBuf;
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Help, Result, SectionExt,
+};
+use owo_colors::{colors, OwoColorize, Style};
+use std::{
+    collections::HashMap,
+    fmt,
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, BufReader},
+    sync::watch,
+};
+
+/// Runs test suites to find their failing tests, printing each test's status
+/// as it completes.
+///
+/// Suites run in the shared process [`Pool`], so up to `--jobs` suites may
+/// run at the same time. When more than one suite
+/// can run at once, each line of output names the suite it came from.
+///
+/// With `--fail-fast`, every suite is stopped as soon as any test fails.
+#[derive(Debug)]
+pub(crate) struct Discovery {
+    printer: Mutex<StatusPrinter>,
+    processes: Arc<Pool>,
+    raw: bool,
+    json: bool,
+    /// Whether the tests are run with loom's model log, to tell which
+    /// passing tests explored their models' whole state spaces.
+    model_log: bool,
+    limits: Limits,
+    warn_slow: Option<Duration>,
+    fail_fast: bool,
+    /// Set once a test fails with `--fail-fast`, to stop the other suites.
+    stop: watch::Sender<bool>,
+}
+
+/// How long suites and tests may run for while finding failing tests.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Limits {
+    /// How long a suite may run for, with `--suite-timeout`.
+    pub(crate) suite: Timeout,
+    /// How long a single test may run for, with `--test-timeout`.
+    pub(crate) test: Option<Duration>,
+    /// When the run reaches `--total-timeout`, after which no more suites
+    /// are started.
+    pub(crate) deadline: Option<Instant>,
+}
+
+/// The results of running a single test suite.
+#[derive(Debug)]
+pub(crate) struct SuiteRun {
+    pub(crate) suite: TestSuite,
+    pub(crate) checkpoint_dir: Utf8PathBuf,
+    /// The failing tests, including those that were skipped because they
+    /// were already checkpointed.
+    pub(crate) failed: Vec<String>,
+    /// The panic message each failing test printed, so that it can be
+    /// reported even if the test doesn't fail when it's rerun.
+    pub(crate) panics: HashMap<String, String>,
+    pub(crate) counts: Counts,
+    /// Passing tests which stopped at a bound before exploring their whole
+    /// models.
+    pub(crate) partially_explored: Vec<String>,
+    /// Passing tests which explored their whole models.
+    pub(crate) fully_explored: Vec<String>,
+    pub(crate) passed: Vec<String>,
+    /// Whether the suite was stopped because it reached `--suite-timeout`.
+    pub(crate) timed_out: bool,
+    /// Tests which reached `--test-timeout`, which stopped the suite.
+    pub(crate) timed_out_tests: Vec<String>,
+    /// Whether the suite wasn't run, because the run reached
+    /// `--total-timeout`.
+    pub(crate) skipped: bool,
+    /// Tests which took at least `--warn-slow`, and how long they took.
+    pub(crate) slow: Vec<(String, Duration)>,
+    /// The result of each test that finished, to record in the run's
+    /// history.
+    pub(crate) results: Vec<history::Entry>,
+    /// Tests which have started, but not finished, and when they started.
+    running: Vec<(String, Instant)>,
+    /// When the suite's process started, if it was run.
+    pub(crate) started: Option<Instant>,
+}
+
+/// Prints the status of each test in the initial test run, in the selected
+/// `--format`.
+///
+/// On a terminal, the progress of each running suite is also shown in a
+/// status line below the statuses.
+#[derive(Debug)]
+struct StatusPrinter {
+    format: TestFormat,
+    color: ColorMode,
+    multiplexed: bool,
+    column: usize,
+    done: usize,
+    total: Option<usize>,
+    status: Option<StatusLine>,
+    suites: Vec<SuiteStatus>,
+}
+
+/// The progress of a running suite, shown in the status line.
+#[derive(Debug)]
+struct SuiteStatus {
+    name: String,
+    run: usize,
+    failed: usize,
+    /// The tests which have started, but not finished, in the order they
+    /// started.
+    running: Vec<String>,
+    started: Instant,
+}
+
+impl Discovery {
+    pub(crate) fn new(
+        settings: &TraceSettings,
+        processes: Arc<Pool>,
+        model_log: bool,
+        limits: Limits,
+        warn_slow: Option<Duration>,
+        fail_fast: bool,
+    ) -> Arc<Self> {
+        let raw = settings.raw_test_output();
+        let json = !raw && settings.message_format().is_json();
+        // The status line is only shown on a terminal, even with
+        // `--progress always`, since it's redrawn in place.
+        let status_line = !raw
+            && !json
+            && settings.progress() != ProgressWhen::Never
+            && ProgressWhen::Auto.is_enabled();
+        let discovery = Arc::new(Self {
+            printer: Mutex::new(StatusPrinter::new(
+                settings.test_format(),
+                settings.color(),
+                processes.size() > 1,
+                status_line,
+            )),
+            processes,
+            raw,
+            json,
+            model_log,
+            limits,
+            warn_slow,
+            fail_fast,
+            stop: watch::channel(false).0,
+        });
+
+        if status_line {
+            // Keep the elapsed times in the status line ticking while tests
+            // run, until the run is finished.
+            let discovery = Arc::downgrade(&discovery);
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(1));
+                loop {
+                    interval.tick().await;
+                    match discovery.upgrade() {
+                        Some(discovery) => discovery.printer().redraw(),
+                        None => break,
+                    }
+                }
+            });
+        }
+
+        discovery
+    }
+
+    /// Runs the test suite `suite` using `cmd`, once a process slot is
+    /// available.
+    ///
+    /// If the suite runs for longer than `--suite-timeout`, or another suite
+    /// has a failing test with `--fail-fast`, it's stopped, and the tests
+    /// that hadn't finished are counted as not run.
+    ///
+    /// `skipped` are the names of tests that were previously checkpointed, and
+    /// which `cmd` skips.
+    pub(crate) async fn run(
+        self: Arc<Self>,
+        suite: TestSuite,
+        cmd: Command,
+        checkpoint_dir: Utf8PathBuf,
+        skipped: Vec<String>,
+    ) -> Result<SuiteRun> {
+        let mut stop = self.stop.subscribe();
+        let slot = self.processes.acquire().await;
+
+        // Don't log in the middle of a line of terse output, or over the
+        // status line (which isn't redrawn while the printer is locked).
+        {
+            let mut printer = self.printer();
+            printer.end_line();
+            if suite.kind() == "lib" {
+                tracing::info!(
+                    path = %suite.path().display(),
+                    checkpoint_dir = %checkpoint_dir,
+                    "Running unittests"
+                )
+            } else {
+                tracing::info!(
+                    path = %suite.path().display(),
+                    checkpoint_dir = %checkpoint_dir,
+                    "Running {}",
+                    suite.name()
+                )
+            }
+
+            if !skipped.is_empty() {
+                tracing::info!(
+                    "Skipping {} tests previously checkpointed as failing in {}; run with \
+                    `--verify-checkpoints` to re-evaluate them",
+                    skipped.len(),
+                    suite.name(),
+                );
+            }
+        }
+
+        let mut run = SuiteRun {
+            suite,
+            checkpoint_dir,
+            failed: skipped,
+            panics: HashMap::new(),
+            counts: Counts::default(),
+            partially_explored: Vec::new(),
+            fully_explored: Vec::new(),
+            passed: Vec::new(),
+            timed_out: false,
+            timed_out_tests: Vec::new(),
+            skipped: false,
+            slow: Vec::new(),
+            results: Vec::new(),
+            running: Vec::new(),
+            started: None,
+        };
+
+        if *stop.borrow() {
+            let _printer = self.printer();
+            tracing::info!(
+                "Not running {}, because a test already failed with `--fail-fast`",
+                run.suite.name()
+            );
+            return Ok(run);
+        }
+        if self
+            .limits
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            let _printer = self.printer();
+            tracing::info!(
+                "Not running {}, because the run reached `--total-timeout`",
+                run.suite.name()
+            );
+            run.skipped = true;
+            return Ok(run);
+        }
+
+        let mut cmd = tokio::process::Command::from(cmd);
+        let (mut child, group) =
+            process::spawn(cmd.stdout(Stdio::piped()).stderr(Stdio::piped()), &slot)
+                .with_context(|| format!("running test suite `{}`", run.suite.name()))?;
+
+        // Read stderr concurrently, so the test binary can't block on a full
+        // pipe while we're waiting for it to write to stdout.
+        let mut stderr = child.stderr.take().expect("stderr is piped");
+        let stderr = tokio::spawn(async move {
+            let mut buf = Vec::new();
+            stderr
+                .read_to_end(&mut buf)
+                .await
+                .map(|_| String::from_utf8_lossy(&buf).into_owned())
+        });
+
+        let mut stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        let t0 = Instant::now();
+        run.started = Some(t0);
+        let mut finished = false;
+        let mut stopped = false;
+        let read_events = async {
+            // A test may print invalid UTF-8, so don't let that stop us from
+            // reading the rest of the suite's events.
+            let mut line = Vec::new();
+            loop {
+                // When the longest running test reaches `--test-timeout`.
+                let deadline = self.limits.test.and_then(|limit| {
+                    let started = run.running.iter().map(|&(_, started)| started).min()?;
+                    Some(tokio::time::Instant::from_std(started + limit))
+                });
+                tokio::select! {
+                    // A partially read line is kept in `line`, so it's
+                    // finished by the next read.
+                    read = stdout.read_until(b'\n', &mut line) => {
+                        let read = read.with_context(|| {
+                            format!("reading output from test suite `{}`", run.suite.name())
+                        })?;
+                        if read == 0 {
+                            break;
+                        }
+                        let text = String::from_utf8_lossy(&line);
+                        finished |=
+                            self.event(&mut run, text.trim_end_matches(&['\r', '\n'][..]), t0)?;
+                        line.clear();
+                    }
+                    // Keep reading the events that were already written, until
+                    // the killed test binary's stdout is closed.
+                    _ = stop.changed(), if !stopped => {
+                        stopped = true;
+                        group.kill();
+                    }
+                    // A test which hangs would keep the suite running until
+                    // `--suite-timeout` (if ever), so the suite is stopped.
+                    _ = sleep_until(deadline), if deadline.is_some() && !stopped => {
+                        let limit = self.limits.test.unwrap_or_default();
+                        run.timed_out_tests = run
+                            .running
+                            .iter()
+                            .filter(|(_, started)| started.elapsed() >= limit)
+                            .map(|(name, _)| name.clone())
+                            .collect();
+                        stopped = true;
+                        group.kill();
+                    }
+                }
+            }
+            Ok::<_, color_eyre::Report>(())
+        };
+        // Once the test binary exits, kill anything it spawned, so that
+        // they can't keep its stdout open.
+        let wait = async { Ok(self.limits.suite.wait(&mut child, &group).await?) };
+        let ((status, killed_by), ()) = tokio::try_join!(wait, read_events)?;
+        let stderr = stderr.await?.unwrap_or_default();
+        if let Some(signal) = killed_by {
+            tracing::debug!(suite = %run.suite.name(), signal = %signal.as_str(), "stopped test suite");
+            self.timed_out(&mut run);
+            return Ok(run);
+        }
+        if !run.timed_out_tests.is_empty() && !finished {
+            self.test_timed_out(&mut run);
+            return Ok(run);
+        }
+        if stopped && !finished {
+            self.stopped(&mut run);
+            return Ok(run);
+        }
+        // A test that fails to allocate aborts the whole test binary.
+        if !status.success() && !finished {
+            if let Some(message) = out_of_memory(&stderr) {
+                self.out_of_memory(&mut run, message);
+                return Ok(run);
+            }
+        }
+        // If tests fail, the test binary exits with an error, so that's only
+        // interesting if the suite didn't finish.
+        if !status.success() && !finished {
+            return Err(eyre!(
+                "test suite `{}` exited unexpectedly ({})",
+                run.suite.name(),
+                status
+            ))
+            .section(stderr.trim().to_owned().header("Stderr:"));
+        }
+
+        Ok(run)
+    }
+
+    /// Records that `run`'s suite was stopped by `--suite-timeout`.
+    fn timed_out(&self, run: &mut SuiteRun) {
+        let counts = &mut run.counts;
+        counts.not_run = counts
+            .tests
+            .saturating_sub(counts.passed + counts.failed + counts.ignored);
+        run.timed_out = true;
+
+        let mut printer = self.printer();
+        printer.end_suite_status(run.suite.name());
+        printer.end_line();
+        let finished = counts.passed + counts.failed + counts.ignored;
+        if run.running.is_empty() {
+            tracing::warn!(
+                "test suite `{}` reached `--suite-timeout` after {} tests \
+                finished; {} tests were not run",
+                run.suite.name(),
+                finished,
+                counts.not_run,
+            );
+        } else {
+            tracing::warn!(
+                "test suite `{}` reached `--suite-timeout` after {} tests \
+                finished; {} tests were not run, including these, which were \
+                still running:\n    {}",
+                run.suite.name(),
+                finished,
+                counts.not_run,
+                run.running
+                    .iter()
+                    .map(|(name, _)| name.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n    "),
+            );
+        }
+    }
+
+    /// Records that `run`'s suite was stopped because some of its tests
+    /// reached `--test-timeout`.
+    fn test_timed_out(&self, run: &mut SuiteRun) {
+        let counts = &mut run.counts;
+        counts.not_run = counts
+            .tests
+            .saturating_sub(counts.passed + counts.failed + counts.ignored);
+
+        let mut printer = self.printer();
+        printer.end_suite_status(run.suite.name());
+        printer.end_line();
+        tracing::warn!(
+            "stopped test suite `{}`, because these tests reached `--test-timeout` ({}s); \
+            {} tests were not run, including them:\n    {}",
+            run.suite.name(),
+            self.limits.test.unwrap_or_default().as_secs(),
+            counts.not_run,
+            run.timed_out_tests.join("\n    "),
+        );
+    }
+
+    /// Records that `run`'s suite was aborted because a test failed to
+    /// allocate memory, with `message`.
+    ///
+    /// Which of the tests that were running failed to allocate isn't known,
+    /// so they're all counted as failing.
+    fn out_of_memory(&self, run: &mut SuiteRun, message: &str) {
+        let running = std::mem::take(&mut run.running)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        for test in &running {
+            run.counts.failed += 1;
+            run.failed.push(test.clone());
+            run.panics.insert(test.clone(), message.to_owned());
+        }
+        let counts = &mut run.counts;
+        counts.not_run = counts
+            .tests
+            .saturating_sub(counts.passed + counts.failed + counts.ignored);
+        if self.fail_fast {
+            self.stop.send_replace(true);
+        }
+
+        let mut printer = self.printer();
+        printer.end_suite_status(run.suite.name());
+        printer.end_line();
+        tracing::warn!(
+            "test suite `{}` ran out of memory ({}); {} tests were not run, and these \
+            tests, which were running, failed:\n    {}",
+            run.suite.name(),
+            message.trim(),
+            counts.not_run,
+            running.join("\n    "),
+        );
+    }
+
+    /// Records that `run`'s suite was stopped by `--fail-fast`.
+    fn stopped(&self, run: &mut SuiteRun) {
+        let counts = &mut run.counts;
+        counts.not_run = counts
+            .tests
+            .saturating_sub(counts.passed + counts.failed + counts.ignored);
+
+        let mut printer = self.printer();
+        printer.end_suite_status(run.suite.name());
+        printer.end_line();
+        tracing::info!(
+            "Stopped {} after a test failed with `--fail-fast`; {} tests were not run",
+            run.suite.name(),
+            counts.not_run,
+        );
+    }
+
+    /// Ends the last line of terse output, and removes the status line, once
+    /// every suite has finished.
+    pub(crate) fn finish(&self) {
+        let mut printer = self.printer();
+        printer.end_line();
+        printer.status = None;
+    }
+
+    /// Handles a line of output from a suite's test binary, returning `true`
+    /// if the suite has finished.
+    fn event(&self, run: &mut SuiteRun, line: &str, t0: Instant) -> Result<bool> {
+        use libtest::{Suite, Test};
+
+        if self.raw {
+            if let Ok(event) = serde_json::from_str::<&serde_json::value::RawValue>(line) {
+                println!("{}", event.get());
+            }
+        }
+
+        let event = match serde_json::from_str::<Event>(line) {
+            Ok(event) => event,
+            Err(error) => {
+                let mut printer = self.printer();
+                printer.end_line();
+                tracing::warn!(suite = %run.suite.name(), %error, "error from test");
+                return Ok(false);
+            }
+        };
+        let human = !self.json && !self.raw;
+        let took = match event {
+            Event::Test(Test::Started(ref started)) => {
+                run.running.push((started.name.clone(), Instant::now()));
+                if human {
+                    self.printer().test_started(run.suite.name(), &started.name);
+                }
+                None
+            }
+            Event::Test(Test::Ok(ref test)) | Event::Test(Test::Failed(ref test)) => {
+                Self::duration(run, test)
+            }
+            _ => None,
+        };
+        let slow = match event {
+            Event::Test(Test::Ok(ref test)) | Event::Test(Test::Failed(ref test)) => {
+                took.and_then(|took| self.slow(run, &test.name, took))
+            }
+            _ => None,
+        };
+        let suite = run.suite.name();
+        if human {
+            let finished = match event {
+                Event::Test(Test::Ok(ref test)) => Some((test, Some(false))),
+                Event::Test(Test::Failed(ref test)) => Some((test, Some(true))),
+                Event::Test(Test::Ignored(ref test)) => Some((test, None)),
+                _ => None,
+            };
+            if let Some((test, failed)) = finished {
+                self.printer().test_finished(suite, &test.name, failed);
+            }
+        }
+        match event {
+            Event::Test(Test::Failed(ref test_failed)) => {
+                run.counts.failed += 1;
+                if human {
+                    let mut printer = self.printer();
+                    printer.print::<colors::Red>(suite, &test_failed.name, "failed", 'F', slow);
+                    // Checkpointing and rerunning the test can take a while,
+                    // so print the panic message right away to give the user
+                    // something to go on.
+                    if let Some(panic) = test_failed
+                        .stdout
+                        .as_deref()
+                        .and_then(panic_message)
+                        .filter(|_| !printer.is_terse())
+                    {
+                        let panic = panic.lines().fold(String::new(), |mut out, line| {
+                            if !out.is_empty() {
+                                out.push('\n');
+                            }
+                            out.push_str("    ");
+                            out.push_str(line);
+                            out
+                        });
+                        printer.line(format_args!("{}", panic));
+                    }
+                }
+                if let Some(panic) = test_failed.stdout.as_deref().and_then(panic_message) {
+                    run.panics
+                        .insert(test_failed.name.clone(), panic.to_owned());
+                }
+                run.failed.push(test_failed.name.clone());
+                if self.fail_fast {
+                    self.stop.send_replace(true);
+                }
+                run.results.push(history::Entry {
+                    suite: suite.to_owned(),
+                    name: test_failed.name.clone(),
+                    outcome: history::Outcome::Failed,
+                    duration: took.map(|took| took.as_secs_f64()),
+                    iterations: test_failed
+                        .stdout
+                        .as_deref()
+                        .and_then(model_log::completed_iterations),
+                });
+            }
+            Event::Test(Test::Ok(ref ok)) => {
+                run.counts.passed += 1;
+                let exploration = ok
+                    .stdout
+                    .as_deref()
+                    .filter(|_| self.model_log)
+                    .and_then(model_log::exploration);
+                let partial = exploration == Some(Exploration::Bounded);
+                let complete = exploration == Some(Exploration::Complete);
+                if partial {
+                    run.partially_explored
+                        .push(format!("{}::{}", suite, ok.name));
+                } else if complete {
+                    run.fully_explored.push(format!("{}::{}", suite, ok.name));
+                }
+                run.passed.push(ok.name.clone());
+                run.results.push(history::Entry {
+                    suite: suite.to_owned(),
+                    name: ok.name.clone(),
+                    outcome: if partial {
+                        history::Outcome::PartiallyExplored
+                    } else {
+                        history::Outcome::Passed
+                    },
+                    duration: took.map(|took| took.as_secs_f64()),
+                    iterations: ok
+                        .stdout
+                        .as_deref()
+                        .and_then(model_log::completed_iterations),
+                });
+                if human && partial {
+                    self.printer().print::<colors::Cyan>(
+                        suite,
+                        &ok.name,
+                        "ok (partially explored)",
+                        '.',
+                        slow,
+                    );
+                } else if human && complete {
+                    self.printer().print::<colors::Green>(
+                        suite,
+                        &ok.name,
+                        "ok (fully explored)",
+                        '.',
+                        slow,
+                    );
+                } else if human {
+                    self.printer()
+                        .print::<colors::Green>(suite, &ok.name, "ok", '.', slow);
+                }
+            }
+            Event::Test(Test::Ignored(ref ignored)) => {
+                run.counts.ignored += 1;
+                if human {
+                    self.printer().print::<colors::Yellow>(
+                        suite,
+                        &ignored.name,
+                        "ignored",
+                        'i',
+                        None,
+                    );
+                }
+            }
+            Event::Suite(Suite::Started(ref started)) => {
+                run.counts.tests += started.test_count;
+                if human {
+                    self.printer().start(suite, started.test_count);
+                }
+            }
+            Event::Suite(Suite::Ok(ref finished)) => {
+                run.counts.filtered_out += finished.filtered_out;
+                if human {
+                    self.printer().result(suite, "ok", finished, t0);
+                }
+            }
+            Event::Suite(Suite::Failed(ref finished)) => {
+                run.counts.filtered_out += finished.filtered_out;
+                if human {
+                    self.printer().result(suite, "FAILED", finished, t0);
+                }
+            }
+            _ => {}
+        }
+        if let Event::Test(Test::Ok(ref test))
+        | Event::Test(Test::Failed(ref test))
+        | Event::Test(Test::Ignored(ref test)) = event
+        {
+            run.running.retain(|(name, _)| name != &test.name);
+        }
+
+        if self.json {
+            self.emit_json(&event)?;
+        }
+
+        Ok(matches!(
+            event,
+            Event::Suite(Suite::Ok(_)) | Event::Suite(Suite::Failed(_))
+        ))
+    }
+
+    /// Returns how long `test` took.
+    fn duration(run: &SuiteRun, test: &libtest::TestEvent) -> Option<Duration> {
+        // libtest only reports how long tests took with `--report-time`.
+        match test.exec_time {
+            Some(secs) => Some(Duration::from_secs_f64(secs)),
+            None => run
+                .running
+                .iter()
+                .find(|(name, _)| name == &test.name)
+                .map(|(_, started)| started.elapsed()),
+        }
+    }
+
+    /// Returns how long the test `name` took, if it took at least
+    /// `--warn-slow`, and records it as slow.
+    fn slow(&self, run: &mut SuiteRun, name: &str, took: Duration) -> Option<Duration> {
+        if took < self.warn_slow? {
+            return None;
+        }
+        run.slow
+            .push((format!("{}::{}", run.suite.name(), name), took));
+        Some(took)
+    }
+
+    fn emit_json(&self, event: &Event) -> Result<()> {
+        use libtest::{Suite, Test};
+        // Test and suite results are written without the event's tags.
+        let json = match event {
+            Event::Test(Test::Failed(test))
+            | Event::Test(Test::Ok(test))
+            | Event::Test(Test::Ignored(test)) => serde_json::to_string(test),
+            Event::Suite(Suite::Started(started)) => serde_json::to_string(started),
+            Event::Suite(Suite::Ok(finished)) | Event::Suite(Suite::Failed(finished)) => {
+                serde_json::to_string(finished)
+            }
+            event => serde_json::to_string(event),
+        }
+        .context("write json message")?;
+        // Write the whole message at once, so messages from suites running
+        // concurrently don't interleave.
+        eprint!("{}", json);
+        Ok(())
+    }
+
+    fn printer(&self) -> std::sync::MutexGuard<'_, StatusPrinter> {
+        self.printer
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+}
+
+// === impl StatusPrinter ===
+
+impl StatusPrinter {
+    /// The number of tests printed on each line in terse mode (the same as
+    /// libtest).
+    const TERSE_COLUMNS: usize = 88;
+
+    fn new(format: TestFormat, color: ColorMode, multiplexed: bool, status_line: bool) -> Self {
+        // Terse output is printed a character at a time, so it can't be
+        // printed above a status line.
+        let status = (status_line && format == TestFormat::Pretty).then(StatusLine::default);
+        Self {
+            format,
+            color,
+            multiplexed,
+            column: 0,
+            done: 0,
+            total: None,
+            status,
+            suites: Vec::new(),
+        }
+    }
+
+    fn is_terse(&self) -> bool {
+        self.format == TestFormat::Terse
+    }
+
+    /// Starts printing the statuses of a suite of `total` tests.
+    fn start(&mut self, suite: &str, total: usize) {
+        if self.multiplexed {
+            // Statuses from several suites are interleaved, so count
+            // progress across all of them.
+            self.total = Some(self.total.unwrap_or(0) + total);
+            if !self.is_terse() {
+                self.line(format_args!("\nrunning {} tests in {}", total, suite));
+            }
+            return;
+        }
+
+        self.end_line();
+        self.done = 0;
+        self.total = Some(total);
+        self.line(format_args!("\nrunning {} tests", total));
+    }
+
+    /// Prints the status of a test, marking it as slow if it took `slow`.
+    fn print<C: owo_colors::Color>(
+        &mut self,
+        suite: &str,
+        name: &str,
+        status: &str,
+        terse: char,
+        slow: Option<Duration>,
+    ) {
+        let style = self.color.if_color(Style::new().fg::<C>());
+        if !self.is_terse() {
+            let status = status.style(style);
+            let slow = slow.map_or_else(String::new, |took| {
+                format!(
+                    " {} ({:.1?})",
+                    "slow".style(self.color.if_color(Style::new().yellow())),
+                    took
+                )
+            });
+            if self.multiplexed {
+                self.line(format_args!(
+                    "test {}::{} ... {}{}",
+                    suite, name, status, slow
+                ));
+            } else {
+                self.line(format_args!("test {} ... {}{}", name, status, slow));
+            }
+            return;
+        }
+
+        eprint!("{}", terse.style(style));
+        self.done += 1;
+        self.column += 1;
+        if self.column == Self::TERSE_COLUMNS {
+            match self.total {
+                Some(total) => eprintln!(" {}/{}", self.done, total),
+                None => eprintln!(),
+            }
+            self.column = 0;
+        }
+    }
+
+    fn result(&mut self, suite: &str, result: &str, finished: &SuiteFinished, t0: Instant) {
+        let SuiteFinished {
+            passed,
+            failed,
+            ignored,
+            measured,
+            filtered_out,
+            ..
+        } = finished;
+        let counts = format!("{passed} passed; {failed} failed; {ignored} ignored; {measured} measured; {filtered_out} filtered out; finished in {:?}", t0.elapsed());
+        self.end_suite_status(suite);
+        if !self.multiplexed {
+            self.end_suite();
+            self.line(format_args!("\ntest result: {result}. {counts}"));
+        } else if !self.is_terse() {
+            self.line(format_args!(
+                "\ntest result for {suite}: {result}. {counts}"
+            ));
+        }
+    }
+
+    /// Prints a line of output above the status line.
+    fn line(&mut self, line: fmt::Arguments<'_>) {
+        match self.status {
+            Some(ref mut status) => {
+                status.clear();
+                eprintln!("{}", line);
+                self.redraw();
+            }
+            None => eprintln!("{}", line),
+        }
+    }
+
+    /// Records that the test `name` in `suite` started.
+    fn test_started(&mut self, suite: &str, name: &str) {
+        if let Some(status) = self.suite_status(suite) {
+            status.running.push(name.to_owned());
+            self.redraw();
+        }
+    }
+
+    /// Records that the test `name` in `suite` finished, and whether it
+    /// failed (or `None` if it was ignored).
+    fn test_finished(&mut self, suite: &str, name: &str, failed: Option<bool>) {
+        if let Some(status) = self.suite_status(suite) {
+            status.running.retain(|running| running != name);
+            if let Some(failed) = failed {
+                status.run += 1;
+                status.failed += failed as usize;
+            }
+        }
+    }
+
+    /// Stops showing the progress of `suite` in the status line.
+    fn end_suite_status(&mut self, suite: &str) {
+        self.suites.retain(|status| status.name != suite);
+        self.redraw();
+    }
+
+    /// Returns the progress of `suite`, if the status line is shown.
+    fn suite_status(&mut self, suite: &str) -> Option<&mut SuiteStatus> {
+        self.status.as_ref()?;
+        let idx = match self.suites.iter().position(|status| status.name == suite) {
+            Some(idx) => idx,
+            None => {
+                self.suites.push(SuiteStatus {
+                    name: suite.to_owned(),
+                    run: 0,
+                    failed: 0,
+                    running: Vec::new(),
+                    started: Instant::now(),
+                });
+                self.suites.len() - 1
+            }
+        };
+        Some(&mut self.suites[idx])
+    }
+
+    /// Redraws the status line with the current progress of each running
+    /// suite.
+    fn redraw(&mut self) {
+        let status = match self.status {
+            Some(ref mut status) => status,
+            None => return,
+        };
+        if self.suites.is_empty() {
+            status.clear();
+            return;
+        }
+        let line = self
+            .suites
+            .iter()
+            .map(|suite| {
+                let current = suite
+                    .running
+                    .last()
+                    .map_or_else(String::new, |test| format!(", current: {}", test));
+                format!(
+                    "suite {}: {} run, {} failed{}, {}s",
+                    suite.name,
+                    suite.run,
+                    suite.failed,
+                    current,
+                    suite.started.elapsed().as_secs()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(" | ");
+        status.draw(&line);
+    }
+
+    /// Ends the statuses of a suite.
+    fn end_suite(&mut self) {
+        if !self.multiplexed {
+            self.end_line();
+        }
+    }
+
+    /// Ends the current line of terse output, if one was started, and clears
+    /// the status line, so that something else can be printed.
+    fn end_line(&mut self) {
+        if let Some(ref mut status) = self.status {
+            status.clear();
+        }
+        if self.column > 0 {
+            eprintln!();
+            self.column = 0;
+        }
+    }
+}
+
+impl Drop for StatusPrinter {
+    fn drop(&mut self) {
+        // Don't leave the status line behind if the run is stopped by an
+        // error.
+        if let Some(ref mut status) = self.status {
+            status.clear();
+        }
+    }
+}
+
+/// Extracts the first panic message from a failed test's captured output.
+pub(crate) fn panic_message(stdout: &str) -> Option<&str> {
+    let start = stdout
+        .match_indices("thread '")
+        .map(|(idx, _)| idx)
+        .find(|&idx| {
+            stdout[idx..]
+                .lines()
+                .next()
+                .is_some_and(|line| line.contains(" panicked at "))
+        })?;
+    let panic = &stdout[start..];
+    // Skip the backtrace (if any), the hint about `RUST_BACKTRACE`, and
+    // anything after them.
+    let end = ["\nstack backtrace:", "\nnote: "]
+        .iter()
+        .filter_map(|marker| panic.find(marker))
+        .min()
+        .unwrap_or(panic.len());
+    let panic = panic[..end].trim_end();
+    Some(panic)
+}
+
+/// Part of the message loom panics with when a model exceeds
+/// `LOOM_MAX_BRANCHES`.
+pub(crate) const MAX_BRANCHES_PANIC: &str = "Model exceeded maximum number of branches";
+
+/// The message loom panics with when every thread in a model is blocked.
+const DEADLOCK_PANIC: &str = "deadlock; threads =";
+
+/// Part of the message the Rust runtime aborts with when an allocation
+/// fails, which it writes to stderr rather than panicking.
+const OUT_OF_MEMORY: &str = "memory allocation of ";
+
+/// Returns the line a process that ran out of memory aborted with, if it did.
+pub(crate) fn out_of_memory(output: &str) -> Option<&str> {
+    output.lines().find(|line| line.contains(OUT_OF_MEMORY))
+}
+
+/// Classifies a failing test by the message it panicked with.
+pub(crate) fn failure_kind(panic: &str) -> FailureKind {
+    if panic.contains(DEADLOCK_PANIC) {
+        FailureKind::Deadlock
+    } else if panic.contains(MAX_BRANCHES_PANIC) {
+        FailureKind::TooManyBranches
+    } else if panic.contains(OUT_OF_MEMORY) {
+        FailureKind::OutOfMemory
+    } else {
+        FailureKind::Panic
+    }
+}
+
+/// Sleeps until `deadline`, or forever if there isn't one.
+async fn sleep_until(deadline: Option<tokio::time::Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
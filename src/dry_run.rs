@@ -0,0 +1,208 @@
+//! Printing the commands a run would run, with `--dry-run`.
+//!
+//! The tests are built (as the test binaries' paths aren't known until
+//! they are), but not run. Instead, the command each phase of the run would
+//! run is printed with every environment variable `cargo loom` sets for it,
+//! so that a test which behaves differently under `cargo loom` can be run
+//! the same way by hand.
+
+use crate::{config::Config, file_stem, log_header, test_args, App, TestFilter, ENV_MAX_THREADS};
+use color_eyre::{eyre::WrapErr, Result};
+use owo_colors::{OwoColorize, Style};
+use serde::Serialize;
+use std::{collections::BTreeMap, process::Command};
+
+/// The name failing tests are printed with, since which tests fail (or, with
+/// `--smoke`, pass) isn't known without running them.
+const PLACEHOLDER: &str = "<TEST>";
+
+/// A command a run would run.
+#[derive(Debug, Serialize)]
+struct PlannedCommand<'a> {
+    phase: &'static str,
+    package: &'a str,
+    /// The feature set the package is tested with, when running a feature
+    /// matrix.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    features: Option<&'a str>,
+    /// The test suite the command runs, if it isn't the build.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suite: Option<&'a str>,
+    /// Whether the command is run with only the variables in `env`, with
+    /// `--clean-env`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    clean_env: bool,
+    /// The environment variables `cargo loom` sets for the command.
+    env: BTreeMap<String, String>,
+    program: String,
+    args: Vec<String>,
+}
+
+impl App {
+    /// Builds the tests, and prints the commands that each phase of a run
+    /// would run them with, without running them.
+    pub(crate) fn dry_run(&self) -> Result<()> {
+        let filter = TestFilter::Contains(self.args.testname.as_deref());
+        for features in &self.feature_sets {
+            let label = self
+                .args
+                .cargo
+                .feature_matrix
+                .as_ref()
+                .map(|_| features.to_string());
+            for pkg in self.wanted_packages() {
+                let config = Config::load(&self.metadata, pkg)?;
+                let settings = self.settings(&config);
+                // With `--smoke`, the tests are found to fail with a lower
+                // preemption bound, and the ones that pass are run again.
+                let discovery_settings = if self.args.loom.smoke {
+                    App::smoke_settings(&settings)
+                } else {
+                    settings.clone()
+                };
+                let failed_first = self.failed_first(pkg, label.as_deref())?;
+                let planned = |phase, suite: Option<&str>, cmd: &Command| {
+                    let mut planned =
+                        PlannedCommand::new(phase, &pkg.name, label.as_deref(), suite, cmd);
+                    // The build isn't a test process, so it always inherits
+                    // the environment.
+                    planned.clean_env = suite.is_some() && self.args.loom.clean_env;
+                    planned.print(self)
+                };
+                planned("build", None, &self.test_cmd(pkg, &settings, features))?;
+
+                let suites = self
+                    .build_tests(pkg, &settings, features)
+                    .with_context(|| format!("Error building tests for package `{}`", pkg.name))?;
+                for suite in suites {
+                    let suite = suite.context("Getting next test failed")?;
+                    let name = Some(suite.name());
+                    let first = failed_first
+                        .get(suite.name())
+                        .map_or(&[][..], Vec::as_slice);
+                    match self.test_groups(&suite, filter, &[], first)? {
+                        Some(groups) => {
+                            for (max_threads, tests) in groups {
+                                let mut cmd = suite.command();
+                                self.configure_discovery_command(&mut cmd, &discovery_settings);
+                                if let Some(max_threads) = max_threads {
+                                    cmd.env(ENV_MAX_THREADS, max_threads.to_string());
+                                }
+                                test_args::exact(&mut cmd).args(&tests);
+                                planned("discovery", name, &cmd)?;
+                            }
+                        }
+                        None => {
+                            let mut cmd = suite.command();
+                            self.configure_discovery_command(&mut cmd, &discovery_settings);
+                            filter.apply(&mut cmd);
+                            planned("discovery", name, &cmd)?;
+                        }
+                    }
+                    if self.args.loom.smoke {
+                        let mut cmd = suite.command();
+                        self.configure_discovery_command(&mut cmd, &settings)
+                            .env(ENV_MAX_THREADS, settings.max_threads.to_string());
+                        test_args::exact(&mut cmd).arg(PLACEHOLDER);
+                        planned("full", name, &cmd)?;
+                    }
+
+                    let checkpoint = self
+                        .suite_checkpoint_dir(&suite)?
+                        .join(format!("{}.json", file_stem(PLACEHOLDER)));
+                    let mut cmd = Command::new(suite.path());
+                    self.configure_checkpoint_command(&mut cmd, &settings, &checkpoint)
+                        .arg(PLACEHOLDER);
+                    // With `--single-pass`, the rerun writes the checkpoint.
+                    if !self.args.loom.single_pass {
+                        planned("checkpoint", name, &cmd)?;
+                    }
+                    cmd.envs(self.rerun_env(&settings.loom_log));
+                    if self.rerun_nocapture() {
+                        cmd.arg("--nocapture");
+                    }
+                    planned("rerun", name, &cmd)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> PlannedCommand<'a> {
+    fn new(
+        phase: &'static str,
+        package: &'a str,
+        features: Option<&'a str>,
+        suite: Option<&'a str>,
+        cmd: &Command,
+    ) -> Self {
+        Self {
+            phase,
+            package,
+            features,
+            suite,
+            clean_env: false,
+            env: cmd
+                .get_envs()
+                .filter_map(|(name, value)| {
+                    Some((
+                        name.to_string_lossy().into_owned(),
+                        value?.to_string_lossy().into_owned(),
+                    ))
+                })
+                .collect(),
+            program: cmd.get_program().to_string_lossy().into_owned(),
+            args: cmd
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+        }
+    }
+
+    fn print(&self, app: &App) -> Result<()> {
+        if app.args.trace_settings.message_format().is_json() {
+            let json = serde_json::to_string(self).context("write json message")?;
+            eprintln!("{}", json);
+            return Ok(());
+        }
+
+        let mut what = match self.suite {
+            Some(suite) => format!("{} ({})", suite, self.package),
+            None => self.package.to_owned(),
+        };
+        if let Some(features) = self.features {
+            what = format!("{} [{}]", what, features);
+        }
+        eprintln!(
+            "{} {}",
+            format_args!("{:>10}", self.phase).style(
+                app.args
+                    .trace_settings
+                    .color()
+                    .if_color(Style::new().bold())
+            ),
+            what
+        );
+        let command = self
+            .clean_env
+            .then(|| "env -i".to_owned())
+            .into_iter()
+            .chain(
+                self.env
+                    .iter()
+                    .map(|(name, value)| format!("{}={}", name, log_header::quote(value))),
+            )
+            .chain(std::iter::once(
+                log_header::quote(&self.program).into_owned(),
+            ))
+            .chain(
+                self.args
+                    .iter()
+                    .map(|arg| log_header::quote(arg).into_owned()),
+            )
+            .collect::<Vec<_>>();
+        eprintln!("           {}", command.join(" "));
+        Ok(())
+    }
+}
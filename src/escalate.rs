@@ -0,0 +1,130 @@
+use crate::{
+    discovery::panic_message, process, settings::Settings, suite::TestSuite, test_args, App,
+    ENV_MAX_BRANCHES, ENV_MAX_THREADS,
+};
+use color_eyre::{eyre::WrapErr, Result};
+use owo_colors::{OwoColorize, Style};
+use std::process::Command;
+use tokio::task::JoinSet;
+
+/// The largest number of threads loom supports in a model.
+pub(crate) const LOOM_MAX_THREADS: usize = 5;
+
+/// How much `--max-branches` is multiplied by when escalating.
+const BRANCHES_FACTOR: usize = 10;
+
+impl App {
+    /// Returns the `--max-threads` and `--max-branches` that passing tests
+    /// which were run with `settings` and `max_threads` are rerun with.
+    fn escalated_bounds(settings: &Settings, max_threads: usize) -> (usize, usize) {
+        let max_threads = (max_threads + 1).min(LOOM_MAX_THREADS);
+        let max_branches = settings.max_branches * BRANCHES_FACTOR;
+        (max_threads, max_branches)
+    }
+
+    /// Configures a test binary to rerun a passing test which was run with
+    /// `settings` and `max_threads` with stronger bounds.
+    pub(crate) fn configure_escalate_command<'cmd>(
+        &self,
+        cmd: &'cmd mut Command,
+        settings: &Settings,
+        max_threads: usize,
+    ) -> &'cmd mut Command {
+        let (max_threads, max_branches) = Self::escalated_bounds(settings, max_threads);
+        self.configure_loom_command(cmd, settings)
+            .env(ENV_MAX_THREADS, max_threads.to_string())
+            .env(ENV_MAX_BRANCHES, max_branches.to_string())
+    }
+
+    /// Reruns tests that passed with stronger bounds than the initial run, so
+    /// that they explore more of their models, returning the names of any
+    /// tests that fail.
+    ///
+    /// Each test is run with one more thread, more branches, and no duration
+    /// limit, in the run's process pool.
+    pub(crate) async fn escalate(
+        &self,
+        settings: &Settings,
+        passed: Vec<(TestSuite, Vec<String>)>,
+    ) -> Result<Vec<String>> {
+        let (max_threads, max_branches) = Self::escalated_bounds(settings, settings.max_threads);
+        let count = passed.iter().map(|(_, tests)| tests.len()).sum::<usize>();
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        tracing::info!(
+            max_threads,
+            max_branches,
+            "Escalating {} passing tests",
+            count
+        );
+
+        let mut tasks = JoinSet::new();
+        for (suite, tests) in passed {
+            for test in tests {
+                let mut cmd = Command::new(suite.path());
+                self.configure_escalate_command(
+                    &mut cmd,
+                    settings,
+                    self.max_threads(settings, &test),
+                );
+                test_args::exact(&mut cmd).arg(&test);
+                let name = format!("{}::{}", suite.name(), test);
+                let processes = self.processes.clone();
+                let test_timeout = self.test_timeout;
+                tasks.spawn(async move {
+                    let slot = processes.acquire().await;
+                    let mut cmd = tokio::process::Command::from(cmd);
+                    tracing::debug!(test = %name, "Escalating");
+                    let finished = process::output(&mut cmd, &slot, test_timeout, None, None, None)
+                        .await
+                        .with_context(|| format!("spawn process to escalate {name}"))?;
+                    Ok::<_, color_eyre::Report>((name, finished))
+                });
+            }
+        }
+
+        let mut failed = Vec::new();
+        while let Some(result) = tasks.join_one().await? {
+            let (name, finished) = result?;
+            if let Some(signal) = finished.killed_by {
+                tracing::warn!(test = %name, signal = %signal.as_str(), "escalated run timed out");
+                continue;
+            }
+            if finished.status.success() {
+                tracing::debug!(test = %name, "Escalated run passed");
+                continue;
+            }
+
+            eprintln!(
+                "test {} ... {}",
+                name,
+                "failed when escalated".style(
+                    self.args
+                        .trace_settings
+                        .color()
+                        .if_color(Style::new().red())
+                )
+            );
+            let stdout = String::from_utf8_lossy(finished.stdout.bytes());
+            if let Some(panic) = panic_message(&stdout) {
+                for line in panic.lines() {
+                    eprintln!("    {}", line);
+                }
+            }
+            failed.push(name);
+        }
+
+        if !failed.is_empty() {
+            tracing::warn!(
+                "{} tests only failed with stronger bounds; rerun them with \
+                `--max-threads {} --max-branches {}` to debug them",
+                failed.len(),
+                max_threads,
+                max_branches,
+            );
+        }
+
+        Ok(failed)
+    }
+}
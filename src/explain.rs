@@ -0,0 +1,112 @@
+use crate::{
+    checkpoint::{Branch, Checkpoint},
+    trace::ColorMode,
+    App,
+};
+use camino::Utf8PathBuf;
+use color_eyre::{eyre::WrapErr, Result};
+use owo_colors::{OwoColorize, Style};
+use serde::Serialize;
+
+#[derive(Debug, clap::Args)]
+pub(crate) struct ExplainArgs {
+    /// The checkpoint file to explain
+    #[clap(value_hint = clap::ValueHint::FilePath)]
+    checkpoint: Utf8PathBuf,
+}
+
+/// What a checkpoint says about how far a model's exploration got.
+#[derive(Debug, Serialize)]
+struct Explanation {
+    checkpoint: Utf8PathBuf,
+    /// The number of branch points in the execution the checkpoint resumes
+    /// from.
+    depth: usize,
+    schedule_branches: usize,
+    load_branches: usize,
+    spurious_branches: usize,
+    /// The number of threads which could run at any branch point.
+    threads: usize,
+    /// The most times a thread was preempted in the execution.
+    max_preemptions: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preemption_bound: Option<u8>,
+    /// An estimate of how much of the model had been explored, from 0 to 1.
+    progress: f64,
+}
+
+impl App {
+    /// Summarizes a checkpoint file, to show how far the exploration of its
+    /// model got before it was written.
+    pub(crate) fn explain(&self, args: &ExplainArgs) -> Result<()> {
+        let checkpoint = Checkpoint::load(&args.checkpoint)?;
+        let mut explanation = Explanation {
+            checkpoint: args.checkpoint.clone(),
+            depth: checkpoint.branches().len(),
+            schedule_branches: 0,
+            load_branches: 0,
+            spurious_branches: 0,
+            threads: checkpoint.threads(),
+            max_preemptions: 0,
+            preemption_bound: checkpoint.preemption_bound,
+            progress: checkpoint.progress(),
+        };
+        for branch in checkpoint.branches() {
+            match branch {
+                Branch::Schedule(schedule) => {
+                    explanation.schedule_branches += 1;
+                    explanation.max_preemptions =
+                        explanation.max_preemptions.max(schedule.preemptions);
+                }
+                Branch::Load(_) => explanation.load_branches += 1,
+                Branch::Spurious(_) => explanation.spurious_branches += 1,
+            }
+        }
+
+        if self.args.trace_settings.message_format().is_json() {
+            serde_json::to_writer(std::io::stderr(), &explanation).context("write json message")?;
+        } else {
+            explanation.print(self.args.trace_settings.color());
+        }
+        Ok(())
+    }
+}
+
+impl Explanation {
+    fn print(&self, color: ColorMode) {
+        let bold = |text: &dyn std::fmt::Display| {
+            text.style(color.if_color(Style::new().bold())).to_string()
+        };
+        eprintln!("checkpoint {}", bold(&self.checkpoint));
+        eprintln!(
+            "   explored: {} of the model (estimated)",
+            bold(&format_args!("{:.1}%", self.progress * 100.0)),
+        );
+        eprintln!(
+            "      depth: {} branch points ({} schedule, {} load, {} spurious)",
+            self.depth, self.schedule_branches, self.load_branches, self.spurious_branches,
+        );
+        eprintln!("    threads: {}", self.threads);
+        match self.preemption_bound {
+            Some(bound) => eprintln!(
+                "preemptions: up to {} (bounded to {})",
+                self.max_preemptions, bound
+            ),
+            None => eprintln!("preemptions: up to {}", self.max_preemptions),
+        }
+
+        let note = if self.progress < 0.05 {
+            "the exploration had barely started when this checkpoint was written"
+        } else if self.progress > 0.95 {
+            "the exploration was nearly complete when this checkpoint was written"
+        } else {
+            "the exploration was partway through when this checkpoint was written"
+        };
+        eprintln!(
+            "\n{} {}; loom doesn't record how many iterations ran, so this is \
+            estimated from the choices made at each branch point",
+            bold(&"note:"),
+            note,
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Explaining which environment variables are set for each phase of a run.
+
+use crate::{
+    config::Config, loom_env::LoomEnv, trace::ColorMode, App, ENV_CHECKPOINT_INTERVAL,
+    ENV_LOOM_LOG, ENV_MAX_BRANCHES, ENV_MAX_DURATION, ENV_MAX_PERMUTATIONS, ENV_MAX_PREEMPTIONS,
+    ENV_MAX_THREADS, ENV_RUST_BACKTRACE,
+};
+use camino::Utf8PathBuf;
+use color_eyre::{eyre::WrapErr, Result};
+use owo_colors::{OwoColorize, Style};
+use serde::Serialize;
+use std::{collections::BTreeMap, process::Command};
+
+/// An environment variable set for test processes.
+#[derive(Debug, Serialize)]
+struct EnvVar {
+    name: String,
+    /// The command-line flag that controls the variable's value, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flag: Option<&'static str>,
+    /// The variable's value in each phase that sets it.
+    values: BTreeMap<&'static str, String>,
+}
+
+impl App {
+    /// Prints the environment variables that are set for test processes in
+    /// each phase of a run, and the flags that control them.
+    pub(crate) fn explain_env(&self) -> Result<()> {
+        let settings = self.settings(&Config::load_root(&self.metadata)?);
+        let checkpoint = Utf8PathBuf::from("<checkpoint dir>/<test>.json");
+        let mut phases = Vec::new();
+
+        let mut discovery = Command::new("test");
+        self.configure_discovery_command(&mut discovery, &settings);
+        phases.push(("discovery", discovery));
+
+        let mut checkpointing = Command::new("test");
+        self.configure_checkpoint_command(&mut checkpointing, &settings, &checkpoint);
+        let mut rerun = Command::new("test");
+        self.configure_checkpoint_command(&mut rerun, &settings, &checkpoint)
+            .envs(self.rerun_env(&settings.loom_log));
+        phases.push(("checkpoint", checkpointing));
+        phases.push(("rerun", rerun));
+
+        if self.args.loom.escalate_on_pass {
+            let mut escalate = Command::new("test");
+            self.configure_escalate_command(&mut escalate, &settings, settings.max_threads);
+            phases.push(("escalate", escalate));
+        }
+
+        let mut vars: Vec<EnvVar> = Vec::new();
+        for (phase, cmd) in &phases {
+            for (name, value) in cmd.get_envs() {
+                let (name, value) = match value {
+                    Some(value) => (name.to_string_lossy(), value.to_string_lossy()),
+                    None => continue,
+                };
+                let idx = match vars.iter().position(|var| var.name == name) {
+                    Some(idx) => idx,
+                    None => {
+                        vars.push(EnvVar {
+                            name: name.clone().into_owned(),
+                            flag: if LoomEnv::sets(&self.args.loom.loom_env, &name) {
+                                Some("--loom-env")
+                            } else {
+                                flag(&name)
+                            },
+                            values: BTreeMap::new(),
+                        });
+                        vars.len() - 1
+                    }
+                };
+                vars[idx].values.insert(phase, value.into_owned());
+            }
+        }
+
+        if self.args.trace_settings.message_format().is_json() {
+            serde_json::to_writer(std::io::stderr(), &vars).context("write json message")?;
+            return Ok(());
+        }
+
+        let phases = phases.iter().map(|(phase, _)| *phase).collect::<Vec<_>>();
+        print_table(&phases, &vars, self.args.trace_settings.color());
+        Ok(())
+    }
+}
+
+/// Returns the flag that sets the environment variable `name`.
+fn flag(name: &str) -> Option<&'static str> {
+    match name {
+        ENV_MAX_BRANCHES => Some("--max-branches"),
+        ENV_MAX_PERMUTATIONS => Some("--max-permutations"),
+        ENV_MAX_PREEMPTIONS => Some("--max-preemptions"),
+        ENV_MAX_THREADS => Some("--max-threads"),
+        ENV_MAX_DURATION => Some("--max-duration-secs"),
+        ENV_CHECKPOINT_INTERVAL => Some("--checkpoint-interval"),
+        ENV_LOOM_LOG => Some("--loom-log"),
+        ENV_RUST_BACKTRACE => Some("--backtrace-style"),
+        _ => None,
+    }
+}
+
+fn print_table(phases: &[&str], vars: &[EnvVar], color: ColorMode) {
+    let bold = color.if_color(Style::new().bold());
+    let mut rows = vec![{
+        let mut header = vec!["VARIABLE".to_owned()];
+        header.extend(phases.iter().map(|phase| phase.to_uppercase()));
+        header.push("FLAG".to_owned());
+        header
+    }];
+    for var in vars {
+        let mut row = vec![var.name.clone()];
+        row.extend(phases.iter().map(|phase| {
+            var.values
+                .get(phase)
+                .cloned()
+                .unwrap_or_else(|| "-".to_owned())
+        }));
+        row.push(var.flag.unwrap_or("").to_owned());
+        rows.push(row);
+    }
+
+    let mut widths = vec![0; phases.len() + 2];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for (i, row) in rows.iter().enumerate() {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        let line = line.trim_end();
+        if i == 0 {
+            eprintln!("{}", line.style(bold));
+        } else {
+            eprintln!("{}", line);
+        }
+    }
+
+    eprintln!(
+        "\n{} variables shown as `-` aren't set by cargo-loom in that phase, so \
+        tests inherit them from cargo-loom's own environment; durations \
+        (`{}`) only limit discovery, so that reruns with logging aren't cut \
+        short. Values are shown for the workspace; packages may set their own \
+        bounds in `[package.metadata.loom]`, `{}` may be overridden there for \
+        individual tests, and `{}` with `--override`",
+        "note:".style(bold),
+        ENV_MAX_DURATION,
+        ENV_LOOM_LOG,
+        ENV_MAX_THREADS,
+    );
+}
@@ -0,0 +1,243 @@
+use cargo_metadata::{CargoOpt, MetadataCommand, Package};
+use color_eyre::{eyre::eyre, Help, Result};
+use std::{fmt, process::Command};
+
+/// The features requested on the command line, split by the package they
+/// apply to.
+///
+/// Features may be given either as a bare feature name, which is enabled for
+/// every selected package that declares it, or using the `pkg-name/feature`
+/// syntax, which applies only to the named package (or enables a feature of a
+/// dependency with that name).
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FeatureSelection {
+    all_features: bool,
+    no_default_features: bool,
+    unqualified: Vec<String>,
+    qualified: Vec<(String, String)>,
+}
+
+/// The most features `--feature-matrix` builds every combination of, when it's
+/// given no values. Each combination is a separate build, so the number of
+/// builds doubles with each feature.
+const MAX_POWERSET_FEATURES: usize = 10;
+
+impl FeatureSelection {
+    pub(crate) fn new(features: &clap_cargo::Features) -> Self {
+        let mut selection = Self {
+            all_features: features.all_features,
+            no_default_features: features.no_default_features,
+            ..Self::default()
+        };
+        selection.add_features(&features.features);
+        selection
+    }
+
+    /// Returns one selection for each entry in a `--feature-matrix`.
+    ///
+    /// Each entry is a list of features which is added to the features in
+    /// this selection. If there are no entries, the matrix is instead the
+    /// powerset of the features in this selection, which is an error if there
+    /// are more than [`MAX_POWERSET_FEATURES`] of them.
+    pub(crate) fn matrix(&self, entries: &[String]) -> Result<Vec<Self>> {
+        let base = Self {
+            unqualified: Vec::new(),
+            qualified: Vec::new(),
+            ..self.clone()
+        };
+
+        if !entries.is_empty() {
+            return Ok(entries
+                .iter()
+                .map(|entry| {
+                    let mut selection = self.clone();
+                    selection.add_features(std::slice::from_ref(entry));
+                    selection
+                })
+                .collect());
+        }
+
+        let features = self
+            .unqualified
+            .iter()
+            .cloned()
+            .chain(
+                self.qualified
+                    .iter()
+                    .map(|(pkg, feature)| format!("{pkg}/{feature}")),
+            )
+            .collect::<Vec<_>>();
+        if features.len() > MAX_POWERSET_FEATURES {
+            return Err(eyre!(
+                "`--feature-matrix` with no values would build every combination of the {} \
+                features passed with `--features` (2^{} builds)",
+                features.len(),
+                features.len(),
+            ))
+            .note(format!(
+                "every combination is only built for up to {} features",
+                MAX_POWERSET_FEATURES
+            ))
+            .suggestion(
+                "list the combinations to build instead, e.g. \
+                `--feature-matrix=foo --feature-matrix=foo,bar`",
+            );
+        }
+        Ok((0..1usize << features.len())
+            .map(|mask| {
+                let mut selection = base.clone();
+                let features = features
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| mask & (1 << i) != 0)
+                    .map(|(_, feature)| feature.clone())
+                    .collect::<Vec<_>>();
+                selection.add_features(&features);
+                selection
+            })
+            .collect())
+    }
+
+    fn add_features(&mut self, features: &[String]) {
+        let features = features
+            .iter()
+            .flat_map(|features| features.split(|c: char| c == ',' || c.is_whitespace()))
+            .filter(|feature| !feature.is_empty());
+        for feature in features {
+            match feature.split_once('/') {
+                Some((pkg, feature)) => self.qualified.push((pkg.to_owned(), feature.to_owned())),
+                None => self.unqualified.push(feature.to_owned()),
+            }
+        }
+    }
+
+    /// Forwards these features to `cargo metadata`.
+    ///
+    /// Only package-qualified features are forwarded: in a workspace with
+    /// several members, cargo can't tell which package a bare feature name
+    /// refers to. Bare features are instead matched against each package by
+    /// [`FeatureSelection::validate`] and [`FeatureSelection::configure`].
+    pub(crate) fn forward_metadata<'cmd>(
+        &self,
+        cmd: &'cmd mut MetadataCommand,
+    ) -> &'cmd mut MetadataCommand {
+        if self.all_features {
+            cmd.features(CargoOpt::AllFeatures);
+        }
+        if self.no_default_features {
+            cmd.features(CargoOpt::NoDefaultFeatures);
+        }
+        if !self.qualified.is_empty() {
+            let features = self
+                .qualified
+                .iter()
+                .map(|(pkg, feature)| format!("{pkg}/{feature}"))
+                .collect();
+            cmd.features(CargoOpt::SomeFeatures(features));
+        }
+        cmd
+    }
+
+    /// Checks that every bare feature name is declared by at least one of the
+    /// selected packages.
+    pub(crate) fn validate(&self, packages: &[&Package]) -> Result<()> {
+        for feature in &self.unqualified {
+            if !packages.iter().any(|pkg| declares(pkg, feature)) {
+                return Err(eyre!(
+                    "none of the selected packages contain the feature `{}`",
+                    feature
+                ))
+                .suggestion("use `pkg-name/feature` to enable a feature of a specific package");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds the features that apply to `pkg` to a `cargo test` command for
+    /// that package.
+    pub(crate) fn configure<'cmd>(
+        &self,
+        pkg: &Package,
+        cmd: &'cmd mut Command,
+    ) -> &'cmd mut Command {
+        if self.all_features {
+            cmd.arg("--all-features");
+        }
+
+        if self.no_default_features {
+            cmd.arg("--no-default-features");
+        }
+
+        let features = self.for_package(pkg);
+        if !features.is_empty() {
+            cmd.arg("--features").arg(features.join(","));
+        }
+
+        cmd
+    }
+
+    fn for_package(&self, pkg: &Package) -> Vec<String> {
+        let unqualified = self.unqualified.iter().filter(|feature| {
+            let declared = declares(pkg, feature);
+            if !declared {
+                tracing::debug!(package = %pkg.name, %feature, "Package does not declare feature");
+            }
+            declared
+        });
+        let unqualified = unqualified.cloned();
+
+        let qualified = self.qualified.iter().filter_map(|(name, feature)| {
+            if name == &pkg.name {
+                Some(feature.clone())
+            } else if pkg
+                .dependencies
+                .iter()
+                .any(|dep| dep.rename.as_ref().unwrap_or(&dep.name) == name)
+            {
+                Some(format!("{name}/{feature}"))
+            } else {
+                // This feature belongs to some other package.
+                None
+            }
+        });
+
+        unqualified.chain(qualified).collect()
+    }
+}
+
+impl fmt::Display for FeatureSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut wrote_any = false;
+        let mut write = |f: &mut fmt::Formatter<'_>, s: &dyn fmt::Display| {
+            if wrote_any {
+                f.write_str(",")?;
+            }
+            wrote_any = true;
+            s.fmt(f)
+        };
+
+        if self.all_features {
+            write(f, &"all features")?;
+        }
+        if self.no_default_features {
+            write(f, &"no default features")?;
+        }
+        for feature in &self.unqualified {
+            write(f, feature)?;
+        }
+        for (pkg, feature) in &self.qualified {
+            write(f, &format_args!("{pkg}/{feature}"))?;
+        }
+
+        if !wrote_any {
+            f.write_str("default features")?;
+        }
+
+        Ok(())
+    }
+}
+
+fn declares(pkg: &Package, feature: &str) -> bool {
+    pkg.features.contains_key(feature)
+}
@@ -0,0 +1,345 @@
+//! Recording the result of every test in each run, to see how a test's
+//! results change over time.
+//!
+//! Each test that finishes when finding failing tests is appended to
+//! `target/loom/history.jsonl` as a [`Record`], along with the bounds it was
+//! run with and the commit the workspace was at (if it's a git repository).
+
+use crate::{deepen::Bounds, settings::Settings, trace::ColorMode, App};
+use camino::Utf8PathBuf;
+use color_eyre::{eyre::WrapErr, Result};
+use owo_colors::{OwoColorize, Style};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{self, BufRead, Write},
+    process::{Command, Stdio},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, clap::Args)]
+pub(crate) struct HistoryArgs {
+    #[clap(subcommand)]
+    command: HistoryCommand,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum HistoryCommand {
+    /// Print a test's recent results, oldest first.
+    Show {
+        /// The name of the test, with or without the name of its suite
+        test: String,
+
+        /// How many of the test's most recent results to print
+        #[clap(long, short = 'n', default_value_t = 20)]
+        limit: usize,
+    },
+}
+
+/// The result of a single test in a run.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct Entry {
+    pub(crate) suite: String,
+    pub(crate) name: String,
+    pub(crate) outcome: Outcome,
+    /// How long the test took, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) duration: Option<f64>,
+    /// How many iterations the test's models explored, if they were logged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) iterations: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Outcome {
+    Passed,
+    /// The test passed, but reached `--max-permutations`.
+    PartiallyExplored,
+    Failed,
+    /// The test failed, but passed when it was retried with `--retries`.
+    Flaky,
+}
+
+/// A test whose most recent result was only partially explored, to widen
+/// the bounds of with `--deepen`.
+#[derive(Debug)]
+pub(crate) struct Inconclusive {
+    pub(crate) package: String,
+    pub(crate) features: Option<String>,
+    pub(crate) suite: String,
+    pub(crate) name: String,
+    /// The bounds the test was run with.
+    pub(crate) bounds: Bounds,
+}
+
+/// A line of the history file.
+#[derive(Debug, Deserialize, Serialize)]
+struct Record {
+    /// When the result was recorded, in seconds since the Unix epoch.
+    time: u64,
+    /// The commit the workspace was at, if it's a git repository.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    commit: Option<String>,
+    package: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    features: Option<String>,
+    #[serde(flatten)]
+    entry: Entry,
+    max_threads: usize,
+    max_branches: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_preemptions: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_permutations: Option<usize>,
+}
+
+impl App {
+    fn history_file(&self) -> Utf8PathBuf {
+        self.target_dir.join("history.jsonl")
+    }
+
+    /// Appends the results of the tests in `package` (tested with the
+    /// feature set `features`, when running a feature matrix) to the
+    /// history file.
+    pub(crate) fn record_history(
+        &self,
+        package: &str,
+        features: Option<&str>,
+        settings: &Settings,
+        entries: Vec<Entry>,
+    ) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let path = self.history_file();
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let commit = self.git_commit();
+        let mut out = Vec::new();
+        for entry in entries {
+            let record = Record {
+                time,
+                commit: commit.clone(),
+                package: package.to_owned(),
+                features: features.map(ToOwned::to_owned),
+                max_threads: self.max_threads(settings, &entry.name),
+                max_branches: settings.max_branches,
+                max_preemptions: settings.max_preemptions,
+                max_permutations: settings.max_permutations,
+                entry,
+            };
+            serde_json::to_writer(&mut out, &record).context("serializing history record")?;
+            out.push(b'\n');
+        }
+        // Write every record at once, so that an interrupted run doesn't
+        // leave half a line behind.
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(&out))
+            .with_context(|| format!("writing history file `{}`", path))
+    }
+
+    /// Returns the commit the workspace is at, if it's in a git repository.
+    fn git_commit(&self) -> Option<String> {
+        let output = Command::new("git")
+            .arg("rev-parse")
+            .arg("HEAD")
+            .current_dir(&self.metadata.workspace_root)
+            .stderr(Stdio::null())
+            .output()
+            .ok()
+            .filter(|output| output.status.success())?;
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    }
+
+    /// Runs a `history` subcommand.
+    pub(crate) fn history(&self, args: &HistoryArgs) -> Result<()> {
+        match args.command {
+            HistoryCommand::Show { ref test, limit } => self.history_show(test, limit),
+        }
+    }
+
+    /// Returns every record in the history file, oldest first, or `None` if
+    /// no history has been recorded yet.
+    fn history_records(&self) -> Result<Option<Vec<Record>>> {
+        let path = self.history_file();
+        let file = match fs::File::open(&path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => {
+                return Err(error).with_context(|| format!("opening history file `{}`", path))
+            }
+        };
+
+        let mut records = Vec::new();
+        for line in io::BufReader::new(file).lines() {
+            let line = line.with_context(|| format!("reading history file `{}`", path))?;
+            match serde_json::from_str::<Record>(&line) {
+                Ok(record) => records.push(record),
+                Err(error) => tracing::debug!(%error, "skipping invalid history record"),
+            }
+        }
+        Ok(Some(records))
+    }
+
+    /// Returns the tests whose most recent result was
+    /// [`Outcome::PartiallyExplored`], with the bounds they were run with.
+    pub(crate) fn inconclusive_tests(&self) -> Result<Vec<Inconclusive>> {
+        let records = match self.history_records()? {
+            Some(records) => records,
+            None => {
+                tracing::warn!("no history has been recorded yet");
+                return Ok(Vec::new());
+            }
+        };
+        let mut latest = Vec::<Record>::new();
+        for record in records {
+            let same_test = |other: &Record| {
+                other.package == record.package
+                    && other.features == record.features
+                    && other.entry.suite == record.entry.suite
+                    && other.entry.name == record.entry.name
+            };
+            match latest.iter_mut().find(|other| same_test(other)) {
+                Some(other) => *other = record,
+                None => latest.push(record),
+            }
+        }
+        Ok(latest
+            .into_iter()
+            .filter(|record| record.entry.outcome == Outcome::PartiallyExplored)
+            .map(|record| Inconclusive {
+                bounds: Bounds {
+                    max_threads: record.max_threads,
+                    max_branches: record.max_branches,
+                    max_preemptions: record.max_preemptions,
+                    max_permutations: record.max_permutations,
+                },
+                package: record.package,
+                features: record.features,
+                suite: record.entry.suite,
+                name: record.entry.name,
+            })
+            .collect())
+    }
+
+    fn history_show(&self, test: &str, limit: usize) -> Result<()> {
+        let records = match self.history_records()? {
+            Some(records) => records,
+            None => {
+                tracing::warn!("no history has been recorded yet");
+                return Ok(());
+            }
+        };
+        let records = records
+            .into_iter()
+            .filter(|record| {
+                let full_name = format!("{}::{}", record.entry.suite, record.entry.name);
+                record.entry.name == test || full_name == test
+            })
+            .collect::<Vec<_>>();
+        if records.is_empty() {
+            tracing::warn!("no history has been recorded for `{}`", test);
+            return Ok(());
+        }
+        let records = &records[records.len().saturating_sub(limit)..];
+
+        if self.args.trace_settings.message_format().is_json() {
+            serde_json::to_writer(io::stderr(), records).context("write json message")?;
+            return Ok(());
+        }
+        print_records(records, self.args.trace_settings.color());
+        Ok(())
+    }
+}
+
+fn print_records(records: &[Record], color: ColorMode) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let mut rows = vec![[
+        "WHEN",
+        "COMMIT",
+        "OUTCOME",
+        "DURATION",
+        "ITERATIONS",
+        "BOUNDS",
+    ]
+    .map(ToOwned::to_owned)];
+    for record in records {
+        let entry = &record.entry;
+        let mut bounds = format!(
+            "threads={} branches={}",
+            record.max_threads, record.max_branches
+        );
+        if let Some(preemptions) = record.max_preemptions {
+            bounds.push_str(&format!(" preemptions={}", preemptions));
+        }
+        if let Some(permutations) = record.max_permutations {
+            bounds.push_str(&format!(" permutations={}", permutations));
+        }
+        rows.push([
+            age(Duration::from_secs(now.saturating_sub(record.time))),
+            record
+                .commit
+                .as_deref()
+                .map_or("-", |commit| &commit[..commit.len().min(10)])
+                .to_owned(),
+            match entry.outcome {
+                Outcome::Passed => "passed",
+                Outcome::PartiallyExplored => "partially explored",
+                Outcome::Failed => "failed",
+                Outcome::Flaky => "flaky",
+            }
+            .to_owned(),
+            entry
+                .duration
+                .map_or_else(|| "-".to_owned(), |secs| format!("{:.1}s", secs)),
+            entry
+                .iterations
+                .map_or_else(|| "-".to_owned(), |iterations| iterations.to_string()),
+            bounds,
+        ]);
+    }
+
+    let mut widths = [0; 6];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for (i, row) in rows.iter().enumerate() {
+        let line = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{:width$}", cell, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        let line = line.trim_end();
+        if i == 0 {
+            eprintln!("{}", line.style(color.if_color(Style::new().bold())));
+        } else if records[i - 1].entry.outcome == Outcome::Failed {
+            eprintln!("{}", line.style(color.if_color(Style::new().red())));
+        } else if records[i - 1].entry.outcome == Outcome::Flaky {
+            eprintln!("{}", line.style(color.if_color(Style::new().yellow())));
+        } else {
+            eprintln!("{}", line);
+        }
+    }
+}
+
+/// Formats how long ago something happened, e.g. `3h ago`.
+fn age(age: Duration) -> String {
+    let secs = age.as_secs();
+    match secs {
+        0..=59 => format!("{}s ago", secs),
+        60..=3_599 => format!("{}m ago", secs / 60),
+        3_600..=86_399 => format!("{}h ago", secs / 3_600),
+        _ => format!("{}d ago", secs / 86_400),
+    }
+}
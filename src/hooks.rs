@@ -0,0 +1,142 @@
+//! Running user-provided commands at points during a run.
+
+use crate::summary::FailureSummary;
+use camino::Utf8Path;
+use color_eyre::{eyre::WrapErr, Result};
+use serde::Deserialize;
+use std::{error::Error, fmt, process::ExitStatus};
+use tokio::process::Command;
+
+/// A `pre-run` or `post-run` hook from the configuration.
+///
+/// Hooks may be configured as just a command, or as a table with a `command`
+/// and whether the hook is `required` (the default).
+#[derive(Debug, Deserialize)]
+#[serde(from = "HookConfig")]
+pub(crate) struct Hook {
+    command: String,
+    required: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum HookConfig {
+    Command(String),
+    Table {
+        command: String,
+        #[serde(default = "required_by_default")]
+        required: bool,
+    },
+}
+
+/// Returned when a required hook fails.
+#[derive(Debug)]
+pub(crate) struct HookFailed {
+    name: &'static str,
+    command: String,
+    status: ExitStatus,
+}
+
+impl Hook {
+    /// Runs this hook, with `env` set, returning an error if it fails and is
+    /// required.
+    pub(crate) async fn run(&self, name: &'static str, env: &[(&str, String)]) -> Result<()> {
+        tracing::info!(command = %self.command, "Running {} hook", name);
+        let mut cmd = shell(&self.command);
+        cmd.envs(env.iter().map(|(var, value)| (var, value)));
+        let status = cmd
+            .status()
+            .await
+            .with_context(|| format!("running {} hook `{}`", name, self.command))?;
+        if status.success() {
+            return Ok(());
+        }
+
+        if self.required {
+            return Err(HookFailed {
+                name,
+                command: self.command.clone(),
+                status,
+            }
+            .into());
+        }
+
+        tracing::warn!(%status, "{} hook `{}` failed", name, self.command);
+        Ok(())
+    }
+}
+
+impl From<HookConfig> for Hook {
+    fn from(config: HookConfig) -> Self {
+        match config {
+            HookConfig::Command(command) => Self {
+                command,
+                required: true,
+            },
+            HookConfig::Table { command, required } => Self { command, required },
+        }
+    }
+}
+
+fn required_by_default() -> bool {
+    true
+}
+
+impl fmt::Display for HookFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} hook `{}` failed ({})",
+            self.name, self.command, self.status
+        )
+    }
+}
+
+impl Error for HookFailed {}
+
+/// Returns a command that runs `command` with the system's shell.
+pub(crate) fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    }
+}
+
+/// Runs the `--on-failure` hook `command` for a failing test, whose output
+/// was written to `log`.
+///
+/// A hook that fails is reported, but doesn't stop the run.
+pub(crate) async fn on_failure(
+    command: &str,
+    package: &str,
+    failure: &FailureSummary,
+    log: &Utf8Path,
+) -> Result<()> {
+    let name = format!("{}::{}", failure.suite, failure.name);
+    tracing::debug!(test = %name, command, "Running on-failure hook");
+    let mut cmd = shell(command);
+    cmd.env("CARGO_LOOM_PACKAGE", package)
+        .env("CARGO_LOOM_SUITE", &failure.suite)
+        .env("CARGO_LOOM_TEST", &failure.name)
+        .env("CARGO_LOOM_CHECKPOINT", &failure.checkpoint)
+        .env("CARGO_LOOM_LOG", log)
+        .env("CARGO_LOOM_CLASSIFICATION", failure.classification.as_str());
+    if let Some(ref location) = failure.location {
+        cmd.env("CARGO_LOOM_PANIC_FILE", &location.file)
+            .env("CARGO_LOOM_PANIC_LINE", location.line.to_string())
+            .env("CARGO_LOOM_PANIC_COLUMN", location.column.to_string());
+    }
+    let status = cmd
+        .status()
+        .await
+        .with_context(|| format!("running on-failure hook `{}`", command))?;
+    if !status.success() {
+        tracing::warn!(test = %name, %status, "on-failure hook `{}` failed", command);
+    }
+    Ok(())
+}
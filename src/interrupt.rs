@@ -0,0 +1,234 @@
+//! Saving the progress of a run when it's interrupted, and resuming it with
+//! `--resume`.
+//!
+//! While failing tests are found, the test suites that have finished (and
+//! what was found in them) are recorded as [`Progress`]. If the run is
+//! interrupted, that's written to `target/loom/interrupted.json` (and
+//! summarized) before the run's test processes are killed, so the work done
+//! so far isn't lost. `--resume` loads it again, and doesn't run the suites
+//! which had finished, as long as they haven't been rebuilt since.
+
+use crate::{last_run::RunSettings, summary::Counts, App};
+use camino::Utf8PathBuf;
+use color_eyre::{eyre::WrapErr, Result};
+use serde::{Deserialize, Serialize};
+use std::{fs, io};
+
+/// What a run has done so far.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub(crate) struct Progress {
+    /// The settings the run used, since a run can only be resumed with the
+    /// same ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    settings: Option<RunSettings>,
+    /// The run's test name filter.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    filter: Option<String>,
+    packages: Vec<PackageProgress>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct PackageProgress {
+    name: String,
+    /// The feature set the package was tested with, when running a feature
+    /// matrix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    features: Option<String>,
+    /// The test suites which have finished running.
+    finished_suites: Vec<FinishedSuite>,
+}
+
+/// A test suite which finished running.
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct FinishedSuite {
+    /// The file name of the test binary (which is also the name of its
+    /// checkpoint directory).
+    pub(crate) binary: String,
+    pub(crate) suite: String,
+    pub(crate) counts: Counts,
+    /// The failing tests found in the suite.
+    pub(crate) failed: Vec<FailedTest>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct FailedTest {
+    pub(crate) name: String,
+    /// The message the test panicked with, if it was found to fail in this
+    /// run.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) panic: Option<String>,
+}
+
+impl Progress {
+    /// Records that `suite` in `package` has finished running.
+    pub(crate) fn suite_finished(
+        &mut self,
+        package: &str,
+        features: Option<&str>,
+        suite: FinishedSuite,
+    ) {
+        let idx = match self
+            .packages
+            .iter()
+            .position(|pkg| pkg.name == package && pkg.features.as_deref() == features)
+        {
+            Some(idx) => idx,
+            None => {
+                self.packages.push(PackageProgress {
+                    name: package.to_owned(),
+                    features: features.map(ToOwned::to_owned),
+                    finished_suites: Vec::new(),
+                });
+                self.packages.len() - 1
+            }
+        };
+        let suites = &mut self.packages[idx].finished_suites;
+        // A resumed suite which was rebuilt is run again.
+        suites.retain(|finished| finished.binary != suite.binary);
+        suites.push(suite);
+    }
+
+    /// Returns the test binary `binary` in `package`, if it had finished.
+    pub(crate) fn finished_suite(
+        &self,
+        package: &str,
+        features: Option<&str>,
+        binary: &str,
+    ) -> Option<&FinishedSuite> {
+        self.packages
+            .iter()
+            .find(|pkg| pkg.name == package && pkg.features.as_deref() == features)?
+            .finished_suites
+            .iter()
+            .find(|suite| suite.binary == binary)
+    }
+}
+
+impl App {
+    fn progress_file(&self) -> Utf8PathBuf {
+        self.target_dir.join("interrupted.json")
+    }
+
+    /// Loads the progress of an interrupted run, for `--resume`, if it was
+    /// saved with the same settings as this run.
+    pub(crate) fn load_progress(&self) -> Result<Option<Progress>> {
+        let path = self.progress_file();
+        let json = match fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                tracing::warn!("there is no interrupted run to resume");
+                return Ok(None);
+            }
+            Err(error) => {
+                return Err(error).with_context(|| format!("reading progress file `{}`", path))
+            }
+        };
+        let progress = serde_json::from_str::<Progress>(&json)
+            .with_context(|| format!("parsing progress file `{}`", path))?;
+        if progress.settings.as_ref() != Some(&self.run_settings()?)
+            || progress.filter != self.args.testname
+        {
+            tracing::warn!(
+                "the interrupted run can't be resumed, since it used different settings or \
+                test name filters"
+            );
+            return Ok(None);
+        }
+        let suites = progress
+            .packages
+            .iter()
+            .map(|pkg| pkg.finished_suites.len())
+            .sum::<usize>();
+        tracing::info!(
+            "Resuming an interrupted run, which finished {} test suites",
+            suites
+        );
+        Ok(Some(progress))
+    }
+
+    /// Starts recording the progress of a new run, or of the resumed run
+    /// `resumed`.
+    pub(crate) fn reset_progress(&self, resumed: Option<Progress>) -> Result<()> {
+        let progress = match resumed {
+            Some(progress) => progress,
+            None => Progress {
+                settings: Some(self.run_settings()?),
+                filter: self.args.testname.clone(),
+                packages: Vec::new(),
+            },
+        };
+        *self
+            .progress
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner()) = progress;
+        Ok(())
+    }
+
+    /// Removes the progress of an interrupted run, once a run has finished.
+    pub(crate) fn remove_progress(&self) -> Result<()> {
+        let path = self.progress_file();
+        match fs::remove_file(&path) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                Err(error).with_context(|| format!("removing progress file `{}`", path))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Writes the progress of an interrupted run to the progress file, and
+    /// summarizes it.
+    pub(crate) fn save_progress(&self) -> Result<()> {
+        let progress = self
+            .progress
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        if progress.packages.is_empty() {
+            return Ok(());
+        }
+
+        let path = self.progress_file();
+        let json = serde_json::to_vec_pretty(&*progress).context("serializing progress")?;
+        fs::write(&path, json).with_context(|| format!("writing progress file `{}`", path))?;
+
+        let suites = progress
+            .packages
+            .iter()
+            .map(|pkg| pkg.finished_suites.len())
+            .sum::<usize>();
+        let failed = progress
+            .packages
+            .iter()
+            .flat_map(|pkg| {
+                let package = match pkg.features {
+                    Some(ref features) => format!("{} ({})", pkg.name, features),
+                    None => pkg.name.clone(),
+                };
+                pkg.finished_suites.iter().flat_map(move |suite| {
+                    let package = package.clone();
+                    suite
+                        .failed
+                        .iter()
+                        .map(move |test| format!("{}: {}::{}", package, suite.suite, test.name))
+                })
+            })
+            .collect::<Vec<_>>();
+        if failed.is_empty() {
+            tracing::info!(
+                "Interrupted after finishing {} test suites, with no failing tests found",
+                suites
+            );
+        } else {
+            tracing::info!(
+                "Interrupted after finishing {} test suites, with {} failing tests found:\n    {}",
+                suites,
+                failed.len(),
+                failed.join("\n    ")
+            );
+        }
+        tracing::info!(
+            "Saved the run's progress to `{}`; run with `--resume` to continue it",
+            path
+        );
+        Ok(())
+    }
+}
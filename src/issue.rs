@@ -0,0 +1,305 @@
+//! Writing a ready-to-paste issue body for each distinct failure, with
+//! `--emit issue-md=DIR`.
+//!
+//! Failing tests that failed the same way (with the same classification,
+//! location, and panic message) are described by a single issue, so that
+//! one bug isn't reported once per test it breaks. Each issue includes how
+//! the failure was classified, the panic message, a command that reproduces
+//! it with plain `cargo test`, the bounds it was found with, an excerpt of
+//! loom's trace from the logged rerun, and the environment it was run in.
+
+use crate::{
+    blame::strip_ansi,
+    config::Config,
+    file_stem, log_header,
+    summary::{FailureSummary, PackageSummary, Summary},
+    App, ENV_MAX_DURATION,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::{eyre::WrapErr, Result};
+use std::{fmt::Write, fs, process::Command, str::FromStr};
+
+/// How many lines of loom's trace are included in an issue.
+const TRACE_LINES: usize = 40;
+
+/// A report to write with `--emit`.
+#[derive(Clone, Debug)]
+pub(crate) enum Emit {
+    /// An issue body for each distinct failure, in this directory.
+    IssueMd(Utf8PathBuf),
+}
+
+impl FromStr for Emit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, path) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected `KIND=PATH`, found `{}`", s))?;
+        if path.is_empty() {
+            return Err(format!("`--emit {}` needs a path", kind));
+        }
+        match kind {
+            "issue-md" => Ok(Self::IssueMd(Utf8PathBuf::from(path))),
+            _ => Err(format!("unknown kind `{}` (expected `issue-md`)", kind)),
+        }
+    }
+}
+
+impl App {
+    /// Writes the reports requested with `--emit`.
+    pub(crate) fn emit(&self, summary: &Summary) -> Result<()> {
+        for emit in &self.args.ci.emit {
+            match emit {
+                Emit::IssueMd(dir) => self.write_issues(summary, dir)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn write_issues(&self, summary: &Summary, dir: &Utf8Path) -> Result<()> {
+        fs::create_dir_all(dir).with_context(|| format!("creating issue directory `{}`", dir))?;
+        let environment = self.environment();
+        let mut written = 0;
+        for pkg in &summary.packages {
+            // Failures are grouped by how they failed, in the order they
+            // were found.
+            let mut groups = Vec::<Vec<&FailureSummary>>::new();
+            for failure in &pkg.failures {
+                match groups
+                    .iter_mut()
+                    .find(|group| same_failure(group[0], failure))
+                {
+                    Some(group) => group.push(failure),
+                    None => groups.push(vec![failure]),
+                }
+            }
+            for group in groups {
+                let body = self.issue(pkg, &group, &environment)?;
+                let failure = group[0];
+                let package = match pkg.features {
+                    Some(ref features) => format!("{}[{}]", pkg.name, features),
+                    None => pkg.name.clone(),
+                };
+                let path = dir.join(format!(
+                    "{}-{}-{}.md",
+                    package,
+                    failure.suite,
+                    file_stem(&failure.name)
+                ));
+                fs::write(&path, body).with_context(|| format!("writing issue `{}`", path))?;
+                written += 1;
+            }
+        }
+        tracing::info!("Wrote {} issues to {}", written, dir);
+        Ok(())
+    }
+
+    /// Returns the issue body for `failures` in `pkg`, which all failed the
+    /// same way.
+    fn issue(
+        &self,
+        pkg: &PackageSummary,
+        failures: &[&FailureSummary],
+        environment: &str,
+    ) -> Result<String> {
+        let failure = failures[0];
+        let package = self.workspace_package(&pkg.name)?;
+        let settings = self.settings(&Config::load(&self.metadata, package)?);
+        let test = format!("{}::{}", failure.suite, failure.name);
+
+        let mut issue = String::new();
+        let how = match failure.location {
+            Some(ref location) => format!("{} at `{}`", failure.classification.as_str(), location),
+            None => failure.classification.as_str().to_owned(),
+        };
+        let _ = writeln!(issue, "<!-- title: loom: `{}` {} -->\n", test, how);
+        issue.push_str("## Summary\n\n");
+        if failures.len() == 1 {
+            let _ = writeln!(issue, "The loom test `{}` fails ({}).", test, how);
+        } else {
+            let _ = writeln!(
+                issue,
+                "{} loom tests fail the same way ({}):\n",
+                failures.len(),
+                how
+            );
+            for failure in failures {
+                let _ = writeln!(issue, "- `{}::{}`", failure.suite, failure.name);
+            }
+        }
+        let _ = writeln!(
+            issue,
+            "\n- **Classification:** {}",
+            failure.classification.as_str()
+        );
+        let _ = writeln!(issue, "- **Package:** `{}`", pkg.name);
+        if let Some(ref features) = pkg.features {
+            let _ = writeln!(issue, "- **Features:** {}", features);
+        }
+
+        if let Some(ref message) = failure.message {
+            let _ = writeln!(issue, "\n## Panic\n\n```text\n{}\n```", message.trim_end());
+        }
+
+        // Plain `cargo test` reproduces the failure, without `cargo loom`.
+        let mut cmd = Command::new("cargo");
+        cmd.args(["test", "--release", "--package", &pkg.name]);
+        if let Some(target) = package
+            .targets
+            .iter()
+            .find(|target| target.name == failure.suite)
+        {
+            if target.kind.iter().any(|kind| kind == "lib") {
+                cmd.arg("--lib");
+            } else if let Some(kind) = target.kind.first() {
+                cmd.arg(format!("--{}", kind)).arg(&target.name);
+            }
+        }
+        let features = pkg
+            .features
+            .as_ref()
+            .and_then(|label| {
+                self.feature_sets
+                    .iter()
+                    .find(|features| features.to_string() == *label)
+            })
+            .unwrap_or(&self.feature_sets[0]);
+        features.configure(package, &mut cmd);
+        cmd.args(["--", "--exact", &failure.name]);
+        // An ignored test is only run if it's asked for.
+        if self
+            .test_args
+            .iter()
+            .any(|arg| arg == "--ignored" || arg == "--include-ignored")
+        {
+            cmd.arg("--include-ignored");
+        }
+        // Only loom's variables are needed (and not the test binaries'
+        // arguments, which may not even run the test).
+        let mut loom = Command::new("test");
+        self.configure_loom_command(&mut loom, &settings);
+        if let Some(max_duration) = settings.max_duration_secs {
+            loom.env(ENV_MAX_DURATION, max_duration.to_string());
+        }
+        let env = loom
+            .get_envs()
+            .filter_map(|(name, value)| {
+                let name = name.to_str()?;
+                Some((name, value?.to_string_lossy()))
+            })
+            .filter(|(name, _)| name.starts_with("LOOM_"))
+            .map(|(name, value)| format!("{}={}", name, log_header::quote(&value)))
+            .collect::<Vec<_>>();
+        let args = cmd
+            .get_args()
+            .map(|arg| log_header::quote(&arg.to_string_lossy()).into_owned())
+            .collect::<Vec<_>>();
+        let _ = writeln!(
+            issue,
+            "\n## Reproduce\n\n```sh\nRUSTFLAGS={} {} cargo {}\n```",
+            log_header::quote(&self.rustflags),
+            env.join(" "),
+            args.join(" ")
+        );
+
+        issue.push_str("\n## Bounds\n\n");
+        let optional = |value: Option<usize>| {
+            value.map_or_else(|| "unset".to_owned(), |value| value.to_string())
+        };
+        let bounds = [
+            ("max threads", settings.max_threads.to_string()),
+            ("max branches", settings.max_branches.to_string()),
+            ("max preemptions", optional(settings.max_preemptions)),
+            ("max permutations", optional(settings.max_permutations)),
+            ("max duration (secs)", optional(settings.max_duration_secs)),
+            (
+                "checkpoint interval",
+                settings.checkpoint_interval.to_string(),
+            ),
+        ];
+        for (bound, value) in &bounds {
+            let _ = writeln!(issue, "- {}: {}", bound, value);
+        }
+
+        if let Some(trace) = trace_excerpt(&failure.log) {
+            let _ = writeln!(
+                issue,
+                "\n## Trace\n\nThe end of loom's trace of the failing execution \
+                (the full log is `{}`):\n\n```text\n{}\n```",
+                failure.log, trace
+            );
+        }
+
+        let _ = write!(issue, "\n## Environment\n\n{}", environment);
+        Ok(issue)
+    }
+
+    /// Describes the environment the tests were run in.
+    fn environment(&self) -> String {
+        let mut environment = String::new();
+        let _ = writeln!(environment, "- cargo-loom: {}", env!("CARGO_PKG_VERSION"));
+        let rustc = self
+            .toolchain
+            .version()
+            .unwrap_or_else(|error| format!("unknown ({})", error));
+        let _ = writeln!(environment, "- rustc: {}", rustc.trim());
+        let mut loom = self
+            .metadata
+            .packages
+            .iter()
+            .filter(|pkg| pkg.name == "loom")
+            .map(|pkg| pkg.version.to_string())
+            .collect::<Vec<_>>();
+        loom.sort();
+        loom.dedup();
+        if !loom.is_empty() {
+            let _ = writeln!(environment, "- loom: {}", loom.join(", "));
+        }
+        let _ = writeln!(
+            environment,
+            "- OS: {} ({})",
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        );
+        environment
+    }
+}
+
+/// Returns whether `a` and `b` failed the same way.
+fn same_failure(a: &FailureSummary, b: &FailureSummary) -> bool {
+    let first_line = |failure: &FailureSummary| {
+        failure
+            .message
+            .as_deref()
+            .and_then(|message| message.lines().next())
+            .map(ToOwned::to_owned)
+    };
+    a.classification == b.classification
+        && a.location.as_ref().map(ToString::to_string)
+            == b.location.as_ref().map(ToString::to_string)
+        && first_line(a) == first_line(b)
+}
+
+/// Returns the last lines of loom's trace in the log at `log`, up to where
+/// the test panicked.
+fn trace_excerpt(log: &Utf8Path) -> Option<String> {
+    let log = strip_ansi(&fs::read_to_string(log).ok()?);
+    // Skip the log's header.
+    let lines = log
+        .lines()
+        .skip_while(|line| line.starts_with("# ") || line.is_empty())
+        .collect::<Vec<_>>();
+    let end = lines
+        .iter()
+        .position(|line| line.contains(" panicked at "))
+        .unwrap_or(lines.len());
+    let start = end.saturating_sub(TRACE_LINES);
+    let trace = lines[start..end].join("\n");
+    let trace = trace.trim_end();
+    if trace.trim().is_empty() {
+        None
+    } else {
+        Some(trace.to_owned())
+    }
+}
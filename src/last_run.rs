@@ -0,0 +1,333 @@
+//! Remembering which tests failed in the last run, for `--failed`.
+//!
+//! After each run, the tests that failed in each package are written to
+//! `target/loom/last-run.json`. With `--failed`, finding failing tests is
+//! skipped, and the tests listed there are checkpointed and rerun directly.
+//!
+//! The settings each run used are recorded along with its failures, so that
+//! the next run can point out which of them changed, since that often
+//! explains why its results did.
+
+use crate::{
+    config::Config, settings::Settings, summary::Summary, App, Failed, FeatureSelection, TestFilter,
+};
+use camino::Utf8PathBuf;
+use color_eyre::{eyre::WrapErr, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, io};
+
+/// The contents of the last run file.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct LastRun {
+    /// The settings of the last run (which may not have tested every
+    /// package).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    settings: Option<RunSettings>,
+    packages: Vec<Package>,
+}
+
+/// The settings a run used which can change its results: the workspace's
+/// bounds, and how the tests were built.
+#[derive(Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub(crate) struct RunSettings {
+    max_threads: usize,
+    max_branches: usize,
+    max_preemptions: Option<usize>,
+    max_permutations: Option<usize>,
+    max_duration_secs: Option<usize>,
+    checkpoint_interval: usize,
+    features: Vec<String>,
+    rustflags: String,
+    loom_env: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    test_threads: Option<usize>,
+}
+
+/// The tests that failed in a package in the last run that tested it.
+#[derive(Debug, Deserialize, Serialize)]
+struct Package {
+    name: String,
+    /// The feature set the package was tested with, when running a feature
+    /// matrix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    features: Option<String>,
+    failed: Vec<Test>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Test {
+    suite: String,
+    name: String,
+}
+
+impl App {
+    fn last_run_file(&self) -> Utf8PathBuf {
+        self.target_dir.join("last-run.json")
+    }
+
+    fn load_last_run(&self) -> Result<Option<LastRun>> {
+        let path = self.last_run_file();
+        let json = match fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => {
+                return Err(error).with_context(|| format!("reading last run file `{}`", path))
+            }
+        };
+        serde_json::from_str(&json)
+            .with_context(|| format!("parsing last run file `{}`", path))
+            .map(Some)
+    }
+
+    pub(crate) fn run_settings(&self) -> Result<RunSettings> {
+        let settings = self.settings(&Config::load_root(&self.metadata)?);
+        Ok(RunSettings {
+            max_threads: settings.max_threads,
+            max_branches: settings.max_branches,
+            max_preemptions: settings.max_preemptions,
+            max_permutations: settings.max_permutations,
+            max_duration_secs: settings.max_duration_secs,
+            checkpoint_interval: settings.checkpoint_interval,
+            features: self.feature_sets.iter().map(ToString::to_string).collect(),
+            rustflags: self.rustflags.clone(),
+            loom_env: self
+                .args
+                .loom
+                .loom_env
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            test_threads: self.args.cargo.test_threads,
+        })
+    }
+
+    /// Logs which of the settings that can change a run's results have
+    /// changed since the last run.
+    pub(crate) fn report_drift(&self) -> Result<()> {
+        let last = match self.load_last_run()?.and_then(|last_run| last_run.settings) {
+            Some(last) => last,
+            None => return Ok(()),
+        };
+        let current = self.run_settings()?;
+        if last == current {
+            return Ok(());
+        }
+
+        let optional = |value: Option<usize>| {
+            value.map_or_else(|| "unset".to_owned(), |value| value.to_string())
+        };
+        let list = |values: &[String]| {
+            if values.is_empty() {
+                "none".to_owned()
+            } else {
+                values.join("; ")
+            }
+        };
+        let changes = [
+            (
+                "--max-threads",
+                last.max_threads.to_string(),
+                current.max_threads.to_string(),
+            ),
+            (
+                "--max-branches",
+                last.max_branches.to_string(),
+                current.max_branches.to_string(),
+            ),
+            (
+                "--max-preemptions",
+                optional(last.max_preemptions),
+                optional(current.max_preemptions),
+            ),
+            (
+                "--max-permutations",
+                optional(last.max_permutations),
+                optional(current.max_permutations),
+            ),
+            (
+                "--max-duration-secs",
+                optional(last.max_duration_secs),
+                optional(current.max_duration_secs),
+            ),
+            (
+                "--checkpoint-interval",
+                last.checkpoint_interval.to_string(),
+                current.checkpoint_interval.to_string(),
+            ),
+            ("--features", list(&last.features), list(&current.features)),
+            (
+                "RUSTFLAGS",
+                format!("`{}`", last.rustflags),
+                format!("`{}`", current.rustflags),
+            ),
+            ("--loom-env", list(&last.loom_env), list(&current.loom_env)),
+            (
+                "--test-threads",
+                optional(last.test_threads),
+                optional(current.test_threads),
+            ),
+        ];
+        for (setting, last, current) in &changes {
+            if last != current {
+                tracing::info!(
+                    "Changed `{}` from {} to {} since the last run",
+                    setting,
+                    last,
+                    current
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether `test` is part of a run with `filter`.
+    fn includes(&self, filter: TestFilter<'_>, test: &Test) -> bool {
+        self.excludes(filter, &test.suite, &test.name).is_none()
+    }
+
+    /// Records which tests failed in each package in `summary`.
+    ///
+    /// Packages that weren't tested, and tests that the run's filters
+    /// excluded, keep the failures from the run that last tested them.
+    pub(crate) fn record_last_run(&self, summary: &Summary, filter: TestFilter<'_>) -> Result<()> {
+        let mut last_run = self.load_last_run()?.unwrap_or_default();
+        last_run.settings = Some(self.run_settings()?);
+        for pkg in &summary.packages {
+            let mut failed = pkg
+                .failures
+                .iter()
+                .map(|failure| Test {
+                    suite: failure.suite.clone(),
+                    name: failure.name.clone(),
+                })
+                .collect::<Vec<_>>();
+            if let Some(idx) = last_run
+                .packages
+                .iter()
+                .position(|last| last.name == pkg.name && last.features == pkg.features)
+            {
+                let last = last_run.packages.remove(idx);
+                failed.extend(
+                    last.failed
+                        .into_iter()
+                        .filter(|test| !self.includes(filter, test)),
+                );
+            }
+            last_run.packages.push(Package {
+                name: pkg.name.clone(),
+                features: pkg.features.clone(),
+                failed,
+            });
+        }
+
+        let path = self.last_run_file();
+        let json = serde_json::to_vec_pretty(&last_run).context("serializing last run")?;
+        fs::write(&path, json).with_context(|| format!("writing last run file `{}`", path))
+    }
+
+    /// Returns the tests in `pkg` that failed in the last run, by suite, to
+    /// run first with `--failed-first`.
+    pub(crate) fn failed_first(
+        &self,
+        pkg: &cargo_metadata::Package,
+        label: Option<&str>,
+    ) -> Result<HashMap<String, Vec<String>>> {
+        let mut failed = HashMap::<String, Vec<String>>::new();
+        if !self.args.loom.failed_first {
+            return Ok(failed);
+        }
+        let last = self.load_last_run()?.and_then(|last_run| {
+            last_run
+                .packages
+                .into_iter()
+                .find(|last| last.name == pkg.name && last.features.as_deref() == label)
+        });
+        for test in last.into_iter().flat_map(|last| last.failed) {
+            failed.entry(test.suite).or_default().push(test.name);
+        }
+        if !failed.is_empty() {
+            tracing::info!(
+                "Running {} tests in `{}` which failed in the last run first",
+                failed.values().map(Vec::len).sum::<usize>(),
+                pkg.name
+            );
+        }
+        Ok(failed)
+    }
+
+    /// Returns the tests in `pkg` that failed in the last run, as though
+    /// they had just been found to fail, for `--failed`.
+    pub(crate) async fn last_failed(
+        &self,
+        pkg: &cargo_metadata::Package,
+        settings: &Settings,
+        features: &FeatureSelection,
+        label: Option<&str>,
+        filter: TestFilter<'_>,
+    ) -> Result<Failed> {
+        let mut failed = Failed::default();
+        let last_run = match self.load_last_run()? {
+            Some(last_run) => last_run,
+            None => {
+                tracing::warn!(
+                    "`--failed` was passed, but no previous run was recorded in `{}`; \
+                    run `cargo loom` without `--failed` first",
+                    self.last_run_file()
+                );
+                return Ok(failed);
+            }
+        };
+        let mut tests = last_run
+            .packages
+            .into_iter()
+            .find(|last| last.name == pkg.name && last.features.as_deref() == label)
+            .map(|last| last.failed)
+            .unwrap_or_default();
+        tests.retain(|test| self.includes(filter, test));
+        if tests.is_empty() {
+            tracing::info!("No tests in `{}` failed in the last run", pkg.name);
+            return Ok(failed);
+        }
+
+        let mut suites = self.spawn_build_tests(pkg, settings, features)?;
+        while let Some(suite) = suites.next().await {
+            let suite = suite.context("Getting next test failed")?;
+            let checkpoint_dir = self.suite_checkpoint_dir(&suite)?;
+            if let Some(ref coverage) = self.coverage {
+                coverage.add_binary(suite.path());
+            }
+            let mut found = false;
+            tests.retain(|test| {
+                if test.suite != suite.name() {
+                    return true;
+                }
+                found = true;
+                failed.fail_test(&suite, test.name.clone(), &checkpoint_dir, None);
+                false
+            });
+            if found {
+                fs::create_dir_all(&checkpoint_dir).with_context(|| {
+                    format!("failed to create checkpoint directory `{}`", checkpoint_dir)
+                })?;
+            }
+            failed.finish_suite(suite);
+        }
+
+        for test in &tests {
+            tracing::warn!(
+                "`{}::{}` failed in the last run, but it's no longer built",
+                test.suite,
+                test.name
+            );
+        }
+        let rerun = failed.failed.values().map(Vec::len).sum();
+        failed.counts.tests = rerun;
+        failed.counts.failed = rerun;
+        tracing::info!(
+            "Rerunning {} tests in `{}` which failed in the last run",
+            rerun,
+            pkg.name
+        );
+        Ok(failed)
+    }
+}
@@ -12,19 +12,83 @@ use color_eyre::{
     eyre::{eyre, WrapErr},
     Help, Result,
 };
-use escargot::{format::test, CargoTest, CommandMessages};
-use owo_colors::{colors, OwoColorize};
+use futures_util::{stream, StreamExt};
+use serde::Deserialize;
 use std::{
+    borrow::Cow,
     collections::{HashMap, HashSet},
     ffi::OsStr,
-    fmt, fs,
-    process::{Command, Output, Stdio},
-    sync::Arc,
-    time::Instant,
+    fmt, fs, io,
+    process::{Command, ExitStatus, Stdio},
+    sync::{Arc, Mutex, Once},
+    time::{Duration, Instant},
 };
 use tokio::task::JoinSet;
 
-mod trace;
+mod affinity;
+mod archive;
+mod backtrace;
+mod bench;
+mod blame;
+mod checkpoint;
+mod ci;
+mod clean_env;
+mod config;
+mod coverage;
+mod deepen;
+mod diagnostic;
+mod discovery;
+mod dry_run;
+mod escalate;
+mod explain;
+mod explain_env;
+mod features;
+mod history;
+mod hooks;
+mod interrupt;
+mod issue;
+mod last_run;
+mod libtest;
+mod lint;
+mod list;
+mod log_header;
+mod loom_env;
+mod memory;
+mod merge;
+mod model_log;
+mod overrides;
+mod prefixed;
+mod process;
+mod retry;
+mod sample;
+mod sanitizer;
+mod settings;
+mod shard;
+mod smoke;
+mod status_line;
+mod suggest;
+mod suite;
+pub mod summary;
+mod test_args;
+mod timings;
+mod title;
+mod toolchain;
+pub mod trace;
+mod watch;
+
+use ci::LogGroups;
+use config::Config;
+use diagnostic::Diagnostic;
+use discovery::Discovery;
+use features::FeatureSelection;
+use settings::Settings;
+use suite::TestSuite;
+use summary::{
+    Classification, ExplorationIncomplete, FailureKind, FailureKinds, FailureSummary,
+    PackageSummary, SlowTest, Summary, TestTime, TestsFailed,
+};
+use title::TerminalTitle;
+use toolchain::Toolchain;
 
 /// The `cargo-loom` command line application.
 ///
@@ -33,33 +97,127 @@ mod trace;
 #[derive(Debug)]
 pub struct App {
     args: AppArgs,
+    toolchain: Toolchain,
     checkpoint_dir: Utf8PathBuf,
     metadata: cargo_metadata::Metadata,
+    /// The packages tested when none are selected, if cargo reports them.
+    default_members: Option<Vec<cargo_metadata::PackageId>>,
     target_dir: Utf8PathBuf,
-    features: String,
+    feature_sets: Vec<FeatureSelection>,
+    /// Limits how many test processes run at once.
+    processes: Arc<process::Pool>,
+    /// Holds back reruns of failing tests while memory is low.
+    memory: Arc<memory::Monitor>,
     rustflags: String,
-    max_branches: String,
-    max_permutations: Option<String>,
-    max_preemptions: Option<String>,
-    max_duration: Option<String>,
-    max_threads: String,
-    checkpoint_interval: String,
-    loom_log: Arc<str>,
+    /// The `cfg` values of the target tests are built for.
+    target_cfgs: Vec<cargo_platform::Cfg>,
     test_args: Arc<Vec<String>>,
+    /// The filters in `test_args`.
+    test_filters: test_args::Filters,
+    /// The seed of the `--sample`.
+    sample_seed: u64,
+    /// The seed the tests are shuffled with, with `--shuffle`.
+    shuffle_seed: Option<u64>,
+    test_timeout: process::Timeout,
+    suite_timeout: process::Timeout,
+    checkpoint_timeout: process::Timeout,
+    /// When the current run reaches `--total-timeout`.
+    deadline: Mutex<Option<Instant>>,
+    cpus: Option<Arc<affinity::CpuPool>>,
+    artifacts_dir: Option<Utf8PathBuf>,
+    /// The log files the current run has written to the artifacts directory.
+    artifacts: Mutex<Vec<Utf8PathBuf>>,
+    summary_file: Option<Utf8PathBuf>,
+    /// Held by a package while it prints the output of its failing tests, so
+    /// that packages tested at the same time (with `--package-jobs`) don't
+    /// interleave it.
+    output: tokio::sync::Mutex<()>,
+    /// Collects the profiles of instrumented tests, with `--coverage`.
+    coverage: Option<coverage::Coverage>,
+    /// Records how long each part of the run takes, with `--timings`.
+    timings: Option<Arc<timings::Timings>>,
+    log_groups: LogGroups,
+    title: TerminalTitle,
+    /// The test binaries that have been run, so that `--watch` only reruns
+    /// suites which were rebuilt.
+    tested: Mutex<HashSet<std::path::PathBuf>>,
+    /// The suites that have finished running, and the failing tests found
+    /// in them, which are saved if the run is interrupted.
+    progress: Mutex<interrupt::Progress>,
+    /// The progress of the interrupted run to continue, with `--resume`.
+    resumed: Mutex<Option<interrupt::Progress>>,
 }
 
 #[derive(Default)]
 struct Failed {
     failed: HashMap<Arc<str>, Vec<FailedTest>>,
-    test_cmds: HashMap<Arc<str>, CargoTest>,
+    test_cmds: HashMap<Arc<str>, TestSuite>,
     checkpoint_dirs: HashSet<Utf8PathBuf>,
+    /// The checkpoint directory of each suite that was run.
+    suite_checkpoint_dirs: Vec<summary::CheckpointDir>,
     curr_suite_name: Option<Arc<str>>,
+    counts: summary::Counts,
+    /// Passing tests which stopped at a bound before exploring their whole
+    /// models.
+    partially_explored: Vec<String>,
+    /// Passing tests which explored their whole models.
+    fully_explored: Vec<String>,
+    /// Passing tests to rerun with `--escalate-on-pass`.
+    passed: Vec<(TestSuite, Vec<String>)>,
+    /// Suites which were stopped by `--suite-timeout`.
+    timed_out_suites: Vec<String>,
+    /// Tests which reached `--test-timeout` while finding failing tests.
+    timed_out_tests: Vec<String>,
+    /// Suites which weren't run, because the run reached `--total-timeout`.
+    skipped_suites: Vec<String>,
+    /// Tests which failed, but passed when they were retried with
+    /// `--retries`.
+    flaky: Vec<String>,
+    /// Tests which took at least `--warn-slow` to find failing tests.
+    slow: Vec<(String, Duration)>,
+    /// The result of each test that finished, to record in the run's
+    /// history.
+    history: Vec<history::Entry>,
 }
 
+/// The output of the logged rerun of a failing test.
 #[derive(Debug)]
-struct TestOutput {
+pub struct TestOutput {
     name: String,
-    output: Output,
+    test: String,
+    suite: String,
+    checkpoint: Utf8PathBuf,
+    /// The file the output of the logged rerun was written to.
+    log: Utf8PathBuf,
+    status: ExitStatus,
+    /// The end of the logged rerun's output.
+    stdout: process::Tail,
+    /// The signal the logged rerun was ended with, if it timed out.
+    killed_by: Option<process::Signal>,
+    /// Whether checkpointing the test was stopped by `--checkpoint-timeout`.
+    checkpoint_timed_out: bool,
+    /// How long the logged rerun took.
+    duration: Duration,
+    /// How many times the test was rerun with logging.
+    attempts: usize,
+    /// The message the test panicked with when it was found to fail.
+    discovery_panic: Option<String>,
+    /// How the test failed when it was found to fail.
+    discovery_kind: Option<FailureKind>,
+    /// Whether the test passed when it was checkpointed with `--failed`, so
+    /// it wasn't rerun with logging.
+    fixed: bool,
+}
+
+/// The logged reruns of a package's failing tests, which run concurrently.
+///
+/// Returned by [`App::reruns`].
+#[derive(Debug)]
+pub struct Reruns {
+    tasks: JoinSet<Result<Option<TestOutput>>>,
+    /// The failing tests which weren't rerun, because the run reached
+    /// `--total-timeout`.
+    skipped: Arc<Mutex<Vec<String>>>,
 }
 
 #[derive(Debug)]
@@ -67,6 +225,25 @@ struct TestOutput {
 struct FailedTest {
     name: String,
     checkpoint: Utf8PathBuf,
+    /// The message the test panicked with when it was found to fail, if it
+    /// wasn't previously checkpointed.
+    panic: Option<String>,
+    /// How the test failed when it was found to fail, from `panic`.
+    kind: Option<FailureKind>,
+}
+
+/// Tests which are run in their own processes, grouped by the
+/// `--max-threads` they're run with (`None` for the default).
+type TestGroups = Vec<(Option<usize>, Vec<String>)>;
+
+/// Which of a package's tests are run.
+#[derive(Clone, Copy, Debug)]
+enum TestFilter<'a> {
+    /// The tests whose names contain the `TESTNAME` argument, or every test
+    /// if it wasn't passed.
+    Contains(Option<&'a str>),
+    /// Only the tests with exactly these names.
+    Exact(&'a [&'a str]),
 }
 
 #[derive(Parser, Debug)]
@@ -106,14 +283,70 @@ struct AppArgs {
     #[clap(flatten)]
     trace_settings: trace::TraceSettings,
 
+    #[clap(flatten)]
+    ci: ci::CiOptions,
+
+    #[clap(subcommand)]
+    command: Option<LoomCommand>,
+
     /// If specified, only run tests containing this string in their names
     testname: Option<String>,
 
+    /// Only run the test named exactly `TESTNAME`, and only skip the tests
+    /// named exactly by `--skip`
+    #[clap(long)]
+    exact: bool,
+
+    /// Don't run tests whose names contain NAME (may be passed more than
+    /// once)
+    #[clap(long, value_name = "NAME", multiple_occurrences = true)]
+    skip: Vec<String>,
+
+    /// Only run the tests marked `#[ignore]`
+    #[clap(long, conflicts_with = "include-ignored")]
+    ignored: bool,
+
+    /// Run the tests marked `#[ignore]`, as well as the others
+    #[clap(long)]
+    include_ignored: bool,
+
+    /// Run the tests in a random order
+    ///
+    /// This finds tests that only pass (or fail) when they're run in a
+    /// particular order. The seed is logged, and passing it with
+    /// `--shuffle-seed` runs the tests in the same order again. Each test
+    /// suite's tests are shuffled by libtest, and the order the packages are
+    /// tested in is shuffled as well.
+    #[clap(long)]
+    shuffle: bool,
+
+    /// Run the tests in the random order given by SEED (implies
+    /// `--shuffle`)
+    #[clap(long, value_name = "SEED")]
+    shuffle_seed: Option<u64>,
+
     /// Arguments passed to the test binary.
     #[clap(raw = true)]
     test_args: Vec<String>,
 }
 
+#[derive(Debug, clap::Subcommand)]
+enum LoomCommand {
+    /// Measure how quickly loom explores the models of matching tests.
+    BenchModel(bench::BenchArgs),
+    /// Summarize how far the exploration of a model got before a checkpoint
+    /// was written.
+    Explain(explain::ExplainArgs),
+    /// Show the results recorded for tests in previous runs.
+    History(history::HistoryArgs),
+    /// Build the tests and list those that would be run, without running
+    /// them.
+    List(list::ListArgs),
+    /// Merge the summaries of several runs (such as the shards of a CI run)
+    /// into one summary and HTML report.
+    Merge(merge::MergeArgs),
+}
+
 /// Options that configure the underlying `cargo test` invocation.
 #[derive(Debug, clap::Args)]
 #[clap(
@@ -125,13 +358,72 @@ struct CargoOptions {
     #[clap(long, env = "CARGO_MANIFEST_PATH", value_hint = clap::ValueHint::FilePath)]
     manifest_path: Option<std::path::PathBuf>,
 
+    /// Override the Rust toolchain used to build and run tests.
+    ///
+    /// If this is not provided, the toolchain selected with `cargo
+    /// +toolchain loom` (or `RUSTUP_TOOLCHAIN`) is used, or else a
+    /// `rust-toolchain` or `rust-toolchain.toml` file in the workspace will be
+    /// honored, if one exists. Requires rustup.
+    #[clap(long, value_name = "TOOLCHAIN")]
+    toolchain: Option<String>,
+
     #[clap(flatten)]
     workspace: clap_cargo::Workspace,
 
     #[clap(flatten)]
     features: clap_cargo::Features,
 
+    /// Run the tests once for each of these feature combinations
+    ///
+    /// Each value is a comma- or space-separated list of features to enable
+    /// in addition to those passed with `--features`. This flag may be
+    /// repeated, and `--feature-matrix=` runs with no additional features. If
+    /// no values are provided, the tests are run once for every combination
+    /// of the features passed with `--features` (of which there may be at
+    /// most 10).
+    #[clap(
+        long,
+        value_name = "FEATURES",
+        min_values = 0,
+        require_equals = true,
+        multiple_occurrences = true
+    )]
+    feature_matrix: Option<Vec<String>>,
+
+    /// Number of parallel jobs, defaults to # of CPUs
+    ///
+    /// This limits both the number of jobs used to build the tests, and the
+    /// number of test processes (including reruns of failing tests) that are
+    /// run at the same time. Fewer test processes are run at once if the
+    /// limit on open files is too low for this many.
+    #[clap(long, short = 'j', value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Number of packages to test at the same time, defaults to 1
+    ///
+    /// While one package's tests run, the next package's tests can be
+    /// built. Test processes from every package still share the `--jobs`
+    /// limit. The output of each package's failing tests is printed together,
+    /// rather than interleaved with other packages'.
+    #[clap(long, value_name = "N", default_value_t = 1)]
+    package_jobs: usize,
+
+    /// Number of tests each test process runs at the same time while failing
+    /// tests are found, defaults to libtest's default (# of CPUs)
+    ///
+    /// This is passed to the test binaries as libtest's `--test-threads`,
+    /// but only when finding failing tests: failing tests are always rerun
+    /// one at a time. Loom explores each model the same way however many
+    /// tests run at once, but with `--max-duration-secs`, how far each model
+    /// gets depends on how busy the machine is.
+    #[clap(long, value_name = "N")]
+    test_threads: Option<usize>,
+
     /// Test only this package's library unit tests
+    ///
+    /// If no targets are selected (with this, `--tests`, or the other target
+    /// flags), the `lib` and `tests` settings in `[package.metadata.loom]` or
+    /// `[workspace.metadata.loom]` are used.
     #[clap(long)]
     lib: bool,
 
@@ -146,6 +438,20 @@ struct CargoOptions {
     /// Test all binaries
     #[clap(long)]
     bins: bool,
+
+    /// Test only the named integration test (may be passed more than once)
+    ///
+    /// Packages that don't have any of the named targets aren't tested.
+    #[clap(long = "test", value_name = "NAME", multiple_occurrences = true)]
+    test: Vec<String>,
+
+    /// Test only the named binary (may be passed more than once)
+    #[clap(long = "bin", value_name = "NAME", multiple_occurrences = true)]
+    bin: Vec<String>,
+
+    /// Test only the named example (may be passed more than once)
+    #[clap(long = "example", value_name = "NAME", multiple_occurrences = true)]
+    example: Vec<String>,
 }
 
 /// Options that configure Loom's behavior.
@@ -157,14 +463,22 @@ struct CargoOptions {
 struct LoomOptions {
     /// Maximum number of thread switches per permutation.
     ///
+    /// If no value is provided, the `max-branches` setting in
+    /// `[package.metadata.loom]` or `[workspace.metadata.loom]` is used, or
+    /// 1000 if neither sets it.
+    ///
     /// This sets the value of the `LOOM_MAX_BRANCHES` environment variable for
     /// the test executable.
-    #[clap(long, env = ENV_MAX_BRANCHES, default_value_t = 1_000)]
-    max_branches: usize,
+    #[clap(long, env = ENV_MAX_BRANCHES)]
+    max_branches: Option<usize>,
 
     /// Maximum number of permutations to explore
     ///
-    /// If no value is provided, the number of permutations will not be bounded.
+    /// If no value is provided, the `max-permutations` setting in
+    /// `[package.metadata.loom]` or `[workspace.metadata.loom]` is used, and
+    /// if neither sets it, the number of permutations will not be bounded.
+    /// Passing tests which reach this bound are reported as only partially
+    /// explored.
     ///
     /// This sets the value of the `LOOM_MAX_PERMUTATIONS` environment variable
     /// for the test executable.
@@ -173,7 +487,9 @@ struct LoomOptions {
 
     /// Maximum number of thread preemptions to explore
     ///
-    /// If no value is provided, the number of thread preemptions will not be
+    /// If no value is provided, the `max-preemptions` setting in
+    /// `[package.metadata.loom]` or `[workspace.metadata.loom]` is used, and
+    /// if neither sets it, the number of thread preemptions will not be
     /// bounded.
     ///
     /// This sets the value of the `LOOM_MAX_PREEMPTIONS` environment variable
@@ -183,32 +499,411 @@ struct LoomOptions {
 
     /// Max number of threads to check as part of the execution.
     ///
-    /// This should be set as low as possible and must be less than 4.
+    /// This should be set as low as possible and must be less than 4. If no
+    /// value is provided, the `max-threads` setting in
+    /// `[package.metadata.loom]` or `[workspace.metadata.loom]` is used, or 4
+    /// if neither sets it.
     ///
     /// This sets the value of the `LOOM_MAX_THREADS` environment variable for
     /// the test execution.
-    #[clap(long, env = ENV_MAX_THREADS, default_value_t = 4)]
-    max_threads: usize,
+    #[clap(long, env = ENV_MAX_THREADS)]
+    max_threads: Option<usize>,
 
     /// How often to write the checkpoint file
     ///
+    /// If no value is provided, the `checkpoint-interval` setting in
+    /// `[package.metadata.loom]` or `[workspace.metadata.loom]` is used, or 5
+    /// if neither sets it.
+    ///
     /// This sets the value of the `LOOM_CHECKPOINT_INTERVAL` environment
     /// variable for the test executable.
-    #[clap(long, env = ENV_CHECKPOINT_INTERVAL, default_value_t = 5)]
-    checkpoint_interval: usize,
+    #[clap(long, env = ENV_CHECKPOINT_INTERVAL)]
+    checkpoint_interval: Option<usize>,
 
     /// Maximum duration to run each loom model for, in seconds
     ///
-    /// If a value is not provided, no duration limit will be set.
+    /// If a value is not provided, the `max-duration-secs` setting in
+    /// `[package.metadata.loom]` or `[workspace.metadata.loom]` is used, and
+    /// if neither sets it, no duration limit will be set.
     ///
     /// This sets the value of the `LOOM_MAX_DURATION` environment variable for
     /// the test executable.
     #[clap(long, env = ENV_MAX_DURATION)]
     max_duration_secs: Option<usize>,
 
+    /// After running the tests, rerun the passing tests with stronger bounds
+    ///
+    /// Each test that passed is run again with one more thread (up to loom's
+    /// limit of 5), ten times `--max-branches`, and no `--max-duration-secs`,
+    /// using up to `--jobs` processes. Tests that fail this way are reported,
+    /// but not checkpointed.
+    #[clap(long)]
+    escalate_on_pass: bool,
+
+    /// Find failing tests with a preemption bound of 1 first, and then run
+    /// only the tests that pass with the full bounds
+    ///
+    /// Most failures show up with a single preemption, which explores far
+    /// fewer interleavings than the configured bounds, so this finds them
+    /// much sooner. The tests that pass are then run again with the
+    /// configured bounds, and the results of that run are the ones that are
+    /// reported. A failing test is checkpointed and rerun with the configured
+    /// bounds, whichever phase it failed in.
+    #[clap(long, conflicts_with = "failed")]
+    smoke: bool,
+
+    /// Annotate each failing test with the commit that last changed the
+    /// lines involved in it
+    ///
+    /// `git blame` is run on the line where the test panicked, and on the
+    /// lines in loom's trace of the failing execution (up to 20 of them), and
+    /// the commit and author of each is included in the failure's output,
+    /// diagnostic, and JSON summary.
+    #[clap(long)]
+    blame: bool,
+
+    /// Re-evaluate tests that were previously checkpointed as failing
+    ///
+    /// By default, tests with a checkpoint from an earlier run of the same
+    /// build are not run again, and are reported as failing. With this flag,
+    /// their checkpoints are deleted and they're run from scratch, so tests
+    /// that no longer fail (e.g. with different bounds) stop being reported.
+    #[clap(long)]
+    verify_checkpoints: bool,
+
+    /// Stop finding failing tests as soon as one test fails
+    ///
+    /// The suite the test failed in (and any other suites that are running)
+    /// are stopped, and the failing test is checkpointed and rerun right
+    /// away. Tests that hadn't finished are reported as not run.
+    #[clap(long)]
+    fail_fast: bool,
+
+    /// Only rerun the tests that failed in the last run
+    ///
+    /// Finding failing tests is skipped: the tests that failed the last time
+    /// each package was tested (recorded in `target/loom/last-run.json`) are
+    /// checkpointed and rerun with logging directly. A test that passes when
+    /// it's checkpointed no longer fails, and isn't rerun.
+    #[clap(long)]
+    failed: bool,
+
+    /// Run the tests that failed in the last run before the rest of their
+    /// test suites
+    ///
+    /// The tests that failed the last time each package was tested are run
+    /// in a process of their own, ahead of the rest of their suite, so that
+    /// a regression is found (and, with `--fail-fast`, stops the run) sooner.
+    #[clap(long, conflicts_with = "failed")]
+    failed_first: bool,
+
+    /// Rerun the tests each time a file in the workspace changes
+    ///
+    /// The directory of each package in the workspace is watched, and when
+    /// a file changes, the tests are rebuilt and run again. Only the test
+    /// suites that cargo had to recompile are run again, and their
+    /// checkpoints from earlier runs are discarded, since they were written
+    /// by a different build of the tests.
+    #[clap(long)]
+    watch: bool,
+
+    /// Rerun only the tests which were partially explored (because they
+    /// reached `--max-permutations`) in their last run, with wider bounds
+    ///
+    /// Each test is rerun up to 3 times, with one more thread and ten times
+    /// as many branches and permutations each time, until it fails or its
+    /// whole model is explored. The tests are run at a low priority (unless
+    /// `--nice` is passed), so that this can run in the background. The
+    /// strongest bounds each test passes with are recorded in
+    /// `target/loom/deepened.json`.
+    #[clap(long, conflicts_with_all = &["watch", "failed"])]
+    deepen: bool,
+
+    /// Fail the run if any passing test's models weren't fully explored
+    ///
+    /// Each model logs whether it explored its whole state space, or stopped
+    /// at a bound (such as `--max-permutations`, `--max-duration-secs`, or a
+    /// bound set on a `loom::model::Builder`) first. With this flag, the run
+    /// fails if any passing test was only partially explored, so that a
+    /// passing run means that every model was checked exhaustively. Tests
+    /// whose models don't log anything (such as those which call
+    /// `Builder::check` without installing a `tracing` subscriber, as
+    /// `loom::model` does) can't be checked.
+    #[clap(long)]
+    require_complete: bool,
+
+    /// Continue a run that was interrupted
+    ///
+    /// When a run is interrupted, the test suites that had finished are
+    /// saved to `target/loom/interrupted.json`. With this flag, those suites
+    /// aren't run again (unless they were rebuilt since), and the failing
+    /// tests found in them are checkpointed and rerun along with the rest.
+    /// The run must use the same settings and test name filter as the
+    /// interrupted one.
+    #[clap(long)]
+    resume: bool,
+
+    /// Rerun failing tests from their checkpoints with a sanitizer
+    ///
+    /// The failing tests' package is rebuilt for the host target with
+    /// `-Z sanitizer` (with `-Z build-std` for the thread sanitizer), which
+    /// requires a nightly toolchain. Each failing test is then rerun from its
+    /// checkpoint, and the sanitizer's reports are written next to its log.
+    ///
+    /// Valid values:
+    ///
+    /// •  thread: ThreadSanitizer, to find data races.
+    ///
+    /// •  address: AddressSanitizer, to find memory errors.
+    #[clap(long, arg_enum, value_name = "SANITIZER")]
+    sanitizer: Option<sanitizer::Sanitizer>,
+
+    /// Check the tests' sources for models that may run for a long time
+    /// before building them
+    ///
+    /// Tests that run a loom model without setting `preemption_bound`,
+    /// `max_permutations`, or `max_duration` on a `loom::model::Builder` are
+    /// reported, as are tests that match a pattern in the `expensive-models`
+    /// setting in `[package.metadata.loom]` or `[workspace.metadata.loom]`
+    /// but aren't marked `#[ignore]`.
+    ///
+    /// Valid values:
+    ///
+    /// •  warn: report them, and run the tests anyway (the default).
+    ///
+    /// •  deny: report them, and fail without running the tests.
+    #[clap(
+        long,
+        arg_enum,
+        value_name = "LEVEL",
+        min_values = 0,
+        require_equals = true,
+        default_missing_value = "warn"
+    )]
+    lint_models: Option<lint::LintLevel>,
+
+    /// Measure which code the tests exercise
+    ///
+    /// The tests are built with `-C instrument-coverage`, and the profiles
+    /// written by every test process are merged into an lcov report at
+    /// `target/loom/coverage/lcov.info`. This requires the
+    /// `llvm-tools-preview` rustup component.
+    #[clap(long)]
+    coverage: bool,
+
+    /// Report how long each part of the run took
+    ///
+    /// Like cargo's `--timings`, this writes a report of when building each
+    /// package's tests, finding the failing tests in each suite, and
+    /// checkpointing and rerunning each failing test started, and how long
+    /// each took, to `target/loom/timings/timings.html` (and
+    /// `timings.json`).
+    #[clap(long)]
+    timings: bool,
+
+    /// Maximum time to run each test for, in seconds
+    ///
+    /// While finding failing tests, a suite with a test that runs for longer
+    /// than this is stopped, and the test is reported as having timed out
+    /// (and the suite's unfinished tests as not run). When checkpointing and
+    /// re-running failed tests, a test process that runs for longer than
+    /// this is stopped, and the test is reported as having timed out. If a
+    /// value is not provided, tests may run indefinitely (or, with `--ci`,
+    /// for 30 minutes).
+    #[clap(long = "test-timeout", value_name = "SECS")]
+    test_timeout_secs: Option<u64>,
+
+    /// How many more times to rerun a failing test with logging, if its
+    /// failure doesn't reproduce from its checkpoint
+    ///
+    /// A test that depends on something loom doesn't control (such as time
+    /// or randomness) may pass when it's rerun. If it passes every time, no
+    /// trace of the failure can be collected, and the panic message from
+    /// the run that found the failure is reported instead.
+    #[clap(long, value_name = "N", default_value = "2")]
+    rerun_retries: usize,
+
+    /// Checkpoint each failing test and collect its logs in the same run
+    ///
+    /// By default, a failing test is run once to find the failing iteration
+    /// and checkpoint it, and then again from the checkpoint with logging and
+    /// location tracking. With this flag, it's run once with all three, so
+    /// its model is only explored up to the failure once. Logging every
+    /// iteration makes that run much slower, and its log much longer, so
+    /// this is only faster for tests that fail early in their models. Tests
+    /// that were already checkpointed are still rerun from their
+    /// checkpoints.
+    #[clap(long, conflicts_with = "checkpoint-timeout-secs")]
+    single_pass: bool,
+
+    /// How many more times to run a test that fails while finding failing
+    /// tests, before treating it as failing
+    ///
+    /// A test that passes when it's retried is reported as flaky, and isn't
+    /// checkpointed and rerun.
+    #[clap(long, value_name = "N", default_value = "0")]
+    retries: usize,
+
+    /// Maximum time to run each test binary for when finding failing tests,
+    /// in seconds
+    ///
+    /// A test binary that runs for longer than this is stopped, so that it
+    /// can't hold up the rest of the run. The tests that finished before it
+    /// was stopped are reported as usual, and the rest are reported as not
+    /// run. If a value is not provided, test binaries may run indefinitely.
+    #[clap(long = "suite-timeout", value_name = "SECS")]
+    suite_timeout_secs: Option<u64>,
+
+    /// Maximum time to spend writing each failing test's checkpoint, in
+    /// seconds
+    ///
+    /// A checkpointing run that takes longer than this is stopped, and the
+    /// failure is reported as not checkpointed. The test is still rerun with
+    /// logging (from the last checkpoint it wrote, if any, or else from the
+    /// beginning of its model), so that there's some output to go on. If a
+    /// value is not provided, `--test-timeout` is used.
+    #[clap(long = "checkpoint-timeout", value_name = "SECS")]
+    checkpoint_timeout_secs: Option<u64>,
+
+    /// Maximum time to spend on the whole run, in seconds
+    ///
+    /// Once the run has taken this long, no more test suites are run and no
+    /// more failing tests are checkpointed and rerun, but those that already
+    /// started are allowed to finish. The suites and failing tests that
+    /// weren't run are reported as skipped.
+    #[clap(long = "total-timeout", value_name = "SECS")]
+    total_timeout_secs: Option<u64>,
+
+    /// Warn about tests that take at least this long, in seconds
+    ///
+    /// Tests that are this slow when finding failing tests, or when
+    /// re-running a failing test with logging, are marked as slow, and
+    /// listed at the end of the run and in the summary file.
+    #[clap(long = "warn-slow", value_name = "SECS")]
+    warn_slow_secs: Option<u64>,
+
+    /// How long a timed-out test process has to exit after it's sent
+    /// `SIGTERM`, in seconds
+    ///
+    /// This gives tests that handle `SIGTERM` a chance to clean up (e.g. to
+    /// flush their logs) before they are killed with `SIGKILL`. On platforms
+    /// without signals, timed-out test processes are killed immediately.
+    #[clap(long, value_name = "SECS", default_value_t = 10)]
+    kill_grace_period: u64,
+
+    /// Pin each test process to its own CPU when re-running failed tests
+    ///
+    /// This reduces scheduling jitter between concurrent reruns, so that
+    /// models bounded by `--max-duration-secs` behave more reproducibly. No
+    /// more tests are rerun at once than there are CPUs. This is only
+    /// supported on Linux.
+    #[clap(long)]
+    pin_cpus: bool,
+
+    /// Run test processes with this niceness, from -20 (the highest
+    /// priority) to 19 (the lowest)
+    ///
+    /// This keeps long runs in the background from making the rest of the
+    /// machine unusable. If this isn't provided, test processes have the
+    /// same priority as `cargo loom`. Raising the priority of test processes
+    /// usually requires elevated privileges. This is only supported on Unix.
+    #[clap(long, value_name = "LEVEL", allow_hyphen_values = true)]
+    nice: Option<i32>,
+
+    /// Limit the address space of each test process to this many bytes
+    ///
+    /// A model that allocates without bound can use all of the machine's
+    /// memory before the OOM killer stops it. With a limit, its allocations
+    /// fail instead, and the tests that were running are reported as failing
+    /// because they ran out of memory. This limits virtual memory rather than
+    /// what's resident, which includes every thread's stack, so it should be
+    /// generous. This is only supported on Unix.
+    #[clap(long, value_name = "BYTES")]
+    memory_limit: Option<u64>,
+
+    /// Override `--max-threads` for the tests whose names contain PATTERN
+    ///
+    /// For example, `--override 'mpsc::tests::close_race:max_threads=3'`.
+    /// Matching tests are run in their own test process, so that the rest
+    /// of the run isn't run with the same bound. If a test matches more
+    /// than one override, the first one is used. This may be passed more
+    /// than once.
+    #[clap(
+        long = "override",
+        value_name = "PATTERN:max_threads=N",
+        multiple_occurrences = true
+    )]
+    overrides: Vec<overrides::Override>,
+
+    /// Set a loom environment variable for the tests in every phase of the
+    /// run
+    ///
+    /// loom's bounds (such as `LOOM_MAX_BRANCHES`) may be set this way
+    /// instead of with their own flags, but not with both. Other variables
+    /// are rejected unless `--force` is passed, so that variables added by
+    /// newer versions of loom can be set before `cargo loom` has a flag for
+    /// them. Variables that `cargo loom` sets for a particular phase (such as
+    /// `LOOM_LOG` when finding failing tests) still take precedence in that
+    /// phase. This may be passed more than once.
+    #[clap(long, value_name = "KEY=VALUE", multiple_occurrences = true)]
+    loom_env: Vec<loom_env::LoomEnv>,
+
+    /// Allow `--loom-env` to set variables that `cargo loom` doesn't know
+    /// about
+    #[clap(long)]
+    force: bool,
+
+    /// Run test processes with only the environment variables they need
+    ///
+    /// Test processes are started with an empty environment instead of
+    /// inheriting this one, and are only given the loom variables that
+    /// `cargo loom` sets, `PATH`, `HOME`, the temporary directory, the
+    /// dynamic library search path, and any variables passed to
+    /// `--keep-env`. This keeps variables that happen to be set on one
+    /// machine from changing how the code under test behaves.
+    #[clap(long)]
+    clean_env: bool,
+
+    /// Pass this variable from `cargo loom`'s environment to test processes
+    /// with `--clean-env`
+    ///
+    /// This may be passed more than once.
+    #[clap(
+        long,
+        value_name = "NAME",
+        multiple_occurrences = true,
+        requires = "clean-env"
+    )]
+    keep_env: Vec<String>,
+
     /// Log level filter for `loom` when re-running failed tests
-    #[clap(long, env = ENV_LOOM_LOG, default_value = "trace")]
-    loom_log: String,
+    ///
+    /// If no value is provided, the `default-loom-log` setting in
+    /// `[package.metadata.loom]` or `[workspace.metadata.loom]` is used, or
+    /// `trace` if neither sets it. This may be overridden for individual
+    /// tests with the `loom-log` table.
+    #[clap(long, env = ENV_LOOM_LOG)]
+    loom_log: Option<String>,
+
+    /// Print the environment variables set for test processes in each phase
+    /// of the run, and the flags that control them, then exit
+    ///
+    /// Tests are first run to discover which of them fail, then each failing
+    /// test is run again to write its checkpoint, and again with logging.
+    /// Some variables (such as `LOOM_MAX_DURATION`) are only set in some of
+    /// these phases.
+    #[clap(long)]
+    explain_env: bool,
+
+    /// Build the tests, then print the commands each phase of the run would
+    /// run them with, and their environment variables, instead of running
+    /// them
+    ///
+    /// The checkpointing and logged rerun commands are printed for a
+    /// placeholder failing test, `<TEST>`, since which tests fail isn't
+    /// known without running them.
+    #[clap(long, conflicts_with_all = &["watch", "deepen", "explain-env"])]
+    dry_run: bool,
 }
 
 const ENV_CHECKPOINT_INTERVAL: &str = "LOOM_CHECKPOINT_INTERVAL";
@@ -220,135 +915,946 @@ const ENV_MAX_THREADS: &str = "LOOM_MAX_THREADS";
 const ENV_LOOM_LOG: &str = "LOOM_LOG";
 const ENV_CHECKPOINT_FILE: &str = "LOOM_CHECKPOINT_FILE";
 const ENV_LOOM_LOCATION: &str = "LOOM_LOCATION";
+const ENV_RUST_BACKTRACE: &str = "RUST_BACKTRACE";
+
+/// The members of the workspace that cargo runs when no packages are
+/// selected, as `cargo metadata` reports them.
+#[derive(Debug, Deserialize)]
+struct DefaultMembers {
+    /// Only reported by cargo 1.71 and later.
+    #[serde(default)]
+    workspace_default_members: Option<Vec<cargo_metadata::PackageId>>,
+}
 
 impl AppArgs {
-    fn metadata(&self) -> Result<cargo_metadata::Metadata> {
+    /// Returns the workspace's metadata, and its default members, if cargo
+    /// reports them.
+    fn metadata(
+        &self,
+        toolchain: &Toolchain,
+        features: &FeatureSelection,
+    ) -> Result<(
+        cargo_metadata::Metadata,
+        Option<Vec<cargo_metadata::PackageId>>,
+    )> {
         let mut cmd = cargo_metadata::MetadataCommand::new();
+        cmd.cargo_path(toolchain.cargo());
         if let Some(ref manifest_path) = self.cargo.manifest_path {
             cmd.manifest_path(manifest_path);
         }
-        self.cargo.features.forward_metadata(&mut cmd);
-        cmd.exec().context("getting cargo metadata")
+        features.forward_metadata(&mut cmd);
+
+        // `MetadataCommand::exec` doesn't let us set environment variables, so
+        // run the command ourselves.
+        let mut cmd = cmd.cargo_command();
+        let output = toolchain
+            .configure(&mut cmd)
+            .stderr(Stdio::inherit())
+            .output()
+            .context("running `cargo metadata`")?;
+        if !output.status.success() {
+            return Err(eyre!("`cargo metadata` failed ({})", output.status));
+        }
+        let json = std::str::from_utf8(&output.stdout)
+            .context("`cargo metadata` output was not utf8")?
+            .lines()
+            .find(|line| line.starts_with('{'))
+            .ok_or_else(|| eyre!("`cargo metadata` did not output any JSON"))?;
+        let metadata =
+            cargo_metadata::MetadataCommand::parse(json).context("getting cargo metadata")?;
+        let default_members = serde_json::from_str::<DefaultMembers>(json)
+            .context("getting the workspace's default members")?
+            .workspace_default_members;
+        Ok((metadata, default_members))
+    }
+
+    /// Returns the packages to test: those selected with `--package`,
+    /// `--workspace`, and `--exclude`, or else the workspace's default
+    /// members, like cargo.
+    ///
+    /// If the only targets selected are selected by name (with `--test`,
+    /// `--bin`, or `--example`), packages that have none of them are left
+    /// out.
+    fn wanted_packages<'m>(
+        &self,
+        metadata: &'m cargo_metadata::Metadata,
+        default_members: Option<&[cargo_metadata::PackageId]>,
+    ) -> Vec<&'m cargo_metadata::Package> {
+        let mut packages = self.selected_packages(metadata, default_members);
+        let cargo = &self.cargo;
+        if cargo.selects_named_targets()
+            && !(cargo.lib || cargo.tests || cargo.examples || cargo.bins)
+        {
+            packages.retain(|pkg| {
+                cargo
+                    .named_targets()
+                    .any(|(kind, name)| has_target(pkg, kind, name))
+            });
+        }
+        packages
+    }
+
+    fn selected_packages<'m>(
+        &self,
+        metadata: &'m cargo_metadata::Metadata,
+        default_members: Option<&[cargo_metadata::PackageId]>,
+    ) -> Vec<&'m cargo_metadata::Package> {
+        let workspace = &self.cargo.workspace;
+        let selected = !workspace.package.is_empty()
+            || workspace.workspace
+            || workspace.all
+            || !workspace.exclude.is_empty();
+        match default_members {
+            Some(default_members) if !selected => metadata
+                .packages
+                .iter()
+                .filter(|pkg| default_members.contains(&pkg.id))
+                .collect(),
+            // Without the default members (from an older cargo), this tests
+            // the package in the current directory, or else every member.
+            _ => workspace.partition_packages(metadata).0,
+        }
+    }
+}
+
+impl CargoOptions {
+    /// Returns whether any test targets were selected on the command line.
+    fn selects_targets(&self) -> bool {
+        self.lib || self.tests || self.examples || self.bins || self.selects_named_targets()
+    }
+
+    /// Returns whether any test targets were selected by name.
+    fn selects_named_targets(&self) -> bool {
+        !self.test.is_empty() || !self.bin.is_empty() || !self.example.is_empty()
+    }
+
+    /// Returns the targets selected by name, with the kind of each.
+    fn named_targets(&self) -> impl Iterator<Item = (&'static str, &String)> + '_ {
+        let tests = self.test.iter().map(|name| ("test", name));
+        let bins = self.bin.iter().map(|name| ("bin", name));
+        let examples = self.example.iter().map(|name| ("example", name));
+        tests.chain(bins).chain(examples)
+    }
+
+    /// Returns the target selection flags for `pkg`: those of the targets
+    /// selected by name that `pkg` has, as cargo rejects targets that don't
+    /// exist, and `--examples` and `--bins`.
+    fn target_args(&self, pkg: &cargo_metadata::Package) -> Vec<String> {
+        let mut args = Vec::new();
+        for (kind, name) in self.named_targets() {
+            if has_target(pkg, kind, name) {
+                args.push(format!("--{}", kind));
+                args.push(name.clone());
+            }
+        }
+        if self.examples {
+            args.push("--examples".to_owned());
+        }
+        if self.bins {
+            args.push("--bins".to_owned());
+        }
+        args
     }
 }
 
+/// Returns whether `pkg` has a `kind` target named `name`.
+fn has_target(pkg: &cargo_metadata::Package, kind: &str, name: &str) -> bool {
+    pkg.targets
+        .iter()
+        .any(|target| target.name == name && target.kind.iter().any(|k| k == kind))
+}
+
 impl App {
     /// Parse an [`App`] configuration from command-line arguments and
     /// environment variables.
     pub fn parse() -> Result<Self> {
+        Self::parse_with_tracing(trace::Tracing::Default)
+    }
+
+    /// Parse an [`App`] configuration from command-line arguments and
+    /// environment variables, setting up `tracing` as configured by
+    /// `tracing` rather than always installing a global subscriber.
+    pub fn parse_with_tracing(tracing: trace::Tracing) -> Result<Self> {
         let CargoArgs {
             cmd: Subcommand::Loom(args),
         } = CargoArgs::parse();
-        Self::from_args(args)
+        Self::from_args(args, tracing)
+    }
+
+    /// Run the subcommand specified by this `App`'s command-line arguments, or
+    /// [`App::run_all`] if there is none.
+    pub async fn run(&self) -> Result<()> {
+        // Nothing is run, so the hooks aren't either.
+        if self.args.loom.explain_env {
+            return self.explain_env();
+        }
+        if self.args.loom.dry_run {
+            return self.dry_run();
+        }
+
+        let config = Config::load_root(&self.metadata)?;
+        let mut env = self.hook_env();
+        if let Some(ref pre_run) = config.pre_run {
+            pre_run.run("pre-run", &env).await?;
+        }
+
+        let run = async {
+            match self.args.command {
+                Some(LoomCommand::BenchModel(ref args)) => self.bench_models(args),
+                Some(LoomCommand::Explain(ref args)) => self.explain(args),
+                Some(LoomCommand::History(ref args)) => self.history(args),
+                Some(LoomCommand::List(ref args)) => self.list(args),
+                Some(LoomCommand::Merge(ref args)) => self.merge(args),
+                None if self.args.loom.deepen => self.deepen().await,
+                None if self.args.loom.watch => self.watch().await,
+                None => self.run_all().await,
+            }
+        };
+        // If the run is interrupted, it's dropped, killing any tests that are
+        // still running, and what it had done so far is saved.
+        let result = tokio::select! {
+            result = run => result,
+            error = process::interrupted() => {
+                if let Err(error) = self.save_progress() {
+                    tracing::warn!("failed to save the run's progress: {:#}", error);
+                }
+                Err(error)
+            },
+        };
+
+        if let Some(ref post_run) = config.post_run {
+            let status = if result.is_ok() { "success" } else { "failure" };
+            env.push(("CARGO_LOOM_RESULT", status.to_owned()));
+            // Report the run's own error over the hook's.
+            let hook = post_run.run("post-run", &env).await;
+            result?;
+            hook?;
+            return Ok(());
+        }
+
+        result
+    }
+
+    /// Returns the environment variables describing this run that are set for
+    /// `pre-run` and `post-run` hooks.
+    fn hook_env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![
+            (
+                "CARGO_LOOM_WORKSPACE_ROOT",
+                self.metadata.workspace_root.to_string(),
+            ),
+            ("CARGO_LOOM_TARGET_DIR", self.target_dir.to_string()),
+            ("CARGO_LOOM_CHECKPOINT_DIR", self.checkpoint_dir.to_string()),
+        ];
+        if let Some(ref artifacts_dir) = self.artifacts_dir {
+            env.push(("CARGO_LOOM_ARTIFACTS_DIR", artifacts_dir.to_string()));
+        }
+        if let Some(ref summary_file) = self.summary_file {
+            env.push(("CARGO_LOOM_SUMMARY_FILE", summary_file.to_string()));
+        }
+        env
     }
 
     /// Run all tests specified by this `App`'s command-line arguments and print
     /// the output of any failing tests.
+    ///
+    /// If any tests failed, this returns an error (unless
+    /// `--no-fail-exit-code` was passed), which [`exit_code`] maps to
+    /// [`EXIT_TESTS_FAILED`].
+    ///
+    /// This may be called more than once, e.g. to rerun the tests after
+    /// they've changed. Each run starts without the artifacts written by the
+    /// previous one.
     pub async fn run_all(&self) -> Result<()> {
-        for pkg in self.wanted_packages() {
-            self.run_package(pkg).await?;
+        let filter = TestFilter::Contains(self.args.testname.as_deref());
+        self.report_drift()?;
+        let summary = self.run_packages(self.wanted_packages(), filter).await?;
+        self.record_last_run(&summary, filter)?;
+        self.remove_progress()?;
+
+        if let Some(ref summary_file) = self.summary_file {
+            summary.write_to(summary_file)?;
+            tracing::info!("Wrote summary to {}", summary_file);
+        }
+
+        if let Some(ref archive) = self.args.ci.archive_failures {
+            self.archive_failures(&summary, archive)?;
+        }
+        self.emit(&summary)?;
+
+        let settings = &self.args.trace_settings;
+        if settings.message_format().is_json() && !settings.quiet() {
+            summary.print_human(settings.color());
+        }
+
+        if self.args.ci.no_fail_exit_code {
+            return Ok(());
+        }
+
+        let failures = summary.failures();
+        if failures > 0 {
+            return Err(TestsFailed(failures).into());
+        }
+
+        if self.args.loom.require_complete {
+            let partial = summary
+                .packages
+                .iter()
+                .map(|pkg| pkg.partially_explored.len())
+                .sum::<usize>();
+            if partial > 0 {
+                return Err(ExplorationIncomplete(partial).into());
+            }
         }
 
         Ok(())
     }
 
-    async fn run_package(&self, pkg: &cargo_metadata::Package) -> Result<()> {
-        let mut failing = self.failing_tests(pkg).with_context(|| {
-            format!("Error collecting failing tests for package `{}`", pkg.name)
-        })?;
-        let mut tasks = self
-            .run_failed(&mut failing)
-            .with_context(|| format!("Error rerunning failing tests for package `{}`", pkg.name))?;
-        while let Some(result) = tasks.join_one().await? {
-            let output = result?;
-            println!("\n --- test {} ---\n\n{}", output.name(), output.stdout()?);
+    /// Runs only the tests in `package` named `tests`, and returns their
+    /// results.
+    ///
+    /// Test names are given without the name of their suite (e.g.
+    /// `tests::my_model`), and must match exactly; if none are given, every
+    /// test in the package is run. Tests which don't appear
+    /// in any of the returned [`PackageSummary`]s' failures passed (or
+    /// didn't exist; see [`PackageSummary::counts`]).
+    ///
+    /// Unlike [`App::run_all`], this doesn't write a summary file, or fail
+    /// if tests failed in CI mode.
+    pub async fn run_tests(&self, package: &str, tests: &[&str]) -> Result<Summary> {
+        let pkg = self.workspace_package(package)?;
+        self.run_packages(vec![pkg], TestFilter::Exact(tests)).await
+    }
+
+    async fn run_packages(
+        &self,
+        packages: Vec<&cargo_metadata::Package>,
+        filter: TestFilter<'_>,
+    ) -> Result<Summary> {
+        self.title.reset();
+        // Only the first run with `--watch` is resumed.
+        let resumed = self
+            .resumed
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+            .take();
+        self.reset_progress(resumed)?;
+        *self
+            .deadline
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner()) = self
+            .args
+            .loom
+            .total_timeout_secs
+            .map(|secs| Instant::now() + Duration::from_secs(secs));
+        self.remove_previous_artifacts()?;
+        if let Some(ref coverage) = self.coverage {
+            coverage.reset()?;
+        }
+        if let Some(ref timings) = self.timings {
+            timings.reset();
+        }
+        if let Some(level) = self.args.loom.lint_models {
+            self.lint_models(&packages, level)?;
+        }
+        let mut summary = Summary::default();
+
+        // When running a feature matrix, several feature sets may be
+        // identical for a given package (e.g. if it doesn't declare some of
+        // the features). Don't run the same build more than once.
+        let mut already_run = HashSet::new();
+        for features in &self.feature_sets {
+            if self.args.cargo.feature_matrix.is_some() {
+                tracing::info!("Testing with {features}");
+            }
+
+            let mut runs = Vec::new();
+            for &pkg in &packages {
+                let config = Config::load(&self.metadata, pkg)?;
+                let settings = self.settings(&config);
+                let build = self.test_cmd(pkg, &settings, features);
+                let build_args = build.get_args().map(OsStr::to_owned).collect::<Vec<_>>();
+                if !already_run.insert(build_args) {
+                    tracing::debug!(package = %pkg.name, %features, "Skipping duplicate feature set");
+                    continue;
+                }
+                runs.push((pkg, config));
+            }
+
+            // Packages are run up to `--package-jobs` at a time, and their
+            // summaries are kept in order. cargo only runs one build in the
+            // target directory at a time, so this mostly lets a package's
+            // tests run while the next package is built.
+            let runs = runs
+                .iter()
+                .map(|(pkg, config)| self.run_package(pkg, config, features, filter))
+                .collect::<Vec<_>>();
+            let mut runs = stream::iter(runs).buffered(self.args.cargo.package_jobs);
+            while let Some(package) = runs.next().await {
+                summary.packages.push(package?);
+            }
+        }
+
+        if let Some(ref coverage) = self.coverage {
+            // The tests' results are still worth reporting if the coverage
+            // report can't be written.
+            match coverage.report(&self.toolchain) {
+                Ok(report) => summary.coverage_report = report,
+                Err(error) => tracing::warn!(
+                    "failed to write coverage report (the tests' profiles are in `{}`): {:#}",
+                    coverage.profraw_dir(),
+                    error
+                ),
+            }
+            if let Some(ref report) = summary.coverage_report {
+                tracing::info!("Wrote coverage report to {}", report);
+            }
         }
 
-        for checkpoint_dir in failing.checkpoint_dirs() {
-            tracing::info!(checkpoint_dir = %checkpoint_dir, "Completed loom run");
+        if let Some(ref timings) = self.timings {
+            match timings.report() {
+                Ok(report) => {
+                    tracing::info!("Wrote timings report to {}", report);
+                    summary.timings_report = Some(report);
+                }
+                Err(error) => tracing::warn!("failed to write timings report: {:#}", error),
+            }
         }
 
+        summary.print_suggestions();
+        Ok(summary)
+    }
+
+    /// Removes the artifacts written by this `App`'s previous run, so that
+    /// the artifacts directory only describes the latest run.
+    fn remove_previous_artifacts(&self) -> Result<()> {
+        let mut artifacts = self
+            .artifacts
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        for log in artifacts.drain(..) {
+            for file in [log.with_extension("json"), log] {
+                match fs::remove_file(&file) {
+                    Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                        return Err(error)
+                            .with_context(|| format!("removing previous artifact `{}`", file))
+                    }
+                    _ => {}
+                }
+            }
+        }
         Ok(())
     }
 
-    fn failing_tests(&self, pkg: &cargo_metadata::Package) -> Result<Failed> {
-        let json = self.args.trace_settings.message_format().is_json();
-        let tests = self.test_cmd(pkg).run_tests()?;
-        let mut failed = Failed::default();
+    /// Finds the failing tests in `package`, and starts rerunning them to
+    /// generate checkpoints and logs, returning a [`Reruns`] which yields
+    /// each test's output as its rerun completes.
+    ///
+    /// This is the part of [`App::run_all`] that runs tests, without any of
+    /// its reporting (besides the status of each test as it's discovered),
+    /// so that callers can display the results themselves. Tests are built
+    /// with the features selected on the command line (the first set, when
+    /// running a feature matrix).
+    pub async fn reruns(&self, package: &str) -> Result<Reruns> {
+        let pkg = self.workspace_package(package)?;
+        let features = &self.feature_sets[0];
+        let config = Config::load(&self.metadata, pkg)?;
+        let settings = self.settings(&config);
+        let filter = TestFilter::Contains(self.args.testname.as_deref());
+        let mut failing = self
+            .failing_tests(pkg, &settings, features, filter)
+            .await
+            .with_context(|| {
+                format!("Error collecting failing tests for package `{}`", pkg.name)
+            })?;
+        let label = self
+            .args
+            .cargo
+            .feature_matrix
+            .as_ref()
+            .map(|_| features.to_string());
+        self.run_failed(
+            &pkg.name,
+            &mut failing,
+            &config,
+            &settings,
+            label.as_deref(),
+        )
+        .with_context(|| format!("Error rerunning failing tests for package `{}`", pkg.name))
+    }
 
-        for suite in tests {
-            let suite = suite.context("Getting next test failed")?;
+    fn workspace_package(&self, name: &str) -> Result<&cargo_metadata::Package> {
+        self.metadata
+            .packages
+            .iter()
+            .find(|pkg| pkg.name == name && self.metadata.workspace_members.contains(&pkg.id))
+            .ok_or_else(|| eyre!("no package named `{}` in the workspace", name))
+    }
 
-            let bin_path = suite
-                .path()
-                .file_name()
-                .ok_or_else(|| eyre!("test binary must have a file name"))
-                .and_then(|os_str| {
-                    os_str
-                        .to_str()
-                        .ok_or_else(|| eyre!("binary path was not utf8"))
+    async fn run_package(
+        &self,
+        pkg: &cargo_metadata::Package,
+        config: &Config,
+        features: &FeatureSelection,
+        filter: TestFilter<'_>,
+    ) -> Result<PackageSummary> {
+        self.title.testing(&pkg.name);
+        let settings = self.settings(config);
+        let label = self
+            .args
+            .cargo
+            .feature_matrix
+            .as_ref()
+            .map(|_| features.to_string());
+        let mut failing = if self.args.loom.failed {
+            self.last_failed(pkg, &settings, features, label.as_deref(), filter)
+                .await
+        } else {
+            self.failing_tests(pkg, &settings, features, filter).await
+        }
+        .with_context(|| format!("Error collecting failing tests for package `{}`", pkg.name))?;
+        let history = std::mem::take(&mut failing.history);
+        let test_times = history
+            .iter()
+            .filter_map(|entry| {
+                Some(TestTime {
+                    test: format!("{}::{}", entry.suite, entry.name),
+                    duration: entry.duration?,
                 })
-                .with_note(|| format!("bin path: {}", suite.path().display()))?;
+            })
+            .collect::<Vec<_>>();
+        self.record_history(&pkg.name, label.as_deref(), &settings, history)?;
+        self.title
+            .rerunning(failing.failed.values().map(Vec::len).sum());
+        let mut reruns = self
+            .run_failed(&pkg.name, &mut failing, config, &settings, label.as_deref())
+            .with_context(|| format!("Error rerunning failing tests for package `{}`", pkg.name))?;
+        let on_failure = self
+            .args
+            .ci
+            .on_failure
+            .as_deref()
+            .or(config.on_failure.as_deref());
+        let warn_slow = self.args.loom.warn_slow_secs.map(Duration::from_secs);
+        let mut slow_tests = failing
+            .slow
+            .drain(..)
+            .map(|(test, took)| SlowTest {
+                test,
+                phase: "discovery",
+                duration: took.as_secs_f64(),
+            })
+            .collect::<Vec<_>>();
+        let mut failures = Vec::new();
+        let output_lock = self.output.lock().await;
+        // With `--buffer-output`, every rerun is finished before any is
+        // reported, so that they're reported in a stable order.
+        let mut buffered = None;
+        if self.args.trace_settings.buffer_output() {
+            let mut outputs = Vec::new();
+            while let Some(output) = reruns.next().await {
+                outputs.push(output?);
+                self.title.rerun_done();
+            }
+            outputs.sort_by(|a, b| a.name().cmp(b.name()));
+            buffered = Some(outputs.into_iter());
+        }
+        loop {
+            let output = match buffered {
+                Some(ref mut outputs) => outputs.next(),
+                None => {
+                    let output = reruns.next().await.transpose()?;
+                    if output.is_some() {
+                        self.title.rerun_done();
+                    }
+                    output
+                }
+            };
+            let output = match output {
+                Some(output) => output,
+                None => break,
+            };
+            if output.fixed {
+                tracing::info!(test = %output.name(), "Test no longer fails");
+                failing.counts.failed -= 1;
+                failing.counts.passed += 1;
+                continue;
+            }
+            if let Some(signal) = output.killed_by {
+                tracing::warn!(test = %output.name(), signal = %signal.as_str(), "rerun timed out");
+            } else if warn_slow.is_some_and(|threshold| output.duration >= threshold) {
+                tracing::warn!(test = %output.name(), "slow rerun took {:.1?}", output.duration);
+                slow_tests.push(SlowTest {
+                    test: output.name().to_owned(),
+                    phase: "rerun",
+                    duration: output.duration.as_secs_f64(),
+                });
+            }
+            if !output.stdout_is_utf8() {
+                tracing::warn!(
+                    test = %output.name(),
+                    "output contained invalid UTF-8, which is shown as `\u{FFFD}` \
+                    (the log file has the original output)"
+                );
+            }
+            // With `--rerun-output prefixed`, the output was already printed
+            // as it was written.
+            let mut failure = output.failure_summary();
+            if failure.classification == Classification::Passed {
+                let found = match (&failure.message, &failure.location) {
+                    (Some(message), Some(location)) => {
+                        format!("; it panicked at {} with:\n    {}", location, message)
+                    }
+                    (Some(message), None) => format!("; it panicked with:\n    {}", message),
+                    _ => String::new(),
+                };
+                tracing::warn!(
+                    test = %output.name(),
+                    "could not collect a trace, because the test passed each time it was \
+                    rerun from its checkpoint ({} attempts){}",
+                    failure.attempts,
+                    found
+                );
+            }
+            if self.args.loom.blame {
+                failure.blame = self.blame(failure.location.as_ref(), &output.stdout());
+            }
+            if self.args.trace_settings.rerun_output() == trace::RerunOutput::Grouped {
+                let heading = ci::Heading {
+                    test: output.name(),
+                    classification: failure.classification,
+                    duration: output.duration,
+                };
+                let color = self.args.trace_settings.color();
+                let mut stdout =
+                    backtrace::filter(self.args.trace_settings.backtrace_style(), &output.stdout());
+                if output.stdout.omitted() > 0 {
+                    stdout.insert_str(
+                        0,
+                        &format!(
+                            "... {} bytes of output omitted; the full output is in `{}`\n",
+                            output.stdout.omitted(),
+                            output.log
+                        ),
+                    );
+                }
+                // With `--raw-test-output`, stdout is reserved for libtest's
+                // JSON.
+                if self.args.trace_settings.raw_test_output() {
+                    let styles = trace::Styles::new(color);
+                    self.log_groups
+                        .print(io::stderr().lock(), heading, &styles, &stdout)
+                } else {
+                    let styles = trace::Styles::stdout(color);
+                    self.log_groups
+                        .print(io::stdout().lock(), heading, &styles, &stdout)
+                }
+                .context("writing test output")?;
+            }
+            if self.args.trace_settings.error_format() == trace::ErrorFormat::Human
+                && !self.args.trace_settings.message_format().is_json()
+            {
+                blame::print(
+                    output.name(),
+                    &failure.blame,
+                    self.args.trace_settings.color(),
+                );
+            }
 
-            let checkpoint_dir = self.checkpoint_dir.as_path().join(bin_path);
+            if self.artifacts_dir.is_some() {
+                output.save_checkpoint()?;
+            }
+            if self.args.trace_settings.error_format() == trace::ErrorFormat::Diagnostic {
+                let mut diagnostic = Diagnostic::for_failure(&failure);
+                diagnostic
+                    .note(format_args!("test: {}", output.name()))
+                    .note(format_args!("checkpoint: {}", output.checkpoint))
+                    .note(format_args!("log: {}", output.log));
+                for blame in &failure.blame {
+                    diagnostic.note(format_args!("blame: {} {}", blame.location, blame));
+                }
+                diagnostic
+                    .render(
+                        &self.metadata.workspace_root,
+                        self.args.trace_settings.color(),
+                        &mut io::stderr().lock(),
+                    )
+                    .context("writing diagnostic")?;
+            }
+            if self.args.trace_settings.message_format().is_json() {
+                failure.emit_json(&pkg.name, label.as_deref())?;
+            }
+            if let Some(command) = on_failure {
+                hooks::on_failure(command, &pkg.name, &failure, &failure.log).await?;
+            }
+            failures.push(failure);
+        }
 
-            if suite.kind() == "lib" {
-                tracing::info!(path = %suite.path().display(), "Running unittests")
-            } else {
-                tracing::info!(path = %suite.path().display(), "Running {}", suite.name())
+        for dir in &failing.suite_checkpoint_dirs {
+            if failing.checkpoint_dirs().contains(&dir.path) {
+                tracing::info!(suite = %dir.suite, checkpoint_dir = %dir.path, "Completed loom run");
             }
+        }
 
-            let mut cmd = suite.command();
+        let skipped_tests = reruns.skipped();
+        if !failing.skipped_suites.is_empty() {
+            tracing::warn!(
+                "the run reached `--total-timeout`, so {} test suites in `{}` weren't run",
+                failing.skipped_suites.len(),
+                pkg.name,
+            );
+        }
+        if !skipped_tests.is_empty() {
+            tracing::warn!(
+                "the run reached `--total-timeout`, so {} failing tests in `{}` weren't rerun",
+                skipped_tests.len(),
+                pkg.name,
+            );
+        }
 
-            // Don't enable checkpoints, logging, or location tracking for this
-            // run. Our goal here is *only* to get the names of the failing
-            // tests so we can re-run them individually with their own
-            // checkpoint files.
-            self.configure_loom_command(&mut cmd)
-                .env(ENV_LOOM_LOG, "off");
-
-            // If a test name filter was provided, pass that to the test
-            // command.
-            //
-            // This isn't added by `configure_loom_command`, because we don't
-            // want to set duration limits when re-running with logging etc (as
-            // it may be slower).
-            if let Some(max_duration) = self.max_duration.as_deref() {
-                cmd.env(ENV_MAX_DURATION, max_duration);
-            }
-
-            // If a test name filter was provided, pass that to the test command.
-            if let Some(testname) = self.args.testname.as_deref() {
-                cmd.arg(testname);
+        drop(output_lock);
+
+        if let Some(sanitizer) = self.args.loom.sanitizer {
+            self.sanitize(pkg, &settings, features, sanitizer, &mut failures)
+                .await?;
+        }
+
+        let escalated_failures = if self.args.loom.escalate_on_pass {
+            let passed = std::mem::take(&mut failing.passed);
+            Some(self.escalate(&settings, passed).await?)
+        } else {
+            None
+        };
+
+        if !failing.partially_explored.is_empty() {
+            tracing::warn!(
+                "{} passing tests in `{}` stopped at a bound, and were only \
+                partially explored:\n    {}",
+                failing.partially_explored.len(),
+                pkg.name,
+                failing.partially_explored.join("\n    "),
+            );
+        }
+
+        if !failing.flaky.is_empty() {
+            tracing::warn!(
+                "{} tests in `{}` failed, but passed when they were retried:\n    {}",
+                failing.flaky.len(),
+                pkg.name,
+                failing.flaky.join("\n    "),
+            );
+        }
+
+        if !slow_tests.is_empty() {
+            tracing::warn!(
+                "{} tests in `{}` took at least `--warn-slow`:\n    {}",
+                slow_tests.len(),
+                pkg.name,
+                slow_tests
+                    .iter()
+                    .map(|slow| format!("{} ({}, {:.1}s)", slow.test, slow.phase, slow.duration))
+                    .collect::<Vec<_>>()
+                    .join("\n    "),
+            );
+        }
+
+        let mut failure_kinds = FailureKinds::default();
+        for kind in failures.iter().filter_map(|failure| failure.kind) {
+            failure_kinds.add(kind);
+        }
+        if !failure_kinds.is_empty() {
+            tracing::info!("Failing tests in `{}` by kind: {}", pkg.name, failure_kinds);
+        }
+
+        let suggestions = self.suggestions(&settings, &failures, &failing.partially_explored);
+        Ok(PackageSummary {
+            name: pkg.name.clone(),
+            features: label,
+            counts: failing.counts,
+            failures,
+            failure_kinds,
+            checkpoint_dirs: failing.suite_checkpoint_dirs,
+            partially_explored: failing.partially_explored,
+            fully_explored: failing.fully_explored,
+            flaky_tests: failing.flaky,
+            escalated_failures,
+            slow_tests,
+            test_times,
+            timed_out_suites: failing.timed_out_suites,
+            timed_out_tests: failing.timed_out_tests,
+            skipped_suites: failing.skipped_suites,
+            skipped_tests,
+            suggestions,
+        })
+    }
+
+    async fn failing_tests(
+        &self,
+        pkg: &cargo_metadata::Package,
+        settings: &Settings,
+        features: &FeatureSelection,
+        filter: TestFilter<'_>,
+    ) -> Result<Failed> {
+        let build_started = Instant::now();
+        let mut tests = self.spawn_build_tests(pkg, settings, features)?;
+        // With `--smoke`, the tests are first run with a low preemption
+        // bound, and then the ones that passed with `settings`.
+        let full_settings = settings;
+        let smoke_settings;
+        let settings = if self.args.loom.smoke {
+            smoke_settings = Self::smoke_settings(settings);
+            &smoke_settings
+        } else {
+            settings
+        };
+        let mut smoke_passed = Vec::new();
+        let discovery = Discovery::new(
+            &self.args.trace_settings,
+            self.processes.clone(),
+            self.logs_models(settings),
+            discovery::Limits {
+                suite: self.suite_timeout,
+                test: self.test_timeout.limit(),
+                deadline: self.deadline(),
+            },
+            self.args.loom.warn_slow_secs.map(Duration::from_secs),
+            self.args.loom.fail_fast,
+        );
+        let mut tasks = JoinSet::new();
+        // Suites which only have previously checkpointed tests to run.
+        let mut checkpointed = Vec::new();
+        // How many processes each suite is run in, by checkpoint directory,
+        // so that it's only recorded as finished once they all have.
+        let mut runs = HashMap::new();
+        let label = self
+            .args
+            .cargo
+            .feature_matrix
+            .as_ref()
+            .map(|_| features.to_string());
+        // The failing tests found in each suite so far, its test counts, and
+        // whether each of its processes ran all of its tests.
+        let mut found =
+            HashMap::<Utf8PathBuf, (Vec<interrupt::FailedTest>, summary::Counts, bool)>::new();
+        let mut failed = Failed::default();
+        // Tests that failed, to retry with `--retries`.
+        let mut retry = Vec::new();
+        let failed_first = self.failed_first(pkg, label.as_deref())?;
+
+        while let Some(suite) = tests.next().await {
+            let suite = suite.context("Getting next test failed")?;
+
+            let checkpoint_dir = self.suite_checkpoint_dir(&suite)?;
+            // With `--watch`, a suite that was already run is only run again
+            // if it was rebuilt, and then its tests' checkpoints are from the
+            // old build.
+            let mut verify_checkpoints = self.args.loom.verify_checkpoints;
+            if self.args.loom.watch {
+                let tested_before = !self
+                    .tested
+                    .lock()
+                    .unwrap_or_else(|poison| poison.into_inner())
+                    .insert(suite.path().to_owned());
+                if tested_before && suite.fresh() {
+                    tracing::info!(
+                        "Not running `{}`, which hasn't changed since it was last run",
+                        suite.name()
+                    );
+                    continue;
+                }
+                verify_checkpoints |= tested_before;
+            }
+            failed.run_suite(&suite, &checkpoint_dir);
+            if self.args.loom.resume {
+                let progress = self
+                    .progress
+                    .lock()
+                    .unwrap_or_else(|poison| poison.into_inner());
+                let finished = checkpoint_dir.file_name().and_then(|binary| {
+                    progress.finished_suite(&pkg.name, label.as_deref(), binary)
+                });
+                if let Some(finished) = finished {
+                    if suite.fresh() {
+                        tracing::info!(
+                            "Not running `{}`, which finished before the run was interrupted",
+                            suite.name()
+                        );
+                        for test in &finished.failed {
+                            failed.fail_test(
+                                &suite,
+                                test.name.clone(),
+                                &checkpoint_dir,
+                                test.panic.clone(),
+                            );
+                        }
+                        failed.counts += finished.counts;
+                        self.title.failed(finished.failed.len());
+                        failed.finish_suite(suite);
+                        continue;
+                    }
+                    tracing::info!(
+                        "Running `{}` again, because it was rebuilt since the run was \
+                        interrupted",
+                        suite.name()
+                    );
+                }
             }
+            if let Some(ref coverage) = self.coverage {
+                coverage.add_binary(suite.path());
+            }
+
+            let mut cmd = suite.command();
+            self.configure_discovery_command(&mut cmd, settings);
+            filter.apply(&mut cmd);
 
             // If there is already a checkpoint dir for this artifact hash, skip
             // any previously checkpointed tests.
+            let mut skipped = Vec::new();
             if checkpoint_dir.exists() {
                 (|| {
-                    let mut has_printed = false;
                     for entry in fs::read_dir(checkpoint_dir.as_std_path())? {
                         let path = entry?.path();
                         match path.extension() {
                             Some(extension) if extension == "json" => {
-                                if let Some(test) = path.file_stem().and_then(OsStr::to_str) {
-                                    // does the test name filter care about
-                                    // this test?
-                                    let is_included = self
-                                        .args
-                                        .testname
-                                        .as_deref()
-                                        .map(|testname| test.contains(testname))
-                                        .unwrap_or(true);
-                                    if is_included {
+                                if let Some(stem) = path.file_stem().and_then(OsStr::to_str) {
+                                    let test = test_name(stem);
+                                    let test = test.as_str();
+                                    // do the run's filters include this
+                                    // test, and can it be resumed from its
+                                    // checkpoint?
+                                    let checkpoint = checkpoint_dir.join(format!("{}.json", stem));
+                                    if let Some(reason) = self.excludes(filter, suite.name(), test)
+                                    {
+                                        tracing::debug!(
+                                            %checkpoint,
+                                            "Ignoring checkpoint of `{}`, which isn't run \
+                                            because {}",
+                                            test,
+                                            reason
+                                        );
+                                    } else if !checkpoint::exists(&checkpoint) {
+                                        tracing::debug!(
+                                            %checkpoint,
+                                            "Running `{}`, because its checkpoint is incomplete",
+                                            test
+                                        );
+                                    } else if verify_checkpoints {
+                                        tracing::debug!(
+                                            %checkpoint,
+                                            "Deleting checkpoint of `{}`, to run it again",
+                                            test
+                                        );
+                                        // If the test still fails, it's
+                                        // checkpointed again.
+                                        fs::remove_file(&checkpoint)?;
+                                    } else {
+                                        tracing::debug!(
+                                            %checkpoint,
+                                            "Skipping `{}`, which was checkpointed as failing",
+                                            test
+                                        );
                                         cmd.arg("--skip").arg(test);
-                                        failed.fail_test(&suite, test.to_owned(), &checkpoint_dir);
-                                        if !has_printed {
-                                            eprintln!("\npreviously checkpointed");
-                                            has_printed = true;
-                                        }
-
-                                        test_status::<colors::Red>(test, "failed")
+                                        skipped.push(test.to_owned());
                                     }
                                 }
                             }
@@ -366,188 +1872,507 @@ impl App {
                 })?;
             }
 
-            let res = CommandMessages::with_command(cmd)
-                .with_note(|| format!("running test suite `{}`", suite.name()))?;
-            let t0 = std::time::Instant::now();
-            for msg in res {
-                use test::*;
-                match msg.and_then(|msg| msg.decode_custom::<Event>()) {
-                    Ok(Event::Test(Test::Failed(test_failed))) => {
-                        if json {
-                            serde_json::to_writer(std::io::stderr(), &test_failed)
-                                .context("write json message")?;
-                        } else {
-                            test_status::<colors::Red>(&test_failed.name, "failed");
-                        }
-                        failed.fail_test(&suite, test_failed.name, &checkpoint_dir);
-                    }
-                    Ok(Event::Test(Test::Ok(ok))) => {
-                        if json {
-                            serde_json::to_writer(std::io::stderr(), &ok)
-                                .context("write json message")?;
-                        } else {
-                            test_status::<colors::Green>(&ok.name, "ok");
-                        }
-                    }
-                    Ok(Event::Test(Test::Ignored(ignored))) => {
-                        if json {
-                            serde_json::to_writer(std::io::stderr(), &ignored)
-                                .context("write json message")?;
-                        } else {
-                            test_status::<colors::Yellow>(&ignored.name, "ignored")
-                        }
-                    }
-                    Ok(Event::Suite(Suite::Started(started))) => {
-                        if json {
-                            serde_json::to_writer(std::io::stderr(), &started)
-                                .context("write json message")?;
-                        } else {
-                            eprintln!("\nrunning {} tests", started.test_count);
-                        }
-                    }
-                    Ok(Event::Suite(Suite::Ok(ok))) => {
-                        if json {
-                            serde_json::to_writer(std::io::stderr(), &ok)
-                                .context("write json message")?;
-                        } else {
-                            let SuiteOk {
-                                passed,
-                                failed,
-                                ignored,
-                                measured,
-                                filtered_out,
-                                ..
-                            } = ok;
-                            eprintln!("\ntest result: ok. {passed} passed; {failed} failed; {ignored} ignored; {measured} measured; {filtered_out} filtered out; finished in {:?}", t0.elapsed());
-                        }
-                    }
-                    Ok(Event::Suite(Suite::Failed(suite_failed))) => {
-                        if json {
-                            serde_json::to_writer(std::io::stderr(), &suite_failed)
-                                .context("write json message")?;
-                        } else {
-                            let SuiteFailed {
-                                passed,
-                                failed,
-                                ignored,
-                                measured,
-                                filtered_out,
-                                ..
-                            } = suite_failed;
-                            eprintln!("\ntest result: FAILED. {passed} passed; {failed} failed; {ignored} ignored; {measured} measured; {filtered_out} filtered out; finished in {:?}", t0.elapsed());
-                        }
-                    }
-                    Err(error) => tracing::warn!(
-                        suite = %suite.name(),
-                        %error,
-                        "error from test",
-                    ),
-                    Ok(msg) if json => {
-                        serde_json::to_writer(std::io::stderr(), &msg)
-                            .context("write json message")?;
-                    }
-                    _ => {} // TODO(eliza: do something nice here...
+            let first = failed_first
+                .get(suite.name())
+                .map_or(&[][..], Vec::as_slice);
+            let groups = match self.test_groups(&suite, filter, &skipped, first)? {
+                Some(groups) => groups,
+                None => {
+                    *runs.entry(checkpoint_dir.clone()).or_insert(0) += 1;
+                    tasks.spawn(discovery.clone().run(suite, cmd, checkpoint_dir, skipped));
+                    continue;
+                }
+            };
+            if groups.is_empty() {
+                // None of the suite's tests are in this shard (or sample),
+                // but its checkpointed tests (which are) still failed.
+                tracing::debug!(
+                    suite = suite.name(),
+                    "No tests to run in this shard or sample"
+                );
+                if !skipped.is_empty() {
+                    checkpointed.push((suite, checkpoint_dir, skipped));
+                }
+                continue;
+            }
+
+            // Tests with overridden bounds are run in their own processes,
+            // and tests are only sharded by naming them, so every test is
+            // named exactly.
+            let mut skipped = Some(skipped);
+            for (max_threads, tests) in groups {
+                let mut cmd = suite.command();
+                self.configure_discovery_command(&mut cmd, settings);
+                if let Some(max_threads) = max_threads {
+                    cmd.env(ENV_MAX_THREADS, max_threads.to_string());
                 }
+                test_args::exact(&mut cmd).args(&tests);
+                *runs.entry(checkpoint_dir.clone()).or_insert(0) += 1;
+                tasks.spawn(discovery.clone().run(
+                    suite.clone(),
+                    cmd,
+                    checkpoint_dir.clone(),
+                    skipped.take().unwrap_or_default(),
+                ));
             }
+        }
+        if let Some(ref timings) = self.timings {
+            timings.record(
+                &pkg.name,
+                label.as_deref(),
+                timings::Phase::Build,
+                None,
+                build_started,
+            );
+        }
 
+        for (suite, checkpoint_dir, tests) in checkpointed {
+            tracing::info!(
+                "Skipping {} tests previously checkpointed as failing in {}; run with \
+                `--verify-checkpoints` to re-evaluate them",
+                tests.len(),
+                suite.name(),
+            );
+            for test in tests {
+                failed.fail_test(&suite, test, &checkpoint_dir, None);
+            }
             failed.finish_suite(suite);
         }
+        while let Some(run) = tasks.join_one().await? {
+            let mut run = run?;
+            self.title.failed(run.failed.len());
+            if let (Some(ref timings), Some(started)) = (&self.timings, run.started) {
+                timings.record(
+                    &pkg.name,
+                    label.as_deref(),
+                    timings::Phase::Discovery,
+                    Some(run.suite.name()),
+                    started,
+                );
+            }
+            let (found, counts, complete) = found
+                .entry(run.checkpoint_dir.clone())
+                .or_insert_with(|| (Vec::new(), summary::Counts::default(), true));
+            // A suite stopped by `--fail-fast` didn't finish.
+            *complete &= (run.counts.not_run == 0 || run.timed_out) && !run.skipped;
+            *counts += run.counts;
+            if self.args.loom.smoke {
+                // Passing tests are run again, and their results from then
+                // are the ones that count.
+                if !run.passed.is_empty() {
+                    smoke_passed.push((
+                        run.suite.clone(),
+                        run.checkpoint_dir.clone(),
+                        std::mem::take(&mut run.passed),
+                    ));
+                }
+                run.partially_explored.clear();
+                run.fully_explored.clear();
+                run.results
+                    .retain(|entry| entry.outcome == history::Outcome::Failed);
+            }
+            if self.args.loom.retries > 0 && !run.failed.is_empty() {
+                retry.push((run.suite.clone(), run.failed.clone()));
+            }
+            for test in run.failed {
+                let panic = run.panics.remove(&test);
+                found.push(interrupt::FailedTest {
+                    name: test.clone(),
+                    panic: panic.clone(),
+                });
+                failed.fail_test(&run.suite, test, &run.checkpoint_dir, panic);
+            }
+            let remaining = runs.get_mut(&run.checkpoint_dir).map_or(0, |runs| {
+                *runs -= 1;
+                *runs
+            });
+            // With `--smoke`, a suite isn't finished until its passing tests
+            // have been run again, so an interrupted run starts it over.
+            if remaining == 0 && *complete && !self.args.loom.smoke {
+                if let Some(binary) = run.checkpoint_dir.file_name() {
+                    self.progress
+                        .lock()
+                        .unwrap_or_else(|poison| poison.into_inner())
+                        .suite_finished(
+                            &pkg.name,
+                            label.as_deref(),
+                            interrupt::FinishedSuite {
+                                binary: binary.to_owned(),
+                                suite: run.suite.name().to_owned(),
+                                counts: *counts,
+                                failed: std::mem::take(found),
+                            },
+                        );
+                }
+            }
+            failed.counts += run.counts;
+            failed.partially_explored.extend(run.partially_explored);
+            failed.fully_explored.extend(run.fully_explored);
+            if run.timed_out {
+                failed.timed_out_suites.push(run.suite.name().to_owned());
+            }
+            if run.skipped {
+                failed.skipped_suites.push(run.suite.name().to_owned());
+            }
+            failed.timed_out_tests.extend(
+                run.timed_out_tests
+                    .iter()
+                    .map(|test| format!("{}::{}", run.suite.name(), test)),
+            );
+            failed.slow.extend(run.slow);
+            failed.history.extend(run.results);
+            if self.args.loom.escalate_on_pass && !run.passed.is_empty() {
+                failed.passed.push((run.suite.clone(), run.passed));
+            }
+            failed.finish_suite(run.suite);
+        }
+        self.explore_smoke_passed(
+            &discovery,
+            full_settings,
+            smoke_passed,
+            &mut failed,
+            &mut retry,
+        )
+        .await?;
+        discovery.finish();
+
+        for (suite, test) in self.retry(settings, retry, self.args.loom.retries).await? {
+            if let Some(tests) = failed.failed.get_mut(suite.as_str()) {
+                tests.retain(|failed| failed.name != test);
+                if tests.is_empty() {
+                    failed.failed.remove(suite.as_str());
+                }
+            }
+            failed.counts.failed -= 1;
+            failed.counts.passed += 1;
+            for entry in &mut failed.history {
+                if entry.suite == suite && entry.name == test {
+                    entry.outcome = history::Outcome::Flaky;
+                }
+            }
+            failed.flaky.push(format!("{}::{}", suite, test));
+        }
+
+        // If the package has no tests at all when built with `--cfg loom`,
+        // that's probably not what the user wanted.
+        if failed.counts.tests + failed.counts.filtered_out == 0 {
+            if let Some(shard) = self.args.ci.shard {
+                tracing::info!("No tests in `{}` are in shard {}", pkg.name, shard);
+                return Ok(failed);
+            }
+            tracing::warn!(
+                package = %pkg.name,
+                "no tests were found when building with `--cfg loom`; check that \
+                `#[cfg(loom)]` gates and required features are enabled",
+            );
+        } else {
+            tracing::info!(
+                filtered_out = failed.counts.filtered_out,
+                "Found {} tests in `{}`",
+                failed.counts.tests,
+                pkg.name
+            );
+        }
 
         Ok(failed)
     }
 
-    fn run_failed(&self, failed: &mut Failed) -> Result<JoinSet<Result<TestOutput>>> {
+    /// Returns the directory the checkpoints of the failing tests in `suite`
+    /// are written to, which is specific to the test binary's hash.
+    fn suite_checkpoint_dir(&self, suite: &TestSuite) -> Result<Utf8PathBuf> {
+        let bin_path = suite
+            .path()
+            .file_name()
+            .ok_or_else(|| eyre!("test binary must have a file name"))
+            .and_then(|os_str| {
+                os_str
+                    .to_str()
+                    .ok_or_else(|| eyre!("binary path was not utf8"))
+            })
+            .with_note(|| format!("bin path: {}", suite.path().display()))?;
+        Ok(self.checkpoint_dir.as_path().join(bin_path))
+    }
+
+    /// Reruns each failing test to generate a checkpoint, and then again with
+    /// logging enabled.
+    ///
+    /// The output of each logged rerun is written to a log file in the
+    /// artifacts directory (if there is one), or next to the test's
+    /// checkpoint.
+    ///
+    /// If a `label` is provided, it is appended to the name of each test (e.g.
+    /// to indicate which feature set the failure was found with).
+    fn run_failed(
+        &self,
+        package: &str,
+        failed: &mut Failed,
+        config: &Config,
+        settings: &Settings,
+        label: Option<&str>,
+    ) -> Result<Reruns> {
         let mut tasks = JoinSet::new();
+        let skipped = Arc::new(Mutex::new(Vec::new()));
+        let prefixed = self.args.trace_settings.rerun_output() == trace::RerunOutput::Prefixed;
+        let backtrace_style = self.args.trace_settings.backtrace_style();
+        let prefix_width = failed
+            .failed
+            .values()
+            .flatten()
+            .map(|test| test.name.len())
+            .max()
+            .unwrap_or(0);
+        let mut idx = 0;
         for (suite, tests) in failed.failed.drain() {
             let suite = failed
                 .test_cmds
                 .get(&suite)
                 .ok_or_else(|| eyre!("missing test command for suite `{}`", suite))?;
-            for FailedTest { name, checkpoint } in tests {
+            for FailedTest {
+                name,
+                checkpoint,
+                panic,
+                kind,
+            } in tests
+            {
                 let mut cmd = Command::new(suite.path());
-                self.configure_loom_command(&mut cmd)
-                    .env(ENV_CHECKPOINT_INTERVAL, &self.checkpoint_interval)
-                    .env(ENV_CHECKPOINT_FILE, &checkpoint)
+                self.configure_checkpoint_command(&mut cmd, settings, &checkpoint)
+                    .env(
+                        ENV_MAX_THREADS,
+                        self.max_threads(settings, &name).to_string(),
+                    )
                     .arg(&name);
-                let loom_log = self.loom_log.clone();
-                let pretty_name = format!("{suite}::{name}", suite = suite.name());
+                let log = match self.artifacts_dir {
+                    Some(ref artifacts_dir) => {
+                        let dir = artifacts_dir.join(package).join(suite.name());
+                        fs::create_dir_all(&dir)
+                            .with_context(|| format!("creating artifacts directory `{}`", dir))?;
+                        let log = dir.join(format!("{}.log", file_stem(&name)));
+                        self.artifacts
+                            .lock()
+                            .unwrap_or_else(|poison| poison.into_inner())
+                            .push(log.clone());
+                        log
+                    }
+                    None => checkpoint.with_extension("log"),
+                };
+                let loom_log = config
+                    .loom_log(suite.name(), &name)
+                    .unwrap_or(&settings.loom_log);
+                let rerun_env = self.rerun_env(loom_log);
+                let nocapture = self.rerun_nocapture();
+                let test_timeout = self.test_timeout;
+                let checkpoint_timeout = self.checkpoint_timeout;
+                let retries = self.args.loom.rerun_retries;
+                let only_failed = self.args.loom.failed;
+                let single_pass = self.args.loom.single_pass;
+                let processes = self.processes.clone();
+                let deadline = self.deadline();
+                let skipped = skipped.clone();
+                let memory = self.memory.clone();
+                let cpus = self.cpus.clone();
+                let timings = self.timings.clone();
+                let package = package.to_owned();
+                let features = label.map(ToOwned::to_owned);
+                let suite_name = suite.name().to_owned();
+                let mut pretty_name = format!("{suite}::{name}", suite = suite.name());
+                if let Some(label) = label {
+                    pretty_name.push_str(&format!(" [{label}]"));
+                }
+                // With `--raw-test-output`, stdout is reserved for libtest's
+                // JSON.
+                let prefix = prefixed.then(|| {
+                    prefixed::Prefix::new(
+                        name.clone(),
+                        idx,
+                        prefix_width,
+                        self.args.trace_settings.color(),
+                        self.args.trace_settings.raw_test_output(),
+                    )
+                });
+                idx += 1;
                 let task = async move {
-                    let t0 = Instant::now();
                     let mut cmd = tokio::process::Command::from(cmd);
-                    if checkpoint.exists() {
-                        tracing::debug!(test = %pretty_name, "Already checkpointed", )
+                    let slot = processes.acquire().await;
+                    let admitted = memory.admit().await;
+                    let cpu = match cpus {
+                        Some(ref cpus) => Some(cpus.acquire().await),
+                        None => None,
+                    };
+                    if let Some(ref cpu) = cpu {
+                        cpu.pin(&mut cmd);
+                    }
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        tracing::debug!(
+                            test = %pretty_name,
+                            "Not rerunning, because the run reached `--total-timeout`"
+                        );
+                        skipped
+                            .lock()
+                            .unwrap_or_else(|poison| poison.into_inner())
+                            .push(pretty_name);
+                        return Ok(None);
+                    }
+                    let t0 = Instant::now();
+                    let mut checkpoint_timed_out = false;
+                    if checkpoint::exists(&checkpoint) {
+                        tracing::debug!(test = %pretty_name, file = %checkpoint, "Already checkpointed")
+                    } else if single_pass {
+                        // The logged run below writes the checkpoint, too.
+                        tracing::info!(test = %pretty_name, file = %checkpoint, "Checkpointing with logging");
                     } else {
-                        tracing::info!(test = %pretty_name, "Generating checkpoint");
+                        tracing::info!(test = %pretty_name, file = %checkpoint, "Generating checkpoint");
                         tracing::trace!(?cmd);
-                        let _ = cmd
-                            .stderr(Stdio::null())
-                            .stdout(Stdio::null())
-                            .status()
-                            .await
-                            .with_context(|| format!("spawn process to checkpoint {pretty_name}"));
+                        let (status, killed_by) =
+                            process::status(&mut cmd, &slot, checkpoint_timeout, Some(&admitted))
+                                .await
+                                .with_context(|| {
+                                    format!("spawn process to checkpoint {pretty_name}")
+                                })?;
+                        if let Some(signal) = killed_by {
+                            tracing::warn!(
+                                test = %pretty_name,
+                                signal = %signal.as_str(),
+                                "checkpointing timed out; rerunning it with logging anyway"
+                            );
+                            checkpoint_timed_out = true;
+                        }
                         let elapsed = t0.elapsed();
                         tracing::debug!(test = %pretty_name, ?elapsed, file = %checkpoint, "checkpointed");
+                        if let Some(ref timings) = timings {
+                            timings.record(
+                                &package,
+                                features.as_deref(),
+                                timings::Phase::Checkpoint,
+                                Some(&format!("{}::{}", suite_name, name)),
+                                t0,
+                            );
+                        }
+                        // If the test was killed while writing its checkpoint,
+                        // the rerun starts from the beginning instead.
+                        let checkpointed = checkpoint::exists(&checkpoint);
+
+                        // With `--failed`, the test wasn't just found to fail,
+                        // so it may have been fixed since. Rerunning its whole
+                        // model with logging would only show that it passes.
+                        if only_failed && status.success() && !checkpointed {
+                            return Ok(Some(TestOutput {
+                                name: pretty_name,
+                                test: name,
+                                suite: suite_name,
+                                checkpoint,
+                                log,
+                                status,
+                                stdout: process::Tail::default(),
+                                killed_by: None,
+                                checkpoint_timed_out: false,
+                                duration: elapsed,
+                                attempts: 0,
+                                discovery_panic: panic,
+                                discovery_kind: kind,
+                                fixed: true,
+                            }));
+                        }
                     }
 
                     // now, run it again with logging
-                    let output = cmd
-                        .env(ENV_LOOM_LOG, loom_log.as_ref())
-                        .env(ENV_LOOM_LOCATION, "1")
-                        .output()
+                    cmd.envs(rerun_env);
+                    if nocapture {
+                        cmd.arg("--nocapture");
+                    }
+                    let header = log_header::header(&pretty_name, cmd.as_std());
+                    let mut attempts = 0;
+                    let rerun_started = Instant::now();
+                    let (finished, duration) = loop {
+                        let t1 = Instant::now();
+                        let prefix = prefix
+                            .as_ref()
+                            .map(|prefix| (prefix, backtrace::Filter::new(backtrace_style)));
+                        let finished = process::output(
+                            &mut cmd,
+                            &slot,
+                            test_timeout,
+                            prefix,
+                            Some((&log, &header)),
+                            Some(&admitted),
+                        )
                         .await
                         .with_context(|| format!("spawn process to rerun {pretty_name}"))?;
+                        attempts += 1;
+                        // The failure may not reproduce from the checkpoint if
+                        // the test is nondeterministic outside of loom's
+                        // control, so give it a few more chances.
+                        let passed = finished.status.success() && finished.killed_by.is_none();
+                        if !passed || attempts > retries {
+                            break (finished, t1.elapsed());
+                        }
+                        tracing::info!(
+                            test = %pretty_name,
+                            "Retrying rerun which passed ({}/{})",
+                            attempts,
+                            retries
+                        );
+                    };
+                    if let Some(ref timings) = timings {
+                        timings.record(
+                            &package,
+                            features.as_deref(),
+                            timings::Phase::Rerun,
+                            Some(&format!("{}::{}", suite_name, name)),
+                            rerun_started,
+                        );
+                    }
+                    // Like a checkpointing run with `--failed`, a single pass
+                    // that passes without writing a checkpoint ran the whole
+                    // model, so the test was fixed.
+                    let fixed = single_pass
+                        && only_failed
+                        && finished.status.success()
+                        && finished.killed_by.is_none()
+                        && !checkpoint::exists(&checkpoint);
                     let output = TestOutput {
                         name: pretty_name,
-                        output,
+                        test: name,
+                        suite: suite_name,
+                        checkpoint,
+                        log,
+                        status: finished.status,
+                        stdout: finished.stdout,
+                        killed_by: finished.killed_by,
+                        checkpoint_timed_out,
+                        duration,
+                        attempts,
+                        discovery_panic: panic,
+                        discovery_kind: kind,
+                        fixed,
                     };
-                    Ok(output)
+                    Ok(Some(output))
                 };
                 tasks.spawn(task);
             }
         }
-        Ok(tasks)
+        Ok(Reruns { tasks, skipped })
     }
 
-    fn from_args(mut args: AppArgs) -> Result<Self> {
-        color_eyre::config::HookBuilder::default()
-            .issue_url(concat!(env!("CARGO_PKG_REPOSITORY"), "/issues/new"))
-            .add_issue_metadata("version", env!("CARGO_PKG_VERSION"))
-            .add_issue_metadata(
-                "args",
-                std::env::args().fold(String::new(), |mut s, arg| {
-                    s.push_str(arg.as_str());
-                    s.push(' ');
-                    s
-                }),
-            )
-            .issue_filter(|kind| match kind {
-                color_eyre::ErrorKind::NonRecoverable(_) => true,
-                color_eyre::ErrorKind::Recoverable(error) =>
-                // Skip any IO errors and any errors forwarded from a cargo
-                // subcommand, as these may not be our fault.
-                {
-                    error_is_issue(error)
-                }
-            })
-            .display_env_section(true)
-            .add_default_filters()
-            .add_frame_filter(Box::new(|frames| {
-                const SKIPPED: &[&str] = &[
-                    "tokio::runtime",
-                    "tokio::coop",
-                    "tokio::park",
-                    "std::thread::local",
-                ];
-                frames.retain(|frame| match frame.name.as_ref() {
-                    Some(name) => !SKIPPED.iter().any(|prefix| name.starts_with(prefix)),
-                    None => true,
-                })
-            }))
-            .install()?;
+    fn from_args(mut args: AppArgs, tracing: trace::Tracing) -> Result<Self> {
+        if args.ci.ci {
+            args.trace_settings.ci_defaults();
+        }
+        // The error report hooks are global, so they're only installed by the
+        // first `App` in a process.
+        static ERROR_HOOKS: Once = Once::new();
+        let mut installed = Ok(());
+        ERROR_HOOKS.call_once(|| installed = install_error_hooks(&args.trace_settings));
+        installed?;
         args.trace_settings
-            .try_init()
+            .init(tracing)
             .context("initialize tracing")?;
-        let metadata = args.metadata()?;
+        loom_env::validate(&args.loom)?;
+        let toolchain = Toolchain::resolve(
+            args.cargo.toolchain.as_deref(),
+            args.cargo.manifest_path.as_deref(),
+        )?;
+        let features = FeatureSelection::new(&args.cargo.features);
+        let (metadata, default_members) = args.metadata(&toolchain, &features)?;
         let target_dir = {
             let mut target_dir = metadata.workspace_root.clone();
             target_dir.push("target");
@@ -558,110 +2383,567 @@ impl App {
         fs::create_dir_all(checkpoint_dir.as_os_str())
             .with_context(|| format!("creating checkpoint directory `{}`", checkpoint_dir))?;
 
-        let mut features = String::new();
-        let mut feature_list = args.cargo.features.features.iter();
-        if let Some(feature) = feature_list.next() {
-            features.push_str(feature);
-            for feature in feature_list {
-                features.push(' ');
-                features.push_str(feature);
+        let feature_sets = match args.cargo.feature_matrix.as_deref() {
+            Some(entries) => features.matrix(entries)?,
+            None => vec![features],
+        };
+        let wanted_packages = args.wanted_packages(&metadata, default_members.as_deref());
+        for (kind, name) in args.cargo.named_targets() {
+            if !wanted_packages
+                .iter()
+                .any(|pkg| has_target(pkg, kind, name))
+            {
+                return Err(eyre!(
+                    "no {} target named `{}` in the packages being tested",
+                    kind,
+                    name
+                ));
             }
         }
+        for features in &feature_sets {
+            features.validate(&wanted_packages)?;
+        }
+
+        let jobs = match args.cargo.jobs {
+            Some(0) => return Err(eyre!("`--jobs` may not be 0")),
+            Some(jobs) => jobs,
+            None => num_cpus::get(),
+        };
+        if args.cargo.package_jobs == 0 {
+            return Err(eyre!("`--package-jobs` may not be 0"));
+        }
+        if args.cargo.test_threads == Some(0) {
+            return Err(eyre!("`--test-threads` may not be 0"));
+        }
+        if args.cargo.test_threads.is_some()
+            && args
+                .test_args
+                .iter()
+                .any(|arg| arg == "--test-threads" || arg.starts_with("--test-threads="))
+        {
+            return Err(eyre!(
+                "`--test-threads` was passed both to `cargo loom` and to the test binaries"
+            ))
+            .note("pass it before the `--`, so that it only applies when finding failing tests");
+        }
+
         let mut rustflags = std::env::var("RUSTFLAGS").unwrap_or_default();
         if !rustflags.is_empty() {
             rustflags.push(' ');
         }
-        rustflags.push_str("--cfg loom --cfg debug_assertions");
+        // `-C debug-assertions` rather than `--cfg debug_assertions`, which
+        // rustc rejects as a builtin cfg. Unlike the cfg, it also enables
+        // overflow checks in profiles (such as `release`) that disable them.
+        rustflags.push_str("--cfg loom -C debug-assertions");
+        let coverage = args
+            .loom
+            .coverage
+            .then(|| coverage::Coverage::new(&target_dir));
+        let timings = args
+            .loom
+            .timings
+            .then(|| Arc::new(timings::Timings::new(&target_dir)));
+        if coverage.is_some() {
+            rustflags.push(' ');
+            rustflags.push_str(coverage::RUSTFLAGS);
+        }
+        let target_cfgs = suite::target_cfgs(toolchain.rustc(), &rustflags);
+
+        // `--exact`, `--skip`, `--ignored`, and `--include-ignored` are
+        // passed on to the test binaries, so they apply to every run of them,
+        // like the arguments after `--`. libtest rejects flags that are
+        // passed twice.
+        let mut test_args = args.test_args.clone();
+        for (flag, arg) in [
+            (args.exact, "--exact"),
+            (args.ignored, "--ignored"),
+            (args.include_ignored, "--include-ignored"),
+        ] {
+            if flag && !test_args.iter().any(|test_arg| test_arg == arg) {
+                test_args.push(arg.to_owned());
+            }
+        }
+        for skip in &args.skip {
+            test_args.push("--skip".to_owned());
+            test_args.push(skip.clone());
+        }
+        let test_filters = test_args::Filters::parse(&test_args);
+        let sample_seed = args.ci.sample_seed.unwrap_or_else(sample::default_seed);
+        if let Some(sample) = args.ci.sample {
+            tracing::info!("Sampling {}, with `--sample-seed {}`", sample, sample_seed);
+        }
+        let shuffle_seed = args.shuffle_seed.or_else(|| args.shuffle.then(random_seed));
+        if let Some(seed) = shuffle_seed {
+            tracing::info!("Shuffling the tests, with `--shuffle-seed {}`", seed);
+        }
+        let test_args = Arc::new(test_args);
 
-        // These all need to be represented as strings to pass them as env
-        // variables. Format them a single time so we don't have to do it every
-        // time we run a test.
-        let max_duration = args
+        let test_timeout = args
             .loom
-            .max_duration_secs
-            .as_ref()
-            .map(ToString::to_string);
-        let max_permutations = args.loom.max_permutations.as_ref().map(ToString::to_string);
-        let max_preemptions = args.loom.max_preemptions.as_ref().map(ToString::to_string);
-        let max_branches = args.loom.max_branches.to_string();
-        let max_threads = args.loom.max_threads.to_string();
-        let checkpoint_interval = args.loom.checkpoint_interval.to_string();
-        let loom_log = Arc::from(args.loom.loom_log.clone());
-        let test_args = Arc::from(args.test_args.clone());
-        Ok(Self {
+            .test_timeout_secs
+            .or_else(|| args.ci.ci.then_some(ci::DEFAULT_TEST_TIMEOUT_SECS))
+            .map(Duration::from_secs);
+        let kill_grace_period = Duration::from_secs(args.loom.kill_grace_period);
+        let test_timeout = process::Timeout::new(test_timeout, kill_grace_period);
+        let suite_timeout = process::Timeout::new(
+            args.loom.suite_timeout_secs.map(Duration::from_secs),
+            kill_grace_period,
+        );
+        let checkpoint_timeout = process::Timeout::new(
+            args.loom
+                .checkpoint_timeout_secs
+                .map(Duration::from_secs)
+                .or_else(|| test_timeout.limit()),
+            kill_grace_period,
+        );
+        if args.loom.nice.is_some() && !cfg!(unix) {
+            tracing::warn!("`--nice` is not supported on this platform, and will be ignored");
+        }
+        if args.loom.memory_limit.is_some() && !cfg!(unix) {
+            tracing::warn!(
+                "`--memory-limit` is not supported on this platform, and will be ignored"
+            );
+        }
+        let cpus = if !args.loom.pin_cpus {
+            None
+        } else if affinity::is_supported() {
+            let cpus = affinity::CpuPool::new().context("finding CPUs to pin tests to")?;
+            Some(Arc::new(cpus))
+        } else {
+            tracing::warn!("`--pin-cpus` is not supported on this platform, and will be ignored");
+            None
+        };
+        let artifacts_dir = args
+            .ci
+            .artifacts_dir
+            .clone()
+            .or_else(|| args.ci.ci.then(|| target_dir.join("artifacts")));
+        let summary_file = args.ci.summary_file.clone().or_else(|| {
+            let artifacts_dir = artifacts_dir.as_ref().filter(|_| args.ci.ci)?;
+            Some(artifacts_dir.join("summary.json"))
+        });
+        let title = TerminalTitle::detect(args.trace_settings.progress());
+        let log_groups = if args.ci.ci {
+            LogGroups::detect()
+        } else {
+            LogGroups::None
+        };
+
+        let app = Self {
             args,
+            toolchain,
             metadata,
+            default_members,
             target_dir,
             checkpoint_dir,
-            features,
+            feature_sets,
+            processes: Arc::new(process::Pool::new(jobs)),
+            memory: Arc::new(memory::Monitor::default()),
             rustflags,
-            max_branches,
-            max_duration,
-            max_permutations,
-            max_preemptions,
-            max_threads,
-            checkpoint_interval,
-            loom_log,
+            target_cfgs,
             test_args,
-        })
+            test_filters,
+            sample_seed,
+            shuffle_seed,
+            test_timeout,
+            suite_timeout,
+            checkpoint_timeout,
+            deadline: Mutex::new(None),
+            cpus,
+            artifacts_dir,
+            artifacts: Mutex::new(Vec::new()),
+            summary_file,
+            output: tokio::sync::Mutex::new(()),
+            coverage,
+            timings,
+            log_groups,
+            title,
+            tested: Mutex::new(HashSet::new()),
+            progress: Mutex::new(interrupt::Progress::default()),
+            resumed: Mutex::new(None),
+        };
+        if let Some(test_threads) = app.args.cargo.test_threads {
+            let settings = app.settings(&Config::load_root(&app.metadata)?);
+            if test_threads > 1 && settings.max_duration_secs.is_some() {
+                tracing::warn!(
+                    "with `--test-threads {}` and a `--max-duration-secs` limit, which tests \
+                    fail may depend on how busy the machine is, since models explored at the \
+                    same time are slower",
+                    test_threads
+                );
+            }
+        }
+        if app.args.loom.resume {
+            *app.resumed
+                .lock()
+                .unwrap_or_else(|poison| poison.into_inner()) = app.load_progress()?;
+        }
+        Ok(app)
     }
 
     fn wanted_packages(&self) -> Vec<&cargo_metadata::Package> {
-        self.args
-            .cargo
-            .workspace
-            .partition_packages(&self.metadata)
-            .0
+        let mut packages = self
+            .args
+            .wanted_packages(&self.metadata, self.default_members.as_deref());
+        if let Some(seed) = self.shuffle_seed {
+            packages.sort_by_key(|pkg| {
+                shard::fnv1a(seed.to_le_bytes().iter().chain(pkg.name.as_bytes()))
+            });
+        }
+        packages
+    }
+
+    /// Returns when the current run reaches `--total-timeout`.
+    fn deadline(&self) -> Option<Instant> {
+        *self
+            .deadline
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+    }
+
+    /// Returns the `--max-threads` that `test` is run with.
+    fn max_threads(&self, settings: &Settings, test: &str) -> usize {
+        overrides::Override::max_threads(&self.args.loom.overrides, test)
+            .unwrap_or(settings.max_threads)
+    }
+
+    /// Returns why `test` in `suite` isn't part of this run with `filter`
+    /// (because of the filters passed to the test binaries, `--shard`, or
+    /// `--sample`), or `None` if it is.
+    fn excludes(&self, filter: TestFilter<'_>, suite: &str, test: &str) -> Option<String> {
+        if let Some(reason) = self.test_filters.excludes(filter, test) {
+            return Some(reason);
+        }
+        match self.args.ci.shard {
+            Some(shard) if !shard.includes(suite, test) => {
+                return Some(format!("it's in a different shard than {}", shard))
+            }
+            _ => {}
+        }
+        match self.args.ci.sample {
+            Some(sample) if !sample.includes(self.sample_seed, suite, test) => {
+                Some("it isn't in the `--sample`".to_owned())
+            }
+            _ => None,
+        }
+    }
+
+    /// Groups the tests in `suite` which `filter` includes (other than
+    /// `skipped` ones, and those in other `--shard`s or outside the
+    /// `--sample`) by the `--max-threads` they're run with, if it's
+    /// overridden for any of them.
+    ///
+    /// Tests with the default bound are grouped under `None`. If every test
+    /// the filter includes can be run at once, `None` is returned instead.
+    ///
+    /// The tests in `first` (which failed in the last run, with
+    /// `--failed-first`) are grouped separately, ahead of the others.
+    fn test_groups(
+        &self,
+        suite: &suite::TestSuite,
+        filter: TestFilter<'_>,
+        skipped: &[String],
+        first: &[String],
+    ) -> Result<Option<TestGroups>> {
+        let shard = self.args.ci.shard;
+        let sample = self.args.ci.sample;
+        if self.args.loom.overrides.is_empty()
+            && shard.is_none()
+            && sample.is_none()
+            && first.is_empty()
+        {
+            return Ok(None);
+        }
+
+        let tests = self.list_tests(suite, filter)?;
+        let mut first_groups: TestGroups = Vec::new();
+        let mut groups: TestGroups = vec![(None, Vec::new())];
+        let mut overridden = false;
+        for test in tests
+            .iter()
+            .filter(|test| !skipped.iter().any(|skipped| skipped == *test))
+        {
+            let max_threads = overrides::Override::max_threads(&self.args.loom.overrides, test);
+            overridden |= max_threads.is_some();
+            let groups = if first.contains(test) {
+                &mut first_groups
+            } else {
+                &mut groups
+            };
+            match groups.iter_mut().find(|(group, _)| *group == max_threads) {
+                Some((_, tests)) => tests.push(test.to_owned()),
+                None => groups.push((max_threads, vec![test.to_owned()])),
+            }
+        }
+        if !overridden && shard.is_none() && sample.is_none() && first_groups.is_empty() {
+            return Ok(None);
+        }
+
+        first_groups.extend(groups);
+        first_groups.retain(|(_, tests)| !tests.is_empty());
+        Ok(Some(first_groups))
+    }
+
+    /// Lists the tests in `suite` which match `filter`, leaving out those
+    /// excluded by `--skip`, `--shard`, or `--sample`.
+    fn list_tests(&self, suite: &suite::TestSuite, filter: TestFilter<'_>) -> Result<Vec<String>> {
+        let mut cmd = Command::new(suite.path());
+        cmd.arg("--list").arg("--format").arg("terse");
+        if self.test_args.iter().any(|arg| arg == "--ignored") {
+            cmd.arg("--ignored");
+        }
+        filter.apply(&mut cmd);
+        let output = cmd
+            .stderr(Stdio::inherit())
+            .output()
+            .with_context(|| format!("listing tests in `{}`", suite.name()))?;
+        if !output.status.success() {
+            return Err(eyre!(
+                "listing tests in `{}` failed ({})",
+                suite.name(),
+                output.status
+            ));
+        }
+
+        let mut tests = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.strip_suffix(": test"))
+            .filter(|test| self.excludes(filter, suite.name(), test).is_none())
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>();
+        if let Some(sample) = self.args.ci.sample {
+            sample.choose(self.sample_seed, suite.name(), &mut tests);
+        }
+        Ok(tests)
     }
 
-    fn test_cmd(&self, pkg: &cargo_metadata::Package) -> escargot::CargoBuild {
-        let mut cmd = escargot::Cargo::new()
-            .build_with("test")
+    /// Builds `pkg`'s tests with `features`, showing the build's progress,
+    /// and yields each test binary as it's built.
+    fn build_tests<'a>(
+        &'a self,
+        pkg: &cargo_metadata::Package,
+        settings: &Settings,
+        features: &FeatureSelection,
+    ) -> Result<impl Iterator<Item = Result<suite::TestSuite>> + 'a> {
+        suite::build(
+            self.test_cmd(pkg, settings, features),
+            self.build_progress(pkg, settings),
+        )
+    }
+
+    /// Like [`App::build_tests`], but reads the build's output on a blocking
+    /// thread, so that other packages can run while this one is built.
+    pub(crate) fn spawn_build_tests(
+        &self,
+        pkg: &cargo_metadata::Package,
+        settings: &Settings,
+        features: &FeatureSelection,
+    ) -> Result<suite::Build<'_>> {
+        suite::spawn_build(
+            self.test_cmd(pkg, settings, features),
+            self.build_progress(pkg, settings),
+        )
+    }
+
+    fn build_progress(
+        &self,
+        pkg: &cargo_metadata::Package,
+        settings: &Settings,
+    ) -> suite::BuildProgress<'_> {
+        let progress = suite::BuildProgress::new(
+            &self.metadata,
+            &self.target_cfgs,
+            pkg,
+            settings.integration_tests,
+        );
+        if self.args.trace_settings.progress() == trace::ProgressWhen::Never {
+            progress.without_counts()
+        } else {
+            progress
+        }
+    }
+
+    fn test_cmd(
+        &self,
+        pkg: &cargo_metadata::Package,
+        settings: &Settings,
+        features: &FeatureSelection,
+    ) -> Command {
+        let mut cmd = self.toolchain.command();
+        cmd.arg("test")
             .arg("--no-run")
+            .arg("--message-format=json")
             .env("RUSTFLAGS", &self.rustflags)
-            .target_dir(&self.target_dir)
-            .package(&pkg.name)
-            .release();
+            // cargo's stderr is only read if the build fails, so its progress
+            // bar would only fill the pipe. The build's progress is shown by
+            // `suite::build` instead.
+            .env("CARGO_TERM_PROGRESS_WHEN", "never")
+            .arg("--target-dir")
+            .arg(&self.target_dir)
+            .arg("--package")
+            .arg(&pkg.name)
+            .arg("--release");
+
+        if settings.lib {
+            cmd.arg("--lib");
+        }
 
-        if self.args.cargo.lib {
-            cmd = cmd.arg("--lib");
+        if settings.integration_tests {
+            cmd.arg("--tests");
         }
 
-        if self.args.cargo.tests || !self.args.cargo.lib {
-            cmd = cmd.tests()
+        cmd.args(self.args.cargo.target_args(pkg));
+
+        features.configure(pkg, &mut cmd);
+
+        if let Some(jobs) = self.args.cargo.jobs {
+            cmd.arg("--jobs").arg(jobs.to_string());
         }
 
-        if self.args.cargo.features.all_features {
-            cmd = cmd.all_features()
+        if let Some(manifest) = self.args.cargo.manifest_path.as_deref() {
+            cmd.arg("--manifest-path").arg(manifest);
         }
 
-        if self.args.cargo.features.no_default_features {
-            cmd = cmd.no_default_features();
+        cmd
+    }
+
+    /// Configures a test binary to find which of its tests fail.
+    fn configure_discovery_command<'cmd>(
+        &self,
+        cmd: &'cmd mut Command,
+        settings: &Settings,
+    ) -> &'cmd mut Command {
+        // Don't enable checkpoints, logging, or location tracking for this
+        // run. Our goal here is *only* to get the names of the failing
+        // tests so we can re-run them individually with their own
+        // checkpoint files.
+        self.configure_loom_command(cmd, settings)
+            .env(ENV_LOOM_LOG, "off");
+
+        // To tell which passing tests stopped at a bound, we need loom's
+        // iteration log lines, and the output of passing tests.
+        if self.logs_models(settings) {
+            cmd.env(ENV_LOOM_LOG, model_log::LOOM_LOG_MODEL)
+                .arg("--show-output");
         }
 
-        if !&self.args.cargo.features.features.is_empty() {
-            cmd = cmd.features(&self.features)
+        // This isn't added by `configure_loom_command`, because we don't
+        // want to set duration limits when re-running with logging etc (as
+        // it may be slower).
+        if let Some(max_duration) = settings.max_duration_secs {
+            cmd.env(ENV_MAX_DURATION, max_duration.to_string());
         }
 
-        if let Some(manifest) = self.args.cargo.manifest_path.as_deref() {
-            cmd = cmd.manifest_path(manifest);
+        if let Some(test_threads) = self.args.cargo.test_threads {
+            cmd.arg("--test-threads").arg(test_threads.to_string());
+        }
+
+        // libtest measures how long each test took much more accurately than
+        // we can from its output, but only with `--report-time`. libtest
+        // rejects the flag if it's given twice, so leave it alone if it's
+        // already in the test arguments.
+        if !self.test_args.iter().any(|arg| arg == "--report-time") {
+            cmd.arg("--report-time");
+        }
+
+        // Like `--format=json`, libtest only accepts `--shuffle-seed` with
+        // `-Z unstable-options`.
+        if let Some(seed) = self.shuffle_seed {
+            if !self.test_args.iter().any(|arg| arg == "--shuffle-seed") {
+                cmd.arg("--shuffle-seed").arg(seed.to_string());
+            }
         }
 
         cmd
     }
 
-    fn configure_loom_command<'cmd>(&self, cmd: &'cmd mut Command) -> &'cmd mut Command {
-        cmd.env(ENV_MAX_BRANCHES, &self.max_branches);
+    /// Returns whether tests are run with loom's model log while finding
+    /// failing tests, to tell which passing tests stopped at a bound before
+    /// exploring their whole models.
+    ///
+    /// Without a bound, a model that passes has explored its whole state
+    /// space, unless it sets its own bounds, which is only checked with
+    /// `--require-complete`.
+    fn logs_models(&self, settings: &Settings) -> bool {
+        settings.max_permutations.is_some()
+            || settings.max_duration_secs.is_some()
+            || self.args.loom.require_complete
+    }
+
+    /// Configures a test binary to write a failing test's checkpoint to
+    /// `checkpoint`, or to resume from it if it exists.
+    fn configure_checkpoint_command<'cmd>(
+        &self,
+        cmd: &'cmd mut Command,
+        settings: &Settings,
+        checkpoint: &Utf8Path,
+    ) -> &'cmd mut Command {
+        self.configure_loom_command(cmd, settings)
+            .env(
+                ENV_CHECKPOINT_INTERVAL,
+                settings.checkpoint_interval.to_string(),
+            )
+            .env(ENV_CHECKPOINT_FILE, checkpoint)
+    }
+
+    /// Returns whether `--nocapture` is passed to test binaries when failing
+    /// tests are rerun with logging, so that their output is streamed.
+    ///
+    /// libtest rejects the flag if it's given twice, so it isn't passed if
+    /// it's already in the test arguments.
+    fn rerun_nocapture(&self) -> bool {
+        self.args.trace_settings.nocapture()
+            && !self.test_args.iter().any(|arg| arg == "--nocapture")
+    }
+
+    /// Returns the environment variables added to a checkpointing command to
+    /// rerun a failing test with logging.
+    fn rerun_env(&self, loom_log: &str) -> [(&'static str, String); 3] {
+        let backtrace_style = self.args.trace_settings.backtrace_style();
+        [
+            (ENV_LOOM_LOG, loom_log.to_owned()),
+            (ENV_LOOM_LOCATION, "1".to_owned()),
+            (
+                ENV_RUST_BACKTRACE,
+                backtrace_style.rust_backtrace().to_owned(),
+            ),
+        ]
+    }
 
-        if let Some(max_permutations) = self.max_permutations.as_deref() {
-            cmd.env(ENV_MAX_PERMUTATIONS, max_permutations);
+    fn configure_loom_command<'cmd>(
+        &self,
+        cmd: &'cmd mut Command,
+        settings: &Settings,
+    ) -> &'cmd mut Command {
+        if self.args.loom.clean_env {
+            clean_env::configure(&self.args.loom.keep_env, cmd);
         }
 
-        if let Some(max_preemptions) = self.max_preemptions.as_deref() {
-            cmd.env(ENV_MAX_PREEMPTIONS, max_preemptions);
+        cmd.env(ENV_MAX_BRANCHES, settings.max_branches.to_string());
+
+        if let Some(max_permutations) = settings.max_permutations {
+            cmd.env(ENV_MAX_PERMUTATIONS, max_permutations.to_string());
         }
 
-        cmd.env(ENV_MAX_THREADS, &self.max_threads);
+        if let Some(max_preemptions) = settings.max_preemptions {
+            cmd.env(ENV_MAX_PREEMPTIONS, max_preemptions.to_string());
+        }
+
+        cmd.env(ENV_MAX_THREADS, settings.max_threads.to_string());
+
+        loom_env::LoomEnv::configure(&self.args.loom.loom_env, cmd);
+
+        if let Some(ref coverage) = self.coverage {
+            coverage.configure(cmd);
+        }
+
+        if let Some(nice) = self.args.loom.nice {
+            process::set_nice(cmd, nice);
+        }
+
+        if let Some(bytes) = self.args.loom.memory_limit {
+            process::set_memory_limit(cmd, bytes);
+        }
 
         if !self.test_args.is_empty() {
             cmd.args(&self.test_args[..]);
@@ -672,12 +2954,63 @@ impl App {
 }
 
 impl FailedTest {
-    fn new(name: String, checkpoint_dir: impl AsRef<Utf8Path>) -> Self {
-        let checkpoint = checkpoint_dir.as_ref().join(format!("{name}.json"));
-        Self { name, checkpoint }
+    fn new(name: String, checkpoint_dir: impl AsRef<Utf8Path>, panic: Option<String>) -> Self {
+        let checkpoint = checkpoint_dir
+            .as_ref()
+            .join(format!("{}.json", file_stem(&name)));
+        let kind = panic.as_deref().map(discovery::failure_kind);
+        Self {
+            name,
+            checkpoint,
+            panic,
+            kind,
+        }
+    }
+}
+
+impl TestFilter<'_> {
+    /// Passes this filter to a test binary.
+    fn apply(self, cmd: &mut Command) {
+        match self {
+            TestFilter::Contains(None) => {}
+            TestFilter::Contains(Some(testname)) => {
+                cmd.arg(testname);
+            }
+            TestFilter::Exact(tests) => {
+                test_args::exact(cmd).args(tests);
+            }
+        }
     }
 }
 
+/// Returns a seed for `--shuffle`, from the current time and this process's
+/// ID.
+fn random_seed() -> u64 {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos() as u64);
+    shard::fnv1a(
+        nanos
+            .to_le_bytes()
+            .iter()
+            .chain(&std::process::id().to_le_bytes()),
+    )
+}
+
+/// Returns the stem of the names of the files (e.g. checkpoints) for the test
+/// `name`.
+///
+/// The `::`s in the test's path are replaced with `.`s, since `:` isn't
+/// allowed in file names on Windows.
+fn file_stem(test: &str) -> String {
+    test.replace("::", ".")
+}
+
+/// Returns the name of the test whose files are named with `stem`.
+fn test_name(stem: &str) -> String {
+    stem.replace('.', "::")
+}
+
 impl fmt::Display for FailedTest {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         self.name.fmt(f)
@@ -691,14 +3024,16 @@ impl Failed {
 
     fn fail_test(
         &mut self,
-        suite: &CargoTest,
+        suite: &TestSuite,
         test_name: String,
         checkpoint_dir: impl AsRef<Utf8Path>,
+        panic: Option<String>,
     ) {
         let checkpoint_dir = checkpoint_dir.as_ref();
         if !self.checkpoint_dirs.contains(checkpoint_dir) {
             self.checkpoint_dirs.insert(checkpoint_dir.to_path_buf());
         }
+        self.run_suite(suite, checkpoint_dir);
         let suite_name = self
             .curr_suite_name
             .get_or_insert_with(|| Arc::from(suite.name().to_owned()))
@@ -707,46 +3042,259 @@ impl Failed {
         self.failed
             .entry(suite_name)
             .or_default()
-            .push(FailedTest::new(test_name, checkpoint_dir));
+            .push(FailedTest::new(test_name, checkpoint_dir, panic));
+    }
+
+    /// Records that `suite`, with checkpoints in `checkpoint_dir`, was run.
+    fn run_suite(&mut self, suite: &TestSuite, checkpoint_dir: &Utf8Path) {
+        if !self
+            .suite_checkpoint_dirs
+            .iter()
+            .any(|dir| dir.path == checkpoint_dir)
+        {
+            self.suite_checkpoint_dirs.push(summary::CheckpointDir {
+                suite: suite.name().to_owned(),
+                path: checkpoint_dir.to_path_buf(),
+            });
+        }
     }
 
-    fn finish_suite(&mut self, suite: CargoTest) {
+    fn finish_suite(&mut self, suite: TestSuite) {
         if let Some(suite_name) = self.curr_suite_name.take() {
             self.test_cmds.insert(suite_name, suite);
         }
     }
 }
 
+// === impl Reruns ===
+
+impl Reruns {
+    /// Waits for the next rerun to complete, and returns its output, or
+    /// `None` once every rerun has completed.
+    ///
+    /// Any reruns that haven't completed are killed if this is dropped.
+    pub async fn next(&mut self) -> Option<Result<TestOutput>> {
+        loop {
+            match self.tasks.join_one().await {
+                Ok(Some(Ok(None))) => continue,
+                Ok(output) => return output.map(|output| output.map(Option::unwrap)),
+                Err(error) => return Some(Err(error.into())),
+            }
+        }
+    }
+
+    /// Returns the failing tests which weren't rerun, because the run
+    /// reached `--total-timeout`.
+    fn skipped(&self) -> Vec<String> {
+        self.skipped
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner())
+            .clone()
+    }
+
+    /// Returns how many reruns haven't completed yet.
+    pub fn remaining(&self) -> usize {
+        self.tasks.len()
+    }
+}
+
 // === impl TestOutput ===
 
 impl TestOutput {
-    fn name(&self) -> &str {
+    /// Returns the name of the test, including its suite (and its features,
+    /// when running a feature matrix).
+    pub fn name(&self) -> &str {
         self.name.as_str()
     }
 
-    fn stdout(&self) -> Result<&str> {
-        std::str::from_utf8(&self.output.stdout[..])
-            .with_context(|| format!("stdout from test `{}` was not utf8", self.name))
+    /// Returns the name of the test, without the name of its suite.
+    pub fn test(&self) -> &str {
+        self.test.as_str()
+    }
+
+    /// Returns the name of the suite the test is in.
+    pub fn suite(&self) -> &str {
+        self.suite.as_str()
+    }
+
+    /// Returns the path to the test's checkpoint.
+    pub fn checkpoint(&self) -> &Utf8Path {
+        &self.checkpoint
+    }
+
+    /// Returns the path to the file the rerun's output was written to.
+    pub fn log(&self) -> &Utf8Path {
+        &self.log
+    }
+
+    /// Returns the rerun's exit status.
+    pub fn status(&self) -> ExitStatus {
+        self.status
     }
 
-    // fn stderr(&self) -> Result<&str> {
-    //     std::str::from_utf8(&self.output.stderr[..])
-    //         .with_context(|| format!("stderr from test `{}` was not utf8", self.name))
-    // }
+    /// Returns the end of this test's output, with any invalid UTF-8
+    /// replaced. The full output is in the [log file](Self::log).
+    pub fn stdout(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(self.stdout.bytes())
+    }
+
+    fn stdout_is_utf8(&self) -> bool {
+        std::str::from_utf8(self.stdout.bytes()).is_ok()
+    }
+
+    /// Returns whether the rerun was killed by `--test-timeout`.
+    pub fn timed_out(&self) -> bool {
+        self.killed_by.is_some()
+    }
+
+    /// Returns how long the rerun took.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Summarizes how this test failed.
+    pub fn failure_summary(&self) -> FailureSummary {
+        let stdout = self.stdout();
+        let rerun_panic = discovery::panic_message(&stdout);
+        // A rerun that fails to allocate aborts rather than panicking, so it
+        // may not have failed the way the initial run did.
+        let out_of_memory = if self.status.success() || rerun_panic.is_some() {
+            None
+        } else {
+            discovery::out_of_memory(&stdout)
+        };
+        let classification = if self.timed_out() {
+            Classification::TimedOut
+        } else if rerun_panic.is_some() {
+            Classification::Panicked
+        } else if self.status.success() {
+            Classification::Passed
+        } else {
+            Classification::Crashed
+        };
+        // If the rerun didn't panic, the initial run's panic is the best
+        // explanation of the failure there is.
+        let (location, message) = match rerun_panic
+            .or(out_of_memory)
+            .or(self.discovery_panic.as_deref())
+        {
+            Some(panic) => {
+                let (location, message) = diagnostic::split_panic(panic);
+                (location, Some(message.to_owned()))
+            }
+            None => (None, None),
+        };
+        FailureSummary {
+            name: self.test.clone(),
+            suite: self.suite.clone(),
+            checkpoint: self.checkpoint.clone(),
+            log: self.log.clone(),
+            timed_out: self.timed_out(),
+            killed_by: self.killed_by,
+            checkpoint_timed_out: self.checkpoint_timed_out,
+            classification,
+            kind: match out_of_memory {
+                Some(_) => Some(FailureKind::OutOfMemory),
+                None => self.discovery_kind,
+            },
+            message,
+            location,
+            duration: self.duration.as_secs_f64(),
+            attempts: self.attempts,
+            blame: Vec::new(),
+            sanitizer: None,
+        }
+    }
+
+    /// Copies this test's checkpoint into the artifacts directory, alongside
+    /// its log file.
+    fn save_checkpoint(&self) -> Result<()> {
+        if self.checkpoint.exists() {
+            let checkpoint = self.log.with_extension("json");
+            fs::copy(&self.checkpoint, &checkpoint)
+                .with_context(|| format!("copying checkpoint to `{}`", checkpoint))?;
+        }
+        Ok(())
+    }
 }
 
-fn test_status<C: owo_colors::Color>(name: &str, status: &str) {
-    eprintln!(
-        "test {} ... {}",
-        name,
-        status.if_supports_color(owo_colors::Stream::Stderr, |text| text.fg::<C>())
-    )
+/// Installs `color-eyre`'s error report and panic hooks.
+fn install_error_hooks(settings: &trace::TraceSettings) -> Result<()> {
+    let mut hook = color_eyre::config::HookBuilder::default();
+    if !settings.color().should_color_stderr() {
+        hook = hook.theme(color_eyre::config::Theme::new());
+    }
+    hook.issue_url(concat!(env!("CARGO_PKG_REPOSITORY"), "/issues/new"))
+        .add_issue_metadata("version", env!("CARGO_PKG_VERSION"))
+        .add_issue_metadata(
+            "args",
+            std::env::args().fold(String::new(), |mut s, arg| {
+                s.push_str(arg.as_str());
+                s.push(' ');
+                s
+            }),
+        )
+        .issue_filter(|kind| match kind {
+            color_eyre::ErrorKind::NonRecoverable(_) => true,
+            color_eyre::ErrorKind::Recoverable(error) =>
+            // Skip any IO errors and any errors forwarded from a cargo
+            // subcommand, as these may not be our fault.
+            {
+                error_is_issue(error)
+            }
+        })
+        .display_env_section(true)
+        .add_default_filters()
+        .add_frame_filter(Box::new(|frames| {
+            const SKIPPED: &[&str] = &[
+                "tokio::runtime",
+                "tokio::coop",
+                "tokio::park",
+                "std::thread::local",
+            ];
+            frames.retain(|frame| match frame.name.as_ref() {
+                Some(name) => !SKIPPED.iter().any(|prefix| name.starts_with(prefix)),
+                None => true,
+            })
+        }))
+        .install()
+}
+
+/// The exit code of a run in which some tests failed (or, with
+/// `--require-complete`, were only partially explored).
+pub const EXIT_TESTS_FAILED: i32 = 1;
+
+/// The exit code of a run in which the tests couldn't be built.
+pub const EXIT_BUILD_FAILED: i32 = 2;
+
+/// The exit code of a run which failed for any other reason.
+pub const EXIT_ERROR: i32 = 3;
+
+/// Returns the code `cargo loom` exits with when a run fails with `error`.
+pub fn exit_code(error: &color_eyre::Report) -> i32 {
+    for error in error.chain() {
+        if error.is::<TestsFailed>() || error.is::<ExplorationIncomplete>() {
+            return EXIT_TESTS_FAILED;
+        }
+        if error.is::<escargot::error::CargoError>() {
+            return EXIT_BUILD_FAILED;
+        }
+    }
+    EXIT_ERROR
 }
 
 fn error_is_issue(error: &(dyn std::error::Error + 'static)) -> bool {
     let mut current = Some(error);
     while let Some(error) = current.take() {
-        if error.is::<std::io::Error>() || error.is::<escargot::error::CargoError>() {
+        if error.is::<std::io::Error>()
+            || error.is::<escargot::error::CargoError>()
+            || error.is::<TestsFailed>()
+            || error.is::<ExplorationIncomplete>()
+            || error.is::<config::InvalidConfig>()
+            || error.is::<checkpoint::InvalidCheckpoint>()
+            || error.is::<hooks::HookFailed>()
+            || error.is::<process::Interrupted>()
+        {
             return false;
         }
 
@@ -0,0 +1,70 @@
+//! libtest's JSON event format.
+//!
+//! These types are similar to the ones in `escargot::format::test`, but are
+//! more lenient about missing fields, since the format is unstable and
+//! fields have been added and removed over time.
+
+use serde::{Deserialize, Serialize};
+
+/// A test-runner event.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub(crate) enum Event {
+    Suite(Suite),
+    Test(Test),
+    #[serde(other)]
+    Unknown,
+}
+
+/// A suite event.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", tag = "event")]
+pub(crate) enum Suite {
+    Started(SuiteStarted),
+    Ok(SuiteFinished),
+    Failed(SuiteFinished),
+    #[serde(other)]
+    Unknown,
+}
+
+/// A test case event.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", tag = "event")]
+pub(crate) enum Test {
+    Started(TestEvent),
+    Ok(TestEvent),
+    Failed(TestEvent),
+    Ignored(TestEvent),
+    Timeout(TestEvent),
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct SuiteStarted {
+    /// The number of tests that will be run (i.e. not filtered out).
+    pub(crate) test_count: usize,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub(crate) struct SuiteFinished {
+    pub(crate) passed: usize,
+    pub(crate) failed: usize,
+    pub(crate) ignored: usize,
+    pub(crate) measured: usize,
+    pub(crate) filtered_out: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) exec_time: Option<f64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub(crate) struct TestEvent {
+    pub(crate) name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) stdout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) exec_time: Option<f64>,
+}
@@ -0,0 +1,268 @@
+//! Checking the loom tests' sources for models that may run for a long time,
+//! with `--lint-models`.
+//!
+//! This is a quick scan of the source text rather than a parse of it, so that
+//! it can run before anything is built. It finds the `#[test]` functions that
+//! run a loom model, and reports:
+//!
+//! - models without local bounds: without `preemption_bound`,
+//!   `max_permutations`, or `max_duration` set on a `loom::model::Builder`,
+//!   a model is only bounded by the environment it's run in, so running it
+//!   by hand (or with different flags) may explore it for hours.
+//! - tests whose names match a pattern in the `expensive-models`
+//!   configuration, but which aren't marked `#[ignore]`.
+
+use crate::{config::Config, App};
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Result,
+};
+use std::{collections::BTreeSet, fmt, fs};
+
+/// Whether models reported by `--lint-models` fail the run.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, clap::ArgEnum)]
+pub(crate) enum LintLevel {
+    Warn,
+    Deny,
+}
+
+/// The builder settings that bound how much of a model loom explores.
+const BOUNDS: &[&str] = &["preemption_bound", "max_permutations", "max_duration"];
+
+/// A test whose model may run for a long time.
+#[derive(Debug)]
+struct Lint {
+    file: Utf8PathBuf,
+    line: usize,
+    test: String,
+    kind: LintKind,
+}
+
+#[derive(Debug)]
+enum LintKind {
+    /// The test runs a model without local bounds.
+    Unbounded,
+    /// The test matches an `expensive-models` pattern, but isn't ignored.
+    NotIgnored { pattern: String },
+}
+
+/// A `#[test]` function, while it's being scanned.
+#[derive(Debug)]
+struct Test {
+    name: String,
+    line: usize,
+    ignored: bool,
+    /// How deeply nested in braces the scan is within the test's body.
+    depth: usize,
+    opened: bool,
+    runs_model: bool,
+    bounded: bool,
+}
+
+impl App {
+    /// Checks the sources of `packages`' tests for models that may run for a
+    /// long time, failing if any are found and `level` is
+    /// [`LintLevel::Deny`].
+    pub(crate) fn lint_models(
+        &self,
+        packages: &[&cargo_metadata::Package],
+        level: LintLevel,
+    ) -> Result<()> {
+        let mut lints = Vec::new();
+        for pkg in packages {
+            let config = Config::load(&self.metadata, pkg)?;
+            for file in source_files(pkg)? {
+                let source =
+                    fs::read_to_string(&file).with_context(|| format!("reading `{}`", file))?;
+                let file = file
+                    .strip_prefix(&self.metadata.workspace_root)
+                    .map_or_else(|_| file.clone(), ToOwned::to_owned);
+                lints.extend(lint_source(&file, &source, &config.expensive_models));
+            }
+        }
+
+        for lint in &lints {
+            tracing::warn!("{}", lint);
+        }
+        if lints.is_empty() {
+            tracing::info!("Found no unbounded or expensive models");
+        } else if level == LintLevel::Deny {
+            return Err(eyre!(
+                "`--lint-models=deny` found {} models that may run for a long time",
+                lints.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the Rust source files of `pkg`'s library and integration tests:
+/// those in the directory of each target's root file.
+fn source_files(pkg: &cargo_metadata::Package) -> Result<BTreeSet<Utf8PathBuf>> {
+    let mut files = BTreeSet::new();
+    let dirs = pkg
+        .targets
+        .iter()
+        .filter(|target| {
+            target
+                .kind
+                .iter()
+                .any(|kind| kind == "lib" || kind == "test")
+        })
+        .filter_map(|target| target.src_path.parent())
+        .collect::<BTreeSet<_>>();
+    for dir in dirs {
+        collect_files(dir, &mut files)?;
+    }
+    Ok(files)
+}
+
+fn collect_files(dir: &Utf8Path, files: &mut BTreeSet<Utf8PathBuf>) -> Result<()> {
+    let entries = fs::read_dir(dir).with_context(|| format!("reading directory `{}`", dir))?;
+    for entry in entries {
+        let entry = entry.with_context(|| format!("reading directory `{}`", dir))?;
+        // Files whose paths aren't UTF-8 aren't Rust modules.
+        let path = match Utf8PathBuf::from_path_buf(entry.path()) {
+            Ok(path) => path,
+            Err(_) => continue,
+        };
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else if path.extension() == Some("rs") {
+            files.insert(path);
+        }
+    }
+    Ok(())
+}
+
+/// Scans `source` (the contents of `file`) for tests whose models may run for
+/// a long time.
+fn lint_source(file: &Utf8Path, source: &str, expensive: &[String]) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    let mut is_test = false;
+    let mut ignored = false;
+    let mut test: Option<Test> = None;
+
+    for (idx, line) in source.lines().enumerate() {
+        let line = line.split("//").next().unwrap_or("");
+        let trimmed = line.trim();
+
+        match test {
+            Some(ref mut test) => {
+                if trimmed.contains("model(") || trimmed.contains(".check(") {
+                    test.runs_model = true;
+                }
+                if BOUNDS.iter().any(|bound| trimmed.contains(bound)) {
+                    test.bounded = true;
+                }
+            }
+            None => {
+                if trimmed.starts_with("#[test]") || trimmed.ends_with("::test]") {
+                    is_test = true;
+                } else if trimmed.starts_with("#[ignore") {
+                    ignored = true;
+                } else if let Some(name) = fn_name(trimmed) {
+                    if is_test {
+                        test = Some(Test {
+                            name: name.to_owned(),
+                            line: idx + 1,
+                            ignored,
+                            depth: 0,
+                            opened: false,
+                            runs_model: false,
+                            bounded: false,
+                        });
+                    }
+                    is_test = false;
+                    ignored = false;
+                } else if !trimmed.starts_with("#[") && !trimmed.is_empty() {
+                    is_test = false;
+                    ignored = false;
+                }
+            }
+        }
+
+        // The test's body ends where its braces are balanced again.
+        let finished = match test {
+            Some(ref mut test) => {
+                for c in line.chars() {
+                    match c {
+                        '{' => {
+                            test.depth += 1;
+                            test.opened = true;
+                        }
+                        '}' => test.depth = test.depth.saturating_sub(1),
+                        _ => {}
+                    }
+                }
+                test.opened && test.depth == 0
+            }
+            None => false,
+        };
+        if finished {
+            if let Some(test) = test.take() {
+                lints.extend(lint_test(file, test, expensive));
+            }
+        }
+    }
+    lints
+}
+
+fn lint_test(file: &Utf8Path, test: Test, expensive: &[String]) -> Vec<Lint> {
+    let mut lints = Vec::new();
+    if !test.runs_model {
+        return lints;
+    }
+    let lint = |kind| Lint {
+        file: file.to_owned(),
+        line: test.line,
+        test: test.name.clone(),
+        kind,
+    };
+    if !test.bounded {
+        lints.push(lint(LintKind::Unbounded));
+    }
+    if !test.ignored {
+        if let Some(pattern) = expensive
+            .iter()
+            .find(|pattern| test.name.contains(pattern.as_str()))
+        {
+            lints.push(lint(LintKind::NotIgnored {
+                pattern: pattern.clone(),
+            }));
+        }
+    }
+    lints
+}
+
+/// Returns the name of the function declared on `line`, if it declares one.
+fn fn_name(line: &str) -> Option<&str> {
+    let start = line.find("fn ")? + "fn ".len();
+    let before = line[..start - "fn ".len()].trim();
+    let qualifiers = ["", "pub", "pub(crate)", "async", "pub async", "unsafe"];
+    if !qualifiers.contains(&before) {
+        return None;
+    }
+    let rest = &line[start..];
+    let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_'))?;
+    Some(&rest[..end]).filter(|name| !name.is_empty())
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: `{}` ", self.file, self.line, self.test)?;
+        match self.kind {
+            LintKind::Unbounded => write!(
+                f,
+                "runs a loom model without local bounds; set `preemption_bound`, \
+                `max_permutations`, or `max_duration` on a `loom::model::Builder`"
+            ),
+            LintKind::NotIgnored { ref pattern } => write!(
+                f,
+                "matches the `expensive-models` pattern `{}`, but isn't marked `#[ignore]`",
+                pattern
+            ),
+        }
+    }
+}
@@ -0,0 +1,117 @@
+//! Listing the tests `cargo loom` would run, with `cargo loom list`.
+//!
+//! The test binaries are built and run with `--list`, which doesn't run any
+//! tests, so this shows what a run would cover before committing to a long
+//! exploration. Each test is listed with its checkpoint, if a previous run
+//! left one on disk (in which case a run starts from it, rather than from the
+//! beginning of the model).
+
+use crate::{config::Config, file_stem, trace::ColorMode, App, TestFilter};
+use camino::Utf8PathBuf;
+use color_eyre::{eyre::WrapErr, Result};
+use owo_colors::{OwoColorize, Style};
+use serde::Serialize;
+
+#[derive(Debug, clap::Args)]
+pub(crate) struct ListArgs {
+    /// If specified, only list tests containing this string in their names
+    filter: Option<String>,
+}
+
+/// A test that would be run.
+#[derive(Debug, Serialize)]
+struct ListedTest<'a> {
+    package: &'a str,
+    /// The feature set the package is tested with, when running a feature
+    /// matrix.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    features: Option<&'a str>,
+    suite: &'a str,
+    name: &'a str,
+    /// The test's checkpoint, if it has one on disk.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checkpoint: Option<Utf8PathBuf>,
+}
+
+impl App {
+    /// Builds the tests and lists those that match `args.filter`, with their
+    /// checkpoints.
+    pub(crate) fn list(&self, args: &ListArgs) -> Result<()> {
+        let json = self.args.trace_settings.message_format().is_json();
+        let color = self.args.trace_settings.color();
+        let filter = TestFilter::Contains(args.filter.as_deref());
+        let mut total = 0;
+        let mut checkpointed = 0;
+
+        for features in &self.feature_sets {
+            let label = self
+                .args
+                .cargo
+                .feature_matrix
+                .as_ref()
+                .map(|_| features.to_string());
+            for pkg in self.wanted_packages() {
+                let settings = self.settings(&Config::load(&self.metadata, pkg)?);
+                let suites = self
+                    .build_tests(pkg, &settings, features)
+                    .with_context(|| format!("Error building tests for package `{}`", pkg.name))?;
+                for suite in suites {
+                    let suite = suite.context("Getting next test failed")?;
+                    let checkpoint_dir = self.suite_checkpoint_dir(&suite)?;
+                    let tests = self.list_tests(&suite, filter)?;
+                    if tests.is_empty() {
+                        continue;
+                    }
+                    if !json {
+                        eprintln!(
+                            "{} ({}, {} tests)",
+                            suite.name().style(color.if_color(Style::new().bold())),
+                            pkg.name,
+                            tests.len()
+                        );
+                    }
+                    for test in &tests {
+                        let checkpoint = checkpoint_dir.join(format!("{}.json", file_stem(test)));
+                        let listed = ListedTest {
+                            package: &pkg.name,
+                            features: label.as_deref(),
+                            suite: suite.name(),
+                            name: test,
+                            checkpoint: checkpoint.exists().then_some(checkpoint),
+                        };
+                        total += 1;
+                        checkpointed += usize::from(listed.checkpoint.is_some());
+                        if json {
+                            let json =
+                                serde_json::to_string(&listed).context("write json message")?;
+                            eprintln!("{}", json);
+                        } else {
+                            listed.print(color);
+                        }
+                    }
+                }
+            }
+        }
+
+        tracing::info!(
+            "Found {} tests, of which {} have checkpoints",
+            total,
+            checkpointed
+        );
+        Ok(())
+    }
+}
+
+impl ListedTest<'_> {
+    fn print(&self, color: ColorMode) {
+        match self.checkpoint {
+            Some(ref checkpoint) => eprintln!(
+                "    {} {}",
+                self.name,
+                format_args!("(checkpoint: {})", checkpoint)
+                    .style(color.if_color(Style::new().dimmed())),
+            ),
+            None => eprintln!("    {}", self.name),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! The header at the top of each logged rerun's log file.
+//!
+//! A log file is often shared without the run it came from, so the header
+//! records everything needed to make sense of it, and to rerun the test the
+//! same way: the command (with the environment variables `cargo loom` set
+//! for it), the test binary and its hash, when the rerun started, and loom's
+//! bounds.
+
+use crate::{
+    ENV_CHECKPOINT_INTERVAL, ENV_MAX_BRANCHES, ENV_MAX_PERMUTATIONS, ENV_MAX_PREEMPTIONS,
+    ENV_MAX_THREADS,
+};
+use std::{
+    borrow::Cow,
+    ffi::OsStr,
+    fmt::Write,
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// The bounds shown in the header, and the names they're shown with.
+const BOUNDS: &[(&str, &str)] = &[
+    (ENV_MAX_THREADS, "threads"),
+    (ENV_MAX_BRANCHES, "branches"),
+    (ENV_MAX_PREEMPTIONS, "preemptions"),
+    (ENV_MAX_PERMUTATIONS, "permutations"),
+    (ENV_CHECKPOINT_INTERVAL, "checkpoint_interval"),
+];
+
+/// Returns the header for the log of `test`, which is rerun by `cmd`.
+///
+/// Each line starts with `# `, and the header ends with a blank line.
+pub(crate) fn header(test: &str, cmd: &Command) -> String {
+    let mut header = String::new();
+    let _ = writeln!(
+        header,
+        "# cargo-loom {}: logged rerun of {}",
+        env!("CARGO_PKG_VERSION"),
+        test
+    );
+    let _ = writeln!(header, "# started: {}", utc_now());
+
+    let binary = Path::new(cmd.get_program());
+    let _ = writeln!(header, "# binary: {}", binary.display());
+    // cargo names test binaries `<target>-<hash>`.
+    if let Some((_, hash)) = binary
+        .file_stem()
+        .and_then(OsStr::to_str)
+        .and_then(|stem| stem.rsplit_once('-'))
+    {
+        let _ = writeln!(header, "# binary hash: {}", hash);
+    }
+
+    let envs = cmd
+        .get_envs()
+        .filter_map(|(name, value)| Some((name.to_string_lossy(), value?.to_string_lossy())))
+        .collect::<Vec<_>>();
+    let bounds = BOUNDS
+        .iter()
+        .map(|&(env, bound)| {
+            let value = envs
+                .iter()
+                .find(|(name, _)| name == env)
+                .map_or("-", |(_, value)| value.as_ref());
+            format!("{}={}", bound, value)
+        })
+        .collect::<Vec<_>>();
+    let _ = writeln!(header, "# bounds: {}", bounds.join(" "));
+    header.push_str("# env:\n");
+    for (name, value) in &envs {
+        let _ = writeln!(header, "#   {}={}", name, value);
+    }
+
+    let command = envs
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, quote(value)))
+        .chain(std::iter::once(
+            quote(&binary.to_string_lossy()).into_owned(),
+        ))
+        .chain(
+            cmd.get_args()
+                .map(|arg| quote(&arg.to_string_lossy()).into_owned()),
+        )
+        .collect::<Vec<_>>();
+    let _ = writeln!(header, "# command:\n#   {}", command.join(" "));
+    header.push('\n');
+    header
+}
+
+/// Quotes `arg` for a POSIX shell, if it needs to be.
+pub(crate) fn quote(arg: &str) -> Cow<'_, str> {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-./=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        return Cow::Borrowed(arg);
+    }
+    Cow::Owned(format!("'{}'", arg.replace('\'', r"'\''")))
+}
+
+/// Returns the current time in UTC, as `YYYY-MM-DDTHH:MM:SSZ`.
+fn utc_now() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+
+    // Converts days since the epoch to a date in the proleptic Gregorian
+    // calendar (Howard Hinnant's `civil_from_days`).
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60
+    )
+}
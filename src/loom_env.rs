@@ -0,0 +1,151 @@
+//! Setting loom's environment variables directly, for `--loom-env`.
+//!
+//! loom's bounds can be set with `--loom-env` as well as with their own
+//! flags; they're resolved into the run's [`Settings`] like the flags are.
+//! Any other variable is only passed to the tests with `--force`, so that a
+//! typo isn't silently ignored, but a variable added by a newer version of
+//! loom can be used before `cargo loom` knows about it.
+//!
+//! [`Settings`]: crate::settings::Settings
+
+use crate::{
+    LoomOptions, ENV_CHECKPOINT_FILE, ENV_CHECKPOINT_INTERVAL, ENV_LOOM_LOCATION, ENV_LOOM_LOG,
+    ENV_MAX_BRANCHES, ENV_MAX_DURATION, ENV_MAX_PERMUTATIONS, ENV_MAX_PREEMPTIONS, ENV_MAX_THREADS,
+};
+use color_eyre::{eyre::eyre, Result};
+use std::{fmt, process::Command, str::FromStr};
+
+/// A `--loom-env KEY=VALUE`.
+#[derive(Clone, Debug)]
+pub(crate) struct LoomEnv {
+    key: String,
+    value: String,
+}
+
+/// The loom variables which may be set with `--loom-env` without `--force`,
+/// and the flags which also set them.
+const BOUNDS: &[(&str, &str)] = &[
+    (ENV_MAX_BRANCHES, "--max-branches"),
+    (ENV_MAX_DURATION, "--max-duration-secs"),
+    (ENV_MAX_PERMUTATIONS, "--max-permutations"),
+    (ENV_MAX_PREEMPTIONS, "--max-preemptions"),
+    (ENV_MAX_THREADS, "--max-threads"),
+    (ENV_CHECKPOINT_INTERVAL, "--checkpoint-interval"),
+];
+
+/// The loom variables which `cargo loom` sets differently in each phase of a
+/// run, so setting them for every phase would break the run.
+const PER_PHASE: &[(&str, &str)] = &[
+    (
+        ENV_LOOM_LOG,
+        "it's disabled when finding failing tests; use `--loom-log` to set it for reruns",
+    ),
+    (
+        ENV_LOOM_LOCATION,
+        "it's enabled when rerunning failing tests",
+    ),
+    (
+        ENV_CHECKPOINT_FILE,
+        "each failing test has its own checkpoint",
+    ),
+];
+
+impl LoomEnv {
+    /// Returns the value of the loom bound `name` set by `envs`, if any.
+    ///
+    /// The value was already checked by [`validate`].
+    pub(crate) fn bound(envs: &[LoomEnv], name: &str) -> Option<usize> {
+        envs.iter()
+            .rev()
+            .find(|env| env.key == name)
+            .and_then(|env| env.value.parse().ok())
+    }
+
+    /// Returns whether `envs` sets the variable `name`.
+    pub(crate) fn sets(envs: &[LoomEnv], name: &str) -> bool {
+        envs.iter().any(|env| env.key == name)
+    }
+
+    /// Sets the variables in `envs` that aren't resolved into the run's
+    /// settings on a test command.
+    pub(crate) fn configure<'cmd>(envs: &[LoomEnv], cmd: &'cmd mut Command) -> &'cmd mut Command {
+        for env in envs {
+            if !BOUNDS.iter().any(|&(name, _)| name == env.key) {
+                cmd.env(&env.key, &env.value);
+            }
+        }
+        cmd
+    }
+}
+
+/// Checks that each `--loom-env` sets a variable that's known to loom (or
+/// that `--force` was passed), and that it doesn't conflict with the flag
+/// for the same bound.
+pub(crate) fn validate(options: &LoomOptions) -> Result<()> {
+    for env in &options.loom_env {
+        if let Some(&(_, flag)) = BOUNDS.iter().find(|&&(name, _)| name == env.key) {
+            env.value
+                .parse::<usize>()
+                .map_err(|error| eyre!("invalid `--loom-env {}`: {}", env, error))?;
+            let flag_set = match env.key.as_str() {
+                ENV_MAX_BRANCHES => options.max_branches.is_some(),
+                ENV_MAX_DURATION => options.max_duration_secs.is_some(),
+                ENV_MAX_PERMUTATIONS => options.max_permutations.is_some(),
+                ENV_MAX_PREEMPTIONS => options.max_preemptions.is_some(),
+                ENV_MAX_THREADS => options.max_threads.is_some(),
+                ENV_CHECKPOINT_INTERVAL => options.checkpoint_interval.is_some(),
+                _ => false,
+            };
+            if flag_set {
+                return Err(eyre!(
+                    "`--loom-env {}` conflicts with `{}`; pass only one of them",
+                    env.key,
+                    flag
+                ));
+            }
+            continue;
+        }
+
+        if options.force {
+            tracing::debug!(%env, "Setting unknown loom environment variable");
+            continue;
+        }
+        if let Some(&(_, reason)) = PER_PHASE.iter().find(|&&(name, _)| name == env.key) {
+            return Err(eyre!(
+                "`{}` can't be set with `--loom-env`, because `cargo loom` sets it for each \
+                phase of a run ({}); pass `--force` to set it anyway",
+                env.key,
+                reason
+            ));
+        }
+        return Err(eyre!(
+            "`{}` isn't a loom environment variable that `cargo loom` knows about; pass \
+            `--force` to set it anyway",
+            env.key
+        ));
+    }
+    Ok(())
+}
+
+impl FromStr for LoomEnv {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected `KEY=VALUE`, found `{}`", s))?;
+        if key.is_empty() {
+            return Err("the variable's name may not be empty".to_owned());
+        }
+        Ok(Self {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for LoomEnv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
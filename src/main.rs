@@ -1,9 +1,14 @@
 use cargo_loom::App;
+use std::process;
 
 #[tokio::main]
-async fn main() -> color_eyre::Result<()> {
-    let app = App::parse()?;
-    tokio::spawn(async move { app.run_all().await })
-        .await
-        .unwrap()
+async fn main() {
+    let result = match App::parse() {
+        Ok(app) => tokio::spawn(async move { app.run().await }).await.unwrap(),
+        Err(error) => Err(error),
+    };
+    if let Err(error) = result {
+        eprintln!("Error: {:?}", error);
+        process::exit(cargo_loom::exit_code(&error));
+    }
 }
@@ -0,0 +1,170 @@
+//! Holding back reruns of failing tests while the system is low on memory.
+//!
+//! Reruns log at `trace` level, and a few of them at once can use enough
+//! memory to drive the machine into swap. Before each rerun starts, the
+//! memory available is compared to the most any rerun has used so far, and
+//! if there isn't room for another, it waits for running reruns to finish.
+//! Reruns that have started, but whose memory use hasn't been measured yet,
+//! aren't reflected in the memory available, so room is reserved for each of
+//! them. Memory is only measured on Linux; elsewhere, reruns are never held
+//! back.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+/// How much memory is assumed to be needed by a rerun before any have
+/// finished.
+const MIN_HEADROOM: u64 = 256 * 1024 * 1024;
+
+/// How often memory is measured.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Tracks how much memory the reruns of failing tests use.
+#[derive(Debug, Default)]
+pub(crate) struct Monitor {
+    /// The largest resident set size of any rerun process, in bytes.
+    peak_rss: AtomicU64,
+    running: AtomicUsize,
+    /// Running reruns whose memory use hasn't been measured yet.
+    pending: AtomicUsize,
+    /// Held while a rerun is admitted, so that reruns waiting at the same
+    /// time are admitted one at a time.
+    admitting: Mutex<()>,
+    /// Whether a warning has been logged about holding back reruns.
+    warned: AtomicBool,
+}
+
+/// A rerun that was admitted by a [`Monitor`]. It's no longer counted as
+/// running once this is dropped.
+#[derive(Debug)]
+pub(crate) struct Admitted<'monitor> {
+    monitor: &'monitor Monitor,
+    measured: AtomicBool,
+}
+
+impl Monitor {
+    /// Waits until there's enough memory available to start another rerun.
+    ///
+    /// A rerun is always admitted if no others are running, so that the run
+    /// makes progress even if memory stays low.
+    pub(crate) async fn admit(&self) -> Admitted<'_> {
+        loop {
+            if self.try_admit() {
+                return Admitted {
+                    monitor: self,
+                    measured: AtomicBool::new(false),
+                };
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Admits a rerun if there's enough memory available for it, returning
+    /// whether it was admitted.
+    fn try_admit(&self) -> bool {
+        let _admitting = self
+            .admitting
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        let running = self.running.load(Ordering::Acquire);
+        let pending = self.pending.load(Ordering::Acquire) as u64;
+        let needed = self.peak_rss.load(Ordering::Acquire).max(MIN_HEADROOM);
+        // Reruns that haven't been measured yet will use memory that's still
+        // counted as available.
+        match available().map(|available| available.saturating_sub(pending * needed)) {
+            Some(available) if running > 0 && available < needed => {
+                if !self.warned.swap(true, Ordering::AcqRel) {
+                    tracing::warn!(
+                        "only {} MiB of memory is available, and reruns have used up to {} MiB; \
+                        running fewer reruns at once until more is available",
+                        available / (1024 * 1024),
+                        needed / (1024 * 1024),
+                    );
+                }
+                tracing::debug!(available, needed, running, pending, "Waiting for memory");
+                false
+            }
+            _ => {
+                self.running.fetch_add(1, Ordering::AcqRel);
+                self.pending.fetch_add(1, Ordering::AcqRel);
+                true
+            }
+        }
+    }
+}
+
+impl Admitted<'_> {
+    /// Measures the resident set size of the rerun's process `pid` until it
+    /// exits, recording the largest it gets.
+    ///
+    /// This never completes, so it should be raced against the process
+    /// exiting.
+    pub(crate) async fn watch(&self, pid: Option<u32>) {
+        loop {
+            if let Some(rss) = pid.and_then(rss) {
+                self.monitor.peak_rss.fetch_max(rss, Ordering::AcqRel);
+                self.measured();
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+
+    /// Stops reserving memory for this rerun, now that its memory use is
+    /// counted in the memory available.
+    fn measured(&self) {
+        if !self.measured.swap(true, Ordering::AcqRel) {
+            self.monitor.pending.fetch_sub(1, Ordering::AcqRel);
+        }
+    }
+}
+
+impl Drop for Admitted<'_> {
+    fn drop(&mut self) {
+        self.measured();
+        self.monitor.running.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Returns how much memory is available for new processes, in bytes.
+#[cfg(target_os = "linux")]
+fn available() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    kib_field(&meminfo, "MemAvailable:")
+}
+
+/// Returns the resident set size of the process `pid`, in bytes.
+#[cfg(target_os = "linux")]
+fn rss(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    kib_field(&status, "VmRSS:")
+}
+
+/// Parses a field like `MemAvailable:  1234 kB` from a file in `/proc`, in
+/// bytes.
+#[cfg(target_os = "linux")]
+fn kib_field(file: &str, name: &str) -> Option<u64> {
+    let kib = file
+        .lines()
+        .find_map(|line| line.strip_prefix(name))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn available() -> Option<u64> {
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+fn rss(_: u32) -> Option<u64> {
+    None
+}
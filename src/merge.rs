@@ -0,0 +1,281 @@
+//! Combining the summaries of several runs into one report, with `cargo loom
+//! merge`.
+//!
+//! When the tests are split across CI jobs with `--shard` (or run on several
+//! platforms or toolchains), each job writes its own summary. Merging them
+//! writes a single `summary.json`, in which each package's results from every
+//! summary are combined (its test counts are added up, and its failures and
+//! other lists are concatenated), and a self-contained `summary.html` that
+//! lists every failure in one place.
+//!
+//! Summaries are merged as JSON, so that fields this version of `cargo loom`
+//! doesn't know about are kept. Each failure is tagged with the summary it
+//! came from, in `source`, since its checkpoint and log are on the machine
+//! that ran it.
+
+use crate::{timings::escape, App};
+use camino::Utf8PathBuf;
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Result,
+};
+use serde_json::{Map, Value};
+use std::{fmt::Write, fs};
+
+#[derive(Debug, clap::Args)]
+pub(crate) struct MergeArgs {
+    /// The summaries to merge, as written with `--ci` or `--summary-file`
+    #[clap(required = true, value_hint = clap::ValueHint::FilePath)]
+    summaries: Vec<Utf8PathBuf>,
+
+    /// The directory to write the merged summary and report to [default:
+    /// target/loom/merged]
+    #[clap(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+    output: Option<Utf8PathBuf>,
+}
+
+/// The fields of a package's summary that are counts of its tests, which are
+/// added up when they're merged.
+const COUNTS: &[&str] = &[
+    "tests",
+    "passed",
+    "failed",
+    "ignored",
+    "filtered_out",
+    "not_run",
+];
+
+/// The field of a package's summary that counts its failures by kind, which
+/// are added up when they're merged.
+const FAILURE_KINDS: &str = "failure_kinds";
+
+/// The fields of a package's summary that list failing tests.
+const FAILURE_LISTS: &[&str] = &[
+    "failures",
+    "escalated_failures",
+    "timed_out_tests",
+    "skipped_tests",
+];
+
+impl App {
+    /// Merges the summaries in `args.summaries`, and writes the merged
+    /// summary and its HTML report.
+    pub(crate) fn merge(&self, args: &MergeArgs) -> Result<()> {
+        let mut packages = Vec::<Map<String, Value>>::new();
+        for path in &args.summaries {
+            let summary =
+                fs::read_to_string(path).with_context(|| format!("reading summary `{}`", path))?;
+            let summary: Value = serde_json::from_str(&summary)
+                .with_context(|| format!("parsing summary `{}`", path))?;
+            let summary_packages = summary
+                .get("packages")
+                .and_then(Value::as_array)
+                .ok_or_else(|| eyre!("`{}` isn't a `cargo loom` summary", path))?;
+            for pkg in summary_packages {
+                let mut pkg = pkg
+                    .as_object()
+                    .cloned()
+                    .ok_or_else(|| eyre!("`{}` isn't a `cargo loom` summary", path))?;
+                if let Some(Value::Array(failures)) = pkg.get_mut("failures") {
+                    for failure in failures.iter_mut().filter_map(Value::as_object_mut) {
+                        failure.insert("source".to_owned(), Value::String(path.to_string()));
+                    }
+                }
+                match packages
+                    .iter_mut()
+                    .find(|merged| same_package(merged, &pkg))
+                {
+                    Some(merged) => merge_package(merged, pkg),
+                    None => packages.push(pkg),
+                }
+            }
+        }
+
+        let failures = packages
+            .iter()
+            .map(|pkg| {
+                FAILURE_LISTS
+                    .iter()
+                    .filter_map(|field| pkg.get(*field)?.as_array())
+                    .map(Vec::len)
+                    .sum::<usize>()
+            })
+            .sum::<usize>();
+        let merged = serde_json::json!({
+            "sources": args.summaries,
+            "packages": packages,
+        });
+
+        let dir = args
+            .output
+            .clone()
+            .unwrap_or_else(|| self.target_dir.join("merged"));
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("creating merged summary directory `{}`", dir))?;
+        let json = dir.join("summary.json");
+        let file = fs::File::create(&json).with_context(|| format!("creating `{}`", json))?;
+        serde_json::to_writer_pretty(file, &merged)
+            .with_context(|| format!("writing `{}`", json))?;
+        let html = dir.join("summary.html");
+        fs::write(&html, report(&args.summaries, &packages, failures))
+            .with_context(|| format!("writing `{}`", html))?;
+
+        tracing::info!(
+            "Merged {} summaries ({} packages, {} failures) into {}",
+            args.summaries.len(),
+            packages.len(),
+            failures,
+            json
+        );
+        tracing::info!("Wrote report to {}", html);
+        Ok(())
+    }
+}
+
+/// Returns whether `a` and `b` are the results of the same package, with the
+/// same feature set.
+fn same_package(a: &Map<String, Value>, b: &Map<String, Value>) -> bool {
+    a.get("name") == b.get("name") && a.get("features") == b.get("features")
+}
+
+/// Merges `pkg` into `merged`: counts are added up, lists are concatenated,
+/// and other fields are kept from the first summary that has them.
+fn merge_package(merged: &mut Map<String, Value>, pkg: Map<String, Value>) {
+    for (field, value) in pkg {
+        match (merged.get_mut(&field), value) {
+            (Some(Value::Number(total)), Value::Number(count))
+                if COUNTS.contains(&field.as_str()) =>
+            {
+                let sum = total.as_u64().unwrap_or(0) + count.as_u64().unwrap_or(0);
+                *total = sum.into();
+            }
+            (Some(Value::Array(list)), Value::Array(more)) => list.extend(more),
+            (Some(Value::Object(kinds)), Value::Object(more)) if field == FAILURE_KINDS => {
+                for (kind, count) in more {
+                    let total = kinds.entry(kind).or_insert_with(|| 0.into());
+                    let sum = total.as_u64().unwrap_or(0) + count.as_u64().unwrap_or(0);
+                    *total = sum.into();
+                }
+            }
+            (None, value) => {
+                merged.insert(field, value);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Renders the merged summary as an HTML page: each package's test counts,
+/// and every failure.
+fn report(sources: &[Utf8PathBuf], packages: &[Map<String, Value>], failures: usize) -> String {
+    let text = |value: Option<&Value>| match value {
+        Some(Value::String(text)) => escape(text),
+        Some(Value::Number(number)) => number.to_string(),
+        _ => String::new(),
+    };
+    let mut html = String::new();
+    html.push_str(concat!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
+        "<title>cargo loom summary</title>\n<style>\n",
+        "body { font-family: sans-serif; margin: 2em; }\n",
+        "table { border-collapse: collapse; }\n",
+        "td, th { padding: 2px 8px; text-align: left; vertical-align: top; }\n",
+        "td.num { text-align: right; }\n",
+        ".failed { color: #c0392b; font-weight: bold; }\n",
+        "pre { margin: 0; white-space: pre-wrap; }\n",
+        "</style>\n</head>\n<body>\n",
+    ));
+    let result = if failures == 0 {
+        "ok".to_owned()
+    } else {
+        format!("<span class=\"failed\">{} failures</span>", failures)
+    };
+    let _ = writeln!(
+        html,
+        "<h1>cargo loom {} summary</h1>\n<p>Result: {}, from {} summaries:</p>\n<ul>",
+        env!("CARGO_PKG_VERSION"),
+        result,
+        sources.len()
+    );
+    for source in sources {
+        let _ = writeln!(html, "<li><code>{}</code></li>", escape(source.as_str()));
+    }
+    html.push_str("</ul>\n");
+
+    html.push_str("<h2>Packages</h2>\n<table>\n<tr><th>Package</th>");
+    for count in COUNTS {
+        let _ = write!(html, "<th>{}</th>", count.replace('_', " "));
+    }
+    html.push_str("</tr>\n");
+    for pkg in packages {
+        let mut package = text(pkg.get("name"));
+        if let Some(Value::String(features)) = pkg.get("features") {
+            let _ = write!(package, " ({})", escape(features));
+        }
+        let _ = write!(html, "<tr><td>{}</td>", package);
+        for count in COUNTS {
+            let _ = write!(html, "<td class=\"num\">{}</td>", text(pkg.get(*count)));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+
+    html.push_str(
+        "<h2>Failures</h2>\n<table>\n<tr><th>Package</th><th>Test</th>\
+        <th>Classification</th><th>Kind</th><th>Location</th><th>Message</th><th>Source</th></tr>\n",
+    );
+    for pkg in packages {
+        let failures = pkg
+            .get("failures")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten();
+        for failure in failures {
+            let location = match failure.get("location") {
+                Some(location) => format!(
+                    "{}:{}:{}",
+                    text(location.get("file")),
+                    text(location.get("line")),
+                    text(location.get("column"))
+                ),
+                None => String::new(),
+            };
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td>{}::{}</td><td>{}</td><td>{}</td><td>{}</td>\
+                <td><pre>{}</pre></td><td><code>{}</code></td></tr>",
+                text(pkg.get("name")),
+                text(failure.get("suite")),
+                text(failure.get("name")),
+                text(failure.get("classification")),
+                text(failure.get("kind")),
+                location,
+                text(failure.get("message")),
+                text(failure.get("source")),
+            );
+        }
+        // Tests that failed without a logged rerun are only listed by name.
+        for (field, why) in [
+            ("escalated_failures", "failed with escalated bounds"),
+            ("timed_out_tests", "timed out"),
+            ("skipped_tests", "not rerun, reached --total-timeout"),
+        ] {
+            let tests = pkg
+                .get(field)
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten();
+            for test in tests {
+                let _ = writeln!(
+                    html,
+                    "<tr><td>{}</td><td>{}</td><td>{}</td><td></td><td></td><td></td><td></td></tr>",
+                    text(pkg.get("name")),
+                    text(Some(test)),
+                    why,
+                );
+            }
+        }
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
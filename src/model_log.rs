@@ -0,0 +1,75 @@
+//! Parsing the log lines that loom prints as it explores a model.
+
+/// A `LOOM_LOG` filter which enables only the lines loom prints every
+/// checkpoint interval and when a model completes, without enabling any of its
+/// other diagnostics.
+pub(crate) const LOOM_LOG_MODEL: &str = "loom::model=info";
+
+/// Returns the total number of iterations reported by every loom model that
+/// ran to completion in a test's captured output.
+pub(crate) fn completed_iterations(output: &str) -> Option<u64> {
+    numbers_after(output, "Completed in ").fold(None, |total, iterations| {
+        Some(total.unwrap_or(0) + iterations)
+    })
+}
+
+/// Returns `true` if a loom model in a test's captured output stopped
+/// exploring because it reached `max_permutations`.
+///
+/// Loom only checks the bound when it logs an iteration (every checkpoint
+/// interval), and stops right after logging an iteration past the bound.
+pub(crate) fn hit_max_permutations(output: &str, max_permutations: u64) -> bool {
+    numbers_after(output, "= Iteration ").any(|iteration| iteration >= max_permutations)
+}
+
+/// How much of their state space the loom models in a passing test explored.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Exploration {
+    /// Every model reported completing its whole state space.
+    Complete,
+    /// A model stopped at a bound (`max_permutations` or `max_duration`)
+    /// before completing.
+    Bounded,
+}
+
+/// Returns how much of their state space the loom models in a passing test's
+/// captured output explored, or `None` if it didn't log anything about its
+/// models.
+///
+/// A model that explores its whole state space logs how many iterations it
+/// completed in, but a model that stops at a bound just returns, right after
+/// logging an iteration. So a model stopped at a bound if an iteration it
+/// logged is followed by the end of the output, or by an iteration that isn't
+/// later than it (from the next model), rather than by its completion.
+pub(crate) fn exploration(output: &str) -> Option<Exploration> {
+    let mut completed = 0;
+    let mut last_iteration = None;
+    for line in output.lines() {
+        if let Some(iteration) = numbers_after(line, "= Iteration ").next() {
+            if last_iteration.is_some_and(|last| iteration <= last) {
+                return Some(Exploration::Bounded);
+            }
+            last_iteration = Some(iteration);
+        } else if line.contains("Completed in ") {
+            completed += 1;
+            last_iteration = None;
+        }
+    }
+    if last_iteration.is_some() {
+        Some(Exploration::Bounded)
+    } else if completed > 0 {
+        Some(Exploration::Complete)
+    } else {
+        None
+    }
+}
+
+fn numbers_after<'a>(output: &'a str, prefix: &'a str) -> impl Iterator<Item = u64> + 'a {
+    output.match_indices(prefix).filter_map(move |(idx, _)| {
+        let rest = &output[idx + prefix.len()..];
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        rest[..end].parse().ok()
+    })
+}
@@ -0,0 +1,61 @@
+//! Overriding loom's bounds for some of a run's tests.
+
+use std::{fmt, str::FromStr};
+
+/// An `--override` of loom's bounds for the tests whose names contain
+/// `pattern`.
+#[derive(Clone, Debug)]
+pub(crate) struct Override {
+    pattern: String,
+    max_threads: usize,
+}
+
+impl Override {
+    /// Returns the `--max-threads` that the first of `overrides` to match
+    /// `test` sets, if any match.
+    pub(crate) fn max_threads(overrides: &[Override], test: &str) -> Option<usize> {
+        overrides
+            .iter()
+            .find(|o| test.contains(o.pattern.as_str()))
+            .map(|o| o.max_threads)
+    }
+}
+
+impl FromStr for Override {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Test names contain `::`, so the pattern ends at the last `:`.
+        let (pattern, setting) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected `PATTERN:max_threads=N`, found `{}`", s))?;
+        if pattern.is_empty() {
+            return Err("the pattern may not be empty".to_owned());
+        }
+        let (key, value) = setting
+            .split_once('=')
+            .ok_or_else(|| format!("expected `max_threads=N`, found `{}`", setting))?;
+        let max_threads = match key.trim() {
+            "max_threads" | "max-threads" => value
+                .trim()
+                .parse()
+                .map_err(|error| format!("invalid `max_threads` `{}`: {}", value, error))?,
+            key => {
+                return Err(format!(
+                    "`{}` can't be overridden; only `max_threads` can",
+                    key
+                ))
+            }
+        };
+        Ok(Self {
+            pattern: pattern.to_owned(),
+            max_threads,
+        })
+    }
+}
+
+impl fmt::Display for Override {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:max_threads={}", self.pattern, self.max_threads)
+    }
+}
@@ -0,0 +1,108 @@
+//! Streaming a process's output as it's written, with each line prefixed by a
+//! name (like `docker compose logs`).
+//!
+//! Lines the process wrote to stderr are marked with `!` rather than `|`
+//! after the name, since they're interleaved with its stdout.
+
+use crate::{backtrace, trace::ColorMode};
+use owo_colors::{AnsiColors, OwoColorize, Style};
+use std::io::{self, Write};
+
+/// A name printed before each line of a process's output.
+#[derive(Clone, Debug)]
+pub(crate) struct Prefix {
+    name: String,
+    width: usize,
+    style: Style,
+    to_stderr: bool,
+}
+
+/// Colors that are distinguishable from each other, and from the default
+/// foreground color.
+const COLORS: &[AnsiColors] = &[
+    AnsiColors::Cyan,
+    AnsiColors::Yellow,
+    AnsiColors::Green,
+    AnsiColors::Magenta,
+    AnsiColors::Blue,
+    AnsiColors::BrightCyan,
+    AnsiColors::BrightYellow,
+    AnsiColors::BrightGreen,
+    AnsiColors::BrightMagenta,
+    AnsiColors::BrightBlue,
+];
+
+/// Which of a process's output streams a line was written to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Source {
+    Stdout,
+    Stderr,
+}
+
+impl Prefix {
+    /// Returns a prefix for the `idx`th of a set of processes, padded to
+    /// `width` so that the output of every process in the set lines up.
+    ///
+    /// The prefix is colored if `color` allows colors on the stream it's
+    /// printed to.
+    pub(crate) fn new(
+        name: String,
+        idx: usize,
+        width: usize,
+        color: ColorMode,
+        to_stderr: bool,
+    ) -> Self {
+        let colored = if to_stderr {
+            color.should_color_stderr()
+        } else {
+            color.should_color_stdout()
+        };
+        let style = if colored {
+            Style::new().color(COLORS[idx % COLORS.len()])
+        } else {
+            Style::new()
+        };
+        Self {
+            name,
+            width,
+            style,
+            to_stderr,
+        }
+    }
+
+    fn print(&self, source: Source, line: &str) -> io::Result<()> {
+        let marker = match source {
+            Source::Stdout => '|',
+            Source::Stderr => '!',
+        };
+        let prefix = format!("{:width$} {}", self.name, marker, width = self.width);
+        let line = format!("{} {}\n", prefix.style(self.style), line);
+        // Write the whole line at once, so that lines from other processes
+        // don't interleave with it.
+        if self.to_stderr {
+            io::stderr().write_all(line.as_bytes())
+        } else {
+            io::stdout().write_all(line.as_bytes())
+        }
+    }
+
+    /// Prints a `line` the process wrote to `source` with this prefix.
+    ///
+    /// Backtraces are filtered out with `filter` (which should only be used
+    /// for lines from `source`).
+    pub(crate) fn line(
+        &self,
+        source: Source,
+        filter: &mut backtrace::Filter,
+        line: &str,
+    ) -> io::Result<()> {
+        filter.line(line.trim_end_matches(&['\r', '\n'][..]), |line| {
+            self.print(source, line)
+        })
+    }
+
+    /// Prints whatever `filter` held back once `source` is closed.
+    pub(crate) fn finish(&self, source: Source, filter: &mut backtrace::Filter) -> io::Result<()> {
+        filter.finish(|line| self.print(source, line))
+    }
+}
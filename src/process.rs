@@ -0,0 +1,725 @@
+//! Running test processes, and stopping them when they run for too long.
+//!
+//! Each test process is spawned in its own process group (or, on Windows, job
+//! object), so that any processes it spawns can be stopped along with it.
+//! Processes are only spawned while holding a [`Slot`] from the run's
+//! [`Pool`], which bounds how many pipes are open at once.
+
+use crate::{
+    backtrace, memory,
+    prefixed::{Prefix, Source},
+};
+use camino::Utf8Path;
+use serde::Serialize;
+use std::{
+    error::Error,
+    fmt, io,
+    process::{ExitStatus, Stdio},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter},
+    process::{Child, Command},
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+
+/// How much of the end of a process's output is kept in memory.
+const TAIL_BYTES: usize = 1024 * 1024;
+
+/// How many file descriptors a single process may need: both ends of its
+/// stdin, stdout, and stderr pipes while it's being spawned, and its log
+/// file.
+const FDS_PER_PROCESS: u64 = 7;
+
+/// How many file descriptors are left for everything other than running
+/// processes (`cargo loom`'s own stdio, the async runtime, checkpoints, and
+/// so on).
+const RESERVED_FDS: u64 = 64;
+
+/// Limits how many processes may run at the same time, to `--jobs` or however
+/// many the limit on open files allows, whichever is lower.
+#[derive(Debug)]
+pub(crate) struct Pool {
+    slots: Arc<Semaphore>,
+    size: usize,
+}
+
+/// Permission to run a process, which is returned to its [`Pool`] when this
+/// is dropped.
+#[derive(Debug)]
+pub(crate) struct Slot {
+    _permit: OwnedSemaphorePermit,
+}
+
+/// How long a test process may run for, and how it's stopped if it runs for
+/// longer.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Timeout {
+    limit: Option<Duration>,
+    grace: Duration,
+}
+
+/// The signal that ended a process which timed out.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize)]
+pub enum Signal {
+    #[serde(rename = "SIGTERM")]
+    Term,
+    #[serde(rename = "SIGKILL")]
+    Kill,
+}
+
+/// The process group of a spawned test process.
+///
+/// Every process in the group is killed when this is dropped, so processes
+/// spawned by a test can't outlive it (or a run that's cancelled).
+#[derive(Debug)]
+pub(crate) struct ProcessGroup {
+    #[cfg(unix)]
+    id: Option<u32>,
+    #[cfg(windows)]
+    job: Option<job::Job>,
+}
+
+/// Returned when a run is stopped by a signal.
+#[derive(Debug)]
+pub(crate) struct Interrupted(&'static str);
+
+/// A process that has finished running.
+#[derive(Debug)]
+pub(crate) struct Finished {
+    pub(crate) status: ExitStatus,
+    /// The end of the process's output, on stdout and stderr.
+    pub(crate) stdout: Tail,
+    /// The signal the process was ended with, if it timed out.
+    pub(crate) killed_by: Option<Signal>,
+}
+
+/// The end of a process's output, which is all that's kept in memory (since
+/// the output of a test with trace-level logs can be enormous).
+#[derive(Debug, Default)]
+pub(crate) struct Tail {
+    bytes: Vec<u8>,
+    /// How many bytes of output came before `bytes`.
+    omitted: u64,
+}
+
+/// Captures a process's output as it's written, by writing all of it to a log
+/// file (if there is one), and keeping its [`Tail`] in memory.
+#[derive(Debug)]
+pub(crate) struct Capture {
+    tail: Tail,
+    log: Option<BufWriter<File>>,
+}
+
+impl Timeout {
+    /// Returns a timeout which allows a process to run for `limit` (or
+    /// forever, if it's `None`), and then for another `grace` period after
+    /// it's asked to stop.
+    pub(crate) fn new(limit: Option<Duration>, grace: Duration) -> Self {
+        Self { limit, grace }
+    }
+
+    /// Returns how long a process may run for before it's stopped.
+    pub(crate) fn limit(&self) -> Option<Duration> {
+        self.limit
+    }
+
+    /// Waits for `child` to exit, stopping it if it runs for too long.
+    ///
+    /// A process that times out is sent `SIGTERM` (on Unix), so that it can
+    /// clean up, and then `SIGKILL` if it's still running once the grace
+    /// period has passed.
+    ///
+    /// Once `child` exits, the rest of its process group is killed.
+    pub(crate) async fn wait(
+        &self,
+        child: &mut Child,
+        group: &ProcessGroup,
+    ) -> io::Result<(ExitStatus, Option<Signal>)> {
+        let result = self.wait_inner(child, group).await;
+        group.kill();
+        result
+    }
+
+    async fn wait_inner(
+        &self,
+        child: &mut Child,
+        group: &ProcessGroup,
+    ) -> io::Result<(ExitStatus, Option<Signal>)> {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return Ok((child.wait().await?, None)),
+        };
+        if let Ok(status) = tokio::time::timeout(limit, child.wait()).await {
+            return Ok((status?, None));
+        }
+
+        if group.terminate() {
+            if let Ok(status) = tokio::time::timeout(self.grace, child.wait()).await {
+                return Ok((status?, Some(Signal::Term)));
+            }
+        }
+
+        group.kill();
+        child.kill().await?;
+        Ok((child.wait().await?, Some(Signal::Kill)))
+    }
+}
+
+impl Signal {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Term => "SIGTERM",
+            Self::Kill => "SIGKILL",
+        }
+    }
+}
+
+/// Runs `cmd` to completion using `slot`, stopping it if it runs for longer
+/// than `timeout`.
+///
+/// The process's stdout and stderr are streamed to the file at `log` (if one
+/// is provided, after the given header) as they're written, a line at a time,
+/// and only their [`Tail`] is kept in memory. A test run with `--nocapture`
+/// writes its panics and backtraces to stderr, so they're kept along with
+/// the rest of its output. If a `prefix` is provided, each line is also
+/// printed with that prefix (with backtraces filtered by the given
+/// filter). If the rerun was `admitted` by a memory monitor, the process's
+/// memory use is recorded by it.
+pub(crate) async fn output(
+    cmd: &mut Command,
+    slot: &Slot,
+    timeout: Timeout,
+    prefix: Option<(&Prefix, backtrace::Filter)>,
+    log: Option<(&Utf8Path, &str)>,
+    admitted: Option<&memory::Admitted<'_>>,
+) -> io::Result<Finished> {
+    let mut capture = Capture::new(log).await?;
+    let (mut child, group) = spawn(
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+        slot,
+    )?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+
+    let read_output = async {
+        // Backtraces are filtered separately in each stream, since a
+        // backtrace in one may be interleaved with lines from the other.
+        let mut prefix = prefix.map(|(prefix, filter)| (prefix, filter.clone(), filter));
+        let mut stdout = BufReader::new(stdout);
+        let mut stderr = BufReader::new(stderr);
+        let mut stdout_line = Vec::new();
+        let mut stderr_line = Vec::new();
+        let mut stdout_open = true;
+        let mut stderr_open = true;
+        while stdout_open || stderr_open {
+            // A partially read line is kept in its buffer, so it's finished
+            // by the next read.
+            let (source, line) = tokio::select! {
+                read = stdout.read_until(b'\n', &mut stdout_line), if stdout_open => {
+                    stdout_open = read? > 0;
+                    (Source::Stdout, &mut stdout_line)
+                }
+                read = stderr.read_until(b'\n', &mut stderr_line), if stderr_open => {
+                    stderr_open = read? > 0;
+                    (Source::Stderr, &mut stderr_line)
+                }
+            };
+            if line.is_empty() {
+                continue;
+            }
+            if let Some((prefix, ref mut stdout_filter, ref mut stderr_filter)) = prefix {
+                let filter = match source {
+                    Source::Stdout => stdout_filter,
+                    Source::Stderr => stderr_filter,
+                };
+                prefix.line(source, filter, &String::from_utf8_lossy(line))?;
+            }
+            capture.write(line).await?;
+            line.clear();
+        }
+        if let Some((prefix, ref mut stdout_filter, ref mut stderr_filter)) = prefix {
+            prefix.finish(Source::Stdout, stdout_filter)?;
+            prefix.finish(Source::Stderr, stderr_filter)?;
+        }
+        Ok(())
+    };
+    let ((status, killed_by), ()) =
+        tokio::try_join!(wait(&mut child, &group, timeout, admitted), read_output)?;
+    Ok(Finished {
+        status,
+        stdout: capture.finish().await?,
+        killed_by,
+    })
+}
+
+/// Runs `cmd` to completion using `slot` with its output discarded, stopping
+/// it if it runs for longer than `timeout`. If the rerun was `admitted` by a
+/// memory monitor, the process's memory use is recorded by it.
+pub(crate) async fn status(
+    cmd: &mut Command,
+    slot: &Slot,
+    timeout: Timeout,
+    admitted: Option<&memory::Admitted<'_>>,
+) -> io::Result<(ExitStatus, Option<Signal>)> {
+    let (mut child, group) = spawn(
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null()),
+        slot,
+    )?;
+    wait(&mut child, &group, timeout, admitted).await
+}
+
+/// Waits for `child` to exit, as [`Timeout::wait`] does, while the memory
+/// monitor that `admitted` it (if there is one) records its memory use.
+async fn wait(
+    child: &mut Child,
+    group: &ProcessGroup,
+    timeout: Timeout,
+    admitted: Option<&memory::Admitted<'_>>,
+) -> io::Result<(ExitStatus, Option<Signal>)> {
+    let admitted = match admitted {
+        Some(admitted) => admitted,
+        None => return timeout.wait(child, group).await,
+    };
+    let pid = child.id();
+    tokio::select! {
+        result = timeout.wait(child, group) => result,
+        () = admitted.watch(pid) => unreachable!("watching memory use never completes"),
+    }
+}
+
+/// Spawns `cmd` in a new process group, using `slot`.
+///
+/// The process is killed if the returned [`Child`] is dropped, and the rest
+/// of its group is killed if the [`ProcessGroup`] is.
+pub(crate) fn spawn(cmd: &mut Command, _slot: &Slot) -> io::Result<(Child, ProcessGroup)> {
+    set_process_group(cmd);
+    let child = cmd
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(too_many_open_files)?;
+    let group = ProcessGroup::new(&child);
+    Ok((child, group))
+}
+
+/// Adds a hint to errors caused by running out of file descriptors, which can
+/// still happen if something other than test processes is using them.
+fn too_many_open_files(error: io::Error) -> io::Error {
+    #[cfg(unix)]
+    if error.raw_os_error() == Some(libc::EMFILE) {
+        return io::Error::new(
+            error.kind(),
+            format!(
+                "{} (raise the limit on open files with `ulimit -n`, or pass a lower `--jobs`)",
+                error
+            ),
+        );
+    }
+    error
+}
+
+/// Returns the largest number of files this process may have open at once,
+/// if there is a limit.
+#[cfg(unix)]
+fn max_open_files() -> Option<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // Safety: `limit` is a valid `rlimit` for `getrlimit` to write to.
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0
+        || limit.rlim_cur == libc::RLIM_INFINITY
+    {
+        return None;
+    }
+    #[allow(clippy::useless_conversion)] // `rlim_t` isn't `u64` everywhere.
+    u64::try_from(limit.rlim_cur).ok()
+}
+
+/// On Windows, handles aren't limited in the same way.
+#[cfg(not(unix))]
+fn max_open_files() -> Option<u64> {
+    None
+}
+
+/// Waits until `cargo loom` is asked to stop (with Ctrl-C, or `SIGTERM` on
+/// Unix), and returns an error describing how it was stopped.
+///
+/// Test processes are spawned in their own process groups, so they don't
+/// receive the signals sent to `cargo loom`; they're killed as the run is
+/// dropped instead.
+pub(crate) async fn interrupted() -> color_eyre::Report {
+    match shutdown_signal().await {
+        Ok(signal) => Interrupted(signal).into(),
+        Err(error) => color_eyre::Report::new(error).wrap_err("listening for signals"),
+    }
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() -> io::Result<&'static str> {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result.map(|_| "SIGINT"),
+        _ = terminate.recv() => Ok("SIGTERM"),
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() -> io::Result<&'static str> {
+    tokio::signal::ctrl_c().await.map(|_| "Ctrl-C")
+}
+
+#[cfg(unix)]
+fn set_process_group(cmd: &mut Command) {
+    // Safety: `setpgid` is async-signal-safe, so it may be called between
+    // `fork` and `exec`.
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setpgid(0, 0) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        });
+    }
+}
+
+/// On Windows, the process is added to a job object once it's spawned.
+#[cfg(windows)]
+fn set_process_group(_: &mut Command) {}
+
+/// Sets the niceness of the process spawned by `cmd`.
+#[cfg(unix)]
+pub(crate) fn set_nice(cmd: &mut std::process::Command, nice: i32) {
+    use std::os::unix::process::CommandExt;
+    // Safety: `setpriority` is async-signal-safe, so it may be called between
+    // `fork` and `exec`.
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::setpriority(libc::PRIO_PROCESS, 0, nice) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn set_nice(_: &mut std::process::Command, _: i32) {}
+
+/// Limits the address space of the process spawned by `cmd` to `bytes`, so
+/// that allocations past the limit fail.
+#[cfg(unix)]
+pub(crate) fn set_memory_limit(cmd: &mut std::process::Command, bytes: u64) {
+    use std::os::unix::process::CommandExt;
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // Safety: `limit` is a valid `rlimit` for `getrlimit` to write to.
+    if unsafe { libc::getrlimit(libc::RLIMIT_AS, &mut limit) } != 0 {
+        limit.rlim_max = libc::RLIM_INFINITY;
+    }
+    // Only the soft limit is lowered, since it can't be raised past the
+    // hard limit.
+    #[allow(clippy::useless_conversion)] // `rlim_t` isn't `u64` everywhere.
+    let bytes = libc::rlim_t::try_from(bytes).unwrap_or(libc::RLIM_INFINITY);
+    if limit.rlim_max == libc::RLIM_INFINITY || bytes < limit.rlim_max {
+        limit.rlim_cur = bytes;
+    } else {
+        limit.rlim_cur = limit.rlim_max;
+    }
+    // Safety: `setrlimit` is async-signal-safe, so it may be called between
+    // `fork` and `exec`.
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::setrlimit(libc::RLIMIT_AS, &limit) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        });
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn set_memory_limit(_: &mut std::process::Command, _: u64) {}
+
+// === impl Pool ===
+
+impl Pool {
+    /// Returns a pool which allows up to `jobs` processes to run at a time, or
+    /// fewer if there aren't enough file descriptors for that many.
+    pub(crate) fn new(jobs: usize) -> Self {
+        let mut size = jobs;
+        if let Some(max) = max_open_files() {
+            let fits = max.saturating_sub(RESERVED_FDS) / FDS_PER_PROCESS;
+            size = usize::try_from(fits).unwrap_or(usize::MAX).clamp(1, jobs);
+            if size < jobs {
+                tracing::warn!(
+                    "only {} test processes can run at once, rather than `--jobs {}`, because \
+                    the limit on open files is {}; raise it with `ulimit -n` to run more",
+                    size,
+                    jobs,
+                    max,
+                );
+            }
+        }
+        Self {
+            slots: Arc::new(Semaphore::new(size)),
+            size,
+        }
+    }
+
+    /// Returns how many processes may run at once.
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Waits until fewer than [`size`](Self::size) processes are running, and
+    /// reserves a slot for another.
+    pub(crate) async fn acquire(&self) -> Slot {
+        let permit = self
+            .slots
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        Slot { _permit: permit }
+    }
+}
+
+// === impl Tail ===
+
+impl Tail {
+    pub(crate) fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns how many bytes of output were discarded from the start.
+    pub(crate) fn omitted(&self) -> u64 {
+        self.omitted
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+        // Only discard output once there's a lot to discard, so that it's
+        // not moved for every write.
+        if self.bytes.len() > TAIL_BYTES * 2 {
+            self.truncate();
+        }
+    }
+
+    fn truncate(&mut self) {
+        if self.bytes.len() <= TAIL_BYTES {
+            return;
+        }
+        let mut start = self.bytes.len() - TAIL_BYTES;
+        // Start at the beginning of a line, so that no line (or UTF-8
+        // character) is cut in half.
+        if let Some(newline) = self.bytes[start..].iter().position(|&b| b == b'\n') {
+            start += newline + 1;
+        }
+        self.bytes.drain(..start);
+        self.omitted += start as u64;
+    }
+}
+
+// === impl Capture ===
+
+impl Capture {
+    async fn new(log: Option<(&Utf8Path, &str)>) -> io::Result<Self> {
+        let log = match log {
+            Some((log, header)) => {
+                let mut log = BufWriter::new(File::create(log).await?);
+                log.write_all(header.as_bytes()).await?;
+                Some(log)
+            }
+            None => None,
+        };
+        Ok(Self {
+            tail: Tail::default(),
+            log,
+        })
+    }
+
+    pub(crate) async fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if let Some(ref mut log) = self.log {
+            log.write_all(bytes).await?;
+        }
+        self.tail.push(bytes);
+        Ok(())
+    }
+
+    async fn finish(mut self) -> io::Result<Tail> {
+        if let Some(ref mut log) = self.log {
+            log.flush().await?;
+        }
+        self.tail.truncate();
+        Ok(self.tail)
+    }
+}
+
+// === impl ProcessGroup ===
+
+impl ProcessGroup {
+    #[cfg(unix)]
+    fn new(child: &Child) -> Self {
+        Self { id: child.id() }
+    }
+
+    #[cfg(windows)]
+    fn new(child: &Child) -> Self {
+        let job = child.raw_handle().and_then(|process| {
+            job::Job::new(process)
+                .map_err(|error| {
+                    tracing::debug!(%error, "failed to create a job object for a test process");
+                })
+                .ok()
+        });
+        Self { job }
+    }
+
+    /// Asks every process in the group to exit, returning whether they could
+    /// be asked.
+    fn terminate(&self) -> bool {
+        self.signal(Signal::Term)
+    }
+
+    /// Kills every process in the group.
+    pub(crate) fn kill(&self) {
+        self.signal(Signal::Kill);
+    }
+
+    #[cfg(unix)]
+    fn signal(&self, signal: Signal) -> bool {
+        let id = match self.id {
+            Some(id) => id as libc::pid_t,
+            // The process had already exited when it was spawned.
+            None => return false,
+        };
+        let signal = match signal {
+            Signal::Term => libc::SIGTERM,
+            Signal::Kill => libc::SIGKILL,
+        };
+        // Safety: `kill` has no memory safety requirements. A negative ID
+        // signals the whole process group.
+        unsafe { libc::kill(-id, signal) == 0 }
+    }
+
+    /// There's no way to ask a job's processes to exit on Windows, so they're
+    /// only ever killed.
+    #[cfg(windows)]
+    fn signal(&self, signal: Signal) -> bool {
+        match (signal, &self.job) {
+            (Signal::Kill, Some(job)) => job.terminate(),
+            _ => false,
+        }
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "interrupted by {}", self.0)
+    }
+}
+
+impl Error for Interrupted {}
+
+#[cfg(windows)]
+mod job {
+    use std::{io, mem, os::windows::io::RawHandle, ptr};
+    use winapi::{
+        shared::minwindef::{DWORD, LPVOID},
+        um::{
+            handleapi::CloseHandle,
+            jobapi2::{
+                AssignProcessToJobObject, CreateJobObjectW, SetInformationJobObject,
+                TerminateJobObject,
+            },
+            winnt::{
+                JobObjectExtendedLimitInformation, HANDLE, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+                JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+            },
+        },
+    };
+
+    /// A job object, which kills its processes when it's closed.
+    #[derive(Debug)]
+    pub(super) struct Job(HANDLE);
+
+    // Safety: job object handles may be used from any thread.
+    unsafe impl Send for Job {}
+    unsafe impl Sync for Job {}
+
+    impl Job {
+        /// Creates a job containing `process`.
+        ///
+        /// Processes spawned by `process` once it's in the job are added to
+        /// the job too.
+        pub(super) fn new(process: RawHandle) -> io::Result<Self> {
+            // Safety: a null name and security attributes are allowed.
+            let handle = unsafe { CreateJobObjectW(ptr::null_mut(), ptr::null()) };
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            // Close the handle if anything goes wrong from here.
+            let job = Self(handle);
+
+            // Safety: the limit information is a plain struct, which may be
+            // zeroed.
+            let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { mem::zeroed() };
+            info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+            // Safety: `info` is the type of limit information we pass, and
+            // its size is passed along with it.
+            let set = unsafe {
+                SetInformationJobObject(
+                    job.0,
+                    JobObjectExtendedLimitInformation,
+                    &mut info as *mut _ as LPVOID,
+                    mem::size_of_val(&info) as DWORD,
+                )
+            };
+            if set == 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            // Safety: `process` is a handle to a process we spawned.
+            if unsafe { AssignProcessToJobObject(job.0, process as HANDLE) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(job)
+        }
+
+        /// Kills every process in the job, returning whether they could be
+        /// killed.
+        pub(super) fn terminate(&self) -> bool {
+            // Safety: the handle is open until `self` is dropped.
+            unsafe { TerminateJobObject(self.0, 1) != 0 }
+        }
+    }
+
+    impl Drop for Job {
+        fn drop(&mut self) {
+            // Safety: the handle is open, and isn't used after this.
+            unsafe {
+                CloseHandle(self.0);
+            }
+        }
+    }
+}
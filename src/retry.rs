@@ -0,0 +1,88 @@
+//! Retrying tests that fail while finding failing tests, with `--retries`.
+//!
+//! A test whose failure depends on something loom doesn't control (such as
+//! which permutations it reaches before `LOOM_MAX_DURATION`) may only fail
+//! some of the time. With `--retries N`, each test that fails is run again
+//! (on its own) up to `N` times before it's treated as failing. A test that
+//! passes when it's retried is reported as flaky, rather than checkpointed
+//! and rerun as a failure.
+
+use crate::{process, settings::Settings, suite::TestSuite, test_args, App, ENV_MAX_THREADS};
+use color_eyre::{eyre::WrapErr, Result};
+use owo_colors::{OwoColorize, Style};
+use std::time::Instant;
+use tokio::task::JoinSet;
+
+impl App {
+    /// Retries the failing tests in `failed` up to `retries` times each,
+    /// returning the tests that passed, with their suites.
+    pub(crate) async fn retry(
+        &self,
+        settings: &Settings,
+        failed: Vec<(TestSuite, Vec<String>)>,
+        retries: usize,
+    ) -> Result<Vec<(String, String)>> {
+        let count = failed.iter().map(|(_, tests)| tests.len()).sum::<usize>();
+        if count == 0 || retries == 0 {
+            return Ok(Vec::new());
+        }
+        tracing::info!("Retrying {} failing tests up to {} times", count, retries);
+
+        let mut tasks = JoinSet::new();
+        for (suite, tests) in failed {
+            for test in tests {
+                let mut cmd = suite.command();
+                self.configure_discovery_command(&mut cmd, settings).env(
+                    ENV_MAX_THREADS,
+                    self.max_threads(settings, &test).to_string(),
+                );
+                test_args::exact(&mut cmd).arg(&test);
+                let suite = suite.name().to_owned();
+                let processes = self.processes.clone();
+                let test_timeout = self.test_timeout;
+                let deadline = self.deadline();
+                tasks.spawn(async move {
+                    let name = format!("{}::{}", suite, test);
+                    let mut cmd = tokio::process::Command::from(cmd);
+                    for attempt in 1..=retries {
+                        // Once the run reaches `--total-timeout`, tests that
+                        // haven't passed yet are treated as failing.
+                        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                            break;
+                        }
+                        let slot = processes.acquire().await;
+                        tracing::debug!(test = %name, attempt, "Retrying");
+                        let finished =
+                            process::output(&mut cmd, &slot, test_timeout, None, None, None)
+                                .await
+                                .with_context(|| format!("spawn process to retry {name}"))?;
+                        if finished.killed_by.is_none() && finished.status.success() {
+                            return Ok::<_, color_eyre::Report>(Some((suite, test, attempt)));
+                        }
+                    }
+                    Ok(None)
+                });
+            }
+        }
+
+        let mut flaky = Vec::new();
+        while let Some(result) = tasks.join_one().await? {
+            if let Some((suite, test, attempt)) = result? {
+                let status = format!("flaky (passed on retry {})", attempt);
+                eprintln!(
+                    "test {}::{} ... {}",
+                    suite,
+                    test,
+                    status.style(
+                        self.args
+                            .trace_settings
+                            .color()
+                            .if_color(Style::new().yellow())
+                    )
+                );
+                flaky.push((suite, test));
+            }
+        }
+        Ok(flaky)
+    }
+}
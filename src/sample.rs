@@ -0,0 +1,118 @@
+//! Running a pseudo-random subset of the tests, with `--sample`.
+//!
+//! A sample is deterministic for a given seed: by default the seed is the
+//! current date (in UTC), so every run on the same day samples the same
+//! tests, and runs on different days cover different ones. Passing the
+//! logged `--sample-seed` runs the same sample again.
+
+use crate::shard;
+use std::{
+    fmt,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// How many of the tests to run, with `--sample`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Sample {
+    /// This percentage of the tests (from 0 to 100).
+    Percent(f64),
+    /// At most this many of the tests in each test suite.
+    Count(usize),
+}
+
+impl Sample {
+    /// Returns whether `test` in `suite` may be in a sample taken with
+    /// `seed`.
+    ///
+    /// With [`Sample::Percent`], a test is in the sample if this returns
+    /// `true`. With [`Sample::Count`], every test may be, so
+    /// [`Sample::choose`] picks them from the suite's tests instead.
+    pub(crate) fn includes(self, seed: u64, suite: &str, test: &str) -> bool {
+        match self {
+            // Comparing the hash to a fraction of its range includes about
+            // `percent` of the tests.
+            Self::Percent(percent) => {
+                (hash(seed, suite, test) as f64) < percent / 100.0 * u64::MAX as f64
+            }
+            Self::Count(_) => true,
+        }
+    }
+
+    /// Picks the tests in the sample taken with `seed` from `tests` in
+    /// `suite`, keeping their order.
+    pub(crate) fn choose(self, seed: u64, suite: &str, tests: &mut Vec<String>) {
+        match self {
+            Self::Percent(_) => tests.retain(|test| self.includes(seed, suite, test)),
+            Self::Count(count) => {
+                if tests.len() <= count {
+                    return;
+                }
+                // The tests with the lowest hashes are the sample.
+                let mut hashes = tests
+                    .iter()
+                    .map(|test| hash(seed, suite, test))
+                    .collect::<Vec<_>>();
+                hashes.sort_unstable();
+                let max = hashes[count - 1];
+                tests.retain(|test| hash(seed, suite, test) <= max);
+            }
+        }
+    }
+}
+
+/// Returns the default `--sample-seed`: the number of days since the Unix
+/// epoch, in UTC.
+pub(crate) fn default_seed() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs() / (24 * 60 * 60))
+}
+
+fn hash(seed: u64, suite: &str, test: &str) -> u64 {
+    shard::fnv1a(
+        seed.to_le_bytes()
+            .iter()
+            .chain(suite.as_bytes())
+            .chain(b"::")
+            .chain(test.as_bytes()),
+    )
+}
+
+impl FromStr for Sample {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_suffix('%') {
+            Some(percent) => {
+                let percent = percent
+                    .trim()
+                    .parse::<f64>()
+                    .map_err(|error| format!("invalid percentage `{}`: {}", percent, error))?;
+                if !(percent > 0.0 && percent <= 100.0) {
+                    return Err("the percentage must be more than 0% and at most 100%".to_owned());
+                }
+                Ok(Self::Percent(percent))
+            }
+            None => {
+                let count = s
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|error| format!("invalid count `{}`: {} (or pass `N%`)", s, error))?;
+                if count == 0 {
+                    return Err("the count must be at least 1".to_owned());
+                }
+                Ok(Self::Count(count))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Percent(percent) => write!(f, "{}% of the tests", percent),
+            Self::Count(count) => write!(f, "{} tests from each test suite", count),
+        }
+    }
+}
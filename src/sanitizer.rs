@@ -0,0 +1,275 @@
+//! Rerunning failing tests with a sanitizer, for `--sanitizer`.
+//!
+//! The failing tests' package is rebuilt with `-Z sanitizer` (which needs a
+//! nightly toolchain), and each failing test is rerun from its checkpoint, so
+//! that a failure loom found can be cross-checked with the sanitizer's
+//! reports. Sanitizers require the target to be passed explicitly, so the
+//! tests are built for the host's target triple, separately from the usual
+//! build.
+
+use crate::{
+    features::FeatureSelection,
+    process,
+    settings::Settings,
+    suite,
+    summary::{FailureSummary, SanitizerRun},
+    test_args, App, ENV_MAX_THREADS,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::{eyre::WrapErr, Help, Result};
+use owo_colors::{OwoColorize, Style};
+use std::{collections::HashMap, fs, io, path::PathBuf, process::Command};
+use tokio::task::JoinSet;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, clap::ArgEnum)]
+pub(crate) enum Sanitizer {
+    Thread,
+    Address,
+}
+
+impl Sanitizer {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Self::Thread => "thread",
+            Self::Address => "address",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Thread => "ThreadSanitizer",
+            Self::Address => "AddressSanitizer",
+        }
+    }
+
+    /// The environment variable the sanitizer reads its runtime options from.
+    fn options_env(self) -> &'static str {
+        match self {
+            Self::Thread => "TSAN_OPTIONS",
+            Self::Address => "ASAN_OPTIONS",
+        }
+    }
+}
+
+impl App {
+    /// Rebuilds `pkg`'s tests with `sanitizer`, and reruns each of the
+    /// `failures` from its checkpoint, recording what the sanitizer reported
+    /// in the failure.
+    pub(crate) async fn sanitize(
+        &self,
+        pkg: &cargo_metadata::Package,
+        settings: &Settings,
+        features: &FeatureSelection,
+        sanitizer: Sanitizer,
+        failures: &mut [FailureSummary],
+    ) -> Result<()> {
+        if failures.is_empty() {
+            return Ok(());
+        }
+        let suites = self
+            .build_sanitized(pkg, settings, features, sanitizer)
+            .await?;
+
+        let mut tasks = JoinSet::new();
+        for (idx, failure) in failures.iter().enumerate() {
+            let path = match suites.get(&failure.suite) {
+                Some(path) => path,
+                None => {
+                    tracing::warn!(
+                        test = %failure.name,
+                        "test suite `{}` wasn't rebuilt with {}",
+                        failure.suite,
+                        sanitizer.name()
+                    );
+                    continue;
+                }
+            };
+            // The sanitizer writes its reports to `<prefix>.<pid>`, rather
+            // than to the test's output.
+            let prefix = failure
+                .log
+                .with_extension(format!("{}-sanitizer", sanitizer.as_str()));
+            remove_reports(&prefix)?;
+            let mut options = std::env::var(sanitizer.options_env()).unwrap_or_default();
+            if !options.is_empty() {
+                options.push(' ');
+            }
+            options.push_str(&format!("log_path={}", prefix));
+
+            let mut cmd = Command::new(path);
+            self.configure_checkpoint_command(&mut cmd, settings, &failure.checkpoint)
+                .env(
+                    ENV_MAX_THREADS,
+                    self.max_threads(settings, &failure.name).to_string(),
+                )
+                .env(sanitizer.options_env(), options);
+            test_args::exact(&mut cmd).arg(&failure.name);
+            let name = format!("{}::{}", failure.suite, failure.name);
+            let processes = self.processes.clone();
+            let test_timeout = self.test_timeout;
+            tasks.spawn(async move {
+                let slot = processes.acquire().await;
+                let mut cmd = tokio::process::Command::from(cmd);
+                tracing::debug!(test = %name, "Rerunning with sanitizer");
+                let (_, killed_by) = process::status(&mut cmd, &slot, test_timeout, None)
+                    .await
+                    .with_context(|| format!("spawn process to sanitize {name}"))?;
+                if let Some(signal) = killed_by {
+                    tracing::warn!(test = %name, signal = %signal.as_str(), "sanitized rerun timed out");
+                }
+                let (reports, log) = collect_reports(&prefix)?;
+                Ok::<_, color_eyre::Report>((idx, name, reports, log))
+            });
+        }
+
+        while let Some(result) = tasks.join_one().await? {
+            let (idx, name, reports, log) = result?;
+            let color = self.args.trace_settings.color();
+            let status = match reports.len() {
+                0 => format!("no {} reports", sanitizer.name())
+                    .style(color.if_color(Style::new().green()))
+                    .to_string(),
+                n => format!("{} {} reports", n, sanitizer.name())
+                    .style(color.if_color(Style::new().red()))
+                    .to_string(),
+            };
+            eprintln!("test {} ... {}", name, status);
+            for report in &reports {
+                eprintln!("    {}", report);
+            }
+            if let Some(ref log) = log {
+                eprintln!("    (the full reports are in `{}`)", log);
+            }
+            failures[idx].sanitizer = Some(SanitizerRun {
+                sanitizer: sanitizer.as_str(),
+                reports,
+                log,
+            });
+        }
+        Ok(())
+    }
+
+    /// Builds `pkg`'s tests with `sanitizer`, returning the path to each test
+    /// suite's binary by the suite's name.
+    async fn build_sanitized(
+        &self,
+        pkg: &cargo_metadata::Package,
+        settings: &Settings,
+        features: &FeatureSelection,
+        sanitizer: Sanitizer,
+    ) -> Result<HashMap<String, PathBuf>> {
+        tracing::info!("Rebuilding `{}` with {}", pkg.name, sanitizer.name());
+        let host = self.toolchain.host()?;
+        let mut cmd = self.test_cmd(pkg, settings, features);
+        cmd.env(
+            "RUSTFLAGS",
+            format!("{} -Z sanitizer={}", self.rustflags, sanitizer.as_str()),
+        )
+        .arg("--target")
+        .arg(&host);
+        // ThreadSanitizer can't see the synchronization in a standard
+        // library that wasn't built with it, so it reports false races.
+        if sanitizer == Sanitizer::Thread {
+            cmd.arg("-Z").arg("build-std");
+        }
+        let progress = suite::BuildProgress::new(
+            &self.metadata,
+            &self.target_cfgs,
+            pkg,
+            settings.integration_tests,
+        )
+        .without_counts();
+
+        let note = match sanitizer {
+            Sanitizer::Thread => {
+                "`--sanitizer thread` requires a nightly toolchain (e.g. `--toolchain nightly`) \
+                with the `rust-src` component"
+            }
+            Sanitizer::Address => {
+                "`--sanitizer address` requires a nightly toolchain (e.g. `--toolchain nightly`)"
+            }
+        };
+        let mut suites = HashMap::new();
+        let mut built = suite::spawn_build(cmd, progress)?;
+        while let Some(suite) = built.next().await {
+            let suite = suite
+                .note(note)
+                .with_context(|| format!("building `{}` with {}", pkg.name, sanitizer.name()))?;
+            suites.insert(suite.name().to_owned(), suite.path().to_owned());
+        }
+        Ok(suites)
+    }
+}
+
+/// Returns the files the sanitizer wrote reports to with `log_path=prefix`.
+fn report_files(prefix: &Utf8Path) -> Result<Vec<Utf8PathBuf>> {
+    let (dir, name) = match (prefix.parent(), prefix.file_name()) {
+        (Some(dir), Some(name)) => (dir, format!("{}.", name)),
+        _ => return Ok(Vec::new()),
+    };
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error).with_context(|| format!("reading `{}`", dir)),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let entry = entry.with_context(|| format!("reading `{}`", dir))?;
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+        let is_report = file_name
+            .strip_prefix(&name)
+            .is_some_and(|pid| pid.bytes().all(|b| b.is_ascii_digit()));
+        if is_report {
+            files.push(dir.join(&*file_name));
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Removes the reports from a previous rerun with `log_path=prefix`.
+fn remove_reports(prefix: &Utf8Path) -> Result<()> {
+    for file in report_files(prefix)?
+        .into_iter()
+        .chain([combined_log(prefix)])
+    {
+        match fs::remove_file(&file) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                return Err(error).with_context(|| format!("removing `{}`", file))
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The file the reports written with `log_path=prefix` are combined into.
+fn combined_log(prefix: &Utf8Path) -> Utf8PathBuf {
+    Utf8PathBuf::from(format!("{}.log", prefix))
+}
+
+/// Combines the reports the sanitizer wrote with `log_path=prefix` into one
+/// file, returning the summary line of each report, and the combined file (if
+/// there were any reports).
+fn collect_reports(prefix: &Utf8Path) -> Result<(Vec<String>, Option<Utf8PathBuf>)> {
+    let files = report_files(prefix)?;
+    if files.is_empty() {
+        return Ok((Vec::new(), None));
+    }
+    let mut combined = String::new();
+    for file in files {
+        let report =
+            fs::read_to_string(&file).with_context(|| format!("reading report `{}`", file))?;
+        combined.push_str(&report);
+        fs::remove_file(&file).with_context(|| format!("removing `{}`", file))?;
+    }
+    let summaries = combined
+        .lines()
+        .filter_map(|line| line.strip_prefix("SUMMARY: "))
+        .map(ToOwned::to_owned)
+        .collect();
+    let log = combined_log(prefix);
+    fs::write(&log, combined).with_context(|| format!("writing `{}`", log))?;
+    Ok((summaries, Some(log)))
+}
@@ -0,0 +1,89 @@
+//! Settings for a package's tests, layered from the command line, the
+//! package's `[package.metadata.loom]`, and the workspace's
+//! `[workspace.metadata.loom]`, in that order of precedence.
+
+use crate::{
+    config::Config, loom_env::LoomEnv, App, ENV_CHECKPOINT_INTERVAL, ENV_MAX_BRANCHES,
+    ENV_MAX_DURATION, ENV_MAX_PERMUTATIONS, ENV_MAX_PREEMPTIONS, ENV_MAX_THREADS,
+};
+use std::sync::Arc;
+
+const DEFAULT_MAX_BRANCHES: usize = 1_000;
+const DEFAULT_MAX_THREADS: usize = 4;
+const DEFAULT_CHECKPOINT_INTERVAL: usize = 5;
+const DEFAULT_LOOM_LOG: &str = "trace";
+
+/// The bounds a package's tests are run with, and which of its tests are
+/// run.
+#[derive(Clone, Debug)]
+pub(crate) struct Settings {
+    pub(crate) max_branches: usize,
+    pub(crate) max_permutations: Option<usize>,
+    pub(crate) max_preemptions: Option<usize>,
+    pub(crate) max_threads: usize,
+    pub(crate) max_duration_secs: Option<usize>,
+    pub(crate) checkpoint_interval: usize,
+    /// The `LOOM_LOG` filter for reruns of failing tests which don't have
+    /// their own.
+    pub(crate) loom_log: Arc<str>,
+    /// Whether the package's library unit tests are run.
+    pub(crate) lib: bool,
+    /// Whether the package's integration tests are run.
+    pub(crate) integration_tests: bool,
+}
+
+impl App {
+    /// Returns the settings for the package configured by `config`.
+    pub(crate) fn settings(&self, config: &Config) -> Settings {
+        let loom = &self.args.loom;
+        let cargo = &self.args.cargo;
+        // A bound set with `--loom-env` is the same as one set with its flag.
+        let env = |name| LoomEnv::bound(&loom.loom_env, name);
+        // `--lib` and `--tests` are only overridden by the configuration if
+        // no targets are selected.
+        let (lib, tests) = if cargo.selects_targets() {
+            (cargo.lib, cargo.tests)
+        } else {
+            (config.lib.unwrap_or(false), config.tests.unwrap_or(false))
+        };
+        Settings {
+            max_branches: loom
+                .max_branches
+                .or_else(|| env(ENV_MAX_BRANCHES))
+                .or(config.max_branches)
+                .unwrap_or(DEFAULT_MAX_BRANCHES),
+            max_permutations: loom
+                .max_permutations
+                .or_else(|| env(ENV_MAX_PERMUTATIONS))
+                .or(config.max_permutations),
+            max_preemptions: loom
+                .max_preemptions
+                .or_else(|| env(ENV_MAX_PREEMPTIONS))
+                .or(config.max_preemptions),
+            max_threads: loom
+                .max_threads
+                .or_else(|| env(ENV_MAX_THREADS))
+                .or(config.max_threads)
+                .unwrap_or(DEFAULT_MAX_THREADS),
+            max_duration_secs: loom
+                .max_duration_secs
+                .or_else(|| env(ENV_MAX_DURATION))
+                .or(config.max_duration_secs),
+            checkpoint_interval: loom
+                .checkpoint_interval
+                .or_else(|| env(ENV_CHECKPOINT_INTERVAL))
+                .or(config.checkpoint_interval)
+                .unwrap_or(DEFAULT_CHECKPOINT_INTERVAL),
+            loom_log: loom
+                .loom_log
+                .as_deref()
+                .map(Arc::from)
+                .or_else(|| config.default_loom_log.clone())
+                .unwrap_or_else(|| Arc::from(DEFAULT_LOOM_LOG)),
+            lib,
+            // Without `--lib`, every test is run, unless only other targets
+            // were selected.
+            integration_tests: tests || !(lib || cargo.selects_targets()),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Splitting a run's tests across several `cargo loom` processes, with
+//! `--shard`.
+
+use std::{fmt, str::FromStr};
+
+/// A `--shard K/N`: the `K`th of `N` disjoint subsets of the tests.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Shard {
+    /// The shard's index, starting at 1.
+    index: u64,
+    count: u64,
+}
+
+impl Shard {
+    /// Returns whether `test` in `suite` is in this shard.
+    ///
+    /// Tests are assigned to shards by a hash of their names, so each test
+    /// stays in the same shard as tests are added and removed, and every
+    /// shard agrees on which tests are in the others, on any platform.
+    pub(crate) fn includes(self, suite: &str, test: &str) -> bool {
+        let hash = fnv1a(suite.as_bytes().iter().chain(b"::").chain(test.as_bytes()));
+        hash % self.count == self.index - 1
+    }
+}
+
+/// Hashes `bytes` with FNV-1a, since the standard library's hasher may
+/// change between releases.
+pub(crate) fn fnv1a<'a>(bytes: impl Iterator<Item = &'a u8>) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (index, count) = s
+            .split_once('/')
+            .ok_or_else(|| format!("expected `K/N`, found `{}`", s))?;
+        let index = index
+            .trim()
+            .parse::<u64>()
+            .map_err(|error| format!("invalid shard `{}`: {}", index, error))?;
+        let count = count
+            .trim()
+            .parse::<u64>()
+            .map_err(|error| format!("invalid shard count `{}`: {}", count, error))?;
+        if count == 0 {
+            return Err("the shard count must be at least 1".to_owned());
+        }
+        if index == 0 || index > count {
+            return Err(format!(
+                "the shard must be between 1 and {} (shards are numbered from 1)",
+                count
+            ));
+        }
+        Ok(Self { index, count })
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
@@ -0,0 +1,146 @@
+//! Finding failing tests in two phases, with `--smoke`.
+//!
+//! Most of a big suite's failures can be found without exploring every
+//! interleaving of its models: a bug that needs more than one preemption to
+//! show up is rare. With `--smoke`, every test is first run with a preemption
+//! bound of [`PREEMPTIONS`], which explores a small fraction of the
+//! interleavings the configured bounds allow. Only the tests that pass are
+//! then run again with the configured bounds, so the slow phase isn't spent
+//! on tests that were going to fail anyway.
+//!
+//! Only the preemption bound is lowered. With a lower `--max-branches`, a
+//! model that needs more branches would fail, rather than pass having
+//! explored less of its state space.
+
+use crate::{
+    discovery::{Discovery, SuiteRun},
+    settings::Settings,
+    suite::TestSuite,
+    test_args, App, Failed, ENV_MAX_THREADS,
+};
+use camino::Utf8PathBuf;
+use color_eyre::Result;
+use std::{collections::BTreeMap, sync::Arc};
+use tokio::task::JoinSet;
+
+/// The preemption bound tests are run with in the smoke phase.
+const PREEMPTIONS: usize = 1;
+
+/// The tests in a suite which passed the smoke phase, with the suite's
+/// checkpoint directory.
+pub(crate) type Passed = (TestSuite, Utf8PathBuf, Vec<String>);
+
+impl App {
+    /// Returns the settings tests are run with in the smoke phase: `settings`,
+    /// with a preemption bound of at most [`PREEMPTIONS`].
+    pub(crate) fn smoke_settings(settings: &Settings) -> Settings {
+        let mut smoke = settings.clone();
+        smoke.max_preemptions = Some(
+            settings
+                .max_preemptions
+                .map_or(PREEMPTIONS, |max| max.min(PREEMPTIONS)),
+        );
+        smoke
+    }
+
+    /// Runs the tests in `passed`, which passed the smoke phase, again with
+    /// the configured `settings`, and records their results in `failed`
+    /// (in place of those from the smoke phase).
+    ///
+    /// Tests that fail are added to `retry`, to retry with `--retries`.
+    pub(crate) async fn explore_smoke_passed(
+        &self,
+        discovery: &Arc<Discovery>,
+        settings: &Settings,
+        passed: Vec<Passed>,
+        failed: &mut Failed,
+        retry: &mut Vec<(TestSuite, Vec<String>)>,
+    ) -> Result<()> {
+        let count = passed
+            .iter()
+            .map(|(_, _, tests)| tests.len())
+            .sum::<usize>();
+        if count == 0 {
+            return Ok(());
+        }
+        tracing::info!(
+            "Running {} tests which passed the smoke phase with the full bounds",
+            count
+        );
+
+        let mut tasks = JoinSet::new();
+        for (suite, checkpoint_dir, tests) in passed {
+            // Tests with overridden bounds are run in their own processes.
+            let mut groups = BTreeMap::<usize, Vec<String>>::new();
+            for test in tests {
+                groups
+                    .entry(self.max_threads(settings, &test))
+                    .or_default()
+                    .push(test);
+            }
+            for (max_threads, tests) in groups {
+                let mut cmd = suite.command();
+                self.configure_discovery_command(&mut cmd, settings)
+                    .env(ENV_MAX_THREADS, max_threads.to_string());
+                test_args::exact(&mut cmd).args(&tests);
+                let run =
+                    discovery
+                        .clone()
+                        .run(suite.clone(), cmd, checkpoint_dir.clone(), Vec::new());
+                let count = tests.len();
+                tasks.spawn(async move { run.await.map(|run| (count, run)) });
+            }
+        }
+
+        while let Some(run) = tasks.join_one().await? {
+            let (count, run) = run?;
+            self.record_smoke_passed(count, run, failed, retry);
+        }
+        Ok(())
+    }
+
+    /// Records the results of running `count` tests which passed the smoke
+    /// phase again, in `run`.
+    fn record_smoke_passed(
+        &self,
+        count: usize,
+        mut run: SuiteRun,
+        failed: &mut Failed,
+        retry: &mut Vec<(TestSuite, Vec<String>)>,
+    ) {
+        self.title.failed(run.failed.len());
+        // The tests were counted as passing in the smoke phase.
+        let counts = &mut failed.counts;
+        counts.passed -= count;
+        counts.passed += run.counts.passed;
+        counts.failed += run.counts.failed;
+        counts.not_run += count.saturating_sub(run.counts.passed + run.counts.failed);
+
+        if self.args.loom.retries > 0 && !run.failed.is_empty() {
+            retry.push((run.suite.clone(), run.failed.clone()));
+        }
+        for test in run.failed {
+            let panic = run.panics.remove(&test);
+            failed.fail_test(&run.suite, test, &run.checkpoint_dir, panic);
+        }
+        failed.partially_explored.extend(run.partially_explored);
+        failed.fully_explored.extend(run.fully_explored);
+        if run.timed_out {
+            failed.timed_out_suites.push(run.suite.name().to_owned());
+        }
+        if run.skipped {
+            failed.skipped_suites.push(run.suite.name().to_owned());
+        }
+        failed.timed_out_tests.extend(
+            run.timed_out_tests
+                .iter()
+                .map(|test| format!("{}::{}", run.suite.name(), test)),
+        );
+        failed.slow.extend(run.slow);
+        failed.history.extend(run.results);
+        if self.args.loom.escalate_on_pass && !run.passed.is_empty() {
+            failed.passed.push((run.suite.clone(), run.passed));
+        }
+        failed.finish_suite(run.suite);
+    }
+}
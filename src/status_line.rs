@@ -0,0 +1,86 @@
+//! A single line at the bottom of the terminal which is redrawn in place, to
+//! show the progress of the initial test run.
+
+use std::io::{self, Write};
+
+/// The width a status line is truncated to if the terminal's width can't be
+/// found.
+const DEFAULT_WIDTH: usize = 80;
+
+/// A status line on stderr.
+///
+/// Anything else printed to stderr while the line is shown must be printed
+/// between [`clear`](StatusLine::clear) and [`draw`](StatusLine::draw), so
+/// that it isn't printed after the status on the same line.
+#[derive(Debug, Default)]
+pub(crate) struct StatusLine {
+    shown: bool,
+}
+
+impl StatusLine {
+    /// Replaces the status line with `status`, truncated to the width of the
+    /// terminal so that it can't wrap onto a second line.
+    pub(crate) fn draw(&mut self, status: &str) {
+        // Leave the last column empty, since some terminals wrap as soon as
+        // it's written to.
+        let width = terminal_width().unwrap_or(DEFAULT_WIDTH).saturating_sub(1);
+        let status = match status.char_indices().nth(width) {
+            Some((end, _)) => &status[..end],
+            None => status,
+        };
+        let stderr = io::stderr();
+        let mut stderr = stderr.lock();
+        let _ = write!(stderr, "\r\x1b[2K{}", status);
+        let _ = stderr.flush();
+        self.shown = true;
+    }
+
+    /// Removes the status line, if it's shown, leaving the cursor at the
+    /// start of the line.
+    pub(crate) fn clear(&mut self) {
+        if self.shown {
+            eprint!("\r\x1b[2K");
+            self.shown = false;
+        }
+    }
+}
+
+/// Returns the width of the terminal stderr is written to.
+#[cfg(unix)]
+fn terminal_width() -> Option<usize> {
+    // Safety: `winsize` is plain data, and `ioctl` is passed a pointer to a
+    // local one to write the size to.
+    unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, &mut size) != 0 {
+            return None;
+        }
+        Some(size.ws_col as usize).filter(|&width| width > 0)
+    }
+}
+
+/// Returns the width of the console stderr is written to.
+#[cfg(windows)]
+fn terminal_width() -> Option<usize> {
+    use winapi::um::{
+        processenv::GetStdHandle,
+        winbase::STD_ERROR_HANDLE,
+        wincon::{GetConsoleScreenBufferInfo, CONSOLE_SCREEN_BUFFER_INFO},
+    };
+
+    // Safety: `GetStdHandle` is passed a valid standard stream ID, and
+    // `GetConsoleScreenBufferInfo` a pointer to a local to write to.
+    unsafe {
+        let mut info: CONSOLE_SCREEN_BUFFER_INFO = std::mem::zeroed();
+        if GetConsoleScreenBufferInfo(GetStdHandle(STD_ERROR_HANDLE), &mut info) == 0 {
+            return None;
+        }
+        let width = info.srWindow.Right - info.srWindow.Left + 1;
+        Some(width as usize).filter(|&width| width > 0)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn terminal_width() -> Option<usize> {
+    None
+}
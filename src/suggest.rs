@@ -0,0 +1,84 @@
+//! Suggesting changes to loom's bounds, based on how a run's tests fared.
+
+use crate::{
+    discovery::MAX_BRANCHES_PANIC,
+    escalate::LOOM_MAX_THREADS,
+    settings::Settings,
+    summary::{Classification, FailureSummary, Suggestion, Summary},
+    App,
+};
+
+/// The assertion that fails when a model spawns more than
+/// `LOOM_MAX_THREADS` threads.
+const MAX_THREADS_PANIC: &str = "self.threads.len() < self.max()";
+
+/// How much a bound that was hit is multiplied by in a suggestion.
+const BOUND_FACTOR: usize = 10;
+
+impl App {
+    /// Returns suggested bounds for tests in a package which failed by
+    /// hitting one of loom's bounds, or which timed out or were only
+    /// partially explored.
+    pub(crate) fn suggestions(
+        &self,
+        settings: &Settings,
+        failures: &[FailureSummary],
+        partially_explored: &[String],
+    ) -> Vec<Suggestion> {
+        let mut suggestions = Vec::new();
+        for failure in failures {
+            let test = format!("{}::{}", failure.suite, failure.name);
+            let message = failure.message.as_deref().unwrap_or("");
+            if message.contains(MAX_BRANCHES_PANIC) {
+                suggestions.push(Suggestion {
+                    test,
+                    flag: "--max-branches",
+                    value: settings.max_branches * BOUND_FACTOR,
+                    reason: "it exceeded the maximum number of branches",
+                });
+            } else if message.contains(MAX_THREADS_PANIC) {
+                let max_threads = self.max_threads(settings, &failure.name);
+                if max_threads < LOOM_MAX_THREADS {
+                    suggestions.push(Suggestion {
+                        test,
+                        flag: "--max-threads",
+                        value: max_threads + 1,
+                        reason: "it spawned more threads than the model allows",
+                    });
+                }
+            } else if failure.classification == Classification::TimedOut {
+                let value = match settings.max_preemptions {
+                    None => 2,
+                    Some(preemptions) if preemptions > 1 => preemptions - 1,
+                    Some(_) => continue,
+                };
+                suggestions.push(Suggestion {
+                    test,
+                    flag: "--max-preemptions",
+                    value,
+                    reason: "it timed out, and fewer preemptions make a smaller model",
+                });
+            }
+        }
+
+        if let Some(max_permutations) = settings.max_permutations {
+            suggestions.extend(partially_explored.iter().map(|test| Suggestion {
+                test: test.clone(),
+                flag: "--max-permutations",
+                value: max_permutations * BOUND_FACTOR,
+                reason: "it was only partially explored",
+            }));
+        }
+
+        suggestions
+    }
+}
+
+impl Summary {
+    /// Prints the suggested bounds for every package in the summary.
+    pub(crate) fn print_suggestions(&self) {
+        for suggestion in self.packages.iter().flat_map(|pkg| &pkg.suggestions) {
+            tracing::info!("Consider {}", suggestion);
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use cargo_metadata::{DependencyKind, Metadata, Package, PackageId};
+use cargo_platform::Cfg;
+use color_eyre::{eyre::WrapErr, Result};
+use escargot::{
+    error::CargoResult,
+    format::{diagnostic::DiagnosticLevel, Message},
+    CommandMessages,
+};
+use serde::Deserialize;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use tokio::sync::mpsc;
+
+/// A compiled test binary, as reported by `cargo test --no-run`.
+#[derive(Clone, Debug)]
+pub(crate) struct TestSuite {
+    path: PathBuf,
+    kind: String,
+    name: String,
+    /// Whether cargo reused the test binary from an earlier build, rather
+    /// than compiling it.
+    fresh: bool,
+}
+
+/// Shows how many of the crates a build needs have been compiled.
+///
+/// cargo doesn't say how many crates it will compile in its JSON messages,
+/// so the total is estimated from the dependency graph in the workspace's
+/// metadata.
+#[derive(Debug)]
+pub(crate) struct BuildProgress<'a> {
+    packages: HashMap<&'a str, &'a Package>,
+    done: usize,
+    total: usize,
+    /// Whether `done` and `total` are shown.
+    counts: bool,
+}
+
+/// The parts of a `compiler-artifact` message that aren't public in
+/// `escargot`'s version of it.
+#[derive(Debug, Deserialize)]
+struct ArtifactId<'a> {
+    #[serde(borrow)]
+    package_id: Cow<'a, str>,
+}
+
+/// Returns the `cfg` values of the target that tests are built for, with
+/// `rustflags`.
+///
+/// If `rustc` can't be run, no values are returned, so only dependencies
+/// that aren't platform-specific are counted in the build's progress.
+pub(crate) fn target_cfgs(mut rustc: Command, rustflags: &str) -> Vec<Cfg> {
+    let output = rustc
+        .arg("--print")
+        .arg("cfg")
+        .args(rustflags.split_whitespace())
+        .stderr(Stdio::null())
+        .output();
+    match output {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.parse().ok())
+            .collect(),
+        Ok(output) => {
+            tracing::debug!(status = %output.status, "`rustc --print cfg` failed");
+            Vec::new()
+        }
+        Err(error) => {
+            tracing::debug!(%error, "failed to run `rustc --print cfg`");
+            Vec::new()
+        }
+    }
+}
+
+/// Runs a `cargo test --no-run --message-format=json` command, forwarding
+/// compiler diagnostics to `tracing` and yielding the test binaries it builds.
+///
+/// Each crate that's compiled is shown as `Compiling` with the build's
+/// `progress`.
+pub(crate) fn build<'a>(
+    cmd: Command,
+    mut progress: BuildProgress<'a>,
+) -> Result<impl Iterator<Item = Result<TestSuite>> + 'a> {
+    let msgs = CommandMessages::with_command(cmd).context("spawn cargo build")?;
+    Ok(msgs.filter_map(move |msg| progress.suite(msg)))
+}
+
+/// Test suites from a build that's read on a blocking thread, so that
+/// waiting for cargo doesn't stall other tasks (such as another package's
+/// tests, with `--package-jobs`).
+pub(crate) struct Build<'a> {
+    msgs: mpsc::UnboundedReceiver<CargoResult<escargot::Message>>,
+    progress: BuildProgress<'a>,
+}
+
+/// Like [`build`], but returns the suites as a [`Build`] that can be
+/// awaited.
+pub(crate) fn spawn_build(cmd: Command, progress: BuildProgress<'_>) -> Result<Build<'_>> {
+    let msgs = CommandMessages::with_command(cmd).context("spawn cargo build")?;
+    let (tx, rx) = mpsc::unbounded_channel();
+    // Every message is read even if the `Build` is dropped, so that cargo
+    // isn't left blocked on a full pipe.
+    tokio::task::spawn_blocking(move || {
+        for msg in msgs {
+            let _ = tx.send(msg);
+        }
+    });
+    Ok(Build { msgs: rx, progress })
+}
+
+impl Build<'_> {
+    /// Returns the next test suite the build produced, or `None` once it's
+    /// finished.
+    pub(crate) async fn next(&mut self) -> Option<Result<TestSuite>> {
+        while let Some(msg) = self.msgs.recv().await {
+            if let Some(suite) = self.progress.suite(msg) {
+                return Some(suite);
+            }
+        }
+        None
+    }
+}
+
+impl<'a> BuildProgress<'a> {
+    /// Records a message from the build, returning the test suite it
+    /// reported, if any.
+    fn suite(&mut self, msg: CargoResult<escargot::Message>) -> Option<Result<TestSuite>> {
+        msg.and_then(|msg| {
+            let decoded = msg.decode()?;
+            if let Message::CompilerArtifact(ref art) = decoded {
+                let id = msg.decode_custom::<ArtifactId<'_>>()?;
+                self.compiled(&id.package_id, art.fresh);
+            }
+            log_message(&decoded);
+            Ok(TestSuite::from_message(decoded))
+        })
+        .context("building test suites")
+        .transpose()
+    }
+
+    /// Estimates how many crates are compiled to build `pkg`'s tests, where
+    /// `cfgs` are the target's `cfg` values.
+    ///
+    /// Every library in `pkg`'s dependency graph (and its build script, if
+    /// it has one) is counted once. Crates that are built for both the host
+    /// and the target are only counted once; if the estimate is too low, the
+    /// total grows as crates are compiled.
+    pub(crate) fn new(
+        metadata: &'a Metadata,
+        cfgs: &'a [Cfg],
+        pkg: &Package,
+        integration_tests: bool,
+    ) -> Self {
+        let packages = metadata
+            .packages
+            .iter()
+            .map(|pkg| (pkg.id.repr.as_str(), pkg))
+            .collect::<HashMap<_, _>>();
+
+        // The package's own targets.
+        let linked = integration_tests
+            && pkg.targets.iter().any(|target| {
+                target
+                    .kind
+                    .iter()
+                    .any(|kind| kind == "bin" || kind == "test")
+            });
+        let mut total = 0;
+        for target in &pkg.targets {
+            let units = match target.kind.first().map(String::as_str) {
+                // A library is built once as a test, and once more if
+                // integration tests or binaries link to it.
+                Some("lib") | Some("proc-macro") => 1 + usize::from(linked),
+                Some("bin") | Some("test") if integration_tests => 1,
+                Some("custom-build") => 1,
+                _ => 0,
+            };
+            total += units;
+        }
+
+        // Its dependencies, including its own dev-dependencies, but not
+        // theirs.
+        let mut seen = HashSet::new();
+        if let Some(resolve) = metadata.resolve.as_ref() {
+            let nodes = resolve
+                .nodes
+                .iter()
+                .map(|node| (&node.id, node))
+                .collect::<HashMap<_, _>>();
+            let mut queue: Vec<(&PackageId, bool)> = vec![(&pkg.id, true)];
+            while let Some((id, root)) = queue.pop() {
+                let node = match nodes.get(id) {
+                    Some(node) => node,
+                    None => continue,
+                };
+                for dep in &node.deps {
+                    let needed = dep.dep_kinds.iter().any(|info| {
+                        (root || info.kind != DependencyKind::Development)
+                            && info
+                                .target
+                                .as_ref()
+                                .map_or(true, |platform| platform.matches("", cfgs))
+                    });
+                    if needed && seen.insert(&dep.pkg) {
+                        queue.push((&dep.pkg, false));
+                    }
+                }
+            }
+        }
+        for id in &seen {
+            if let Some(dep) = packages.get(id.repr.as_str()) {
+                total += 1 + dep
+                    .targets
+                    .iter()
+                    .filter(|target| target.kind.iter().any(|kind| kind == "custom-build"))
+                    .count();
+            }
+        }
+
+        Self {
+            packages,
+            done: 0,
+            total,
+            counts: true,
+        }
+    }
+
+    /// Doesn't show how many crates have been compiled, only which.
+    pub(crate) fn without_counts(self) -> Self {
+        Self {
+            counts: false,
+            ..self
+        }
+    }
+
+    /// Records that a crate in the package `id` was compiled, printing it
+    /// unless it was `fresh` (i.e. already built).
+    fn compiled(&mut self, id: &str, fresh: bool) {
+        self.done += 1;
+        self.total = self.total.max(self.done);
+        if fresh {
+            return;
+        }
+        let name = match self.packages.get(id) {
+            Some(pkg) => format!("{} v{}", pkg.name, pkg.version),
+            None => id.to_owned(),
+        };
+        if self.counts {
+            tracing::info!("Compiling {} ({}/{})", name, self.done, self.total);
+        } else {
+            tracing::info!("Compiling {}", name);
+        }
+    }
+}
+
+impl TestSuite {
+    fn from_message(msg: Message<'_>) -> Option<Self> {
+        match msg {
+            Message::CompilerArtifact(art) if art.profile.test => {
+                let path = art.filenames.first()?.to_path_buf();
+                let kind = art.target.kind.first()?.as_ref().to_owned();
+                let name = art.target.name.as_ref().to_owned();
+                Some(Self {
+                    path,
+                    kind,
+                    name,
+                    fresh: art.fresh,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// The name of the test target.
+    pub(crate) fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// The kind of the test target (e.g. `lib`, `bin`, or `test`).
+    pub(crate) fn kind(&self) -> &str {
+        self.kind.as_str()
+    }
+
+    /// The path to the test binary.
+    pub(crate) fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    /// Whether the test binary was already built, and so hasn't changed
+    /// since the last build.
+    pub(crate) fn fresh(&self) -> bool {
+        self.fresh
+    }
+
+    /// Returns a command that runs the test binary with libtest's JSON output.
+    pub(crate) fn command(&self) -> Command {
+        let mut cmd = Command::new(self.path());
+        cmd.arg("-Z").arg("unstable-options").arg("--format=json");
+        cmd
+    }
+}
+
+fn log_message(msg: &Message<'_>) {
+    match msg {
+        Message::BuildFinished(finished) => {
+            tracing::trace!(target: crate::trace::CARGO_TARGET, ?finished, "Build finished")
+        }
+        Message::CompilerArtifact(art) => {
+            tracing::trace!(target: crate::trace::CARGO_TARGET, package = ?art.package_id, "Built artifact")
+        }
+        Message::CompilerMessage(comp) => {
+            let content = comp
+                .message
+                .rendered
+                .as_deref()
+                .unwrap_or_else(|| comp.message.message.as_ref());
+            match comp.message.level {
+                DiagnosticLevel::Ice | DiagnosticLevel::Error => {
+                    tracing::error!(target: crate::trace::CARGO_TARGET, "{}", content)
+                }
+                DiagnosticLevel::Warning => {
+                    tracing::warn!(target: crate::trace::CARGO_TARGET, "{}", content)
+                }
+                _ => tracing::info!(target: crate::trace::CARGO_TARGET, "{}", content),
+            }
+        }
+        _ => {}
+    }
+}
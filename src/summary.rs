@@ -0,0 +1,551 @@
+//! Machine-readable results of a `cargo loom` run.
+
+pub use crate::process::Signal;
+use crate::trace::ColorMode;
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::{eyre::WrapErr, Result};
+use owo_colors::{OwoColorize, Style};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt, fs};
+
+/// A machine-readable summary of a `cargo loom` run.
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    pub packages: Vec<PackageSummary>,
+    /// The lcov report of the code the tests exercised, with `--coverage`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coverage_report: Option<Utf8PathBuf>,
+    /// The HTML report of how long each part of the run took, with
+    /// `--timings`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings_report: Option<Utf8PathBuf>,
+}
+
+/// The results of running the tests in a single package.
+#[derive(Debug, Serialize)]
+pub struct PackageSummary {
+    /// The name of the package.
+    pub name: String,
+    /// The feature set the package was tested with, when running a feature
+    /// matrix.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub features: Option<String>,
+    #[serde(flatten)]
+    pub counts: Counts,
+    pub failures: Vec<FailureSummary>,
+    /// How many of `failures` failed each way, as classified when they were
+    /// found to fail.
+    pub failure_kinds: FailureKinds,
+    /// The directory each test suite's checkpoints are written to.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub checkpoint_dirs: Vec<CheckpointDir>,
+    /// Passing tests which stopped exploring their models because they
+    /// reached a bound (such as `--max-permutations`), so they were not fully
+    /// verified.
+    pub partially_explored: Vec<String>,
+    /// Passing tests whose models all reported exploring their whole state
+    /// spaces. This is only checked when tests are run with a bound, or with
+    /// `--require-complete`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fully_explored: Vec<String>,
+    /// Tests which failed, but passed when they were retried with
+    /// `--retries`. These aren't counted as failures.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub flaky_tests: Vec<String>,
+    /// Passing tests which failed when rerun with `--escalate-on-pass`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub escalated_failures: Option<Vec<String>>,
+    /// Tests which took at least `--warn-slow`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub slow_tests: Vec<SlowTest>,
+    /// How long each test took while finding failing tests, as reported by
+    /// libtest.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub test_times: Vec<TestTime>,
+    /// Test suites which were stopped because they reached `--suite-timeout`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub timed_out_suites: Vec<String>,
+    /// Tests which reached `--test-timeout` while finding failing tests,
+    /// which stopped their suites.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub timed_out_tests: Vec<String>,
+    /// Test suites which weren't run, because the run reached
+    /// `--total-timeout`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped_suites: Vec<String>,
+    /// Failing tests which weren't checkpointed and rerun, because the run
+    /// reached `--total-timeout`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub skipped_tests: Vec<String>,
+    /// Changes to loom's bounds which might help the package's tests.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub suggestions: Vec<Suggestion>,
+}
+
+/// Where a test suite's checkpoints are written.
+#[derive(Clone, Debug, Serialize)]
+pub struct CheckpointDir {
+    /// The name of the test suite.
+    pub suite: String,
+    pub path: Utf8PathBuf,
+}
+
+/// Test counts collected during discovery.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct Counts {
+    pub tests: usize,
+    pub passed: usize,
+    pub failed: usize,
+    pub ignored: usize,
+    pub filtered_out: usize,
+    /// Tests which didn't finish because their suite reached
+    /// `--suite-timeout`.
+    pub not_run: usize,
+}
+
+impl std::ops::AddAssign for Counts {
+    fn add_assign(&mut self, rhs: Self) {
+        self.tests += rhs.tests;
+        self.passed += rhs.passed;
+        self.failed += rhs.failed;
+        self.ignored += rhs.ignored;
+        self.filtered_out += rhs.filtered_out;
+        self.not_run += rhs.not_run;
+    }
+}
+
+/// A single failing test.
+#[derive(Debug, Serialize)]
+pub struct FailureSummary {
+    /// The name of the test, without the name of its suite.
+    pub name: String,
+    /// The name of the test suite (e.g. the integration test file) the test
+    /// is in.
+    pub suite: String,
+    /// The checkpoint the test was rerun from.
+    pub checkpoint: Utf8PathBuf,
+    /// The file the output of the logged rerun was written to.
+    pub log: Utf8PathBuf,
+    /// Whether the logged rerun was killed by `--test-timeout`.
+    pub timed_out: bool,
+    /// The signal that ended the logged rerun, if it timed out.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub killed_by: Option<Signal>,
+    /// Whether checkpointing the test was stopped by `--checkpoint-timeout`,
+    /// so the logged rerun didn't start from a checkpoint of the failure
+    /// (unless one was written before it was stopped).
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub checkpoint_timed_out: bool,
+    pub classification: Classification,
+    /// How the test failed when it was found to fail, if it panicked then
+    /// (it doesn't run if it was already checkpointed).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<FailureKind>,
+    /// The message the test panicked with, if it panicked. If the logged
+    /// rerun didn't panic, this is the message from the initial run that
+    /// found the failure.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Where the test panicked, if it panicked (in the logged rerun, or
+    /// else in the initial run).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+    /// How long the logged rerun took, in seconds.
+    pub duration: f64,
+    /// How many times the test was rerun with logging. A rerun that passes
+    /// is retried up to `--rerun-retries` times.
+    pub attempts: usize,
+    /// The commit that last changed each line involved in the failure, with
+    /// `--blame`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub blame: Vec<Blame>,
+    /// What the sanitizer reported when the test was rerun with
+    /// `--sanitizer`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sanitizer: Option<SanitizerRun>,
+}
+
+/// The result of rerunning a failing test from its checkpoint with
+/// `--sanitizer`.
+#[derive(Debug, Serialize)]
+pub struct SanitizerRun {
+    /// The sanitizer the test was built with: `"thread"` or `"address"`.
+    pub sanitizer: &'static str,
+    /// The summary line of each report the sanitizer printed.
+    pub reports: Vec<String>,
+    /// The file the sanitizer's reports were written to, if it reported
+    /// anything.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log: Option<Utf8PathBuf>,
+}
+
+/// A location in a source file, such as where a test panicked.
+#[derive(Clone, Debug, Serialize)]
+pub struct Location {
+    /// The path of the file, as reported by the test (usually relative to
+    /// the workspace root).
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The commit that last changed a line involved in a failing test.
+#[derive(Debug, Serialize)]
+pub struct Blame {
+    /// The line, as `file:line`.
+    pub location: String,
+    /// The commit's hash, which is all zeroes if the line has uncommitted
+    /// changes.
+    pub commit: String,
+    pub author: String,
+    /// When the commit was authored, in seconds since the Unix epoch.
+    pub author_time: u64,
+    /// The first line of the commit's message.
+    pub summary: String,
+}
+
+/// A test which took at least `--warn-slow`.
+#[derive(Debug, Serialize)]
+pub struct SlowTest {
+    /// The name of the test, including its suite.
+    pub test: String,
+    /// The phase of the run the test was slow in: `"discovery"` or
+    /// `"rerun"`.
+    pub phase: &'static str,
+    /// How long the test took, in seconds.
+    pub duration: f64,
+}
+
+/// How long a test took while finding failing tests.
+#[derive(Debug, Serialize)]
+pub struct TestTime {
+    /// The name of the test, including its suite.
+    pub test: String,
+    /// How long the test took, in seconds.
+    pub duration: f64,
+}
+
+/// A suggested change to the bounds a test is run with.
+#[derive(Debug, Serialize)]
+pub struct Suggestion {
+    /// The name of the test, including its suite.
+    pub test: String,
+    /// The command-line flag to change (e.g. `--max-branches`).
+    pub flag: &'static str,
+    /// The suggested value for `flag`.
+    pub value: usize,
+    /// Why the change is suggested.
+    pub reason: &'static str,
+}
+
+/// How a failing test failed when it was rerun.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Classification {
+    /// The test panicked.
+    Panicked,
+    /// The test was killed by `--test-timeout`.
+    TimedOut,
+    /// The test exited unsuccessfully without panicking (e.g. it aborted).
+    Crashed,
+    /// The test passed when rerun, so its failure couldn't be reproduced.
+    Passed,
+}
+
+/// How a failing test failed when it was found to fail, from the message it
+/// panicked with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    /// The test panicked, e.g. because an assertion failed.
+    Panic,
+    /// loom found a deadlock: every thread in the model was blocked.
+    Deadlock,
+    /// The model exceeded `--max-branches`.
+    TooManyBranches,
+    /// The test failed to allocate memory, e.g. because it reached
+    /// `--memory-limit`.
+    OutOfMemory,
+}
+
+/// How many failing tests failed each way.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct FailureKinds {
+    pub panic: usize,
+    pub deadlock: usize,
+    pub too_many_branches: usize,
+    pub out_of_memory: usize,
+}
+
+/// A failing test, as emitted with `--message-format json`.
+#[derive(Debug, Serialize)]
+struct FailureEvent<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    package: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    features: Option<&'a str>,
+    #[serde(flatten)]
+    failure: &'a FailureSummary,
+}
+
+/// Returned when a run completes but some tests failed.
+#[derive(Debug)]
+pub(crate) struct TestsFailed(pub(crate) usize);
+
+/// Returned when a run with `--require-complete` completes, but some passing
+/// tests were only partially explored.
+#[derive(Debug)]
+pub(crate) struct ExplorationIncomplete(pub(crate) usize);
+
+impl Summary {
+    /// Returns how many tests failed, including those that only failed with
+    /// `--escalate-on-pass`.
+    pub fn failures(&self) -> usize {
+        self.packages
+            .iter()
+            .map(|pkg| {
+                let escalated = pkg.escalated_failures.as_ref().map_or(0, Vec::len);
+                pkg.failures.len() + escalated + pkg.timed_out_tests.len() + pkg.skipped_tests.len()
+            })
+            .sum()
+    }
+
+    /// Returns how many tests in every package failed each way.
+    pub fn failure_kinds(&self) -> FailureKinds {
+        let mut kinds = FailureKinds::default();
+        for pkg in &self.packages {
+            kinds += pkg.failure_kinds;
+        }
+        kinds
+    }
+
+    pub(crate) fn write_to(&self, path: &Utf8Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("creating summary directory `{}`", dir))?;
+        }
+        let file =
+            fs::File::create(path).with_context(|| format!("creating summary file `{}`", path))?;
+        serde_json::to_writer_pretty(file, self)
+            .with_context(|| format!("writing summary file `{}`", path))
+    }
+
+    /// Prints a brief summary of which tests passed and failed to stderr,
+    /// for humans reading the output of a run with `--message-format json`.
+    pub(crate) fn print_human(&self, color: ColorMode) {
+        let mut counts = Counts::default();
+        for pkg in &self.packages {
+            counts += pkg.counts;
+        }
+        let failures = self.failures();
+        let result = if failures == 0 {
+            "ok".style(color.if_color(Style::new().green())).to_string()
+        } else {
+            "FAILED"
+                .style(color.if_color(Style::new().red()))
+                .to_string()
+        };
+        eprintln!(
+            "\nloom result: {}. {} passed; {} failed; {} ignored; {} filtered out; {} not run",
+            result, counts.passed, failures, counts.ignored, counts.filtered_out, counts.not_run,
+        );
+        let kinds = self.failure_kinds();
+        if !kinds.is_empty() {
+            eprintln!("failures by kind: {}", kinds);
+        }
+        for pkg in &self.packages {
+            let package = match pkg.features {
+                Some(ref features) => format!("{} ({})", pkg.name, features),
+                None => pkg.name.clone(),
+            };
+            for failure in &pkg.failures {
+                let mut how = failure.classification.as_str().to_owned();
+                if let Some(kind) = failure.kind {
+                    how = format!("{}; {}", kind, how);
+                }
+                if failure.checkpoint_timed_out {
+                    how.push_str("; checkpointing timed out");
+                }
+                match failure.location {
+                    Some(ref location) => eprintln!(
+                        "    {}: {}::{} ({} at {})",
+                        package, failure.suite, failure.name, how, location
+                    ),
+                    None => eprintln!(
+                        "    {}: {}::{} ({})",
+                        package, failure.suite, failure.name, how
+                    ),
+                }
+            }
+            for test in pkg.escalated_failures.iter().flatten() {
+                eprintln!("    {}: {} (failed with escalated bounds)", package, test);
+            }
+            for test in &pkg.timed_out_tests {
+                eprintln!("    {}: {} (timed out)", package, test);
+            }
+            for test in &pkg.skipped_tests {
+                eprintln!(
+                    "    {}: {} (not rerun, reached `--total-timeout`)",
+                    package, test
+                );
+            }
+        }
+    }
+}
+
+impl Location {
+    /// Parses a `file:line:column` location.
+    pub(crate) fn parse(location: &str) -> Option<Self> {
+        let mut parts = location.rsplitn(3, ':');
+        let column = parts.next()?.parse().ok()?;
+        let line = parts.next()?.parse().ok()?;
+        let file = parts.next()?.to_owned();
+        Some(Self { file, line, column })
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+impl fmt::Display for Blame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.commit.bytes().all(|b| b == b'0') {
+            return f.write_str("(not committed yet)");
+        }
+        let commit = &self.commit[..self.commit.len().min(10)];
+        write!(f, "{} {}: {}", commit, self.author, self.summary)
+    }
+}
+
+impl fmt::Display for Suggestion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`{} {}` for `{}` ({})",
+            self.flag, self.value, self.test, self.reason
+        )
+    }
+}
+
+impl Classification {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Panicked => "panicked",
+            Self::TimedOut => "timed_out",
+            Self::Crashed => "crashed",
+            Self::Passed => "passed",
+        }
+    }
+}
+
+impl FailureKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Panic => "panic",
+            Self::Deadlock => "deadlock",
+            Self::TooManyBranches => "too_many_branches",
+            Self::OutOfMemory => "out_of_memory",
+        }
+    }
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Panic => "assertion/panic",
+            Self::Deadlock => "deadlock detected by loom",
+            Self::TooManyBranches => "too many branches",
+            Self::OutOfMemory => "out of memory",
+        })
+    }
+}
+
+impl FailureKinds {
+    pub fn is_empty(&self) -> bool {
+        self.panic + self.deadlock + self.too_many_branches + self.out_of_memory == 0
+    }
+
+    pub(crate) fn add(&mut self, kind: FailureKind) {
+        match kind {
+            FailureKind::Panic => self.panic += 1,
+            FailureKind::Deadlock => self.deadlock += 1,
+            FailureKind::TooManyBranches => self.too_many_branches += 1,
+            FailureKind::OutOfMemory => self.out_of_memory += 1,
+        }
+    }
+}
+
+impl std::ops::AddAssign for FailureKinds {
+    fn add_assign(&mut self, rhs: Self) {
+        self.panic += rhs.panic;
+        self.deadlock += rhs.deadlock;
+        self.too_many_branches += rhs.too_many_branches;
+        self.out_of_memory += rhs.out_of_memory;
+    }
+}
+
+impl fmt::Display for FailureKinds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kinds = [
+            (self.panic, FailureKind::Panic),
+            (self.deadlock, FailureKind::Deadlock),
+            (self.too_many_branches, FailureKind::TooManyBranches),
+            (self.out_of_memory, FailureKind::OutOfMemory),
+        ];
+        let mut first = true;
+        for (count, kind) in kinds {
+            if count == 0 {
+                continue;
+            }
+            if !first {
+                f.write_str(", ")?;
+            }
+            write!(f, "{} {}", count, kind)?;
+            first = false;
+        }
+        Ok(())
+    }
+}
+
+impl FailureSummary {
+    /// Writes this failure to stderr as a single JSON object, so that JSON
+    /// consumers don't have to piece it together from other messages.
+    pub(crate) fn emit_json(&self, package: &str, features: Option<&str>) -> Result<()> {
+        let event = FailureEvent {
+            kind: "failure",
+            package,
+            features,
+            failure: self,
+        };
+        let json = serde_json::to_string(&event).context("write json message")?;
+        eprintln!("{}", json);
+        Ok(())
+    }
+}
+
+impl fmt::Display for TestsFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            1 => f.write_str("1 loom test failed"),
+            n => write!(f, "{n} loom tests failed"),
+        }
+    }
+}
+
+impl Error for TestsFailed {}
+
+impl fmt::Display for ExplorationIncomplete {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} passing tests were only partially explored, and `--require-complete` was passed",
+            self.0
+        )
+    }
+}
+
+impl Error for ExplorationIncomplete {}
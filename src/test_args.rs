@@ -0,0 +1,100 @@
+//! Interpreting the arguments passed to test binaries after `--`, to know
+//! which tests they run.
+//!
+//! `cargo loom` decides some things about tests without running them (such
+//! as whether a previously checkpointed test is part of a run), so it has to
+//! filter them the same way libtest does.
+
+use crate::TestFilter;
+use std::process::Command;
+
+/// libtest's options which take a value as the next argument.
+const TAKES_VALUE: &[&str] = &[
+    "--color",
+    "--format",
+    "--logfile",
+    "--shuffle-seed",
+    "--test-threads",
+    "-Z",
+];
+
+/// Passes `--exact` to a test binary, unless it was already passed (in the
+/// arguments after `--`), since libtest rejects it if it's passed twice.
+pub(crate) fn exact(cmd: &mut Command) -> &mut Command {
+    if !cmd.get_args().any(|arg| arg == "--exact") {
+        cmd.arg("--exact");
+    }
+    cmd
+}
+
+/// The filters in a test binary's arguments.
+#[derive(Debug, Default)]
+pub(crate) struct Filters {
+    /// Positional arguments: a test is run if it matches any of them.
+    filters: Vec<String>,
+    /// `--skip` patterns: a test is not run if it matches any of them.
+    skip: Vec<String>,
+    /// Whether `--exact` was passed, so patterns must match test names
+    /// exactly, rather than be contained in them.
+    exact: bool,
+}
+
+impl Filters {
+    pub(crate) fn parse(args: &[String]) -> Self {
+        let mut filters = Self::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--exact" {
+                filters.exact = true;
+            } else if arg == "--skip" {
+                filters.skip.extend(args.next().cloned());
+            } else if let Some(skip) = arg.strip_prefix("--skip=") {
+                filters.skip.push(skip.to_owned());
+            } else if TAKES_VALUE.contains(&arg.as_str()) {
+                args.next();
+            } else if !arg.starts_with('-') {
+                filters.filters.push(arg.clone());
+            }
+        }
+        filters
+    }
+
+    /// Returns why `test` isn't run with these arguments and `filter`, or
+    /// `None` if it is.
+    pub(crate) fn excludes(&self, filter: TestFilter<'_>, test: &str) -> Option<String> {
+        let mut patterns = self.filters.iter().map(String::as_str).collect::<Vec<_>>();
+        // `TestFilter::Exact` passes `--exact`, which applies to every
+        // pattern.
+        let exact = match filter {
+            TestFilter::Contains(testname) => {
+                patterns.extend(testname);
+                self.exact
+            }
+            TestFilter::Exact(tests) => {
+                patterns.extend(tests.iter().copied());
+                true
+            }
+        };
+        let matches = |pattern: &str| {
+            if exact {
+                test == pattern
+            } else {
+                test.contains(pattern)
+            }
+        };
+
+        if !patterns.is_empty() && !patterns.iter().any(|&pattern| matches(pattern)) {
+            let patterns = patterns
+                .iter()
+                .map(|pattern| format!("`{}`", pattern))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let exactly = if exact { " exactly" } else { "" };
+            return Some(format!("it doesn't match{} {}", exactly, patterns));
+        }
+        self.skip
+            .iter()
+            .find(|&skip| matches(skip))
+            .map(|skip| format!("it matches `--skip {}`", skip))
+    }
+}
@@ -0,0 +1,258 @@
+//! Recording how long each part of a run takes, with `--timings`.
+//!
+//! Like cargo's `--timings`, this writes a report of when each unit of work
+//! started and how long it took: building each package's tests, finding the
+//! failing tests in each suite, and checkpointing and rerunning each failing
+//! test. The report is written to `target/loom/timings/`, both as
+//! `timings.json` and as a self-contained `timings.html`, to find what makes
+//! a slow run slow.
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::{eyre::WrapErr, Result};
+use serde::Serialize;
+use std::{
+    fmt::{self, Write},
+    fs,
+    sync::Mutex,
+    time::Instant,
+};
+
+/// The units of work of the current run.
+#[derive(Debug)]
+pub(crate) struct Timings {
+    dir: Utf8PathBuf,
+    run: Mutex<Run>,
+}
+
+#[derive(Debug)]
+struct Run {
+    started: Instant,
+    units: Vec<Unit>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Phase {
+    /// Building a package's tests.
+    Build,
+    /// Running a test suite to find its failing tests.
+    Discovery,
+    /// Rerunning a failing test to generate its checkpoint.
+    Checkpoint,
+    /// Rerunning a failing test from its checkpoint, with logging.
+    Rerun,
+}
+
+/// A unit of work, and when it ran.
+#[derive(Debug, Serialize)]
+struct Unit {
+    package: String,
+    /// The feature set the package was tested with, when running a feature
+    /// matrix.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    features: Option<String>,
+    phase: Phase,
+    /// The test suite or test the unit ran, if it isn't a build.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    /// When the unit started, in seconds since the start of the run.
+    start: f64,
+    /// How long the unit took, in seconds.
+    duration: f64,
+}
+
+/// The report written to `timings.json`.
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    /// How long the run took, in seconds.
+    duration: f64,
+    phases: Vec<PhaseTotal>,
+    units: &'a [Unit],
+}
+
+/// The units of a phase, and the total time spent in them.
+#[derive(Debug, Serialize)]
+struct PhaseTotal {
+    phase: Phase,
+    units: usize,
+    /// The total time spent in the phase's units, in seconds. Units run in
+    /// parallel, so the phases may add up to more than the run's duration.
+    duration: f64,
+}
+
+impl Timings {
+    pub(crate) fn new(target_dir: &Utf8Path) -> Self {
+        Self {
+            dir: target_dir.join("timings"),
+            run: Mutex::new(Run {
+                started: Instant::now(),
+                units: Vec::new(),
+            }),
+        }
+    }
+
+    /// Starts timing a new run.
+    pub(crate) fn reset(&self) {
+        let mut run = self.run();
+        run.started = Instant::now();
+        run.units.clear();
+    }
+
+    /// Records that `phase` of `package` (of `name`, if it's a suite or a
+    /// test) started at `started`, and took until now.
+    pub(crate) fn record(
+        &self,
+        package: &str,
+        features: Option<&str>,
+        phase: Phase,
+        name: Option<&str>,
+        started: Instant,
+    ) {
+        let duration = started.elapsed();
+        let mut run = self.run();
+        let start = started
+            .checked_duration_since(run.started)
+            .unwrap_or_default();
+        run.units.push(Unit {
+            package: package.to_owned(),
+            features: features.map(ToOwned::to_owned),
+            phase,
+            name: name.map(ToOwned::to_owned),
+            start: start.as_secs_f64(),
+            duration: duration.as_secs_f64(),
+        });
+    }
+
+    /// Writes the report of the current run, returning the path to its HTML
+    /// version.
+    pub(crate) fn report(&self) -> Result<Utf8PathBuf> {
+        let mut run = self.run();
+        let duration = run.started.elapsed().as_secs_f64();
+        run.units.sort_by(|a, b| {
+            a.start
+                .partial_cmp(&b.start)
+                .unwrap_or(a.phase.cmp(&b.phase))
+        });
+        let mut phases = Vec::<PhaseTotal>::new();
+        for unit in &run.units {
+            match phases.iter_mut().find(|total| total.phase == unit.phase) {
+                Some(total) => {
+                    total.units += 1;
+                    total.duration += unit.duration;
+                }
+                None => phases.push(PhaseTotal {
+                    phase: unit.phase,
+                    units: 1,
+                    duration: unit.duration,
+                }),
+            }
+        }
+        phases.sort_by_key(|total| total.phase);
+        let report = Report {
+            duration,
+            phases,
+            units: &run.units,
+        };
+
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("creating timings directory `{}`", self.dir))?;
+        let json = self.dir.join("timings.json");
+        let file = fs::File::create(&json).with_context(|| format!("creating `{}`", json))?;
+        serde_json::to_writer_pretty(file, &report)
+            .with_context(|| format!("writing `{}`", json))?;
+        let html = self.dir.join("timings.html");
+        fs::write(&html, report.html()).with_context(|| format!("writing `{}`", html))?;
+        Ok(html)
+    }
+
+    fn run(&self) -> std::sync::MutexGuard<'_, Run> {
+        self.run.lock().unwrap_or_else(|poison| poison.into_inner())
+    }
+}
+
+impl Report<'_> {
+    /// Renders the report as an HTML page: the total time spent in each
+    /// phase, and a timeline of the units.
+    fn html(&self) -> String {
+        let mut html = String::new();
+        html.push_str(concat!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
+            "<title>cargo loom timings</title>\n<style>\n",
+            "body { font-family: sans-serif; margin: 2em; }\n",
+            "table { border-collapse: collapse; }\n",
+            "td, th { padding: 2px 8px; text-align: left; white-space: nowrap; }\n",
+            "td.num { text-align: right; }\n",
+            "td.bar { width: 60em; }\n",
+            ".bar div { height: 1em; position: relative; min-width: 1px; }\n",
+            ".build { background: #95cce8; }\n",
+            ".discovery { background: #f0b165; }\n",
+            ".checkpoint { background: #c89ae0; }\n",
+            ".rerun { background: #7fcf8f; }\n",
+            "</style>\n</head>\n<body>\n",
+        ));
+        let _ = writeln!(
+            html,
+            "<h1>cargo loom {} timings</h1>\n<p>Total time: {:.2}s</p>",
+            env!("CARGO_PKG_VERSION"),
+            self.duration
+        );
+
+        html.push_str("<table>\n<tr><th>Phase</th><th>Units</th><th>Total time</th></tr>\n");
+        for total in &self.phases {
+            let _ = writeln!(
+                html,
+                "<tr><td><span class=\"{phase}\">&nbsp;&nbsp;</span> {phase}</td>\
+                <td class=\"num\">{units}</td><td class=\"num\">{duration:.2}s</td></tr>",
+                phase = total.phase,
+                units = total.units,
+                duration = total.duration,
+            );
+        }
+        html.push_str("</table>\n");
+
+        html.push_str(
+            "<h2>Timeline</h2>\n<table>\n<tr><th>Package</th><th>Phase</th><th>Unit</th>\
+            <th>Start</th><th>Duration</th><th></th></tr>\n",
+        );
+        let scale = 100.0 / self.duration.max(f64::EPSILON);
+        for unit in self.units {
+            let mut package = escape(&unit.package);
+            if let Some(ref features) = unit.features {
+                let _ = write!(package, " ({})", escape(features));
+            }
+            let _ = writeln!(
+                html,
+                "<tr><td>{package}</td><td>{phase}</td><td>{name}</td>\
+                <td class=\"num\">{start:.2}s</td><td class=\"num\">{duration:.2}s</td>\
+                <td class=\"bar\"><div class=\"{phase}\" \
+                style=\"left: {left:.2}%; width: {width:.2}%\"></div></td></tr>",
+                phase = unit.phase,
+                name = unit.name.as_deref().map(escape).unwrap_or_default(),
+                start = unit.start,
+                duration = unit.duration,
+                left = unit.start * scale,
+                width = unit.duration * scale,
+            );
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Build => "build",
+            Self::Discovery => "discovery",
+            Self::Checkpoint => "checkpoint",
+            Self::Rerun => "rerun",
+        })
+    }
+}
+
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,93 @@
+use crate::trace::ProgressWhen;
+use std::{fmt, sync::Mutex};
+
+/// Shows the progress of a run in the terminal's window or tab title, so that
+/// a backgrounded run can be monitored at a glance.
+#[derive(Debug)]
+pub(crate) struct TerminalTitle {
+    enabled: bool,
+    progress: Mutex<Progress>,
+}
+
+#[derive(Debug, Default)]
+struct Progress {
+    package: Option<String>,
+    failed: usize,
+    reruns: usize,
+    reruns_done: usize,
+}
+
+impl TerminalTitle {
+    /// Returns a `TerminalTitle` which is enabled if stderr is a terminal, or
+    /// as `progress` says.
+    pub(crate) fn detect(progress: ProgressWhen) -> Self {
+        let enabled = progress.is_enabled();
+        if enabled {
+            // Save the current title, so it can be restored when we're done.
+            eprint!("\x1b[22;0t");
+        }
+        Self {
+            enabled,
+            progress: Mutex::new(Progress::default()),
+        }
+    }
+
+    /// Clears the progress of the previous run.
+    pub(crate) fn reset(&self) {
+        self.update(|progress| *progress = Progress::default());
+    }
+
+    /// Records that the tests in `package` are being run.
+    pub(crate) fn testing(&self, package: &str) {
+        self.update(|progress| progress.package = Some(package.to_owned()));
+    }
+
+    /// Records that `failed` more tests have failed.
+    pub(crate) fn failed(&self, failed: usize) {
+        self.update(|progress| progress.failed += failed);
+    }
+
+    /// Records that `reruns` failing tests are being rerun.
+    pub(crate) fn rerunning(&self, reruns: usize) {
+        self.update(|progress| progress.reruns += reruns);
+    }
+
+    /// Records that a failing test has been rerun.
+    pub(crate) fn rerun_done(&self) {
+        self.update(|progress| progress.reruns_done += 1);
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Progress)) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut progress = self
+            .progress
+            .lock()
+            .unwrap_or_else(|poison| poison.into_inner());
+        f(&mut progress);
+        eprint!("\x1b]0;cargo-loom: {}\x07", progress);
+    }
+}
+
+impl Drop for TerminalTitle {
+    fn drop(&mut self) {
+        if self.enabled {
+            // Restore the title saved in `detect`.
+            eprint!("\x1b[23;0t");
+        }
+    }
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.reruns_done < self.reruns {
+            write!(f, "{}/{} reruns, ", self.reruns_done, self.reruns)?;
+        } else if let Some(ref package) = self.package {
+            write!(f, "testing {}, ", package)?;
+        }
+
+        write!(f, "{} failed", self.failed)
+    }
+}
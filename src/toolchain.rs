@@ -0,0 +1,362 @@
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Help, Result, SectionExt,
+};
+use std::{
+    ffi::{OsStr, OsString},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// The Rust toolchain used for every cargo command spawned by `cargo-loom`.
+#[derive(Debug)]
+pub(crate) struct Toolchain {
+    cargo: PathBuf,
+    pinned: Option<Pinned>,
+}
+
+/// A toolchain pinned by a `--toolchain` override, `$RUSTUP_TOOLCHAIN`, or a
+/// `rust-toolchain` file.
+#[derive(Debug, PartialEq)]
+struct Pinned {
+    name: String,
+    source: Source,
+}
+
+#[derive(Debug, PartialEq)]
+enum Source {
+    Override,
+    /// `$RUSTUP_TOOLCHAIN`, which rustup sets for `cargo +toolchain loom`.
+    Env,
+    File(PathBuf),
+}
+
+const ENV_RUSTUP_TOOLCHAIN: &str = "RUSTUP_TOOLCHAIN";
+const TOOLCHAIN_FILES: &[&str] = &["rust-toolchain", "rust-toolchain.toml"];
+
+impl Toolchain {
+    /// Resolves the toolchain to use.
+    ///
+    /// A toolchain passed with `--toolchain` takes precedence over
+    /// `$RUSTUP_TOOLCHAIN` (which is how `cargo +toolchain loom` selects one),
+    /// which takes precedence over a `rust-toolchain` file in the workspace,
+    /// as with rustup. If none of them are present, the cargo binary that
+    /// invoked us (`$CARGO`) is used.
+    pub(crate) fn resolve(toolchain: Option<&str>, manifest_path: Option<&Path>) -> Result<Self> {
+        let env = std::env::var_os(ENV_RUSTUP_TOOLCHAIN);
+        let pinned = Pinned::select(toolchain, env.as_deref(), manifest_path)?;
+
+        // When we're invoked as a cargo subcommand, `$CARGO` is the path to
+        // the cargo binary that invoked us. Use it for every cargo command we
+        // spawn, rather than whatever `cargo` happens to be on the `PATH`.
+        let default_cargo =
+            || PathBuf::from(std::env::var_os("CARGO").unwrap_or_else(|| OsString::from("cargo")));
+
+        let pinned = match pinned {
+            Some(pinned) => pinned,
+            None => {
+                let cargo = default_cargo();
+                tracing::debug!(cargo = %cargo.display(), "Resolved cargo");
+                return Ok(Self {
+                    cargo,
+                    pinned: None,
+                });
+            }
+        };
+
+        let cargo = match pinned.which_cargo() {
+            Ok(cargo) => cargo,
+            // If rustup isn't installed, a `rust-toolchain` file can't be
+            // honored by anything else either, so just use `$CARGO`.
+            Err(error) if matches!(pinned.source, Source::File(_)) && is_not_found(&error) => {
+                let cargo = default_cargo();
+                tracing::warn!(
+                    toolchain = %pinned.name,
+                    source = %pinned.source,
+                    cargo = %cargo.display(),
+                    "rustup was not found; ignoring pinned toolchain",
+                );
+                return Ok(Self {
+                    cargo,
+                    pinned: None,
+                });
+            }
+            Err(error) => return Err(error),
+        };
+        tracing::debug!(
+            toolchain = %pinned.name,
+            source = %pinned.source,
+            cargo = %cargo.display(),
+            "Resolved pinned toolchain",
+        );
+        Ok(Self {
+            cargo,
+            pinned: Some(pinned),
+        })
+    }
+
+    /// Returns a command that runs this toolchain's `rustc` (or `$RUSTC`, if
+    /// it's set, as cargo would).
+    pub(crate) fn rustc(&self) -> Command {
+        let rustc = std::env::var_os("RUSTC")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| {
+                let rustc = self
+                    .cargo
+                    .with_file_name(format!("rustc{}", std::env::consts::EXE_SUFFIX));
+                if rustc.exists() {
+                    rustc
+                } else {
+                    PathBuf::from("rustc")
+                }
+            });
+        let mut cmd = Command::new(rustc);
+        self.configure(&mut cmd);
+        cmd
+    }
+
+    /// Returns the target triple of the host, as reported by `rustc -vV`.
+    pub(crate) fn host(&self) -> Result<String> {
+        let output = self.rustc_output(&["-vV"])?;
+        output
+            .lines()
+            .find_map(|line| line.strip_prefix("host: "))
+            .map(ToOwned::to_owned)
+            .ok_or_else(|| eyre!("`rustc -vV` didn't print the host's target triple"))
+    }
+
+    /// Returns the version of this toolchain's `rustc`, as reported by
+    /// `rustc -V`.
+    pub(crate) fn version(&self) -> Result<String> {
+        self.rustc_output(&["-V"])
+    }
+
+    /// Returns the path to this toolchain's sysroot.
+    pub(crate) fn sysroot(&self) -> Result<PathBuf> {
+        let output = self.rustc_output(&["--print", "sysroot"])?;
+        Ok(PathBuf::from(output.trim()))
+    }
+
+    fn rustc_output(&self, args: &[&str]) -> Result<String> {
+        let command = format!("rustc {}", args.join(" "));
+        let output = self
+            .rustc()
+            .args(args)
+            .output()
+            .with_context(|| format!("running `{}`", command))?;
+        if !output.status.success() {
+            return Err(eyre!("`{}` failed ({})", command, output.status));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// The path to the cargo binary for this toolchain.
+    pub(crate) fn cargo(&self) -> &Path {
+        self.cargo.as_path()
+    }
+
+    /// Returns a new cargo command using this toolchain.
+    pub(crate) fn command(&self) -> Command {
+        let mut cmd = Command::new(&self.cargo);
+        self.configure(&mut cmd);
+        cmd
+    }
+
+    /// Configures a cargo command so that any tools it invokes (such as
+    /// `rustc`) also resolve through this toolchain.
+    pub(crate) fn configure<'cmd>(&self, cmd: &'cmd mut Command) -> &'cmd mut Command {
+        if let Some(ref pinned) = self.pinned {
+            cmd.env(ENV_RUSTUP_TOOLCHAIN, &pinned.name);
+        }
+        cmd
+    }
+}
+
+impl Pinned {
+    /// Returns the toolchain pinned by `toolchain` (from `--toolchain`), by
+    /// `env` (from `$RUSTUP_TOOLCHAIN`), or by a `rust-toolchain` file, in
+    /// that order.
+    fn select(
+        toolchain: Option<&str>,
+        env: Option<&OsStr>,
+        manifest_path: Option<&Path>,
+    ) -> Result<Option<Self>> {
+        if let Some(name) = toolchain {
+            return Ok(Some(Self {
+                name: name.to_owned(),
+                source: Source::Override,
+            }));
+        }
+        if let Some(name) = env.and_then(OsStr::to_str).filter(|name| !name.is_empty()) {
+            return Ok(Some(Self {
+                name: name.to_owned(),
+                source: Source::Env,
+            }));
+        }
+        Self::find(manifest_path)
+    }
+
+    /// Searches the directory containing the manifest (or the current
+    /// directory), and all of its ancestors, for a `rust-toolchain` file.
+    fn find(manifest_path: Option<&Path>) -> Result<Option<Self>> {
+        let start = match manifest_path.and_then(Path::parent) {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => std::env::current_dir().context("getting current directory")?,
+        };
+
+        for dir in start.ancestors() {
+            for file in TOOLCHAIN_FILES {
+                let path = dir.join(file);
+                let contents = match fs::read_to_string(&path) {
+                    Ok(contents) => contents,
+                    Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                    Err(error) => {
+                        return Err(error).with_context(|| format!("reading `{}`", path.display()))
+                    }
+                };
+
+                return match parse_channel(&contents) {
+                    Some(name) => Ok(Some(Self {
+                        name,
+                        source: Source::File(path),
+                    })),
+                    None => {
+                        tracing::warn!(
+                            path = %path.display(),
+                            "toolchain file does not specify a channel; ignoring it",
+                        );
+                        Ok(None)
+                    }
+                };
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn which_cargo(&self) -> Result<PathBuf> {
+        let output = Command::new("rustup")
+            .args(["which", "cargo", "--toolchain"])
+            .arg(&self.name)
+            .output()
+            .with_context(|| format!("running `rustup` to resolve toolchain `{}`", self.name))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+            return Err(eyre!(
+                "toolchain `{}` pinned by {} is not installed",
+                self.name,
+                self.source
+            ))
+            .section(stderr.header("rustup output:"))
+            .suggestion(format!(
+                "install it with `rustup toolchain install {}`",
+                self.name
+            ));
+        }
+
+        let path = String::from_utf8(output.stdout)
+            .context("`rustup which` output was not utf8")?
+            .trim()
+            .to_owned();
+        Ok(PathBuf::from(path))
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Override => f.write_str("`--toolchain`"),
+            Source::Env => write!(f, "`${}`", ENV_RUSTUP_TOOLCHAIN),
+            Source::File(path) => write!(f, "`{}`", path.display()),
+        }
+    }
+}
+
+/// Parses the toolchain channel from either a TOML toolchain file or a legacy
+/// `rust-toolchain` file containing only the channel name.
+fn parse_channel(contents: &str) -> Option<String> {
+    let mut lines = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'));
+
+    let first = lines.next()?;
+    if !first.starts_with('[') && !first.contains('=') {
+        return Some(first.to_owned());
+    }
+
+    std::iter::once(first).chain(lines).find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        if key.trim() != "channel" {
+            return None;
+        }
+        let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+        Some(value.to_owned())
+    })
+}
+
+fn is_not_found(error: &color_eyre::Report) -> bool {
+    error
+        .downcast_ref::<io::Error>()
+        .map(|error| error.kind() == io::ErrorKind::NotFound)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a workspace pinned to `stable` by a `rust-toolchain` file, and
+    /// returns the path to its manifest.
+    fn pinned_workspace(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("cargo-loom-{}", std::process::id()))
+            .join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("rust-toolchain.toml"),
+            "[toolchain]\nchannel = \"stable\"\n",
+        )
+        .unwrap();
+        dir.join("Cargo.toml")
+    }
+
+    #[test]
+    fn toolchain_file() {
+        let manifest = pinned_workspace("toolchain_file");
+        let pinned = Pinned::select(None, None, Some(&manifest))
+            .unwrap()
+            .unwrap();
+        assert_eq!(pinned.name, "stable");
+        assert!(matches!(pinned.source, Source::File(_)));
+    }
+
+    #[test]
+    fn env_beats_toolchain_file() {
+        let manifest = pinned_workspace("env_beats_toolchain_file");
+        let pinned = Pinned::select(None, Some(OsStr::new("nightly")), Some(&manifest)).unwrap();
+        assert_eq!(
+            pinned,
+            Some(Pinned {
+                name: "nightly".to_owned(),
+                source: Source::Env,
+            })
+        );
+    }
+
+    #[test]
+    fn override_beats_env() {
+        let manifest = pinned_workspace("override_beats_env");
+        let pinned =
+            Pinned::select(Some("beta"), Some(OsStr::new("nightly")), Some(&manifest)).unwrap();
+        assert_eq!(
+            pinned,
+            Some(Pinned {
+                name: "beta".to_owned(),
+                source: Source::Override,
+            })
+        );
+    }
+}
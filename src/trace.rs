@@ -1,6 +1,25 @@
+//! Output settings, and a [`tracing-subscriber`] formatter that makes log
+//! lines look like cargo's own output.
+//!
+//! [`CargoFormatter`] can be used by other cargo subcommands which want the
+//! same formatting:
+//!
+//! ```no_run
+//! use cargo_loom::trace::{CargoFormatter, ColorMode};
+//!
+//! tracing_subscriber::fmt()
+//!     .event_format(CargoFormatter::new(ColorMode::Auto))
+//!     .with_writer(std::io::stderr)
+//!     .init();
+//!
+//! // Prints `   Compiling my-crate` with a right-aligned, green tag.
+//! tracing::info!("Compiling my-crate");
+//! ```
+//!
+//! [`tracing-subscriber`]: https://docs.rs/tracing-subscriber
 use std::{
     fmt,
-    sync::atomic::{AtomicU8, Ordering},
+    sync::atomic::{AtomicBool, Ordering},
 };
 
 use color_eyre::Result;
@@ -11,8 +30,33 @@ use tracing_subscriber::{
     field::Visit,
     fmt::{format::Writer, FmtContext, FormatEvent, FormatFields, FormattedFields},
     registry::LookupSpan,
+    Layer, Registry,
 };
 
+/// The target used for diagnostics forwarded from the underlying cargo build.
+///
+/// [`CargoFormatter`] prints the messages of events with this target as-is
+/// (after the `error` or `warning` they start with, if any), since they were
+/// already formatted by cargo.
+pub const CARGO_TARGET: &str = "cargo::build";
+
+/// How `tracing` is set up when an [`App`](crate::App) is created.
+///
+/// This lets a tool which embeds `cargo loom` keep control of the global
+/// subscriber.
+pub enum Tracing {
+    /// Install `cargo loom`'s own subscriber as the global default, unless a
+    /// global default has already been set.
+    Default,
+    /// Install `cargo loom`'s own subscriber as the global default, with an
+    /// additional layer. The layer only sees the events enabled by the
+    /// `--trace` filter.
+    Layer(Box<dyn Layer<Registry> + Send + Sync>),
+    /// Don't install a subscriber, so that events are recorded by whichever
+    /// subscriber the caller has installed.
+    External,
+}
+
 #[derive(Debug, clap::Args)]
 #[clap(
     next_help_heading = "OUTPUT OPTIONS",
@@ -28,8 +72,20 @@ pub struct TraceSettings {
     /// •  always: Always display colors.
     ///
     /// •  never: Never display colors.
-    #[clap(long, env = "CARGO_TERM_COLORS", default_value = "auto", arg_enum)]
-    color: ColorMode,
+    #[clap(long, env = "CARGO_TERM_COLORS", arg_enum)]
+    color: Option<ColorMode>,
+
+    /// Controls when the progress of a build and run is shown.
+    ///
+    /// Valid values:
+    ///
+    /// •  auto (default): Show progress in `Compiling` messages, and in the terminal's title and a status line while finding failing tests if stderr is a terminal.
+    ///
+    /// •  always: Also show progress in the terminal's title when stderr isn't a terminal.
+    ///
+    /// •  never: Don't show progress, or the status line.
+    #[clap(long, env = "CARGO_TERM_PROGRESS_WHEN", arg_enum, value_name = "WHEN")]
+    progress: Option<ProgressWhen>,
 
     /// The output format for trace messages and diagnostics.
     ///
@@ -41,9 +97,94 @@ pub struct TraceSettings {
     #[clap(long, default_value = "human", arg_enum)]
     message_format: MessageFormat,
 
+    /// How the result of each test is displayed.
+    ///
+    /// Valid values:
+    ///
+    /// •  pretty (default): Print a line for each test.
+    ///
+    /// •  terse: Print a single character for each test.
+    #[clap(long, default_value = "pretty", arg_enum)]
+    format: TestFormat,
+
+    /// How the output of failing tests is displayed when they are rerun.
+    ///
+    /// Valid values:
+    ///
+    /// •  grouped (default): Print each test's output all at once, when it finishes.
+    ///
+    /// •  prefixed: Print each line of output as soon as it is written, prefixed with the test's name.
+    #[clap(long, default_value = "grouped", arg_enum)]
+    rerun_output: RerunOutput,
+
+    /// Stream the output of failing tests as they're rerun with logging,
+    /// like libtest's `--nocapture`.
+    ///
+    /// This implies `--rerun-output prefixed`. libtest holds a test's output
+    /// until the test finishes (and loom's log is written the same way), so
+    /// `--nocapture` is also passed to the test binaries when failing tests
+    /// are rerun, and each line is printed as soon as it's written. Lines
+    /// written to stderr (where libtest then writes panics) are marked with
+    /// `!` after the test's name.
+    #[clap(long)]
+    nocapture: bool,
+
+    /// Wait until every failing test in a package has been rerun, and then
+    /// report them in order of their names.
+    ///
+    /// By default, each failing test's output (and its diagnostic, with
+    /// `--error-format diagnostic`) is printed as soon as its rerun finishes,
+    /// so the order depends on how long each test took. This keeps the
+    /// output of separate runs in the same order, so that they can be
+    /// compared.
+    #[clap(long)]
+    buffer_output: bool,
+
+    /// How backtraces are captured when failing tests are rerun.
+    ///
+    /// Valid values:
+    ///
+    /// •  short (default): Capture a short backtrace, hiding frames from loom and the standard library.
+    ///
+    /// •  full: Capture a full backtrace, including every frame.
+    ///
+    /// •  off: Don't capture backtraces.
+    #[clap(long, default_value = "short", arg_enum)]
+    backtrace_style: BacktraceStyle,
+
+    /// How failing tests are summarized once they have been rerun.
+    ///
+    /// Valid values:
+    ///
+    /// •  human (default): Only print the output of each failing test.
+    ///
+    /// •  diagnostic: Also print each failure like a compiler error, pointing at the line where the test panicked.
+    #[clap(long, default_value = "human", arg_enum)]
+    error_format: ErrorFormat,
+
+    /// Forward the libtest JSON events from the initial test run to stdout
+    /// verbatim.
+    ///
+    /// This is intended for tools that already parse cargo's test JSON. When
+    /// this is set, the output of failing tests is written to stderr rather
+    /// than stdout, and test results are not also printed in the
+    /// `--message-format`.
+    #[clap(long)]
+    raw_test_output: bool,
+
+    /// Don't print status messages (such as `Compiling`), or a
+    /// human-readable summary of the run when it finishes with
+    /// `--message-format json`.
+    ///
+    /// Warnings and errors are still printed, unless disabled by `--trace`.
+    #[clap(long, short = 'q', env = "CARGO_TERM_QUIET")]
+    quiet: bool,
+
     /// A filter string controlling what traces are enabled.
-    #[clap(long = "trace", default_value = "cargo=info,warn", env = "CARGO_LOG")]
-    filter: tracing_subscriber::EnvFilter,
+    ///
+    /// [default: cargo=info,warn, or warn with `--quiet`]
+    #[clap(long = "trace", env = "CARGO_LOG")]
+    filter: Option<tracing_subscriber::EnvFilter>,
 }
 
 impl TraceSettings {
@@ -51,26 +192,107 @@ impl TraceSettings {
         self.message_format
     }
 
+    pub fn test_format(&self) -> TestFormat {
+        self.format
+    }
+
+    pub fn rerun_output(&self) -> RerunOutput {
+        if self.nocapture {
+            RerunOutput::Prefixed
+        } else {
+            self.rerun_output
+        }
+    }
+
+    pub fn nocapture(&self) -> bool {
+        self.nocapture
+    }
+
+    pub fn buffer_output(&self) -> bool {
+        self.buffer_output
+    }
+
+    pub fn backtrace_style(&self) -> BacktraceStyle {
+        self.backtrace_style
+    }
+
+    pub fn error_format(&self) -> ErrorFormat {
+        self.error_format
+    }
+
+    pub fn raw_test_output(&self) -> bool {
+        self.raw_test_output
+    }
+
+    pub fn quiet(&self) -> bool {
+        self.quiet
+    }
+
+    pub fn color(&self) -> ColorMode {
+        self.color.unwrap_or(ColorMode::Auto)
+    }
+
+    pub fn progress(&self) -> ProgressWhen {
+        self.progress.unwrap_or(ProgressWhen::Auto)
+    }
+
+    /// Disables colors, unless a color mode was explicitly configured.
+    pub(crate) fn ci_defaults(&mut self) {
+        self.color.get_or_insert(ColorMode::Never);
+    }
+
     pub fn try_init(&mut self) -> Result<()> {
-        let filter = std::mem::take(&mut self.filter);
-        self.try_init_with(filter)
+        self.init(Tracing::Default)
     }
 
     pub fn try_init_with(&self, filter: tracing_subscriber::EnvFilter) -> Result<()> {
+        self.install(filter, None)
+    }
+
+    /// Sets up `tracing` as configured by `tracing`.
+    ///
+    /// Colors are configured even if no subscriber is installed, since they
+    /// affect more than log lines.
+    pub fn init(&mut self, tracing: Tracing) -> Result<()> {
+        let filter = self.filter.take().unwrap_or_else(|| {
+            let default = if self.quiet {
+                "warn"
+            } else {
+                "cargo=info,warn"
+            };
+            tracing_subscriber::EnvFilter::new(default)
+        });
+        match tracing {
+            // Another `App` in this process already installed a subscriber.
+            Tracing::Default if tracing::dispatcher::has_been_set() => {
+                TraceSettings::color(self).apply();
+                Ok(())
+            }
+            Tracing::Default => self.install(filter, None),
+            Tracing::Layer(layer) => self.install(filter, Some(layer)),
+            Tracing::External => {
+                TraceSettings::color(self).apply();
+                Ok(())
+            }
+        }
+    }
+
+    fn install(
+        &self,
+        filter: tracing_subscriber::EnvFilter,
+        layer: Option<Box<dyn Layer<Registry> + Send + Sync>>,
+    ) -> Result<()> {
         use tracing_subscriber::prelude::*;
-        self.color.set_global();
-        self.message_format.set_global();
+        let color = self.color();
+        color.apply();
         let fmt = tracing_subscriber::fmt::layer().with_writer(std::io::stderr);
         let fmt = match self.message_format {
-            MessageFormat::Human => fmt
-                .event_format(CargoFormatter {
-                    styles: Styles::new(self.color),
-                })
-                .boxed(),
+            MessageFormat::Human => fmt.event_format(CargoFormatter::new(color)).boxed(),
             MessageFormat::Json => fmt.json().boxed(),
         };
 
         tracing_subscriber::registry()
+            .with(layer)
             .with(fmt)
             .with(filter)
             .try_init()?;
@@ -78,24 +300,91 @@ impl TraceSettings {
     }
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, clap::ArgEnum)]
-#[repr(u8)]
+impl fmt::Debug for Tracing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Tracing::Default => f.write_str("Default"),
+            Tracing::Layer(_) => f.debug_tuple("Layer").field(&format_args!("..")).finish(),
+            Tracing::External => f.write_str("External"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, clap::ArgEnum)]
 pub enum ColorMode {
-    Auto = 0,
-    Always = 1,
-    Never = 2,
+    #[default]
+    Auto,
+    Always,
+    Never,
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, clap::ArgEnum)]
-#[repr(u8)]
+pub enum ProgressWhen {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, clap::ArgEnum)]
 pub enum MessageFormat {
-    Human = 0,
-    Json = 1,
+    #[default]
+    Human,
+    Json,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, clap::ArgEnum)]
+pub enum TestFormat {
+    Pretty,
+    Terse,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, clap::ArgEnum)]
+pub enum RerunOutput {
+    Grouped,
+    Prefixed,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, clap::ArgEnum)]
+pub enum ErrorFormat {
+    Human,
+    Diagnostic,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, clap::ArgEnum)]
+pub enum BacktraceStyle {
+    Short,
+    Full,
+    Off,
+}
+
+// === impl ProgressWhen ===
+
+impl ProgressWhen {
+    /// Returns whether progress should be shown on stderr, which must be a
+    /// terminal that supports ANSI escape sequences, unless progress is
+    /// always shown.
+    pub(crate) fn is_enabled(self) -> bool {
+        let terminal = match self {
+            ProgressWhen::Auto => atty::is(atty::Stream::Stderr),
+            ProgressWhen::Always => true,
+            ProgressWhen::Never => false,
+        };
+        terminal && ansi_supported() && std::env::var_os("TERM").map_or(true, |term| term != "dumb")
+    }
 }
 
 // === impl ColorMode ===
 
-static GLOBAL_COLOR_MODE: AtomicU8 = AtomicU8::new(0);
+/// Whether the terminal interprets ANSI escape sequences, as of the last
+/// [`ColorMode::apply`]. This is only false for Windows consoles where they
+/// couldn't be enabled.
+static ANSI_SUPPORTED: AtomicBool = AtomicBool::new(true);
+
+/// Returns whether the terminal interprets ANSI escape sequences (for colors,
+/// or setting its title).
+pub(crate) fn ansi_supported() -> bool {
+    ANSI_SUPPORTED.load(Ordering::Acquire)
+}
 
 impl fmt::Display for ColorMode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -104,17 +393,6 @@ impl fmt::Display for ColorMode {
 }
 
 impl ColorMode {
-    pub fn current() -> Self {
-        match GLOBAL_COLOR_MODE.load(Ordering::Acquire) {
-            x if x == Self::Always as u8 => Self::Always,
-            x if x == Self::Never as u8 => Self::Never,
-            _x => {
-                debug_assert_eq!(_x, Self::Auto as u8, "weird color mode, what the heck?");
-                Self::Auto
-            }
-        }
-    }
-
     pub fn if_color(self, style: owo_colors::Style) -> owo_colors::Style {
         if self.should_color_stderr() {
             style
@@ -123,10 +401,14 @@ impl ColorMode {
         }
     }
 
-    fn set_global(self) {
-        GLOBAL_COLOR_MODE
-            .compare_exchange(0, self as u8, Ordering::AcqRel, Ordering::Acquire)
-            .expect("global color mode already set");
+    /// Enables ANSI escape sequences if necessary, and records whether the
+    /// terminal supports them.
+    ///
+    /// This doesn't change any process-wide color setting: everything that
+    /// prints colors is passed the color mode of the `App` it prints for, so
+    /// each `App` in a process can use its own.
+    fn apply(self) {
+        ANSI_SUPPORTED.store(enable_ansi(), Ordering::Release);
     }
 
     fn as_str(&self) -> &'static str {
@@ -137,65 +419,92 @@ impl ColorMode {
         }
     }
 
-    // pub fn should_color_stdout(self) -> bool {
-    //     match self {
-    //         ColorMode::Auto => atty::is(atty::Stream::Stdout),
-    //         ColorMode::Always => true,
-    //         ColorMode::Never => false,
-    //     }
-    // }
+    /// Returns whether colors are used on a terminal with `--color auto`:
+    /// they are unless the terminal doesn't support them, or `NO_COLOR` is
+    /// set.
+    fn auto_colors() -> bool {
+        ansi_supported() && std::env::var_os("NO_COLOR").map_or(true, |no| no.is_empty())
+    }
 
-    pub fn should_color_stderr(self) -> bool {
+    pub fn should_color_stdout(self) -> bool {
         match self {
-            ColorMode::Auto => atty::is(atty::Stream::Stderr),
+            ColorMode::Auto => atty::is(atty::Stream::Stdout) && Self::auto_colors(),
             ColorMode::Always => true,
             ColorMode::Never => false,
         }
     }
-}
 
-impl Default for ColorMode {
-    fn default() -> Self {
-        Self::current()
+    pub fn should_color_stderr(self) -> bool {
+        match self {
+            ColorMode::Auto => atty::is(atty::Stream::Stderr) && Self::auto_colors(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
     }
 }
 
-// === impl MessageFormat ===
+/// Enables ANSI escape sequences in the Windows console, returning whether
+/// they're supported.
+#[cfg(windows)]
+fn enable_ansi() -> bool {
+    use winapi::um::{
+        consoleapi::{GetConsoleMode, SetConsoleMode},
+        processenv::GetStdHandle,
+        winbase::{STD_ERROR_HANDLE, STD_OUTPUT_HANDLE},
+        wincon::ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+    };
+
+    [STD_OUTPUT_HANDLE, STD_ERROR_HANDLE].iter().all(|&stream| {
+        // Safety: these functions are passed a valid standard stream ID, and
+        // a pointer to a local.
+        unsafe {
+            let handle = GetStdHandle(stream);
+            let mut mode = 0;
+            if GetConsoleMode(handle, &mut mode) == 0 {
+                // This stream isn't a console, so it doesn't matter.
+                return true;
+            }
+            SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) != 0
+        }
+    })
+}
 
-impl MessageFormat {
-    pub fn is_json(self) -> bool {
-        self == MessageFormat::Json
-    }
+/// Other platforms' terminals all support ANSI escape sequences.
+#[cfg(not(windows))]
+fn enable_ansi() -> bool {
+    true
+}
 
-    pub fn current() -> Self {
-        match GLOBAL_COLOR_MODE.load(Ordering::Acquire) {
-            x if x == Self::Human as u8 => Self::Human,
-            x if x == Self::Json as u8 => Self::Json,
-            _x => {
-                #[cfg(debug_assertions)]
-                panic!("weird message format {}", _x);
+// === impl BacktraceStyle ===
 
-                #[cfg(not(debug_assertions))]
-                Self::Human
-            }
+impl BacktraceStyle {
+    /// Returns the value of `RUST_BACKTRACE` that selects this style.
+    pub(crate) fn rust_backtrace(self) -> &'static str {
+        match self {
+            BacktraceStyle::Short => "1",
+            BacktraceStyle::Full => "full",
+            BacktraceStyle::Off => "0",
         }
     }
-
-    fn set_global(self) {
-        GLOBAL_COLOR_MODE
-            .compare_exchange(0, self as u8, Ordering::AcqRel, Ordering::Acquire)
-            .expect("global color mode already set");
-    }
 }
 
-impl Default for MessageFormat {
-    fn default() -> Self {
-        Self::current()
+// === impl MessageFormat ===
+
+impl MessageFormat {
+    pub fn is_json(self) -> bool {
+        self == MessageFormat::Json
     }
 }
 
-#[derive(Debug)]
-struct CargoFormatter {
+/// A [`FormatEvent`] implementation that formats events like cargo's output.
+///
+/// `INFO` events whose message starts with a short word (such as
+/// `Compiling foo`) have that word printed as a right-aligned, title-cased
+/// tag. Other events are prefixed by their level (such as `warning:`), and
+/// followed by the spans they occurred in. Events with the [`CARGO_TARGET`]
+/// target are printed as output forwarded from cargo.
+#[derive(Debug, Default)]
+pub struct CargoFormatter {
     styles: Styles,
 }
 
@@ -208,15 +517,16 @@ struct Visitor<'styles, 'writer> {
     from_escargot: bool,
 }
 
+/// The styles a [`CargoFormatter`] uses for each part of its output.
 #[derive(Debug)]
-struct Styles {
-    error: Style,
+pub struct Styles {
+    pub(crate) error: Style,
     warn: Style,
-    info: Style,
+    pub(crate) info: Style,
     debug: Style,
     trace: Style,
-    pipes: Style,
-    bold: Style,
+    pub(crate) pipes: Style,
+    pub(crate) bold: Style,
 }
 
 struct Prefixed<T> {
@@ -239,7 +549,8 @@ where
 
         let metadata = event.metadata();
         let level = metadata.level();
-        let from_escargot = metadata.fields().field(LOG_TARGET).is_some();
+        let from_escargot =
+            metadata.fields().field(LOG_TARGET).is_some() || metadata.target() == CARGO_TARGET;
 
         let include_spans = {
             let mut visitor = self.visitor(*level, writer.by_ref(), from_escargot);
@@ -281,6 +592,16 @@ where
 }
 
 impl CargoFormatter {
+    /// Returns a formatter which uses colors according to `colors`.
+    pub fn new(colors: ColorMode) -> Self {
+        Self::with_styles(Styles::new(colors))
+    }
+
+    /// Returns a formatter which uses `styles`.
+    pub fn with_styles(styles: Styles) -> Self {
+        Self { styles }
+    }
+
     fn visitor<'styles, 'writer>(
         &'styles self,
         level: Level,
@@ -429,19 +750,39 @@ impl<'styles, 'writer> Visit for Visitor<'styles, 'writer> {
 // === impl Styles ===
 
 impl Styles {
-    fn new(colors: ColorMode) -> Self {
+    /// Returns cargo's styles, if `colors` allows colors on stderr, or no
+    /// styles otherwise.
+    pub fn new(colors: ColorMode) -> Self {
+        Self::with_colors(colors.should_color_stderr())
+    }
+
+    /// Returns cargo's styles, if `colors` allows colors on stdout, or no
+    /// styles otherwise.
+    pub(crate) fn stdout(colors: ColorMode) -> Self {
+        Self::with_colors(colors.should_color_stdout())
+    }
+
+    fn with_colors(enabled: bool) -> Self {
+        let if_color = |style: Style| if enabled { style } else { owo_colors::style() };
         Self {
-            error: colors.if_color(style().red().bold()),
-            warn: colors.if_color(style().yellow().bold()),
-            info: colors.if_color(style().green().bold()),
-            debug: colors.if_color(style().blue().bold()),
-            trace: colors.if_color(style().purple().bold()),
-            bold: colors.if_color(style().bold()),
-            pipes: colors.if_color(style().blue().bold()),
+            error: if_color(style().red().bold()),
+            warn: if_color(style().yellow().bold()),
+            info: if_color(style().green().bold()),
+            debug: if_color(style().blue().bold()),
+            trace: if_color(style().purple().bold()),
+            bold: if_color(style().bold()),
+            pipes: if_color(style().blue().bold()),
         }
     }
 }
 
+impl Default for Styles {
+    /// Returns the styles for [`ColorMode::Auto`].
+    fn default() -> Self {
+        Self::new(ColorMode::default())
+    }
+}
+
 impl<T> Prefixed<T> {
     fn prefix(prefix: &'static str) -> impl Fn(T) -> Prefixed<T> {
         move |val| Prefixed { val, prefix }
@@ -0,0 +1,178 @@
+//! Rerunning the tests whenever the workspace's sources change, for
+//! `--watch`.
+//!
+//! The workspace members' directories are polled for changes, rather than
+//! watched with the platform's file notification APIs, so that watching
+//! works the same everywhere. Once a change is seen, the tests are rebuilt,
+//! and only the test suites that cargo actually recompiled are run again.
+
+use crate::App;
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::{eyre::WrapErr, Result};
+use std::{
+    collections::HashMap,
+    fs, io,
+    time::{Duration, SystemTime},
+};
+
+/// How often the workspace is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How long the workspace must go without changing before the tests are
+/// rerun, so that saving several files at once only causes one run.
+const SETTLE_TIME: Duration = Duration::from_millis(200);
+
+/// The files being watched, and when each was last modified.
+type Snapshot = HashMap<Utf8PathBuf, (SystemTime, u64)>;
+
+/// The directories and files to watch for changes.
+#[derive(Clone, Debug)]
+struct Sources {
+    roots: Vec<Utf8PathBuf>,
+    /// The target directory, which is always skipped, since building the
+    /// tests changes it.
+    target_dir: Utf8PathBuf,
+}
+
+impl App {
+    /// Runs the tests, and then runs them again each time a file in the
+    /// workspace changes, until interrupted.
+    pub(crate) async fn watch(&self) -> Result<()> {
+        let sources = Sources::new(&self.metadata);
+        let mut snapshot = sources.snapshot_blocking().await?;
+        loop {
+            // Failing tests (and even failing builds) are reported, and then
+            // fixed by the next change.
+            if let Err(error) = self.run_all().await {
+                // The failing tests were already reported.
+                if crate::exit_code(&error) != crate::EXIT_TESTS_FAILED {
+                    tracing::error!("{:?}", error);
+                }
+            }
+
+            tracing::info!("Waiting for changes; press Ctrl-C to stop");
+            let changed = loop {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                let mut next = sources.snapshot_blocking().await?;
+                if next == snapshot {
+                    continue;
+                }
+                // Wait for the changes to stop, so that a run isn't started
+                // halfway through saving.
+                loop {
+                    tokio::time::sleep(SETTLE_TIME).await;
+                    let settled = sources.snapshot_blocking().await?;
+                    if settled == next {
+                        break;
+                    }
+                    next = settled;
+                }
+                let changed = changes(&snapshot, &next);
+                snapshot = next;
+                break changed;
+            };
+
+            let root = &self.metadata.workspace_root;
+            let shown = changed
+                .iter()
+                .take(3)
+                .map(|path| format!("`{}`", path.strip_prefix(root).unwrap_or(path)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            match changed.len() {
+                0..=3 => tracing::info!("Rerunning tests, because {} changed", shown),
+                n => tracing::info!(
+                    "Rerunning tests, because {} and {} more files changed",
+                    shown,
+                    n - 3
+                ),
+            }
+        }
+    }
+}
+
+impl Sources {
+    /// Watches the directory of each package in the workspace, and the
+    /// workspace's manifest and lockfile.
+    fn new(metadata: &cargo_metadata::Metadata) -> Self {
+        let mut roots = metadata
+            .workspace_members
+            .iter()
+            .filter_map(|id| metadata.packages.iter().find(|pkg| &pkg.id == id))
+            .filter_map(|pkg| pkg.manifest_path.parent())
+            .map(Utf8Path::to_path_buf)
+            .collect::<Vec<_>>();
+        roots.sort();
+        // A package's directory may contain other packages' directories, so
+        // they're only walked once.
+        roots.dedup_by(|dir, parent| dir.starts_with(parent));
+        let root = &metadata.workspace_root;
+        for file in [root.join("Cargo.toml"), root.join("Cargo.lock")] {
+            if !roots.iter().any(|dir| file.starts_with(dir)) {
+                roots.push(file);
+            }
+        }
+        Self {
+            roots,
+            target_dir: metadata.target_directory.clone(),
+        }
+    }
+
+    async fn snapshot_blocking(&self) -> Result<Snapshot> {
+        let sources = self.clone();
+        tokio::task::spawn_blocking(move || sources.snapshot())
+            .await
+            .context("watching for changes")?
+    }
+
+    fn snapshot(&self) -> Result<Snapshot> {
+        let mut snapshot = Snapshot::new();
+        for root in &self.roots {
+            self.visit(root, &mut snapshot)
+                .with_context(|| format!("watching `{}` for changes", root))?;
+        }
+        Ok(snapshot)
+    }
+
+    fn visit(&self, path: &Utf8Path, snapshot: &mut Snapshot) -> io::Result<()> {
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            // Files may be removed while the workspace is walked.
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(error),
+        };
+        if !metadata.is_dir() {
+            snapshot.insert(path.to_owned(), (metadata.modified()?, metadata.len()));
+            return Ok(());
+        }
+        if path == self.target_dir {
+            return Ok(());
+        }
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let path = match Utf8PathBuf::from_path_buf(entry.path()) {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+            // Skip `.git` and editors' hidden files.
+            if path.file_name().is_some_and(|name| name.starts_with('.')) {
+                continue;
+            }
+            self.visit(&path, snapshot)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the files that were added, removed, or modified between `old`
+/// and `new`, sorted by path.
+fn changes(old: &Snapshot, new: &Snapshot) -> Vec<Utf8PathBuf> {
+    let mut changed = new
+        .iter()
+        .filter(|&(path, modified)| old.get(path) != Some(modified))
+        .map(|(path, _)| path.clone())
+        .chain(old.keys().filter(|path| !new.contains_key(*path)).cloned())
+        .collect::<Vec<_>>();
+    changed.sort();
+    changed
+}
@@ -0,0 +1,39 @@
+mod support;
+
+use support::Fixture;
+
+#[test]
+fn nocapture_streams_and_logs_panics() {
+    let fixture = Fixture::new(
+        "nocapture",
+        r#"
+            #[test]
+            fn panics() {
+                panic!("the fixture panicked");
+            }
+        "#,
+    );
+    let output = fixture.cargo_loom(&["--nocapture"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        output.status.code(),
+        Some(1),
+        "stderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // With `--nocapture`, libtest writes the panic to stderr, which is
+    // streamed with the `!` marker.
+    let streamed = stdout
+        .lines()
+        .find(|line| line.contains("the fixture panicked"))
+        .unwrap_or_else(|| panic!("panic wasn't streamed; stdout:\n{}", stdout));
+    assert!(streamed.starts_with("panics !"), "{}", streamed);
+
+    let logs = fixture.logs();
+    assert!(
+        logs.iter().any(|log| log.contains("the fixture panicked")),
+        "panic wasn't logged; logs:\n{:#?}",
+        logs
+    );
+}
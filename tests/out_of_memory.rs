@@ -0,0 +1,38 @@
+mod support;
+
+use support::Fixture;
+
+#[test]
+fn rerun_out_of_memory() {
+    // The test panics when it's first run, but runs out of memory when it's
+    // rerun with logging (and location tracking).
+    let fixture = Fixture::new(
+        "out_of_memory",
+        r#"
+            #[test]
+            fn allocates() {
+                if std::env::var_os("LOOM_LOCATION").is_some() {
+                    std::alloc::handle_alloc_error(std::alloc::Layout::new::<[u8; 1024]>());
+                }
+                panic!("the fixture panicked");
+            }
+        "#,
+    );
+    let output = fixture.cargo_loom(&["--message-format", "json"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "stderr:\n{}", stderr);
+
+    // With `--message-format json`, failures are reported on stderr.
+    let failure = stderr
+        .lines()
+        .find(|line| line.starts_with(r#"{"type":"failure""#))
+        .unwrap_or_else(|| panic!("no failure was reported; stderr:\n{}", stderr));
+    let failure: serde_json::Value = serde_json::from_str(failure).expect("parse failure");
+    assert_eq!(failure["kind"], "out_of_memory", "{}", failure);
+    assert_eq!(failure["classification"], "crashed", "{}", failure);
+    assert_eq!(
+        failure["message"], "memory allocation of 1024 bytes failed",
+        "{}",
+        failure
+    );
+}
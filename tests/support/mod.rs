@@ -0,0 +1,76 @@
+//! Running `cargo loom` against small crates written for each test.
+
+#![allow(dead_code)] // Not every test uses every helper.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+/// A crate for `cargo loom` to test, in its own workspace.
+pub struct Fixture {
+    dir: PathBuf,
+}
+
+impl Fixture {
+    /// Writes a crate named `name` whose library is `lib_rs`, replacing any
+    /// crate left by a previous run of the tests.
+    pub fn new(name: &str, lib_rs: &str) -> Self {
+        let dir = Path::new(env!("CARGO_TARGET_TMPDIR"))
+            .join("fixtures")
+            .join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).expect("create fixture directory");
+        fs::write(
+            dir.join("Cargo.toml"),
+            format!(
+                "[package]\nname = \"{}\"\nversion = \"0.0.0\"\nedition = \"2021\"\n\n[workspace]\n",
+                name
+            ),
+        )
+        .expect("write fixture manifest");
+        fs::write(dir.join("src").join("lib.rs"), lib_rs).expect("write fixture library");
+        Self { dir }
+    }
+
+    /// Runs `cargo loom` with `args` on this crate.
+    pub fn cargo_loom(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_cargo-loom"))
+            .arg("loom")
+            .args(args)
+            .current_dir(&self.dir)
+            // cargo sets this for the tests themselves, and `cargo loom` would
+            // read it as `--manifest-path`.
+            .env_remove("CARGO_MANIFEST_PATH")
+            // libtest only accepts the `-Z unstable-options` that `cargo loom`
+            // passes for its JSON output on nightly, or with this set.
+            .env("RUSTC_BOOTSTRAP", "1")
+            .env("NO_COLOR", "1")
+            .output()
+            .expect("run cargo loom")
+    }
+
+    /// Returns the contents of every log `cargo loom` wrote for a failing
+    /// test's rerun.
+    pub fn logs(&self) -> Vec<String> {
+        let mut logs = Vec::new();
+        collect_logs(&self.dir.join("target").join("loom"), &mut logs);
+        logs
+    }
+}
+
+fn collect_logs(dir: &Path, logs: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries {
+        let path = entry.expect("read log directory").path();
+        if path.is_dir() {
+            collect_logs(&path, logs);
+        } else if path.extension().is_some_and(|ext| ext == "log") {
+            logs.push(fs::read_to_string(&path).expect("read log"));
+        }
+    }
+}
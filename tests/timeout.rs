@@ -0,0 +1,48 @@
+mod support;
+
+use std::time::{Duration, Instant};
+use support::Fixture;
+
+#[test]
+fn test_timeout_kills_hung_tests() {
+    let fixture = Fixture::new(
+        "timeout",
+        r#"
+            #[test]
+            fn hangs() {
+                loop {
+                    std::thread::sleep(std::time::Duration::from_secs(1));
+                }
+            }
+
+            #[test]
+            fn passes() {}
+        "#,
+    );
+    // Build the fixture first, so that only running the tests is timed.
+    let output = fixture.cargo_loom(&["list"]);
+    assert!(
+        output.status.success(),
+        "stderr:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let started = Instant::now();
+    let output = fixture.cargo_loom(&["--test-timeout", "1", "--kill-grace-period", "1"]);
+    let elapsed = started.elapsed();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(1), "stderr:\n{}", stderr);
+
+    // If the hung test weren't killed, `cargo loom` would never finish.
+    assert!(elapsed < Duration::from_secs(60), "took {:?}", elapsed);
+    assert!(
+        stderr.contains("reached `--test-timeout` (1s)"),
+        "stderr:\n{}",
+        stderr
+    );
+    assert!(
+        stderr.lines().any(|line| line.trim() == "hangs"),
+        "stderr:\n{}",
+        stderr
+    );
+}